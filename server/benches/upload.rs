@@ -0,0 +1,200 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use bytes::Bytes;
+use criterion::{
+    BenchmarkId, Criterion, Throughput, criterion_group, criterion_main,
+    measurement::{Measurement, ValueFormatter},
+};
+use http_body_util::Full;
+use hyper::{Request, service::Service};
+use server::server::SliceBreadServer;
+use tempdir::TempDir;
+use tokio::runtime::Runtime;
+
+/// Counts every byte handed out by the allocator so the `memory` group can
+/// report allocations per upload instead of wall time.
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size.saturating_sub(layout.size()), Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+struct AllocatedBytes;
+
+impl Measurement for AllocatedBytes {
+    type Intermediate = usize;
+    type Value = usize;
+
+    fn start(&self) -> Self::Intermediate {
+        ALLOCATED.load(Ordering::SeqCst)
+    }
+
+    fn end(&self, i: Self::Intermediate) -> Self::Value {
+        ALLOCATED.load(Ordering::SeqCst) - i
+    }
+
+    fn add(&self, v1: &Self::Value, v2: &Self::Value) -> Self::Value {
+        v1 + v2
+    }
+
+    fn zero(&self) -> Self::Value {
+        0
+    }
+
+    fn to_f64(&self, value: &Self::Value) -> f64 {
+        *value as f64
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        &BytesFormatter
+    }
+}
+
+struct BytesFormatter;
+
+impl ValueFormatter for BytesFormatter {
+    fn scale_values(&self, typical_value: f64, values: &mut [f64]) -> &'static str {
+        let (factor, unit) = if typical_value < 1024.0 {
+            (1.0, "B")
+        } else if typical_value < 1024.0 * 1024.0 {
+            (1024.0, "KiB")
+        } else {
+            (1024.0 * 1024.0, "MiB")
+        };
+        values.iter_mut().for_each(|v| *v /= factor);
+        unit
+    }
+
+    fn scale_throughputs(
+        &self,
+        _typical_value: f64,
+        _throughput: &Throughput,
+        _values: &mut [f64],
+    ) -> &'static str {
+        "B"
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        "B"
+    }
+}
+
+static NEXT_FILE_ID: AtomicUsize = AtomicUsize::new(0);
+
+fn next_file_id() -> String {
+    format!("bench{}", NEXT_FILE_ID.fetch_add(1, Ordering::Relaxed))
+}
+
+fn chunk_request(file_id: &str, index: usize, total: usize, data: Bytes) -> Request<Full<Bytes>> {
+    Request::builder()
+        .method("POST")
+        .header("X-File-Id", file_id)
+        .header("X-File-Name", "bench.bin")
+        .header("X-Chunk-Index", index.to_string())
+        .header("X-Total-Chunks", total.to_string())
+        .body(Full::new(data))
+        .unwrap()
+}
+
+async fn upload(service: &SliceBreadServer<Full<Bytes>>, chunks: usize, data: &Bytes) {
+    let file_id = next_file_id();
+    for i in 0..chunks {
+        service
+            .call(chunk_request(&file_id, i, chunks, data.clone()))
+            .await
+            .unwrap();
+    }
+}
+
+fn new_service(temp_dir: &TempDir) -> SliceBreadServer<Full<Bytes>> {
+    SliceBreadServer::new(temp_dir.path().to_str().unwrap().to_string())
+}
+
+fn bench_chunk_ingest(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let temp_dir = TempDir::new("bench_ingest").unwrap();
+    let service = new_service(&temp_dir);
+
+    let mut group = c.benchmark_group("chunk_ingest");
+    for size in [64 * 1024, 1024 * 1024, 8 * 1024 * 1024] {
+        let data = Bytes::from(vec![b'A'; size]);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &data, |b, data| {
+            // Chunk 0 of 2 is never the final chunk, so this measures the write path only.
+            b.to_async(&rt).iter(|| async {
+                service
+                    .call(chunk_request(&next_file_id(), 0, 2, data.clone()))
+                    .await
+                    .unwrap()
+            });
+        });
+    }
+    group.finish();
+}
+
+fn bench_assembly(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let temp_dir = TempDir::new("bench_assembly").unwrap();
+    let service = new_service(&temp_dir);
+    let data = Bytes::from(vec![b'A'; 16 * 1024]);
+
+    let mut group = c.benchmark_group("assembly");
+    for chunks in [1, 16, 128] {
+        group.throughput(Throughput::Bytes((chunks * data.len()) as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(chunks),
+            &chunks,
+            |b, &chunks| {
+                b.to_async(&rt).iter(|| upload(&service, chunks, &data));
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_memory(c: &mut Criterion<AllocatedBytes>) {
+    let rt = Runtime::new().unwrap();
+    let temp_dir = TempDir::new("bench_memory").unwrap();
+    let service = new_service(&temp_dir);
+
+    let mut group = c.benchmark_group("memory");
+    for size in [64 * 1024, 1024 * 1024] {
+        let data = Bytes::from(vec![b'A'; size]);
+        group.bench_with_input(
+            BenchmarkId::new("upload_4_chunks", size),
+            &data,
+            |b, data| {
+                b.to_async(&rt).iter(|| upload(&service, 4, data));
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_chunk_ingest, bench_assembly);
+criterion_group! {
+    name = memory;
+    config = Criterion::default().with_measurement(AllocatedBytes);
+    targets = bench_memory
+}
+criterion_main!(benches, memory);
@@ -0,0 +1,30 @@
+-- Uploads in flight, as published by the instance that last took a chunk.
+CREATE TABLE upload_sessions (
+    file_id TEXT PRIMARY KEY,
+    session JSONB NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE upload_chunks (
+    file_id TEXT NOT NULL,
+    chunk_index BIGINT NOT NULL,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (file_id, chunk_index)
+);
+
+-- The instance assembling an upload, if any.
+CREATE TABLE assembly_locks (
+    file_id TEXT PRIMARY KEY,
+    node TEXT NOT NULL,
+    locked_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Where each completed upload was assembled, relative to the upload directory.
+CREATE TABLE completed_files (
+    file_id TEXT PRIMARY KEY,
+    path TEXT NOT NULL,
+    tenant TEXT NOT NULL,
+    completed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX completed_files_tenant ON completed_files (tenant, completed_at);
@@ -0,0 +1,445 @@
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{Request, StatusCode, Uri, header};
+use hyper_util::rt::TokioIo;
+use serde::Serialize;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    TlsConnector,
+    rustls::{self, pki_types::ServerName},
+};
+
+use crate::{constants, stats::Usage};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Thresholds that raise an alert, as percentages; `None` turns a rule off.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlertRules {
+    /// Share of requests answered with a 5xx over `error_window`.
+    pub error_rate_percent: Option<f64>,
+    pub error_window: Duration,
+    /// Share of the upload disk left free, below which an alert fires.
+    pub disk_free_percent: Option<f64>,
+    /// Share of the tenant quota a tenant may use before an alert fires.
+    pub quota_percent: Option<f64>,
+}
+
+impl Default for AlertRules {
+    fn default() -> Self {
+        Self {
+            error_rate_percent: None,
+            error_window: constants::DEFAULT_ALERT_ERROR_WINDOW,
+            disk_free_percent: None,
+            quota_percent: None,
+        }
+    }
+}
+
+/// Alerting for deployments without Prometheus and Alertmanager: rules are
+/// checked periodically, and each alert that starts or stops firing is logged
+/// and posted to the webhooks.
+#[derive(Debug, Clone)]
+pub struct AlertConfig {
+    /// Generic or Slack incoming webhooks; alerts are only logged without any.
+    pub webhooks: Vec<Uri>,
+    pub rules: AlertRules,
+    /// Used for `https` webhooks.
+    pub tls: Arc<rustls::ClientConfig>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Firing,
+    Resolved,
+}
+
+/// Body posted to webhooks. `text` makes it a valid Slack message too.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alert {
+    /// `error_rate`, `disk_nearly_full` or `quota_near_limit`.
+    pub rule: &'static str,
+    /// The tenant, for quota alerts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub state: AlertState,
+    /// The percentage measured, and the one the rule compares it to.
+    pub value: f64,
+    pub threshold: f64,
+    pub text: String,
+    pub at: DateTime<Utc>,
+}
+
+/// A rule evaluated against the current readings.
+struct Check {
+    rule: &'static str,
+    tenant: Option<String>,
+    value: f64,
+    threshold: f64,
+    breached: bool,
+    description: String,
+}
+
+impl Check {
+    fn key(&self) -> String {
+        match &self.tenant {
+            Some(tenant) => format!("{}:{}", self.rule, tenant),
+            None => self.rule.to_string(),
+        }
+    }
+}
+
+/// Counts requests and failures over the error window, and remembers which
+/// alerts are firing so that each is only sent when it changes.
+#[derive(Debug)]
+pub struct AlertMonitor {
+    config: AlertConfig,
+    started: Instant,
+    /// Requests and failures per `ALERT_SLICE`, by slice number.
+    requests: Mutex<VecDeque<(u64, u64, u64)>>,
+    firing: Mutex<HashSet<String>>,
+}
+
+impl AlertMonitor {
+    pub fn new(config: AlertConfig) -> Self {
+        Self {
+            config,
+            started: Instant::now(),
+            requests: Mutex::new(VecDeque::new()),
+            firing: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Counts an answered request; server errors count as failures.
+    pub fn record(&self, status: StatusCode) {
+        self.record_at(status, Instant::now());
+    }
+
+    fn record_at(&self, status: StatusCode, now: Instant) {
+        let slice = self.slice(now);
+        let failed = u64::from(status.is_server_error());
+        let mut requests = self.requests.lock().expect("alert lock poisoned");
+        match requests.back_mut() {
+            Some((last, count, failures)) if *last == slice => {
+                *count += 1;
+                *failures += failed;
+            }
+            _ => requests.push_back((slice, 1, failed)),
+        }
+        let oldest = self.oldest_slice(slice);
+        while requests
+            .front()
+            .is_some_and(|(first, _, _)| *first < oldest)
+        {
+            requests.pop_front();
+        }
+    }
+
+    fn slice(&self, now: Instant) -> u64 {
+        now.duration_since(self.started).as_secs() / constants::ALERT_SLICE.as_secs()
+    }
+
+    fn oldest_slice(&self, slice: u64) -> u64 {
+        let slices = self.config.rules.error_window.as_secs() / constants::ALERT_SLICE.as_secs();
+        slice.saturating_sub(slices.max(1) - 1)
+    }
+
+    /// Alerts that started or stopped firing since the last evaluation, given
+    /// the disk's total and free bytes and each tenant's usage.
+    pub fn evaluate(
+        &self,
+        disk: Option<(u64, u64)>,
+        tenants: &BTreeMap<String, Usage>,
+        quota: Option<u64>,
+    ) -> Vec<Alert> {
+        self.evaluate_at(disk, tenants, quota, Instant::now())
+    }
+
+    fn evaluate_at(
+        &self,
+        disk: Option<(u64, u64)>,
+        tenants: &BTreeMap<String, Usage>,
+        quota: Option<u64>,
+        now: Instant,
+    ) -> Vec<Alert> {
+        let rules = &self.config.rules;
+        let mut checks = Vec::new();
+        if let Some(threshold) = rules.error_rate_percent {
+            let oldest = self.oldest_slice(self.slice(now));
+            let (requests, failures) = self
+                .requests
+                .lock()
+                .expect("alert lock poisoned")
+                .iter()
+                .filter(|(slice, _, _)| *slice >= oldest)
+                .fold((0, 0), |(requests, failures), (_, count, failed)| {
+                    (requests + count, failures + failed)
+                });
+            let value = percent(failures, requests);
+            checks.push(Check {
+                rule: "error_rate",
+                tenant: None,
+                value,
+                threshold,
+                breached: requests >= constants::ALERT_MIN_REQUESTS && value > threshold,
+                description: format!(
+                    "{:.1}% of {} requests failed over the last {}s (threshold {:.1}%)",
+                    value,
+                    requests,
+                    rules.error_window.as_secs(),
+                    threshold
+                ),
+            });
+        }
+        if let (Some(threshold), Some((total, free))) = (rules.disk_free_percent, disk) {
+            let value = percent(free, total);
+            checks.push(Check {
+                rule: "disk_nearly_full",
+                tenant: None,
+                value,
+                threshold,
+                breached: value < threshold,
+                description: format!(
+                    "{:.1}% of the upload disk is free, {} of {} bytes (threshold {:.1}%)",
+                    value, free, total, threshold
+                ),
+            });
+        }
+        if let (Some(threshold), Some(quota)) = (rules.quota_percent, quota) {
+            for (tenant, usage) in tenants {
+                let value = percent(usage.bytes_stored, quota);
+                checks.push(Check {
+                    rule: "quota_near_limit",
+                    tenant: Some(tenant.clone()),
+                    value,
+                    threshold,
+                    breached: value >= threshold,
+                    description: format!(
+                        "Tenant {} has used {:.1}% of its {} byte quota (threshold {:.1}%)",
+                        tenant, value, quota, threshold
+                    ),
+                });
+            }
+        }
+
+        let at = Utc::now();
+        let mut firing = self.firing.lock().expect("alert lock poisoned");
+        checks
+            .into_iter()
+            .filter_map(|check| {
+                let state = match (check.breached, firing.contains(&check.key())) {
+                    (true, false) => {
+                        firing.insert(check.key());
+                        AlertState::Firing
+                    }
+                    (false, true) => {
+                        firing.remove(&check.key());
+                        AlertState::Resolved
+                    }
+                    _ => return None,
+                };
+                let text = match state {
+                    AlertState::Firing => format!("[firing] {}", check.description),
+                    AlertState::Resolved => format!("[resolved] {}", check.description),
+                };
+                Some(Alert {
+                    rule: check.rule,
+                    tenant: check.tenant,
+                    state,
+                    value: check.value,
+                    threshold: check.threshold,
+                    text,
+                    at,
+                })
+            })
+            .collect()
+    }
+
+    /// Logs each alert and posts it to every webhook. Failures are only
+    /// logged: an alert isn't resent.
+    pub async fn notify(&self, alerts: &[Alert]) {
+        for alert in alerts {
+            tracing::warn!(rule = alert.rule, tenant = ?alert.tenant, state = ?alert.state, "{}", alert.text);
+            let body = match serde_json::to_vec(alert) {
+                Ok(body) => Bytes::from(body),
+                Err(err) => {
+                    tracing::error!(%err, "Could not encode alert");
+                    continue;
+                }
+            };
+            for webhook in &self.config.webhooks {
+                match tokio::time::timeout(
+                    constants::ALERT_WEBHOOK_TIMEOUT,
+                    self.post(webhook, body.clone()),
+                )
+                .await
+                {
+                    Ok(Ok(status)) if status.is_success() => {}
+                    Ok(Ok(status)) => {
+                        tracing::warn!(%webhook, %status, "Alert webhook refused an alert");
+                    }
+                    Ok(Err(err)) => {
+                        tracing::warn!(%webhook, %err, "Failed to post alert");
+                    }
+                    Err(_) => tracing::warn!(%webhook, "Posting alert timed out"),
+                }
+            }
+        }
+    }
+
+    async fn post(&self, url: &Uri, body: Bytes) -> Result<StatusCode, BoxError> {
+        let https = match url.scheme_str() {
+            Some("https") => true,
+            Some("http") => false,
+            _ => return Err("webhook URL must be http or https".into()),
+        };
+        let host = url
+            .host()
+            .ok_or("webhook URL has no host")?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let port = url.port_u16().unwrap_or(if https { 443 } else { 80 });
+        let request = Request::post(url.path_and_query().map_or("/", |path| path.as_str()))
+            .header(
+                header::HOST,
+                url.authority().map_or(host, |authority| authority.as_str()),
+            )
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(body))?;
+
+        let stream = TcpStream::connect((host, port)).await?;
+        if https {
+            let name = ServerName::try_from(host.to_string())?;
+            let stream = TlsConnector::from(self.config.tls.clone())
+                .connect(name, stream)
+                .await?;
+            send(stream, request).await
+        } else {
+            send(stream, request).await
+        }
+    }
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+async fn send<S>(stream: S, request: Request<Full<Bytes>>) -> Result<StatusCode, BoxError>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (mut sender, connection) =
+        hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        if let Err(err) = connection.await {
+            tracing::debug!(%err, "Webhook connection closed");
+        }
+    });
+    let response = sender.send_request(request).await?;
+    let status = response.status();
+    // Drained so the connection closes cleanly; the body itself is unused.
+    Limited::new(response.into_body(), constants::MAX_ALERT_RESPONSE_BYTES)
+        .collect()
+        .await?;
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(rules: AlertRules) -> AlertMonitor {
+        AlertMonitor::new(AlertConfig {
+            webhooks: Vec::new(),
+            rules,
+            tls: Arc::new(crate::tls::load_client_config(None).unwrap()),
+        })
+    }
+
+    #[test]
+    fn test_error_rate_fires_once_and_resolves_after_the_window() {
+        let monitor = monitor(AlertRules {
+            error_rate_percent: Some(10.0),
+            ..AlertRules::default()
+        });
+        let start = monitor.started;
+        let tenants = BTreeMap::new();
+        for i in 0..constants::ALERT_MIN_REQUESTS {
+            let status = if i % 4 == 0 {
+                StatusCode::INTERNAL_SERVER_ERROR
+            } else {
+                StatusCode::OK
+            };
+            monitor.record_at(status, start);
+        }
+
+        let alerts = monitor.evaluate_at(None, &tenants, None, start);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule, "error_rate");
+        assert_eq!(alerts[0].state, AlertState::Firing);
+        assert_eq!(alerts[0].value, 25.0);
+        assert!(
+            alerts[0]
+                .text
+                .starts_with("[firing] 25.0% of 20 requests failed")
+        );
+        // Still firing: nothing new to send.
+        assert!(monitor.evaluate_at(None, &tenants, None, start).is_empty());
+
+        let later = start + constants::DEFAULT_ALERT_ERROR_WINDOW + constants::ALERT_SLICE;
+        let alerts = monitor.evaluate_at(None, &tenants, None, later);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].state, AlertState::Resolved);
+    }
+
+    #[test]
+    fn test_disk_and_quota_rules() {
+        let monitor = monitor(AlertRules {
+            disk_free_percent: Some(10.0),
+            quota_percent: Some(90.0),
+            ..AlertRules::default()
+        });
+        let usage = |bytes_stored| Usage {
+            bytes_stored,
+            ..Usage::default()
+        };
+        let tenants = BTreeMap::from([
+            ("acme".to_string(), usage(95)),
+            ("globex".to_string(), usage(10)),
+        ]);
+
+        let alerts = monitor.evaluate(Some((1000, 50)), &tenants, Some(100));
+        assert_eq!(alerts.len(), 2);
+        assert_eq!(alerts[0].rule, "disk_nearly_full");
+        assert_eq!(alerts[0].value, 5.0);
+        assert_eq!(alerts[1].rule, "quota_near_limit");
+        assert_eq!(alerts[1].tenant.as_deref(), Some("acme"));
+
+        let json = serde_json::to_value(&alerts[1]).unwrap();
+        assert_eq!(json["state"], "firing");
+        assert_eq!(json["tenant"], "acme");
+
+        // Without a disk reading the disk alert keeps its state.
+        let alerts = monitor.evaluate(None, &BTreeMap::new(), Some(100));
+        assert!(alerts.is_empty());
+        let alerts = monitor.evaluate(Some((1000, 500)), &tenants, Some(100));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule, "disk_nearly_full");
+        assert_eq!(alerts[0].state, AlertState::Resolved);
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use bytes::{Bytes, BytesMut};
+
+use crate::{body::BodySender, constants};
+
+/// Format of `GET /uploads/{id}/archive.tar` and `archive.tar.gz`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "archive.tar" => Some(Self::Tar),
+            "archive.tar.gz" | "archive.tgz" => Some(Self::TarGz),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Tar => "tar",
+            Self::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Tar => "application/x-tar",
+            Self::TarGz => "application/gzip",
+        }
+    }
+}
+
+/// Files under `dir` that go in its archive, relative to it and sorted. Hidden
+/// files and sidecars are bookkeeping and left out, and links are skipped.
+pub fn list(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        for entry in std::fs::read_dir(dir.join(&relative))? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name_str = name.to_string_lossy();
+            if name_str.starts_with('.') || name_str.ends_with(".meta.json") {
+                continue;
+            }
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                pending.push(relative.join(&name));
+            } else if file_type.is_file() {
+                files.push(relative.join(&name));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// One member of an archive, named by its path inside the archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveEntry {
+    /// Copied from a file on disk.
+    File { name: PathBuf, path: PathBuf },
+    /// Contents built for the archive, such as an index of what it holds.
+    Data { name: PathBuf, contents: Vec<u8> },
+}
+
+impl ArchiveEntry {
+    /// The `files` listed under `dir`, keeping their relative paths.
+    pub fn files_under(dir: &Path, files: Vec<PathBuf>) -> Vec<Self> {
+        files
+            .into_iter()
+            .map(|name| Self::File {
+                path: dir.join(&name),
+                name,
+            })
+            .collect()
+    }
+}
+
+/// Builds the archive of `entries` on the blocking pool, sending it to
+/// `sender` as it goes. A failure is sent as the last frame, so the client
+/// sees the response cut short rather than a truncated archive that looks
+/// whole. `label` names the archive in logs.
+pub fn spawn(label: String, entries: Vec<ArchiveEntry>, format: ArchiveFormat, sender: BodySender) {
+    tokio::task::spawn_blocking(move || {
+        let writer = FrameWriter {
+            sender: &sender,
+            buf: BytesMut::new(),
+        };
+        let written = match format {
+            ArchiveFormat::Tar => write_tar(writer, &entries),
+            ArchiveFormat::TarGz => write_tar(
+                flate2::write::GzEncoder::new(writer, flate2::Compression::default()),
+                &entries,
+            )
+            .and_then(|gz| gz.finish()),
+        };
+        match written.and_then(|mut writer| writer.flush()) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => {
+                tracing::debug!(archive = %label, "Archive download abandoned");
+            }
+            Err(err) => {
+                tracing::warn!(archive = %label, %err, "Failed to build archive");
+                let _ = sender.send(Err(err));
+            }
+        }
+    });
+}
+
+fn write_tar<W: Write>(writer: W, entries: &[ArchiveEntry]) -> std::io::Result<W> {
+    let mut tar = tar::Builder::new(writer);
+    for entry in entries {
+        match entry {
+            ArchiveEntry::File { name, path } => {
+                tar.append_file(name, &mut File::open(path)?)?;
+            }
+            ArchiveEntry::Data { name, contents } => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(contents.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map_or(0, |elapsed| elapsed.as_secs()),
+                );
+                tar.append_data(&mut header, name, contents.as_slice())?;
+            }
+        }
+    }
+    tar.into_inner()
+}
+
+/// Collects the archive into frames of about a buffer's size, so the client
+/// isn't sent a frame per tar header.
+struct FrameWriter<'a> {
+    sender: &'a BodySender,
+    buf: BytesMut,
+}
+
+impl Write for FrameWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= constants::DEFAULT_POOL_BUFFER_CAPACITY {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.buf.is_empty() {
+            let frame: Bytes = self.buf.split().freeze();
+            self.sender.send(Ok(frame))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_archive_leaves_out_bookkeeping_files() {
+        let dir = TempDir::new("archive_test").unwrap();
+        std::fs::create_dir_all(dir.path().join("data/2024")).unwrap();
+        for file in [
+            "b.txt",
+            "b.txt.meta.json",
+            ".bundle.json",
+            "data/2024/a.csv",
+        ] {
+            std::fs::write(dir.path().join(file), file).unwrap();
+        }
+        assert_eq!(
+            list(dir.path()).unwrap(),
+            vec![PathBuf::from("b.txt"), PathBuf::from("data/2024/a.csv")]
+        );
+        assert_eq!(
+            ArchiveFormat::from_name("archive.tar.gz"),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(ArchiveFormat::from_name("archive.zip"), None);
+    }
+}
@@ -0,0 +1,97 @@
+use std::{net::IpAddr, path::PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+/// One line of the audit trail.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub actor: String,
+    pub action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
+    /// HTTP status the request was answered with.
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<IpAddr>,
+}
+
+/// Append-only JSON Lines file of audit entries.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Each entry is appended with a single write so concurrent requests never interleave lines.
+    pub async fn record(&self, entry: &AuditEntry) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&line).await?;
+        file.flush().await
+    }
+
+    /// Entries in the order they were recorded, optionally only those for `file_id`.
+    pub async fn read(&self, file_id: Option<&str>) -> std::io::Result<Vec<AuditEntry>> {
+        let contents = match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut entries = Vec::new();
+        for line in contents.lines().filter(|line| !line.is_empty()) {
+            let entry: AuditEntry = serde_json::from_str(line)?;
+            if file_id.is_none() || entry.file_id.as_deref() == file_id {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn entry(action: &str, file_id: Option<&str>) -> AuditEntry {
+        AuditEntry {
+            timestamp: Utc::now(),
+            actor: "acme".to_string(),
+            action: action.to_string(),
+            file_id: file_id.map(str::to_string),
+            status: 201,
+            client_ip: Some("10.0.0.7".parse().unwrap()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_entries_are_appended_and_filtered() {
+        let dir = TempDir::new("audit").unwrap();
+        let log = AuditLog::new(dir.path().join("logs").join("audit.jsonl"));
+        assert!(log.read(None).await.unwrap().is_empty());
+
+        let upload = entry("upload_chunk", Some("a"));
+        let stats = entry("admin_stats", None);
+        log.record(&upload).await.unwrap();
+        log.record(&stats).await.unwrap();
+
+        assert_eq!(log.read(None).await.unwrap(), vec![upload.clone(), stats]);
+        assert_eq!(log.read(Some("a")).await.unwrap(), vec![upload]);
+    }
+}
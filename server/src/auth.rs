@@ -0,0 +1,276 @@
+use std::str::FromStr;
+
+use base64::Engine;
+use chrono::Utc;
+
+use crate::share::Share;
+
+/// What a credential may be used for. `Admin` implies all the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Upload,
+    Download,
+    Delete,
+    Admin,
+}
+
+/// Scopes of a credential that doesn't list any: everything but the admin API.
+pub const DEFAULT_SCOPES: &[Scope] = &[Scope::Upload, Scope::Download, Scope::Delete];
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Upload => "upload",
+            Self::Download => "download",
+            Self::Delete => "delete",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "upload" => Ok(Self::Upload),
+            "download" => Ok(Self::Download),
+            "delete" => Ok(Self::Delete),
+            "admin" => Ok(Self::Admin),
+            other => Err(format!(
+                "Unknown scope: {} (expected upload, download, delete or admin)",
+                other
+            )),
+        }
+    }
+}
+
+/// Parses scopes joined with `+`, e.g. `upload+download`.
+pub fn parse_scopes(s: &str) -> Result<Vec<Scope>, String> {
+    s.split('+').map(str::parse).collect()
+}
+
+/// Maps a client certificate identity (CN or a SAN entry) to the tenant it
+/// may act as, written `name=tenant` or `name=tenant:scope+scope`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdentityRule {
+    pub name: String,
+    pub tenant: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl FromStr for IdentityRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, grant) = s
+            .trim()
+            .rsplit_once('=')
+            .ok_or_else(|| format!("Expected name=tenant[:scopes]: {}", s))?;
+        let (tenant, scopes) = match grant.split_once(':') {
+            Some((tenant, scopes)) => (
+                tenant,
+                parse_scopes(scopes).map_err(|err| format!("{} in identity rule {}", err, s))?,
+            ),
+            None => (grant, DEFAULT_SCOPES.to_vec()),
+        };
+        if name.is_empty() || tenant.is_empty() {
+            return Err(format!("Expected name=tenant[:scopes]: {}", s));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            tenant: tenant.to_string(),
+            scopes,
+        })
+    }
+}
+
+/// An authenticated client and what it is allowed to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    pub name: String,
+    pub tenant: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl Principal {
+    /// Whether the principal holds any of `scopes`.
+    pub fn allows(&self, scopes: &[Scope]) -> bool {
+        self.scopes.contains(&Scope::Admin)
+            || scopes.iter().any(|scope| self.scopes.contains(scope))
+    }
+}
+
+/// Credentials from an `Authorization` header, or a share link.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Credentials {
+    Bearer(String),
+    Basic {
+        user: String,
+        password: String,
+    },
+    /// Token of a share link, from the `share` query parameter.
+    ShareLink(String),
+}
+
+impl Credentials {
+    pub fn parse(value: &str) -> Option<Self> {
+        let (scheme, credentials) = value.trim().split_once(' ')?;
+        let credentials = credentials.trim();
+        if scheme.eq_ignore_ascii_case("bearer") && !credentials.is_empty() {
+            return Some(Self::Bearer(credentials.to_string()));
+        }
+        if scheme.eq_ignore_ascii_case("basic") {
+            let decoded = base64::engine::general_purpose::STANDARD
+                .decode(credentials)
+                .ok()?;
+            let (user, password) = String::from_utf8(decoded)
+                .ok()?
+                .split_once(':')
+                .map(|(user, password)| (user.to_string(), password.to_string()))?;
+            return Some(Self::Basic { user, password });
+        }
+        None
+    }
+}
+
+/// Whether `principal` may read, or with `write` also change, a file that
+/// `owner` uploaded and granted `shares` on. Files uploaded without
+/// authentication have no owner and are open to anyone, as are all files when
+/// the client isn't authenticated.
+pub fn may_access(
+    principal: Option<&Principal>,
+    owner: Option<&str>,
+    shares: &[Share],
+    write: bool,
+) -> bool {
+    let (Some(principal), Some(owner)) = (principal, owner) else {
+        return true;
+    };
+    let now = Utc::now();
+    is_owner(Some(principal), Some(owner))
+        || shares.iter().any(|share| {
+            share.principal.as_deref() == Some(principal.name.as_str()) && share.allows(write, now)
+        })
+}
+
+/// Whether `principal` owns a file, or may act as if it did.
+pub fn is_owner(principal: Option<&Principal>, owner: Option<&str>) -> bool {
+    let (Some(principal), Some(owner)) = (principal, owner) else {
+        return true;
+    };
+    principal.name == owner || principal.scopes.contains(&Scope::Admin)
+}
+
+/// The first rule matching any of the identities presented by the client.
+pub fn resolve(rules: &[IdentityRule], identities: &[String]) -> Option<Principal> {
+    rules
+        .iter()
+        .find(|rule| identities.contains(&rule.name))
+        .map(|rule| Principal {
+            name: rule.name.clone(),
+            tenant: rule.tenant.clone(),
+            scopes: rule.scopes.clone(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::share::ShareAccess;
+
+    #[test]
+    fn test_rules_resolve_identities() {
+        let rules: Vec<IdentityRule> = ["render-01.internal=acme", "spiffe://ops/cli=ops:admin"]
+            .iter()
+            .map(|rule| rule.parse().unwrap())
+            .collect();
+
+        let principal = resolve(&rules, &["spiffe://ops/cli".to_string()]).unwrap();
+        assert_eq!(principal.tenant, "ops");
+        assert!(principal.allows(&[Scope::Admin]));
+
+        let principal = resolve(&rules, &["render-01.internal".to_string()]).unwrap();
+        assert_eq!(principal.tenant, "acme");
+        assert!(!principal.allows(&[Scope::Admin]));
+        assert!(principal.allows(&[Scope::Upload]));
+
+        assert_eq!(resolve(&rules, &["unknown".to_string()]), None);
+    }
+
+    #[test]
+    fn test_only_owners_admins_and_shares_access_files() {
+        let principal = |name: &str, scopes: &[Scope]| Principal {
+            name: name.to_string(),
+            tenant: "acme".to_string(),
+            scopes: scopes.to_vec(),
+        };
+        let alice = principal("alice", DEFAULT_SCOPES);
+        let bob = principal("bob", DEFAULT_SCOPES);
+        let ops = principal("ops", &[Scope::Admin]);
+        let shared = [
+            Share::new(Some("bob".to_string()), ShareAccess::Read, None),
+            Share::new(Some("carol".to_string()), ShareAccess::ReadWrite, None),
+            Share::new(
+                Some("dave".to_string()),
+                ShareAccess::Read,
+                Some(Utc::now() - chrono::Duration::seconds(1)),
+            ),
+        ];
+        let carol = principal("carol", DEFAULT_SCOPES);
+        let dave = principal("dave", DEFAULT_SCOPES);
+
+        assert!(may_access(Some(&alice), Some("alice"), &[], true));
+        assert!(may_access(Some(&ops), Some("alice"), &[], true));
+        assert!(!may_access(Some(&bob), Some("alice"), &[], false));
+        assert!(may_access(Some(&bob), Some("alice"), &shared, false));
+        assert!(!may_access(Some(&bob), Some("alice"), &shared, true));
+        assert!(may_access(Some(&carol), Some("alice"), &shared, true));
+        assert!(!may_access(Some(&dave), Some("alice"), &shared, false));
+        assert!(may_access(Some(&bob), None, &[], true));
+        assert!(may_access(None, Some("alice"), &[], true));
+    }
+
+    #[test]
+    fn test_authorization_header_credentials() {
+        assert!(matches!(
+            Credentials::parse("Bearer abc.def"),
+            Some(Credentials::Bearer(token)) if token == "abc.def"
+        ));
+        assert!(matches!(
+            Credentials::parse("Basic YWxpY2U6czNjcjp0"),
+            Some(Credentials::Basic { user, password }) if user == "alice" && password == "s3cr:t"
+        ));
+        assert!(Credentials::parse("Basic bm9jb2xvbg==").is_none());
+        assert!(Credentials::parse("Digest abc").is_none());
+        assert!(Credentials::parse("Bearer").is_none());
+    }
+
+    #[test]
+    fn test_invalid_rules_are_rejected() {
+        for rule in [
+            "no-tenant",
+            "=acme",
+            "host=",
+            "host=acme:root",
+            "host=acme:upload+",
+        ] {
+            assert!(
+                rule.parse::<IdentityRule>().is_err(),
+                "{} should be rejected",
+                rule
+            );
+        }
+    }
+
+    #[test]
+    fn test_scopes_limit_what_a_rule_allows() {
+        let rule: IdentityRule = "reader=acme:download".parse().unwrap();
+        assert_eq!(rule.scopes, vec![Scope::Download]);
+        let rule: IdentityRule = "ingest=acme:upload+download".parse().unwrap();
+        let principal = resolve(&[rule], &["ingest".to_string()]).unwrap();
+        assert!(principal.allows(&[Scope::Upload]));
+        assert!(principal.allows(&[Scope::Delete, Scope::Download]));
+        assert!(!principal.allows(&[Scope::Delete]));
+    }
+}
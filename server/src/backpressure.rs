@@ -0,0 +1,358 @@
+use std::{
+    path::Path,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{constants, error::SliceBreadServerError};
+
+/// Limits past which chunk uploads are shed with `503` and `Retry-After`, or
+/// refused with `507` when the disk is nearly full; `None` disables the
+/// corresponding check.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct BackpressureConfig {
+    pub max_in_flight_uploads: Option<usize>,
+    pub max_pending_assemblies: Option<usize>,
+    pub min_free_disk_bytes: Option<u64>,
+    /// High-water mark for request bodies held in memory at once.
+    pub max_buffered_bytes: Option<u64>,
+    /// Start in read-only maintenance mode.
+    pub read_only: bool,
+}
+
+/// Whether writes are refused for maintenance, as reported and set by
+/// `/admin/maintenance`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, serde::Deserialize)]
+pub struct Maintenance {
+    pub read_only: bool,
+    /// Seconds refused clients are told to wait; defaults to
+    /// `DEFAULT_MAINTENANCE_RETRY_AFTER`.
+    #[serde(default)]
+    pub retry_after: Option<u64>,
+}
+
+const MIN_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Tracks in-flight work and how long it usually takes, so a shed request can
+/// be told roughly when capacity will be back.
+#[derive(Debug, Default)]
+pub struct LoadShedder {
+    config: BackpressureConfig,
+    in_flight: AtomicUsize,
+    assemblies: AtomicUsize,
+    buffered: AtomicU64,
+    read_only: AtomicBool,
+    /// In seconds; 0 for the default.
+    read_only_retry_after: AtomicU64,
+    avg_upload_ms: AtomicU64,
+    avg_assembly_ms: AtomicU64,
+}
+
+/// Held for the duration of one unit of work; feeds its duration back into the
+/// moving average when dropped.
+pub struct LoadGuard<'a> {
+    count: &'a AtomicUsize,
+    avg_ms: &'a AtomicU64,
+    started: Instant,
+}
+
+impl Drop for LoadGuard<'_> {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+        let sample = self.started.elapsed().as_millis() as u64;
+        let _ = self
+            .avg_ms
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |avg| {
+                Some(if avg == 0 {
+                    sample
+                } else {
+                    (avg * 7 + sample) / 8
+                })
+            });
+    }
+}
+
+/// Bytes of one request body held in memory, counted against
+/// `max_buffered_bytes` until dropped.
+pub struct BufferGuard<'a> {
+    shedder: &'a LoadShedder,
+    bytes: u64,
+}
+
+impl BufferGuard<'_> {
+    /// Raises what this body holds to `total` bytes, shedding it if that
+    /// would take the server past its high-water mark.
+    pub fn hold(&mut self, total: u64) -> Result<(), SliceBreadServerError> {
+        let more = total.saturating_sub(self.bytes);
+        if more == 0 {
+            return Ok(());
+        }
+        let limit = self.shedder.config.max_buffered_bytes.unwrap_or(u64::MAX);
+        self.shedder
+            .buffered
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| {
+                current.checked_add(more).filter(|&held| held <= limit)
+            })
+            .map_err(|current| SliceBreadServerError::Overloaded {
+                reason: format!(
+                    "Too much buffered in memory: {} of {} bytes",
+                    current, limit
+                ),
+                // Memory comes back as uploads finish.
+                retry_after: Duration::from_millis(
+                    self.shedder.avg_upload_ms.load(Ordering::Relaxed),
+                )
+                .max(MIN_RETRY_AFTER),
+            })?;
+        self.bytes = total;
+        Ok(())
+    }
+}
+
+impl Drop for BufferGuard<'_> {
+    fn drop(&mut self) {
+        self.shedder
+            .buffered
+            .fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
+
+impl LoadShedder {
+    pub fn new(config: BackpressureConfig) -> Self {
+        Self {
+            config,
+            read_only: AtomicBool::new(config.read_only),
+            ..Self::default()
+        }
+    }
+
+    pub fn maintenance(&self) -> Maintenance {
+        Maintenance {
+            read_only: self.read_only.load(Ordering::SeqCst),
+            retry_after: Some(self.read_only_retry_after().as_secs()),
+        }
+    }
+
+    /// Enters or leaves read-only mode. Writes already under way finish, so
+    /// the server drains rather than failing them.
+    pub fn set_maintenance(&self, maintenance: Maintenance) {
+        self.read_only_retry_after
+            .store(maintenance.retry_after.unwrap_or(0), Ordering::SeqCst);
+        self.read_only
+            .store(maintenance.read_only, Ordering::SeqCst);
+    }
+
+    /// Refuses a write while in read-only mode.
+    pub fn check_writable(&self) -> Result<(), SliceBreadServerError> {
+        if self.read_only.load(Ordering::SeqCst) {
+            return Err(SliceBreadServerError::ReadOnly {
+                retry_after: self.read_only_retry_after(),
+            });
+        }
+        Ok(())
+    }
+
+    fn read_only_retry_after(&self) -> Duration {
+        match self.read_only_retry_after.load(Ordering::SeqCst) {
+            0 => constants::DEFAULT_MAINTENANCE_RETRY_AFTER,
+            secs => Duration::from_secs(secs),
+        }
+    }
+
+    pub fn begin_upload(&self) -> Result<LoadGuard<'_>, SliceBreadServerError> {
+        Self::begin(
+            &self.in_flight,
+            &self.avg_upload_ms,
+            self.config.max_in_flight_uploads,
+            "Too many uploads in flight",
+        )
+    }
+
+    pub fn begin_assembly(&self) -> Result<LoadGuard<'_>, SliceBreadServerError> {
+        Self::begin(
+            &self.assemblies,
+            &self.avg_assembly_ms,
+            self.config.max_pending_assemblies,
+            "Assembly queue is full",
+        )
+    }
+
+    /// Admits a new request body, holding its declared length up front so
+    /// that bodies which can't fit are refused before any of them is read.
+    pub fn begin_body(&self, declared: u64) -> Result<BufferGuard<'_>, SliceBreadServerError> {
+        let mut guard = BufferGuard {
+            shedder: self,
+            bytes: 0,
+        };
+        guard.hold(declared)?;
+        Ok(guard)
+    }
+
+    /// Uploads and assemblies under way.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst) + self.assemblies.load(Ordering::SeqCst)
+    }
+
+    /// Bytes of request bodies currently held in memory.
+    pub fn buffered_bytes(&self) -> u64 {
+        self.buffered.load(Ordering::SeqCst)
+    }
+
+    pub fn check_disk(&self, dir: &Path) -> Result<(), SliceBreadServerError> {
+        let Some(min_free) = self.config.min_free_disk_bytes else {
+            return Ok(());
+        };
+        // The usable capacity is what the disk holds short of the reserve.
+        match disk_usage(dir) {
+            Some((total, free)) if free < min_free => {
+                Err(SliceBreadServerError::InsufficientStorage {
+                    reason: format!("Disk nearly full: {} bytes free", free),
+                    limit: total.saturating_sub(min_free),
+                    used: total.saturating_sub(free),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn begin<'a>(
+        count: &'a AtomicUsize,
+        avg_ms: &'a AtomicU64,
+        limit: Option<usize>,
+        reason: &str,
+    ) -> Result<LoadGuard<'a>, SliceBreadServerError> {
+        let current = count.fetch_add(1, Ordering::SeqCst);
+        if let Some(limit) = limit
+            && current >= limit
+        {
+            count.fetch_sub(1, Ordering::SeqCst);
+            // Everything ahead of us has to drain through `limit` slots first.
+            let avg = Duration::from_millis(avg_ms.load(Ordering::Relaxed));
+            let waves = (current + 1 - limit).div_ceil(limit.max(1)) as u32;
+            return Err(SliceBreadServerError::Overloaded {
+                reason: reason.to_string(),
+                retry_after: (avg * waves).max(MIN_RETRY_AFTER),
+            });
+        }
+
+        Ok(LoadGuard {
+            count,
+            avg_ms,
+            started: Instant::now(),
+        })
+    }
+}
+
+/// Size of the file system holding `dir` and the bytes free on it, in bytes.
+#[cfg(unix)]
+pub fn disk_usage(dir: &Path) -> Option<(u64, u64)> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid out pointer.
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let block = stat.f_frsize as u64;
+    Some((stat.f_blocks as u64 * block, stat.f_bavail as u64 * block))
+}
+
+#[cfg(not(unix))]
+pub fn disk_usage(_dir: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uploads_over_limit_are_shed() {
+        let shedder = LoadShedder::new(BackpressureConfig {
+            max_in_flight_uploads: Some(1),
+            ..BackpressureConfig::default()
+        });
+
+        let first = shedder.begin_upload().unwrap();
+        let err = shedder.begin_upload().err().unwrap();
+        assert!(matches!(
+            err,
+            SliceBreadServerError::Overloaded { retry_after, .. } if retry_after >= MIN_RETRY_AFTER
+        ));
+
+        drop(first);
+        assert!(shedder.begin_upload().is_ok());
+    }
+
+    #[test]
+    fn test_bodies_past_the_memory_mark_are_shed() {
+        let shedder = LoadShedder::new(BackpressureConfig {
+            max_buffered_bytes: Some(100),
+            ..BackpressureConfig::default()
+        });
+
+        let mut first = shedder.begin_body(60).unwrap();
+        assert!(matches!(
+            shedder.begin_body(50),
+            Err(SliceBreadServerError::Overloaded { .. })
+        ));
+        // A body without a declared length is counted as it arrives.
+        let mut second = shedder.begin_body(0).unwrap();
+        second.hold(30).unwrap();
+        assert!(second.hold(50).is_err());
+        assert_eq!(shedder.buffered_bytes(), 90);
+        first.hold(40).unwrap();
+        assert_eq!(shedder.buffered_bytes(), 90);
+
+        drop(first);
+        drop(second);
+        assert_eq!(shedder.buffered_bytes(), 0);
+        assert!(shedder.begin_body(100).is_ok());
+    }
+
+    #[test]
+    fn test_read_only_mode_refuses_writes() {
+        let shedder = LoadShedder::new(BackpressureConfig {
+            read_only: true,
+            ..BackpressureConfig::default()
+        });
+        assert!(matches!(
+            shedder.check_writable(),
+            Err(SliceBreadServerError::ReadOnly { retry_after })
+                if retry_after == constants::DEFAULT_MAINTENANCE_RETRY_AFTER
+        ));
+
+        shedder.set_maintenance(Maintenance {
+            read_only: true,
+            retry_after: Some(600),
+        });
+        assert!(matches!(
+            shedder.check_writable(),
+            Err(SliceBreadServerError::ReadOnly { retry_after })
+                if retry_after == Duration::from_secs(600)
+        ));
+        shedder.set_maintenance(Maintenance {
+            read_only: false,
+            retry_after: None,
+        });
+        assert!(shedder.check_writable().is_ok());
+        assert!(!shedder.maintenance().read_only);
+    }
+
+    #[test]
+    fn test_disk_check() {
+        let dir = std::env::temp_dir();
+        let shedder = LoadShedder::new(BackpressureConfig {
+            min_free_disk_bytes: Some(u64::MAX),
+            ..BackpressureConfig::default()
+        });
+        assert!(matches!(
+            shedder.check_disk(&dir),
+            Err(SliceBreadServerError::InsufficientStorage { .. })
+        ));
+        assert!(LoadShedder::default().check_disk(&dir).is_ok());
+    }
+}
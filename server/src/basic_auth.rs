@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::{
+    constants,
+    crypto::hmac,
+    error::SliceBreadServerError,
+    ldap::{self, LdapConfig},
+};
+
+/// Where HTTP Basic credentials are checked.
+#[derive(Debug, Clone)]
+pub enum BasicAuthConfig {
+    Htpasswd(Arc<Htpasswd>),
+    Ldap(LdapConfig),
+}
+
+/// Users and their bcrypt hashes, as written by `htpasswd -B`.
+#[derive(Clone, Default)]
+pub struct Htpasswd {
+    users: HashMap<String, String>,
+}
+
+impl std::fmt::Debug for Htpasswd {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Htpasswd")
+            .field("users", &self.users.keys())
+            .finish()
+    }
+}
+
+impl FromStr for Htpasswd {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut users = HashMap::new();
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (user, hash) = line
+                .split_once(':')
+                .ok_or_else(|| format!("Expected user:hash: {}", line))?;
+            if !["$2a$", "$2b$", "$2x$", "$2y$"]
+                .iter()
+                .any(|prefix| hash.starts_with(prefix))
+            {
+                return Err(format!(
+                    "Unsupported hash for {}: only bcrypt is supported (htpasswd -B)",
+                    user
+                ));
+            }
+            users.insert(user.to_string(), hash.to_string());
+        }
+        Ok(Self { users })
+    }
+}
+
+impl Htpasswd {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(std::fs::read_to_string(path)?.parse()?)
+    }
+
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        self.users
+            .get(user)
+            .is_some_and(|hash| bcrypt::verify(password, hash).unwrap_or(false))
+    }
+}
+
+/// Checks Basic credentials, remembering the ones that were valid for a while
+/// so that a client sending many chunks doesn't pay for a bcrypt hash or an
+/// LDAP bind on each.
+#[derive(Debug)]
+pub struct BasicAuthenticator {
+    config: BasicAuthConfig,
+    // Keyed by a MAC under a per-process key, so the cache can't be used to
+    // guess passwords offline.
+    cache_key: hmac::Key,
+    verified: Mutex<HashMap<Vec<u8>, Instant>>,
+}
+
+impl BasicAuthenticator {
+    pub fn new(config: BasicAuthConfig) -> Self {
+        Self {
+            config,
+            cache_key: hmac::Key::new(hmac::HMAC_SHA256, &rand::random::<[u8; 32]>()),
+            verified: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn verify(&self, user: &str, password: &str) -> Result<bool, SliceBreadServerError> {
+        let mut credentials = Vec::with_capacity(user.len() + password.len() + 1);
+        credentials.extend_from_slice(user.as_bytes());
+        credentials.push(0);
+        credentials.extend_from_slice(password.as_bytes());
+        let key = hmac::sign(&self.cache_key, &credentials).as_ref().to_vec();
+        let now = Instant::now();
+        if self
+            .verified
+            .lock()
+            .expect("cache lock poisoned")
+            .get(&key)
+            .is_some_and(|expires_at| *expires_at > now)
+        {
+            return Ok(true);
+        }
+
+        let valid = match &self.config {
+            BasicAuthConfig::Htpasswd(htpasswd) => {
+                let htpasswd = htpasswd.clone();
+                let (user, password) = (user.to_string(), password.to_string());
+                tokio::task::spawn_blocking(move || htpasswd.verify(&user, &password))
+                    .await
+                    .map_err(|err| SliceBreadServerError::InternalServerError(err.to_string()))?
+            }
+            BasicAuthConfig::Ldap(config) => {
+                match tokio::time::timeout(
+                    constants::LDAP_TIMEOUT,
+                    ldap::bind(config, user, password),
+                )
+                .await
+                {
+                    Ok(Ok(valid)) => valid,
+                    Ok(Err(err)) => return Err(ldap_unavailable(config, err)),
+                    Err(_) => {
+                        return Err(ldap_unavailable(
+                            config,
+                            std::io::ErrorKind::TimedOut.into(),
+                        ));
+                    }
+                }
+            }
+        };
+        if valid {
+            let mut verified = self.verified.lock().expect("cache lock poisoned");
+            if verified.len() >= constants::MAX_CREDENTIAL_CACHE_ENTRIES {
+                verified.retain(|_, expires_at| *expires_at > now);
+                if verified.len() >= constants::MAX_CREDENTIAL_CACHE_ENTRIES {
+                    verified.clear();
+                }
+            }
+            verified.insert(key, now + constants::BASIC_AUTH_CACHE_TTL);
+        }
+        Ok(valid)
+    }
+}
+
+fn ldap_unavailable(config: &LdapConfig, err: std::io::Error) -> SliceBreadServerError {
+    tracing::warn!(url = %config.url, %err, "LDAP bind failed");
+    SliceBreadServerError::ServiceUnavailable("LDAP server unavailable".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_htpasswd_users_are_verified() {
+        let hash = bcrypt::hash("s3cret", 4).unwrap();
+        let htpasswd: Htpasswd = format!("# team\nalice:{}\n\n", hash).parse().unwrap();
+        assert!(htpasswd.verify("alice", "s3cret"));
+        assert!(!htpasswd.verify("alice", "wrong"));
+        assert!(!htpasswd.verify("bob", "s3cret"));
+
+        let authenticator = BasicAuthenticator::new(BasicAuthConfig::Htpasswd(Arc::new(htpasswd)));
+        assert!(authenticator.verify("alice", "s3cret").await.unwrap());
+        assert!(authenticator.verify("alice", "s3cret").await.unwrap());
+        assert!(!authenticator.verify("alice", "wrong").await.unwrap());
+
+        assert!("alice:$apr1$abc$def".parse::<Htpasswd>().is_err());
+        assert!("alice".parse::<Htpasswd>().is_err());
+    }
+}
@@ -0,0 +1,110 @@
+/// Which chunk indexes of an upload are stored, with a running count so that
+/// completeness is known without looking at every index.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChunkBitmap {
+    words: Vec<u64>,
+    len: usize,
+    count: usize,
+}
+
+impl ChunkBitmap {
+    pub fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len.div_ceil(64)],
+            len,
+            count: 0,
+        }
+    }
+
+    /// Reads the on-disk form, one byte per chunk index with non-zero meaning
+    /// stored. Indexes past the end of `bytes` are missing.
+    pub fn from_bytes(bytes: &[u8], len: usize) -> Self {
+        let mut bitmap = Self::new(len);
+        for (index, _) in bytes.iter().take(len).enumerate().filter(|(_, b)| **b != 0) {
+            bitmap.insert(index);
+        }
+        bitmap
+    }
+
+    /// Marks `index` as stored, returning whether it was missing before.
+    pub fn insert(&mut self, index: usize) -> bool {
+        if index >= self.len {
+            return false;
+        }
+        let (word, bit) = (index / 64, 1u64 << (index % 64));
+        let added = self.words[word] & bit == 0;
+        self.words[word] |= bit;
+        self.count += added as usize;
+        added
+    }
+
+    /// Adds every index stored in `other`, which must have the same length.
+    pub fn union(&mut self, other: &ChunkBitmap) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+        self.count = self
+            .words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum();
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        index < self.len && self.words[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.count == self.len
+    }
+
+    /// Stored indexes, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len).filter(|&index| self.contains(index))
+    }
+
+    pub fn first_missing(&self) -> Option<usize> {
+        if self.is_full() {
+            return None;
+        }
+        self.words
+            .iter()
+            .enumerate()
+            .find(|(_, word)| **word != u64::MAX)
+            .map(|(i, word)| i * 64 + word.trailing_ones() as usize)
+            .filter(|index| *index < self.len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitmap_tracks_count_and_gaps() {
+        let mut bitmap = ChunkBitmap::new(130);
+        assert_eq!(bitmap.first_missing(), Some(0));
+        for index in 0..130 {
+            if index != 97 {
+                assert!(bitmap.insert(index));
+            }
+        }
+        assert!(!bitmap.insert(5));
+        assert!(!bitmap.insert(130));
+        assert_eq!(bitmap.count(), 129);
+        assert!(!bitmap.contains(97));
+        assert_eq!(bitmap.first_missing(), Some(97));
+
+        bitmap.insert(97);
+        assert!(bitmap.is_full());
+        assert_eq!(bitmap.first_missing(), None);
+
+        let loaded = ChunkBitmap::from_bytes(&[1, 0, 1], 4);
+        assert_eq!(loaded.count(), 2);
+        assert_eq!(loaded.first_missing(), Some(1));
+    }
+}
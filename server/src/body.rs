@@ -0,0 +1,140 @@
+use std::{
+    ops::Deref,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use hyper::body::{Body, Frame, SizeHint};
+use tokio::sync::mpsc;
+
+type Frames = mpsc::Receiver<std::io::Result<Bytes>>;
+
+/// Response body, normally held in memory and sent as a single frame. Unlike
+/// `String`, it can carry binary data such as downloaded chunks. Bodies too
+/// large to hold in memory are streamed from a `BodySender` instead.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseBody {
+    data: Bytes,
+    stream: Option<Arc<Mutex<Frames>>>,
+}
+
+/// Producer side of a streamed body. Sends block, so it is meant for the
+/// blocking pool; they fail once the client has gone away.
+#[derive(Debug)]
+pub struct BodySender(mpsc::Sender<std::io::Result<Bytes>>);
+
+impl ResponseBody {
+    /// A body whose frames are sent as they are produced, holding at most
+    /// `capacity` of them in memory.
+    pub fn channel(capacity: usize) -> (BodySender, Self) {
+        let (tx, rx) = mpsc::channel(capacity);
+        let body = Self {
+            data: Bytes::new(),
+            stream: Some(Arc::new(Mutex::new(rx))),
+        };
+        (BodySender(tx), body)
+    }
+
+    pub fn is_streamed(&self) -> bool {
+        self.stream.is_some()
+    }
+}
+
+impl BodySender {
+    pub fn send(&self, frame: std::io::Result<Bytes>) -> std::io::Result<()> {
+        self.0
+            .blocking_send(frame)
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+    }
+}
+
+impl From<String> for ResponseBody {
+    fn from(value: String) -> Self {
+        Bytes::from(value).into()
+    }
+}
+
+impl From<Bytes> for ResponseBody {
+    fn from(value: Bytes) -> Self {
+        Self {
+            data: value,
+            stream: None,
+        }
+    }
+}
+
+impl From<Vec<u8>> for ResponseBody {
+    fn from(value: Vec<u8>) -> Self {
+        Bytes::from(value).into()
+    }
+}
+
+/// The in-memory part of the body; empty for a streamed one.
+impl Deref for ResponseBody {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl PartialEq for ResponseBody {
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data
+            && match (&self.stream, &other.stream) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (a, b) => a.is_none() && b.is_none(),
+            }
+    }
+}
+
+impl Eq for ResponseBody {}
+
+impl PartialEq<str> for ResponseBody {
+    fn eq(&self, other: &str) -> bool {
+        self.stream.is_none() && self.data == other.as_bytes()
+    }
+}
+
+impl Body for ResponseBody {
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, std::io::Error>>> {
+        let this = self.get_mut();
+        if !this.data.is_empty() {
+            return Poll::Ready(Some(Ok(Frame::data(std::mem::take(&mut this.data)))));
+        }
+        let Some(stream) = &this.stream else {
+            return Poll::Ready(None);
+        };
+        let polled = stream
+            .lock()
+            .expect("response body lock poisoned")
+            .poll_recv(cx);
+        match polled {
+            Poll::Ready(Some(frame)) => Poll::Ready(Some(frame.map(Frame::data))),
+            Poll::Ready(None) => {
+                this.stream = None;
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_empty() && self.stream.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.stream {
+            Some(_) => SizeHint::default(),
+            None => SizeHint::with_exact(self.data.len() as u64),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::path::{Component, Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{constants, error::SliceBreadServerError};
+
+/// Where an upload goes in a bundle, from `X-Bundle-Id` and `X-Bundle-Path`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleMember {
+    pub bundle_id: String,
+    pub path: PathBuf,
+}
+
+/// Contents of `.bundle.json` at the root of a bundle. It is updated as each
+/// file is staged, and `committed_at` is set when the tree is moved into place.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub bundle_id: String,
+    pub tenant: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub committed_at: Option<DateTime<Utc>>,
+    pub files: Vec<BundleFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleFile {
+    pub path: PathBuf,
+    pub file_id: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl BundleManifest {
+    pub fn new(bundle_id: &str, tenant: &str) -> Self {
+        Self {
+            bundle_id: bundle_id.to_string(),
+            tenant: tenant.to_string(),
+            committed_at: None,
+            files: Vec::new(),
+        }
+    }
+}
+
+/// A bundle id names a directory under the upload directory, so it must be a
+/// single path component that doesn't clash with the server's hidden ones.
+pub fn parse_id(value: &str) -> Result<&str, SliceBreadServerError> {
+    if value.is_empty() || value.starts_with('.') || value.contains(['/', '\\']) {
+        return Err(SliceBreadServerError::InvalidHeader(format!(
+            "Invalid bundle id: {}",
+            value
+        )));
+    }
+    Ok(value)
+}
+
+/// Parses `X-Bundle-Path`: a relative path, `/`-separated, without `..`.
+pub fn parse_path(value: &str) -> Result<PathBuf, SliceBreadServerError> {
+    let invalid = || {
+        SliceBreadServerError::InvalidHeader(format!(
+            "{} must be a relative path within the bundle: {}",
+            constants::HEADER_BUNDLE_PATH,
+            value
+        ))
+    };
+    let mut path = PathBuf::new();
+    for component in Path::new(value).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return Err(invalid()),
+        }
+    }
+    if path.as_os_str().is_empty() || path == Path::new(constants::BUNDLE_MANIFEST_FILE) {
+        return Err(invalid());
+    }
+    Ok(path)
+}
+
+/// Two files can't share a path, and a file can't sit where another needs a directory.
+pub fn check_paths<'a>(
+    paths: impl IntoIterator<Item = &'a Path>,
+) -> Result<(), SliceBreadServerError> {
+    let mut paths: Vec<&Path> = paths.into_iter().collect();
+    paths.sort();
+    for pair in paths.windows(2) {
+        if pair[1].starts_with(pair[0]) {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "Bundle paths {} and {} collide",
+                pair[0].display(),
+                pair[1].display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Where a bundle's files are assembled until it is committed.
+pub fn staging_dir(base_dir: &Path, bundle_id: &str) -> PathBuf {
+    base_dir.join(constants::BUNDLE_STAGING_DIR).join(bundle_id)
+}
+
+/// Where a bundle lands, relative to the upload directory.
+pub fn committed_dir(bundle_id: &str) -> PathBuf {
+    PathBuf::from(bundle_id)
+}
+
+pub async fn read(dir: &Path) -> std::io::Result<Option<BundleManifest>> {
+    match tokio::fs::read(dir.join(constants::BUNDLE_MANIFEST_FILE)).await {
+        Ok(json) => Ok(Some(serde_json::from_slice(&json)?)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Writes the manifest through a temporary file so readers never see a partial one.
+pub async fn write(dir: &Path, manifest: &BundleManifest) -> std::io::Result<()> {
+    tokio::fs::create_dir_all(dir).await?;
+    let path = dir.join(constants::BUNDLE_MANIFEST_FILE);
+    let tmp = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec_pretty(manifest)?).await?;
+    tokio::fs::rename(&tmp, &path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle_paths_stay_inside_the_bundle() {
+        assert_eq!(
+            parse_path("data/./2024/report.csv").unwrap(),
+            PathBuf::from("data/2024/report.csv")
+        );
+        for invalid in [
+            "",
+            ".",
+            "../escape",
+            "/etc/passwd",
+            "a/../../b",
+            ".bundle.json",
+        ] {
+            assert!(parse_path(invalid).is_err(), "{}", invalid);
+        }
+        for invalid in ["", ".bundles", "a/b"] {
+            assert!(parse_id(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_colliding_paths_are_refused() {
+        let paths = |paths: &[&'static str]| check_paths(paths.iter().map(Path::new));
+        assert!(paths(&["a/b.csv", "a/c.csv", "b"]).is_ok());
+        assert!(paths(&["a/b.csv", "a/b.csv"]).is_err());
+        assert!(paths(&["a", "a/b.csv"]).is_err());
+    }
+}
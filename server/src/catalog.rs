@@ -0,0 +1,93 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::constants;
+
+/// Where a completed upload ended up, so it can be found by file id no matter
+/// which output template placed it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    pub file_id: String,
+    /// Relative to the upload directory.
+    pub path: PathBuf,
+}
+
+fn entry_path(base_dir: &Path, file_id: &str) -> PathBuf {
+    base_dir
+        .join(constants::CATALOG_DIR)
+        .join(format!("{}.json", file_id))
+}
+
+pub async fn record(base_dir: &Path, entry: &CatalogEntry) -> std::io::Result<()> {
+    let path = entry_path(base_dir, &entry.file_id);
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, serde_json::to_vec(entry)?).await
+}
+
+pub async fn lookup(base_dir: &Path, file_id: &str) -> std::io::Result<Option<CatalogEntry>> {
+    if file_id.is_empty() || file_id == "." || file_id == ".." || file_id.contains(['/', '\\']) {
+        return Ok(None);
+    }
+    match tokio::fs::read(entry_path(base_dir, file_id)).await {
+        Ok(json) => Ok(Some(serde_json::from_slice(&json)?)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+pub async fn remove(base_dir: &Path, file_id: &str) -> std::io::Result<()> {
+    match tokio::fs::remove_file(entry_path(base_dir, file_id)).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Every completed upload, in no particular order.
+pub async fn list(base_dir: &Path) -> std::io::Result<Vec<CatalogEntry>> {
+    let mut dir = match tokio::fs::read_dir(base_dir.join(constants::CATALOG_DIR)).await {
+        Ok(dir) => dir,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut entries = Vec::new();
+    while let Some(file) = dir.next_entry().await? {
+        if file.path().extension().is_some_and(|ext| ext == "json") {
+            entries.push(serde_json::from_slice(
+                &tokio::fs::read(file.path()).await?,
+            )?);
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_record_lookup_remove() {
+        let dir = TempDir::new("catalog").unwrap();
+        let entry = CatalogEntry {
+            file_id: "abc".to_string(),
+            path: PathBuf::from("acme/abc/report.pdf"),
+        };
+
+        record(dir.path(), &entry).await.unwrap();
+        assert_eq!(
+            lookup(dir.path(), "abc").await.unwrap(),
+            Some(entry.clone())
+        );
+        assert_eq!(lookup(dir.path(), "../abc").await.unwrap(), None);
+        assert_eq!(list(dir.path()).await.unwrap(), vec![entry]);
+
+        remove(dir.path(), "abc").await.unwrap();
+        assert_eq!(lookup(dir.path(), "abc").await.unwrap(), None);
+        assert!(list(dir.path()).await.unwrap().is_empty());
+        remove(dir.path(), "abc").await.unwrap();
+    }
+}
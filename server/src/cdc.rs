@@ -0,0 +1,138 @@
+/// Client-side FastCDC content-defined chunking. Cut points depend on the
+/// content rather than on offsets, so an insertion early in a file only
+/// changes the chunks around it and the rest keep their digests across versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FastCdc {
+    pub min_size: usize,
+    /// Rounded down to a power of two.
+    pub avg_size: usize,
+    pub max_size: usize,
+}
+
+impl Default for FastCdc {
+    fn default() -> Self {
+        Self {
+            min_size: 16 * 1024,
+            avg_size: 64 * 1024,
+            max_size: 256 * 1024,
+        }
+    }
+}
+
+impl FastCdc {
+    /// Splits `data` into consecutive chunks that together cover all of it.
+    pub fn chunks<'a>(&self, mut data: &'a [u8]) -> impl Iterator<Item = &'a [u8]> {
+        let config = *self;
+        std::iter::from_fn(move || {
+            if data.is_empty() {
+                return None;
+            }
+            let (chunk, rest) = data.split_at(config.cut(data));
+            data = rest;
+            Some(chunk)
+        })
+    }
+
+    /// Length of the first chunk of `data`. Before `avg_size` a stricter mask
+    /// makes a cut less likely and after it a looser one more likely
+    /// (normalized chunking), which keeps chunk sizes close to the average.
+    fn cut(&self, data: &[u8]) -> usize {
+        let min_size = self.min_size.max(1);
+        if data.len() <= min_size {
+            return data.len();
+        }
+        let end = data.len().min(self.max_size.max(min_size));
+        let normal = self.avg_size.clamp(min_size, end);
+        let bits = self.avg_size.max(2).ilog2();
+        let mask_strict = top_bits(bits + 1);
+        let mask_loose = top_bits(bits - 1);
+
+        let mut hash = 0u64;
+        for (i, &byte) in data.iter().enumerate().take(end).skip(min_size) {
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+            let mask = if i < normal { mask_strict } else { mask_loose };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+}
+
+/// Gear hash bits reflect the last 64 bytes best at the top, so masks use those.
+fn top_bits(bits: u32) -> u64 {
+    !0u64 << (64 - bits.min(63))
+}
+
+/// Random values for the Gear hash, generated with splitmix64 so that every
+/// build cuts identically.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state = 0x5eed_b4ea_d5ee_d001u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn pseudo_random(len: usize) -> Vec<u8> {
+        let mut state = 1u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chunks_respect_bounds_and_cover_input() {
+        let cdc = FastCdc {
+            min_size: 256,
+            avg_size: 1024,
+            max_size: 4096,
+        };
+        let data = pseudo_random(200_000);
+        let chunks: Vec<&[u8]> = cdc.chunks(&data).collect();
+
+        assert_eq!(chunks.concat(), data);
+        let (last, rest) = chunks.split_last().unwrap();
+        assert!(last.len() <= 4096);
+        for chunk in rest {
+            assert!((256..=4096).contains(&chunk.len()), "{}", chunk.len());
+        }
+        let average = data.len() / chunks.len();
+        assert!((512..=2048).contains(&average), "{}", average);
+    }
+
+    #[test]
+    fn test_insertion_only_changes_nearby_chunks() {
+        let cdc = FastCdc {
+            min_size: 256,
+            avg_size: 1024,
+            max_size: 4096,
+        };
+        let original = pseudo_random(100_000);
+        let mut updated = original.clone();
+        updated.splice(500..500, *b"a few inserted bytes");
+
+        let before: HashSet<&[u8]> = cdc.chunks(&original).collect();
+        let after: Vec<&[u8]> = cdc.chunks(&updated).collect();
+        let reused = after.iter().filter(|chunk| before.contains(*chunk)).count();
+        assert!(reused + 3 >= after.len(), "{} of {}", reused, after.len());
+    }
+}
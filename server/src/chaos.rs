@@ -0,0 +1,141 @@
+use std::{str::FromStr, time::Duration};
+
+use crate::error::SliceBreadServerError;
+
+/// Fault injection for exercising client retry logic, parsed from a spec like
+/// `p_fail=0.1,latency=200ms`. Never enable this in production.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChaosConfig {
+    pub p_fail: f64,
+    /// Probability of panicking, to exercise the server's panic handling.
+    pub p_panic: f64,
+    pub latency: Duration,
+}
+
+impl ChaosConfig {
+    /// Sleeps for a random delay up to `latency`, then panics with probability
+    /// `p_panic` or fails the request with probability `p_fail`, alternating
+    /// randomly between 500 and 503.
+    pub async fn inject(&self) -> Result<(), SliceBreadServerError> {
+        if !self.latency.is_zero() {
+            let delay = rand::random_range(0..=self.latency.as_millis() as u64);
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+        }
+
+        if self.p_panic > 0.0 && rand::random::<f64>() < self.p_panic {
+            panic!("Chaos mode injected a panic");
+        }
+
+        if self.p_fail > 0.0 && rand::random::<f64>() < self.p_fail {
+            tracing::warn!("Chaos mode injected a failure");
+            return Err(if rand::random::<bool>() {
+                SliceBreadServerError::ServiceUnavailable("Injected failure".to_string())
+            } else {
+                SliceBreadServerError::InternalServerError("Injected failure".to_string())
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for ChaosConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = ChaosConfig::default();
+
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("Expected key=value, got: {}", pair))?;
+            match key.trim() {
+                "p_fail" => config.p_fail = parse_probability("p_fail", value)?,
+                "p_panic" => config.p_panic = parse_probability("p_panic", value)?,
+                "latency" => config.latency = parse_duration(value.trim())?,
+                other => return Err(format!("Unknown chaos option: {}", other)),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_probability(key: &str, value: &str) -> Result<f64, String> {
+    let p: f64 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid {}: {}", key, value))?;
+    if !(0.0..=1.0).contains(&p) {
+        return Err(format!("{} must be between 0 and 1, got: {}", key, p));
+    }
+    Ok(p)
+}
+
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit) = value
+        .find(|c: char| !c.is_ascii_digit())
+        .map(|i| value.split_at(i))
+        .unwrap_or((value, "ms"));
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration: {}", value))?;
+
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "s" => Ok(Duration::from_secs(number)),
+        _ => Err(format!("Invalid duration unit: {}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_full_spec() {
+        let config: ChaosConfig = "p_fail=0.1,p_panic=0.01,latency=200ms".parse().unwrap();
+        assert_eq!(config.p_fail, 0.1);
+        assert_eq!(config.p_panic, 0.01);
+        assert_eq!(config.latency, Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_parse_seconds_and_defaults() {
+        let config: ChaosConfig = "latency=2s".parse().unwrap();
+        assert_eq!(config.p_fail, 0.0);
+        assert_eq!(config.latency, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_spec() {
+        assert!("p_fail=1.5".parse::<ChaosConfig>().is_err());
+        assert!("p_panic=-1".parse::<ChaosConfig>().is_err());
+        assert!("latency=10h".parse::<ChaosConfig>().is_err());
+        assert!("jitter=10ms".parse::<ChaosConfig>().is_err());
+        assert!("p_fail".parse::<ChaosConfig>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_always_fail() {
+        let config = ChaosConfig {
+            p_fail: 1.0,
+            latency: Duration::ZERO,
+            ..ChaosConfig::default()
+        };
+        let err = config.inject().await.unwrap_err();
+        assert!(matches!(
+            err,
+            SliceBreadServerError::ServiceUnavailable(_)
+                | SliceBreadServerError::InternalServerError(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_never_fail() {
+        let config = ChaosConfig::default();
+        for _ in 0..100 {
+            assert!(config.inject().await.is_ok());
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use crate::crypto::Sha256;
+
+pub type ChunkDigest = [u8; 32];
+
+pub fn sha256(data: &[u8]) -> ChunkDigest {
+    Sha256::digest(data)
+}
+
+/// Hashes several fields as one digest, length-prefixing each so that
+/// `["ab", "c"]` and `["a", "bc"]` don't collide.
+pub fn sha256_parts(parts: &[&[u8]]) -> ChunkDigest {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    hasher.finalize()
+}
+
+pub fn to_hex(digest: &ChunkDigest) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parses a digest written by `to_hex`, in either case.
+pub fn from_hex(value: &str) -> Option<ChunkDigest> {
+    if value.len() != 64 || !value.is_ascii() {
+        return None;
+    }
+    let mut digest = [0; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&value[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(digest)
+}
@@ -0,0 +1,168 @@
+use std::{sync::Mutex, time::Duration};
+
+use crate::constants;
+
+/// Weight kept by older samples each time a new one is recorded, so the fit
+/// follows changes in load.
+const DECAY: f64 = 0.98;
+
+/// Samples needed before the fit is trusted.
+const MIN_SAMPLES: f64 = 8.0;
+
+/// Share of each request's time the fixed cost may take up in a recommended chunk.
+const TARGET_OVERHEAD: f64 = 0.05;
+
+/// Recommended sizes are rounded up to a multiple of this.
+const ALIGNMENT: u64 = 64 * 1024;
+
+/// How long chunk requests take, fitted as a fixed cost per request plus a
+/// cost per byte from recent uploads. Small chunks spend most of their time
+/// on the fixed part, which is what the recommendation avoids.
+#[derive(Debug, Default)]
+pub struct RequestCost {
+    fit: Mutex<Fit>,
+}
+
+/// Exponentially decayed sums for a least-squares line through
+/// (bytes, seconds) samples.
+#[derive(Debug, Default)]
+struct Fit {
+    n: f64,
+    x: f64,
+    y: f64,
+    xx: f64,
+    xy: f64,
+}
+
+impl RequestCost {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, bytes: u64, elapsed: Duration) {
+        let (x, y) = (bytes as f64, elapsed.as_secs_f64());
+        let mut fit = self.fit.lock().expect("request cost lock poisoned");
+        fit.n = fit.n * DECAY + 1.0;
+        fit.x = fit.x * DECAY + x;
+        fit.y = fit.y * DECAY + y;
+        fit.xx = fit.xx * DECAY + x * x;
+        fit.xy = fit.xy * DECAY + x * y;
+    }
+
+    /// Fixed seconds per request and seconds per byte, once enough chunks of
+    /// different sizes have been seen to tell them apart.
+    pub fn estimate(&self) -> Option<(f64, f64)> {
+        let fit = self.fit.lock().expect("request cost lock poisoned");
+        let variance = fit.n * fit.xx - fit.x * fit.x;
+        if fit.n < MIN_SAMPLES || variance <= f64::EPSILON * fit.xx.max(1.0) {
+            return None;
+        }
+        let per_byte = (fit.n * fit.xy - fit.x * fit.y) / variance;
+        if per_byte <= 0.0 {
+            return None;
+        }
+        let fixed = ((fit.y - per_byte * fit.x) / fit.n).max(0.0);
+        Some((fixed, per_byte))
+    }
+}
+
+/// Limits a recommended chunk size has to respect.
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkSizeLimits {
+    /// Smallest chunk accepted before the last; `None` for no minimum.
+    pub min_chunk_bytes: Option<u64>,
+    /// Largest chunk the server accepts; `None` for no limit.
+    pub max_chunk_bytes: Option<u64>,
+    pub max_total_chunks: usize,
+}
+
+/// Chunk size for a file of `file_size` bytes, or of unknown size: large
+/// enough that the fixed cost of a request is at most `TARGET_OVERHEAD` of
+/// it, and that the file fits in `max_total_chunks`, but within the chunk
+/// size limits and no larger than the file.
+pub fn recommend(file_size: Option<u64>, cost: Option<(f64, f64)>, limits: ChunkSizeLimits) -> u64 {
+    let ideal = match cost {
+        Some((fixed, per_byte)) => {
+            (fixed / per_byte * (1.0 - TARGET_OVERHEAD) / TARGET_OVERHEAD) as u64
+        }
+        None => constants::DEFAULT_RECOMMENDED_CHUNK_SIZE,
+    }
+    .clamp(
+        constants::MIN_RECOMMENDED_CHUNK_SIZE,
+        constants::MAX_RECOMMENDED_CHUNK_SIZE,
+    );
+    let fits = file_size.map_or(0, |size| {
+        size.div_ceil(limits.max_total_chunks.max(1) as u64)
+    });
+    let size = ideal
+        .max(fits)
+        .max(limits.min_chunk_bytes.unwrap_or(0))
+        .next_multiple_of(ALIGNMENT);
+    let size = limits.max_chunk_bytes.map_or(size, |max| size.min(max));
+    file_size.map_or(size, |file_size| size.min(file_size.max(1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_fits_fixed_and_per_byte_cost() {
+        let cost = RequestCost::new();
+        assert_eq!(cost.estimate(), None);
+        // 10ms per request plus 10ms per MiB.
+        for i in 0..20 {
+            let bytes = (i % 4 + 1) * MIB;
+            cost.record(bytes, Duration::from_millis(10 + 10 * bytes / MIB));
+        }
+        let (fixed, per_byte) = cost.estimate().unwrap();
+        assert!((fixed - 0.010).abs() < 1e-6, "{}", fixed);
+        assert!((per_byte * MIB as f64 - 0.010).abs() < 1e-6, "{}", per_byte);
+
+        // All the same size: the two costs can't be told apart.
+        let uniform = RequestCost::new();
+        for _ in 0..20 {
+            uniform.record(MIB, Duration::from_millis(20));
+        }
+        assert_eq!(uniform.estimate(), None);
+    }
+
+    #[test]
+    fn test_recommendation_respects_limits() {
+        let limits = ChunkSizeLimits {
+            min_chunk_bytes: None,
+            max_chunk_bytes: None,
+            max_total_chunks: 100_000,
+        };
+        assert_eq!(
+            recommend(None, None, limits),
+            constants::DEFAULT_RECOMMENDED_CHUNK_SIZE
+        );
+        // 19 times the bytes sent in the fixed cost: 19 * 10ms at 100 MiB/s.
+        let cost = Some((0.010, 1.0 / (100 * MIB) as f64));
+        assert_eq!(recommend(None, cost, limits), 19 * MIB);
+        assert_eq!(recommend(Some(3 * MIB), cost, limits), 3 * MIB);
+        assert_eq!(recommend(Some(0), cost, limits), 1);
+
+        let capped = ChunkSizeLimits {
+            max_chunk_bytes: Some(4 * MIB),
+            ..limits
+        };
+        assert_eq!(recommend(None, cost, capped), 4 * MIB);
+
+        let floored = ChunkSizeLimits {
+            min_chunk_bytes: Some(32 * MIB),
+            ..limits
+        };
+        assert_eq!(recommend(None, cost, floored), 32 * MIB);
+
+        // A huge file needs bigger chunks to stay within the chunk count.
+        let few = ChunkSizeLimits {
+            max_total_chunks: 10,
+            ..limits
+        };
+        assert_eq!(recommend(Some(1000 * MIB), None, few), 100 * MIB);
+    }
+}
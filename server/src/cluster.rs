@@ -0,0 +1,347 @@
+use std::{
+    fmt::Debug,
+    future::Future,
+    path::{Path, PathBuf},
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+};
+
+use crate::{bitmap::ChunkBitmap, catalog::CatalogEntry, io, peer::PeerConfig, session::Session};
+
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = std::io::Result<T>> + Send + 'a>>;
+
+/// Upload state that the instances of a cluster share, so that any of them
+/// can take any chunk of an upload: what the upload declared, which chunks
+/// are stored, and who is assembling it. Chunks themselves live in the upload
+/// directory, which every instance mounts.
+pub trait ClusterStore: Debug + Send + Sync {
+    /// Where the state is kept, for logs. Never includes credentials.
+    fn name(&self) -> String;
+    /// The upload's declaration as last published, if it is in flight.
+    fn session<'a>(&'a self, file_id: &'a str) -> StoreFuture<'a, Option<Session>>;
+    fn put_session<'a>(&'a self, file_id: &'a str, session: &'a Session) -> StoreFuture<'a, ()>;
+    fn mark_received<'a>(&'a self, file_id: &'a str, chunk_index: usize) -> StoreFuture<'a, ()>;
+    fn received<'a>(
+        &'a self,
+        file_id: &'a str,
+        total_chunks: usize,
+    ) -> StoreFuture<'a, ChunkBitmap>;
+    /// Takes the upload's assembly lock for `node`, returning false if
+    /// another node holds it. Taking a lock the node already holds succeeds.
+    fn lock_assembly<'a>(&'a self, file_id: &'a str, node: &'a str) -> StoreFuture<'a, bool>;
+    /// Releases the lock if `node` holds it.
+    fn unlock_assembly<'a>(&'a self, file_id: &'a str, node: &'a str) -> StoreFuture<'a, ()>;
+    /// Forgets an upload that was assembled or discarded, lock included.
+    fn remove<'a>(&'a self, file_id: &'a str) -> StoreFuture<'a, ()>;
+    /// Forgets an upload that was assembled into `entry`. Stores that keep a
+    /// record of completed files write it in the same step.
+    fn complete<'a>(&'a self, file_id: &'a str, entry: &'a CatalogEntry) -> StoreFuture<'a, ()> {
+        let _ = entry;
+        self.remove(file_id)
+    }
+    /// Every upload in flight with a published session.
+    fn uploads(&self) -> StoreFuture<'_, Vec<String>>;
+}
+
+/// This instance's part in a cluster.
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    /// Identifies the instance in the locks it takes; unique within the cluster.
+    pub node_id: String,
+    pub store: Arc<dyn ClusterStore>,
+    /// Set when instances keep chunks on their own disks rather than a
+    /// shared upload directory.
+    pub peers: Option<PeerConfig>,
+}
+
+/// Where the cluster state is kept, from `--cluster-store`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreLocation {
+    /// `dir:<path>`, a directory every instance mounts.
+    Dir(PathBuf),
+    /// A `redis://` URL, with the `redis` feature.
+    #[cfg(feature = "redis")]
+    Redis(String),
+    /// A `postgres://` URL, with the `postgres` feature.
+    #[cfg(feature = "postgres")]
+    Postgres(String),
+}
+
+impl StoreLocation {
+    pub fn open(&self) -> Result<Arc<dyn ClusterStore>, String> {
+        match self {
+            Self::Dir(root) => Ok(Arc::new(DirStore::new(root))),
+            #[cfg(feature = "redis")]
+            Self::Redis(url) => Ok(Arc::new(crate::redis_store::RedisStore::new(url)?)),
+            #[cfg(feature = "postgres")]
+            Self::Postgres(url) => Ok(Arc::new(crate::postgres_store::PostgresStore::new(url)?)),
+        }
+    }
+}
+
+impl FromStr for StoreLocation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().split_once(':') {
+            Some(("dir", path)) if !path.is_empty() => Ok(Self::Dir(PathBuf::from(path))),
+            #[cfg(feature = "redis")]
+            Some(("redis", _)) => Ok(Self::Redis(s.trim().to_string())),
+            #[cfg(not(feature = "redis"))]
+            Some(("redis", _)) => Err("A Redis cluster store needs the redis feature".to_string()),
+            #[cfg(feature = "postgres")]
+            Some(("postgres" | "postgresql", _)) => Ok(Self::Postgres(s.trim().to_string())),
+            #[cfg(not(feature = "postgres"))]
+            Some(("postgres" | "postgresql", _)) => {
+                Err("A PostgreSQL cluster store needs the postgres feature".to_string())
+            }
+            _ => Err(format!(
+                "Unknown cluster store: {} (expected dir:<path>, redis://<host> or postgres://<host>)",
+                s
+            )),
+        }
+    }
+}
+
+/// A store URL as it may be logged: without the `user:password@` part, or
+/// the query, which can hold a password as well.
+pub fn redact_url(url: &str) -> String {
+    let url = url.split_once('?').map_or(url, |(url, _)| url);
+    match url.split_once("://") {
+        Some((scheme, rest)) => match rest.rsplit_once('@') {
+            Some((_, host)) => format!("{}://{}", scheme, host),
+            None => url.to_string(),
+        },
+        None => url.to_string(),
+    }
+}
+
+/// Keeps cluster state as files in a shared directory, by default `.cluster`
+/// in the upload directory. Locks are files created exclusively, which needs
+/// a filesystem that honours `O_EXCL`, as NFSv3 and later do.
+#[derive(Debug)]
+pub struct DirStore {
+    root: PathBuf,
+}
+
+impl DirStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, file_id: &str, extension: &str) -> PathBuf {
+        self.root.join(format!("{}.{}", file_id, extension))
+    }
+}
+
+async fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+impl ClusterStore for DirStore {
+    fn name(&self) -> String {
+        format!("dir:{}", self.root.display())
+    }
+
+    fn session<'a>(&'a self, file_id: &'a str) -> StoreFuture<'a, Option<Session>> {
+        Box::pin(async move {
+            match tokio::fs::read(self.path(file_id, "session.json")).await {
+                Ok(json) => Ok(Some(serde_json::from_slice(&json)?)),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err),
+            }
+        })
+    }
+
+    fn put_session<'a>(&'a self, file_id: &'a str, session: &'a Session) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            tokio::fs::create_dir_all(&self.root).await?;
+            // Written aside and renamed, so readers never see half of it.
+            let path = self.path(file_id, "session.json");
+            let tmp = self.path(file_id, &format!("session.{}.tmp", uuid::Uuid::new_v4()));
+            tokio::fs::write(&tmp, serde_json::to_vec(session)?).await?;
+            tokio::fs::rename(&tmp, &path).await
+        })
+    }
+
+    fn mark_received<'a>(&'a self, file_id: &'a str, chunk_index: usize) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            tokio::fs::create_dir_all(&self.root).await?;
+            // A byte per chunk, like the received file, so writers needn't coordinate.
+            io::write_at(self.path(file_id, "received"), chunk_index as u64, &[1]).await
+        })
+    }
+
+    fn received<'a>(
+        &'a self,
+        file_id: &'a str,
+        total_chunks: usize,
+    ) -> StoreFuture<'a, ChunkBitmap> {
+        Box::pin(async move {
+            match tokio::fs::read(self.path(file_id, "received")).await {
+                Ok(bytes) => Ok(ChunkBitmap::from_bytes(&bytes, total_chunks)),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    Ok(ChunkBitmap::new(total_chunks))
+                }
+                Err(err) => Err(err),
+            }
+        })
+    }
+
+    fn lock_assembly<'a>(&'a self, file_id: &'a str, node: &'a str) -> StoreFuture<'a, bool> {
+        Box::pin(async move {
+            tokio::fs::create_dir_all(&self.root).await?;
+            let path = self.path(file_id, "lock");
+            match tokio::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .await
+            {
+                Ok(_) => {
+                    tokio::fs::write(&path, node).await?;
+                    Ok(true)
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    Ok(tokio::fs::read_to_string(&path).await? == node)
+                }
+                Err(err) => Err(err),
+            }
+        })
+    }
+
+    fn unlock_assembly<'a>(&'a self, file_id: &'a str, node: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.path(file_id, "lock");
+            match tokio::fs::read_to_string(&path).await {
+                Ok(holder) if holder == node => remove_if_exists(&path).await,
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn remove<'a>(&'a self, file_id: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            for extension in ["session.json", "received", "lock"] {
+                remove_if_exists(&self.path(file_id, extension)).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn uploads(&self) -> StoreFuture<'_, Vec<String>> {
+        Box::pin(async move {
+            let mut dir = match tokio::fs::read_dir(&self.root).await {
+                Ok(dir) => dir,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(err) => return Err(err),
+            };
+            let mut file_ids = Vec::new();
+            while let Some(file) = dir.next_entry().await? {
+                if let Some(file_id) = file
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.strip_suffix(".session.json"))
+                {
+                    file_ids.push(file_id.to_string());
+                }
+            }
+            Ok(file_ids)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::constants;
+
+    #[test]
+    fn test_parse_store_location() {
+        assert_eq!(
+            "dir:/mnt/shared/.cluster".parse::<StoreLocation>(),
+            Ok(StoreLocation::Dir(PathBuf::from("/mnt/shared/.cluster")))
+        );
+        assert!("dir:".parse::<StoreLocation>().is_err());
+        assert!("/mnt/shared".parse::<StoreLocation>().is_err());
+        assert_eq!(
+            "redis://cache:6379".parse::<StoreLocation>().is_ok(),
+            cfg!(feature = "redis")
+        );
+        assert_eq!(
+            "postgres://app@db/uploads".parse::<StoreLocation>().is_ok(),
+            cfg!(feature = "postgres")
+        );
+    }
+
+    #[test]
+    fn test_redact_url() {
+        assert_eq!(
+            redact_url("redis://:s3cret@cache.internal:6379/2"),
+            "redis://cache.internal:6379/2"
+        );
+        assert_eq!(redact_url("redis://localhost"), "redis://localhost");
+        assert_eq!(
+            redact_url("postgres://app@db/uploads?password=s3cret&sslmode=require"),
+            "postgres://db/uploads"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dir_store_shares_state_between_instances() {
+        let temp_dir = TempDir::new("cluster_test").unwrap();
+        let a = DirStore::new(temp_dir.path());
+        let b = DirStore::new(temp_dir.path());
+
+        assert_eq!(a.session("file1").await.unwrap(), None);
+        let session = Session {
+            tenant: constants::DEFAULT_TENANT.to_string(),
+            file_name: "shared.txt".to_string(),
+            total_chunks: 2,
+            content_type: "text/plain".to_string(),
+            repr_digests: Vec::new(),
+            file_size: Some(10),
+            byte_ranges: false,
+            extract: false,
+            defer_assembly: false,
+            max_duration: None,
+            client_ip: None,
+            generation: 0,
+            bundle: None,
+            owner: None,
+            shares: Vec::new(),
+            category: None,
+            tags: Default::default(),
+            user_metadata: Default::default(),
+            required_sha256: None,
+        };
+        a.put_session("file1", &session).await.unwrap();
+        assert_eq!(b.session("file1").await.unwrap(), Some(session));
+        assert_eq!(b.uploads().await.unwrap(), vec!["file1".to_string()]);
+
+        a.mark_received("file1", 1).await.unwrap();
+        b.mark_received("file1", 0).await.unwrap();
+        assert!(a.received("file1", 2).await.unwrap().is_full());
+
+        assert!(a.lock_assembly("file1", "node-a").await.unwrap());
+        assert!(a.lock_assembly("file1", "node-a").await.unwrap());
+        assert!(!b.lock_assembly("file1", "node-b").await.unwrap());
+        b.unlock_assembly("file1", "node-b").await.unwrap();
+        assert!(!b.lock_assembly("file1", "node-b").await.unwrap());
+        a.unlock_assembly("file1", "node-a").await.unwrap();
+        assert!(b.lock_assembly("file1", "node-b").await.unwrap());
+
+        a.remove("file1").await.unwrap();
+        assert_eq!(b.session("file1").await.unwrap(), None);
+        assert_eq!(b.received("file1", 2).await.unwrap().count(), 0);
+        assert!(b.uploads().await.unwrap().is_empty());
+        assert!(a.lock_assembly("file1", "node-a").await.unwrap());
+    }
+}
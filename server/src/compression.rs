@@ -0,0 +1,219 @@
+use std::io::Write;
+
+use bytes::Bytes;
+use flate2::{Compression, write::GzEncoder};
+use hyper::{
+    HeaderMap, Response,
+    header::{self, HeaderValue},
+};
+
+use crate::{body::ResponseBody, error::SliceBreadServerError};
+
+/// Responses smaller than this are sent as they are: compressing them saves
+/// less than the headers it adds.
+const MIN_BYTES: usize = 1024;
+
+/// Bodies smaller than this are compressed inline rather than on the blocking pool.
+const OFFLOAD_MIN_BYTES: usize = 64 * 1024;
+
+const ZSTD_LEVEL: i32 = 3;
+
+/// Content codings applied to JSON responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Zstd,
+    Gzip,
+}
+
+impl Encoding {
+    /// In order of preference: zstd compresses faster and smaller.
+    pub const SUPPORTED: [Self; 2] = [Self::Zstd, Self::Gzip];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Zstd => "zstd",
+            Self::Gzip => "gzip",
+        }
+    }
+
+    /// The coding to answer a request with, from its `Accept-Encoding`: the
+    /// one with the highest q-value, with ties going to the preferred one.
+    /// `*` stands for any coding not listed, and `q=0` refuses a coding.
+    /// `None` if the client accepts neither.
+    pub fn negotiate(headers: &HeaderMap) -> Option<Self> {
+        let mut listed = Vec::new();
+        let mut any = None;
+        for value in headers.get_all(header::ACCEPT_ENCODING) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            for item in value.split(',') {
+                let mut params = item.split(';');
+                let coding = params.next().unwrap_or_default().trim();
+                let weight = params
+                    .find_map(|param| {
+                        let (name, value) = param.split_once('=')?;
+                        name.trim()
+                            .eq_ignore_ascii_case("q")
+                            .then(|| value.trim().parse::<f32>().ok())
+                            .flatten()
+                    })
+                    .unwrap_or(1.0);
+                if coding == "*" {
+                    any = Some(weight);
+                } else {
+                    listed.push((coding.to_ascii_lowercase(), weight));
+                }
+            }
+        }
+
+        let mut best: Option<(Self, f32)> = None;
+        for encoding in Self::SUPPORTED {
+            let weight = listed
+                .iter()
+                .find(|(coding, _)| coding == encoding.name())
+                .map(|(_, weight)| *weight)
+                .or(any)
+                .unwrap_or(0.0);
+            if weight > 0.0 && best.is_none_or(|(_, best)| weight > best) {
+                best = Some((encoding, weight));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    fn encode(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL),
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Whether a response is worth compressing: JSON or NDJSON held in memory,
+/// large enough to gain from it, and not already encoded.
+fn is_compressible(response: &Response<ResponseBody>) -> bool {
+    let json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media_type| {
+            let media_type = media_type.trim();
+            media_type.eq_ignore_ascii_case("application/json")
+                || media_type.eq_ignore_ascii_case("application/x-ndjson")
+        });
+    json && response.body().len() >= MIN_BYTES
+        && !response.body().is_streamed()
+        && !response.headers().contains_key(header::CONTENT_ENCODING)
+}
+
+/// Compresses a JSON response with `encoding` if it is worth it, marking it as
+/// varying by `Accept-Encoding` so that caches keep the codings apart.
+pub async fn compress(
+    response: &mut Response<ResponseBody>,
+    encoding: Encoding,
+) -> Result<(), SliceBreadServerError> {
+    if !is_compressible(response) {
+        return Ok(());
+    }
+    let data = Bytes::copy_from_slice(response.body());
+    let compressed = if data.len() < OFFLOAD_MIN_BYTES {
+        encoding.encode(&data)?
+    } else {
+        tokio::task::spawn_blocking(move || encoding.encode(&data))
+            .await
+            .map_err(|e| {
+                SliceBreadServerError::InternalServerError(format!(
+                    "Compression task failed: {}",
+                    e
+                ))
+            })??
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.name()),
+    );
+    headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    headers.remove(header::CONTENT_LENGTH);
+    *response.body_mut() = compressed.into();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn accepting(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_ENCODING, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_negotiates_the_preferred_accepted_coding() {
+        assert_eq!(Encoding::negotiate(&HeaderMap::new()), None);
+        assert_eq!(Encoding::negotiate(&accepting("br")), None);
+        assert_eq!(
+            Encoding::negotiate(&accepting("gzip, deflate, br")),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            Encoding::negotiate(&accepting("gzip, zstd")),
+            Some(Encoding::Zstd)
+        );
+        assert_eq!(
+            Encoding::negotiate(&accepting("zstd;q=0.5, GZIP")),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            Encoding::negotiate(&accepting("*;q=0.1, zstd;q=0")),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(Encoding::negotiate(&accepting("*")), Some(Encoding::Zstd));
+        assert_eq!(Encoding::negotiate(&accepting("identity")), None);
+    }
+
+    #[tokio::test]
+    async fn test_compresses_large_json_only() {
+        let json = format!("[{}0]", "1,".repeat(MIN_BYTES));
+        let response = |content_type: &str, body: &str| {
+            Response::builder()
+                .header(header::CONTENT_TYPE, content_type)
+                .body(ResponseBody::from(body.to_string()))
+                .unwrap()
+        };
+
+        let mut res = response("application/json", &json);
+        compress(&mut res, Encoding::Gzip).await.unwrap();
+        assert_eq!(res.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(res.headers()[header::VARY], "accept-encoding");
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&res.body()[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, json);
+
+        let mut res = response("application/json; charset=utf-8", &json);
+        compress(&mut res, Encoding::Zstd).await.unwrap();
+        assert_eq!(res.headers()[header::CONTENT_ENCODING], "zstd");
+        assert_eq!(zstd::decode_all(&res.body()[..]).unwrap(), json.as_bytes());
+
+        let mut res = response("application/octet-stream", &json);
+        compress(&mut res, Encoding::Zstd).await.unwrap();
+        assert!(!res.headers().contains_key(header::CONTENT_ENCODING));
+
+        let mut res = response("application/json", "{}");
+        compress(&mut res, Encoding::Zstd).await.unwrap();
+        assert!(!res.headers().contains_key(header::CONTENT_ENCODING));
+        assert_eq!(res.body(), "{}");
+    }
+}
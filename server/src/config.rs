@@ -0,0 +1,151 @@
+use std::{path::PathBuf, str::FromStr, time::Duration};
+
+use crate::{
+    alerts::AlertConfig, auth::IdentityRule, backpressure::BackpressureConfig,
+    basic_auth::BasicAuthConfig, chaos::ChaosConfig, cluster::ClusterConfig, constants,
+    debug_log::DebugLogFilter, extract::ExtractLimits, headers::HeaderNames, http::HttpConfig,
+    introspection::IntrospectionConfig, ipfilter::IpFilter, layout::ChunkLayout,
+    output::OutputTemplate, retention::RetentionRule, session::SessionLimits,
+    throttle::ThrottleConfig,
+};
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub chaos: Option<ChaosConfig>,
+    pub max_total_chunks: usize,
+    pub require_content_length: bool,
+    /// WORM mode: assembled files are read-only and can only be deleted via the admin API.
+    pub immutable: bool,
+    pub output_template: OutputTemplate,
+    pub chunk_layout: ChunkLayout,
+    pub throttle: ThrottleConfig,
+    pub backpressure: BackpressureConfig,
+    /// Directories completed files are replicated to.
+    pub replicate_to: Vec<PathBuf>,
+    /// How long completed files are kept, per tenant or category; files no
+    /// rule covers are kept forever.
+    pub retention: Vec<RetentionRule>,
+    /// Cold storage directory files are moved to by `archive` retention rules.
+    pub archive_to: Option<PathBuf>,
+    /// Audit trail file; defaults to `.audit.jsonl` in the upload directory.
+    pub audit_log: Option<PathBuf>,
+    /// When set, this instance shares upload state with others behind the same
+    /// load balancer, so chunks of one upload may go to any of them.
+    pub cluster: Option<ClusterConfig>,
+    pub ip_filter: IpFilter,
+    /// Tenants and permissions for mTLS client identities. When set, clients
+    /// whose certificate matches no rule are refused.
+    pub client_identities: Vec<IdentityRule>,
+    /// When set, clients not authenticated by certificate must send a bearer
+    /// token that this endpoint reports active.
+    pub introspection: Option<IntrospectionConfig>,
+    /// When set, clients not authenticated otherwise may log in with HTTP Basic auth.
+    pub basic_auth: Option<BasicAuthConfig>,
+    /// When set, every upload must carry an `X-Upload-Policy` signed with this secret.
+    pub upload_policy_secret: Option<String>,
+    /// Secret share links are signed with; owners can only create links when set.
+    pub share_link_secret: Option<String>,
+    pub http: HttpConfig,
+    pub durability: Durability,
+    /// Caps on archives unpacked for uploads sent with `X-Extract`.
+    pub extract_limits: ExtractLimits,
+    /// Longest any upload may take from its first chunk; `None` for no limit.
+    pub max_upload_duration: Option<Duration>,
+    pub session_limits: SessionLimits,
+    /// Most bytes one tenant may store, as counted in `/admin/stats`; `None` for no limit.
+    pub tenant_quota_bytes: Option<u64>,
+    pub body_limits: BodyLimits,
+    /// Smallest chunk accepted other than the last of an upload, so that
+    /// tiny chunks can't fill the disk with files; `None` for no minimum.
+    pub min_chunk_bytes: Option<u64>,
+    pub header_names: HeaderNames,
+    /// Compress JSON responses with zstd or gzip when the client accepts it.
+    pub compress_responses: bool,
+    /// Requests whose headers and responses are logged at startup; changed
+    /// at runtime through `/admin/debug-log`.
+    pub debug_log: DebugLogFilter,
+    /// When set, failure spikes, a full disk and tenants near their quota are
+    /// reported to webhooks.
+    pub alerts: Option<AlertConfig>,
+}
+
+/// Largest request body accepted per upload route, in bytes; `None` for no
+/// limit. Checked against `Content-Length` before any of the body is read,
+/// and against the bytes received while it streams in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BodyLimits {
+    pub chunk: Option<u64>,
+    pub range: Option<u64>,
+    pub delta: Option<u64>,
+    pub batch: Option<u64>,
+}
+
+/// What is fsynced before a write is acknowledged, trading throughput for
+/// crash safety.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Durability {
+    /// Leave everything to the OS page cache.
+    None,
+    /// Sync the assembled file and its directory before chunks are deleted.
+    #[default]
+    File,
+    /// Also sync every chunk (or byte range) before it is acknowledged.
+    Chunk,
+}
+
+impl FromStr for Durability {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "none" => Ok(Self::None),
+            "file" => Ok(Self::File),
+            "chunk" => Ok(Self::Chunk),
+            other => Err(format!(
+                "Unknown durability: {} (expected none, file or chunk)",
+                other
+            )),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            chaos: None,
+            max_total_chunks: constants::DEFAULT_MAX_TOTAL_CHUNKS,
+            require_content_length: false,
+            immutable: false,
+            output_template: OutputTemplate::default(),
+            chunk_layout: ChunkLayout::default(),
+            throttle: ThrottleConfig::default(),
+            backpressure: BackpressureConfig::default(),
+            replicate_to: Vec::new(),
+            retention: Vec::new(),
+            archive_to: None,
+            audit_log: None,
+            cluster: None,
+            ip_filter: IpFilter::default(),
+            client_identities: Vec::new(),
+            introspection: None,
+            alerts: None,
+            basic_auth: None,
+            upload_policy_secret: None,
+            share_link_secret: None,
+            http: HttpConfig::default(),
+            durability: Durability::default(),
+            extract_limits: ExtractLimits {
+                max_bytes: constants::DEFAULT_EXTRACT_MAX_BYTES,
+                max_entries: constants::DEFAULT_EXTRACT_MAX_ENTRIES,
+            },
+            max_upload_duration: None,
+            session_limits: SessionLimits::default(),
+            tenant_quota_bytes: None,
+            body_limits: BodyLimits::default(),
+            min_chunk_bytes: None,
+            header_names: HeaderNames::default(),
+            compress_responses: false,
+            debug_log: DebugLogFilter::default(),
+        }
+    }
+}
@@ -2,3 +2,156 @@ pub const HEADER_FILE_ID: &str = "X-File-Id";
 pub const HEADER_CHUNK_INDEX: &str = "X-Chunk-Index";
 pub const HEADER_TOTAL_CHUNKS: &str = "X-Total-Chunks";
 pub const HEADER_FILE_NAME: &str = "X-File-Name";
+pub const HEADER_FILE_SIZE: &str = "X-File-Size";
+pub const HEADER_TENANT_ID: &str = "X-Tenant-Id";
+pub const HEADER_CHUNK_OFFSET: &str = "X-Chunk-Offset";
+pub const HEADER_RANGE_OFFSET: &str = "X-Range-Offset";
+pub const HEADER_BLOCK_SIZE: &str = "X-Block-Size";
+pub const HEADER_UPLOAD_POLICY: &str = "X-Upload-Policy";
+pub const HEADER_CHUNK_SIZE: &str = "X-Chunk-Size";
+pub const HEADER_CHUNK_SHA256: &str = "X-Chunk-Sha256";
+pub const HEADER_FILE_SHA256: &str = "X-File-Sha256";
+pub const HEADER_MERKLE_ROOT: &str = "X-Merkle-Root";
+pub const HEADER_UPLOAD_STARTED_AT: &str = "X-Upload-Started-At";
+pub const HEADER_UPLOAD_COMPLETED_AT: &str = "X-Upload-Completed-At";
+pub const HEADER_CONTENT_DIGEST: &str = "Content-Digest";
+pub const HEADER_REPR_DIGEST: &str = "Repr-Digest";
+pub const HEADER_DIGEST: &str = "Digest";
+pub const HEADER_WANT_CONTENT_DIGEST: &str = "Want-Content-Digest";
+pub const HEADER_WANT_REPR_DIGEST: &str = "Want-Repr-Digest";
+pub const HEADER_FORWARDED_FOR: &str = "X-Forwarded-For";
+pub const HEADER_IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+pub const HEADER_IDEMPOTENT_REPLAYED: &str = "Idempotent-Replayed";
+pub const HEADER_EXTRACT: &str = "X-Extract";
+pub const HEADER_UPLOAD_MAX_DURATION: &str = "X-Upload-Max-Duration";
+pub const HEADER_PROTOCOL_VERSION: &str = "X-SliceBread-Version";
+pub const HEADER_DEFER_ASSEMBLY: &str = "X-Defer-Assembly";
+pub const HEADER_UPLOAD_GENERATION: &str = "X-Upload-Generation";
+pub const HEADER_CHUNK_ALREADY_PRESENT: &str = "X-Chunk-Already-Present";
+pub const HEADER_BUNDLE_ID: &str = "X-Bundle-Id";
+pub const HEADER_BUNDLE_PATH: &str = "X-Bundle-Path";
+pub const HEADER_TOTAL_FILES: &str = "X-Total-Files";
+pub const HEADER_SHARED_WITH: &str = "X-Shared-With";
+pub const HEADER_QUOTA_LIMIT: &str = "X-Quota-Limit";
+pub const HEADER_QUOTA_USED: &str = "X-Quota-Used";
+pub const HEADER_QUOTA_REMAINING: &str = "X-Quota-Remaining";
+pub const HEADER_UPLOAD_CATEGORY: &str = "X-Upload-Category";
+pub const HEADER_UPLOAD_TAGS: &str = "X-Upload-Tags";
+pub const HEADER_CLUSTER_SIGNATURE: &str = "X-Cluster-Signature";
+/// The protocol's own headers, which deployments may give other names.
+pub const RENAMEABLE_HEADERS: &[&str] = &[
+    HEADER_FILE_ID,
+    HEADER_CHUNK_INDEX,
+    HEADER_TOTAL_CHUNKS,
+    HEADER_FILE_NAME,
+    HEADER_FILE_SIZE,
+    HEADER_TENANT_ID,
+    HEADER_CHUNK_OFFSET,
+    HEADER_RANGE_OFFSET,
+    HEADER_BLOCK_SIZE,
+    HEADER_UPLOAD_POLICY,
+    HEADER_CHUNK_SIZE,
+    HEADER_CHUNK_SHA256,
+    HEADER_FILE_SHA256,
+    HEADER_MERKLE_ROOT,
+    HEADER_UPLOAD_STARTED_AT,
+    HEADER_UPLOAD_COMPLETED_AT,
+    HEADER_EXTRACT,
+    HEADER_UPLOAD_MAX_DURATION,
+    HEADER_PROTOCOL_VERSION,
+    HEADER_DEFER_ASSEMBLY,
+    HEADER_UPLOAD_GENERATION,
+    HEADER_CHUNK_ALREADY_PRESENT,
+    HEADER_BUNDLE_ID,
+    HEADER_BUNDLE_PATH,
+    HEADER_TOTAL_FILES,
+    HEADER_SHARED_WITH,
+    HEADER_QUOTA_LIMIT,
+    HEADER_QUOTA_USED,
+    HEADER_QUOTA_REMAINING,
+    HEADER_UPLOAD_CATEGORY,
+    HEADER_UPLOAD_TAGS,
+];
+
+pub const MANIFEST_DIR: &str = ".manifests";
+pub const RANGES_FILE: &str = "ranges.bin";
+pub const CHUNK_LAYOUT_FILE: &str = "layout.json";
+pub const RECEIVED_FILE: &str = "received.bin";
+pub const ASSEMBLY_CURSOR_FILE: &str = "assembly.cursor";
+pub const GENERATION_FILE: &str = "generation";
+pub const BUNDLE_STAGING_DIR: &str = ".bundles";
+pub const BUNDLE_MANIFEST_FILE: &str = ".bundle.json";
+pub const CATALOG_DIR: &str = ".catalog";
+pub const CLUSTER_DIR: &str = ".cluster";
+pub const CLUSTER_REDIS_PREFIX: &str = "slicedbread:upload:";
+pub const CLUSTER_POSTGRES_CONNECTIONS: u32 = 10;
+pub const AUDIT_LOG_FILE: &str = ".audit.jsonl";
+pub const SESSION_SNAPSHOT_FILE: &str = ".sessions.json";
+pub const CHUNK_JOURNAL_FILE: &str = ".chunks.journal";
+
+pub const DEFAULT_TENANT: &str = "default";
+pub const DEFAULT_POOL_BUFFERS: usize = 64;
+pub const DEFAULT_POOL_BUFFER_CAPACITY: usize = 256 * 1024;
+pub const DEFAULT_DELTA_BLOCK_SIZE: usize = 64 * 1024;
+pub const MAX_DELTA_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+/// Chunk size `GET /capabilities` recommends before it has measured any uploads.
+pub const DEFAULT_RECOMMENDED_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+pub const MIN_RECOMMENDED_CHUNK_SIZE: u64 = 1024 * 1024;
+pub const MAX_RECOMMENDED_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+pub const DEFAULT_MAX_TOTAL_CHUNKS: usize = 100_000;
+pub const DEFAULT_EXTRACT_MAX_BYTES: u64 = 1024 * 1024 * 1024;
+pub const DEFAULT_EXTRACT_MAX_ENTRIES: usize = 10_000;
+pub const MAX_STATUS_QUERY_IDS: usize = 10_000;
+pub const MAX_STATUS_QUERY_BYTES: usize = 1024 * 1024;
+pub const MAX_SHARE_REQUEST_BYTES: usize = 4 * 1024;
+pub const MAX_TAGS: usize = 32;
+pub const MAX_TAG_KEY_LEN: usize = 64;
+pub const MAX_TAG_VALUE_LEN: usize = 256;
+/// Prefix of the headers carrying user metadata, lowercased as hyper stores header names.
+pub const USER_METADATA_PREFIX: &str = "x-meta-";
+pub const MAX_USER_METADATA_BYTES: usize = 2 * 1024;
+/// Frames of an archive download buffered ahead of a slow client.
+pub const ARCHIVE_FRAMES_BUFFERED: usize = 4;
+pub const IDEMPOTENCY_KEY_TTL: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+/// Idempotency-Keys held at once; the oldest is forgotten to make room.
+pub const MAX_IDEMPOTENCY_KEYS: usize = 10_000;
+pub const EXPIRY_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+pub const RETENTION_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+/// How long shutdown waits for uploads and assemblies in flight to finish
+/// before writing out sessions.
+pub const SHUTDOWN_DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+pub const SHUTDOWN_DRAIN_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+pub const REPLICATION_MAX_ATTEMPTS: u32 = 5;
+pub const REPLICATION_BASE_BACKOFF: std::time::Duration = std::time::Duration::from_millis(500);
+pub const DEFAULT_INTROSPECTION_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(60);
+pub const INTROSPECTION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+pub const MAX_INTROSPECTION_RESPONSE_BYTES: usize = 64 * 1024;
+pub const MAX_CREDENTIAL_CACHE_ENTRIES: usize = 10_000;
+pub const BASIC_AUTH_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(60);
+/// How long an admin has to confirm a purge.
+pub const PURGE_CONFIRMATION_TTL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+/// Listing at the top of an owner's export archive.
+pub const EXPORT_INDEX_FILE: &str = "export.json";
+pub const LDAP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+pub const MAX_LDAP_RESPONSE_BYTES: usize = 64 * 1024;
+pub const PEER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+/// How far a peer's clock may be off before its signed requests are refused.
+pub const PEER_SIGNATURE_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(60);
+pub const MAX_PEER_RESPONSE_BYTES: usize = 64 * 1024;
+pub const RUNTIME_THREAD_NAME: &str = "slicedbread-worker";
+pub const DEFAULT_MAINTENANCE_RETRY_AFTER: std::time::Duration = std::time::Duration::from_secs(60);
+pub const MAX_MAINTENANCE_REQUEST_BYTES: usize = 1024;
+pub const DEFAULT_DEBUG_LOG_BODY_BYTES: usize = 4 * 1024;
+pub const MAX_DEBUG_LOG_REQUEST_BYTES: usize = 16 * 1024;
+/// Span of the upload histograms summarized by `GET /admin/stats/summary`,
+/// kept as slices that expire one at a time.
+pub const HISTOGRAM_WINDOW: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+pub const DEFAULT_ALERT_ERROR_WINDOW: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+/// Granularity of the error window; requests older than the window are forgotten a slice at a time.
+pub const ALERT_SLICE: std::time::Duration = std::time::Duration::from_secs(10);
+/// Requests needed in the window before the error rate can raise an alert.
+pub const ALERT_MIN_REQUESTS: u64 = 20;
+pub const ALERT_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+pub const ALERT_WEBHOOK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+pub const MAX_ALERT_RESPONSE_BYTES: usize = 64 * 1024;
+pub const HISTOGRAM_SLICE: std::time::Duration = std::time::Duration::from_secs(5 * 60);
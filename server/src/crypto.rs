@@ -0,0 +1,82 @@
+//! The cryptography the server relies on: SHA-256, HMAC-SHA256 and the TLS
+//! provider. They come from ring, or from aws-lc-rs with the `fips` feature,
+//! for deployments that may only ship a FIPS-validated module.
+
+use std::sync::Arc;
+
+use tokio_rustls::rustls::crypto::CryptoProvider;
+
+#[cfg(not(any(feature = "ring", feature = "fips")))]
+compile_error!("enable either the `ring` or the `fips` feature");
+
+#[cfg(feature = "fips")]
+use aws_lc_rs::digest;
+#[cfg(feature = "fips")]
+pub use aws_lc_rs::hmac;
+#[cfg(not(feature = "fips"))]
+use ring::digest;
+#[cfg(not(feature = "fips"))]
+pub use ring::hmac;
+
+/// Whether this build uses the FIPS-validated backend.
+pub const FIPS: bool = cfg!(feature = "fips");
+
+/// Incremental SHA-256.
+#[derive(Clone)]
+pub struct Sha256(digest::Context);
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Self(digest::Context::new(&digest::SHA256))
+    }
+
+    pub fn digest(data: impl AsRef<[u8]>) -> [u8; 32] {
+        let mut hasher = Self::new();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    pub fn update(&mut self, data: impl AsRef<[u8]>) {
+        self.0.update(data.as_ref());
+    }
+
+    pub fn finalize(self) -> [u8; 32] {
+        self.0
+            .finish()
+            .as_ref()
+            .try_into()
+            .expect("SHA-256 digests are 32 bytes")
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Provider for TLS, both for the listener and outgoing connections.
+pub fn tls_provider() -> Arc<CryptoProvider> {
+    #[cfg(feature = "fips")]
+    let provider = tokio_rustls::rustls::crypto::aws_lc_rs::default_provider();
+    #[cfg(not(feature = "fips"))]
+    let provider = tokio_rustls::rustls::crypto::ring::default_provider();
+    Arc::new(provider)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256_matches_known_digests() {
+        assert_eq!(
+            crate::checksum::to_hex(&Sha256::digest(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let mut hasher = Sha256::new();
+        hasher.update(b"a");
+        hasher.update([b'b', b'c']);
+        assert_eq!(hasher.finalize(), Sha256::digest("abc"));
+    }
+}
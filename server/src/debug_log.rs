@@ -0,0 +1,154 @@
+use std::sync::RwLock;
+
+use hyper::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+use crate::constants;
+
+/// Headers whose values are credentials, logged as `[redacted]`.
+const REDACTED_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    constants::HEADER_UPLOAD_POLICY,
+    constants::HEADER_CLUSTER_SIGNATURE,
+];
+
+/// Query parameters whose values are credentials.
+const REDACTED_PARAMS: &[&str] = &["share", "confirmation"];
+
+const REDACTED: &str = "[redacted]";
+
+/// Which requests have their headers and response bodies logged, to
+/// troubleshoot a client integration; set at startup and through
+/// `/admin/debug-log`. A request matches if its file id or tenant is listed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DebugLogFilter {
+    #[serde(default)]
+    pub file_ids: Vec<String>,
+    #[serde(default)]
+    pub tenants: Vec<String>,
+    /// Most bytes of each response body logged; defaults to
+    /// `DEFAULT_DEBUG_LOG_BODY_BYTES`.
+    #[serde(default)]
+    pub max_body_bytes: Option<usize>,
+}
+
+impl DebugLogFilter {
+    fn matches(&self, file_id: Option<&str>, tenant: &str) -> bool {
+        file_id.is_some_and(|file_id| self.file_ids.iter().any(|id| id == file_id))
+            || self.tenants.iter().any(|t| t == tenant)
+    }
+}
+
+/// The filter in force, which the admin API swaps at runtime.
+#[derive(Debug, Default)]
+pub struct DebugLog {
+    filter: RwLock<DebugLogFilter>,
+}
+
+impl DebugLog {
+    pub fn new(filter: DebugLogFilter) -> Self {
+        Self {
+            filter: RwLock::new(filter),
+        }
+    }
+
+    pub fn filter(&self) -> DebugLogFilter {
+        self.filter.read().expect("debug log lock poisoned").clone()
+    }
+
+    pub fn set_filter(&self, filter: DebugLogFilter) {
+        *self.filter.write().expect("debug log lock poisoned") = filter;
+    }
+
+    /// How much of the response body to log for a request, or `None` if the
+    /// request isn't logged.
+    pub fn body_limit(&self, file_id: Option<&str>, tenant: &str) -> Option<usize> {
+        let filter = self.filter.read().expect("debug log lock poisoned");
+        filter.matches(file_id, tenant).then(|| {
+            filter
+                .max_body_bytes
+                .unwrap_or(constants::DEFAULT_DEBUG_LOG_BODY_BYTES)
+        })
+    }
+}
+
+/// Headers as `name: value` lines, with credentials left out.
+pub fn headers(headers: &HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if REDACTED_HEADERS
+                .iter()
+                .any(|redacted| name.as_str().eq_ignore_ascii_case(redacted))
+            {
+                REDACTED
+            } else {
+                value.to_str().unwrap_or("[binary]")
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A query string with credentials left out.
+pub fn query(query: &str) -> String {
+    query
+        .split('&')
+        .map(|param| match param.split_once('=') {
+            Some((name, _)) if REDACTED_PARAMS.contains(&name) => format!("{}={}", name, REDACTED),
+            _ => param.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// The first `max` bytes of a body as text, noting how many more there were.
+pub fn body(body: &[u8], max: usize) -> String {
+    let shown = String::from_utf8_lossy(&body[..body.len().min(max)]).into_owned();
+    match body.len().saturating_sub(max) {
+        0 => shown,
+        more => format!("{}... ({} more bytes)", shown, more),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_listed_file_ids_and_tenants() {
+        let log = DebugLog::default();
+        assert_eq!(log.body_limit(Some("abc"), "acme"), None);
+
+        log.set_filter(DebugLogFilter {
+            file_ids: vec!["abc".to_string()],
+            tenants: vec!["acme".to_string()],
+            max_body_bytes: Some(16),
+        });
+        assert_eq!(log.body_limit(Some("abc"), "other"), Some(16));
+        assert_eq!(log.body_limit(None, "acme"), Some(16));
+        assert_eq!(log.body_limit(Some("xyz"), "other"), None);
+    }
+
+    #[test]
+    fn test_leaves_out_credentials_and_caps_bodies() {
+        let mut map = HeaderMap::new();
+        map.insert("authorization", "Bearer s3cret".parse().unwrap());
+        map.insert("x-upload-policy", "policy.sig".parse().unwrap());
+        map.insert("x-file-id", "abc".parse().unwrap());
+        let logged = headers(&map);
+        assert!(!logged.contains("s3cret") && !logged.contains("policy.sig"));
+        assert!(logged.contains("x-file-id: abc"));
+
+        assert_eq!(
+            query("file_id=abc&share=tok.sig"),
+            "file_id=abc&share=[redacted]"
+        );
+        assert_eq!(body(b"hello", 10), "hello");
+        assert_eq!(body(b"hello world", 5), "hello... (6 more bytes)");
+    }
+}
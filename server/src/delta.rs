@@ -0,0 +1,299 @@
+use std::{collections::HashMap, io, path::Path};
+
+use bytes::{Buf, BufMut, Bytes};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::checksum;
+
+const OP_COPY: u8 = b'C';
+const OP_LITERAL: u8 = b'L';
+
+/// rsync's weak checksum over a window of bytes, which can be slid forward one
+/// byte at a time in constant time.
+#[derive(Debug, Clone, Copy)]
+pub struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    pub fn new(window: &[u8]) -> Self {
+        let len = window.len() as u32;
+        let (a, b) = window
+            .iter()
+            .enumerate()
+            .fold((0u32, 0u32), |(a, b), (i, &x)| {
+                (
+                    a.wrapping_add(x as u32),
+                    b.wrapping_add((len - i as u32).wrapping_mul(x as u32)),
+                )
+            });
+        Self { a, b, len }
+    }
+
+    /// Moves the window one byte forward, dropping `out` and taking in `inp`.
+    pub fn roll(&mut self, out: u8, inp: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(inp as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+
+    pub fn digest(&self) -> u32 {
+        (self.b << 16) | (self.a & 0xffff)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockSignature {
+    pub weak: u32,
+    /// Hex SHA-256 of the block, to confirm weak matches.
+    pub strong: String,
+}
+
+/// Block checksums of a stored file, from which a client works out which parts
+/// of its new version the server already has. The last block may be short.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Signature {
+    pub block_size: usize,
+    pub file_size: u64,
+    pub blocks: Vec<BlockSignature>,
+}
+
+/// Reads the file at `path` block by block, so large files are never held in memory.
+pub async fn signature(path: &Path, block_size: usize) -> io::Result<Signature> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buf = vec![0; block_size];
+    let mut blocks = Vec::new();
+    let mut file_size = 0;
+    loop {
+        let n = read_block(&mut file, &mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        blocks.push(BlockSignature {
+            weak: Rolling::new(&buf[..n]).digest(),
+            strong: checksum::to_hex(&checksum::sha256(&buf[..n])),
+        });
+        file_size += n as u64;
+    }
+    Ok(Signature {
+        block_size,
+        file_size,
+        blocks,
+    })
+}
+
+/// One instruction for rebuilding a file from its previous version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// Reuse block `n` of the previous version.
+    Copy(u64),
+    /// New bytes.
+    Literal(Bytes),
+}
+
+/// Serializes ops as `C` + block index (u64, big endian) or `L` + length
+/// (u32, big endian) + bytes.
+pub fn encode(ops: &[Op]) -> Bytes {
+    let mut out = bytes::BytesMut::new();
+    for op in ops {
+        match op {
+            Op::Copy(block) => {
+                out.put_u8(OP_COPY);
+                out.put_u64(*block);
+            }
+            Op::Literal(data) => {
+                out.put_u8(OP_LITERAL);
+                out.put_u32(data.len() as u32);
+                out.put_slice(data);
+            }
+        }
+    }
+    out.freeze()
+}
+
+pub fn decode(mut body: Bytes) -> Result<Vec<Op>, String> {
+    let mut ops = Vec::new();
+    while body.has_remaining() {
+        match body.get_u8() {
+            OP_COPY if body.remaining() >= 8 => ops.push(Op::Copy(body.get_u64())),
+            OP_LITERAL if body.remaining() >= 4 => {
+                let len = body.get_u32() as usize;
+                if body.remaining() < len {
+                    return Err(format!("Literal of {} bytes is truncated", len));
+                }
+                ops.push(Op::Literal(body.split_to(len)));
+            }
+            OP_COPY | OP_LITERAL => return Err("Truncated delta op".to_string()),
+            tag => return Err(format!("Unknown delta op {:#04x}", tag)),
+        }
+    }
+    Ok(ops)
+}
+
+/// Client side of the protocol: the ops that rebuild `data` from the version
+/// described by `signature`. Only full-size blocks are matched.
+pub fn diff(signature: &Signature, data: &[u8]) -> Vec<Op> {
+    let block_size = signature.block_size;
+    let mut by_weak: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (index, block) in signature.blocks.iter().enumerate() {
+        let full = (index as u64 + 1) * block_size as u64 <= signature.file_size;
+        if full {
+            by_weak.entry(block.weak).or_default().push(index);
+        }
+    }
+
+    let mut ops = Vec::new();
+    let mut literal_start = 0;
+    let mut pos = 0;
+    let mut rolling = (data.len() >= block_size).then(|| Rolling::new(&data[..block_size]));
+    while let Some(window) = rolling.as_mut() {
+        let matched = by_weak.get(&window.digest()).and_then(|candidates| {
+            let strong = checksum::to_hex(&checksum::sha256(&data[pos..pos + block_size]));
+            candidates
+                .iter()
+                .find(|&&index| signature.blocks[index].strong == strong)
+        });
+        if let Some(&index) = matched {
+            if literal_start < pos {
+                ops.push(Op::Literal(Bytes::copy_from_slice(
+                    &data[literal_start..pos],
+                )));
+            }
+            ops.push(Op::Copy(index as u64));
+            pos += block_size;
+            literal_start = pos;
+            rolling = (data.len() - pos >= block_size)
+                .then(|| Rolling::new(&data[pos..pos + block_size]));
+        } else if pos + block_size < data.len() {
+            window.roll(data[pos], data[pos + block_size]);
+            pos += 1;
+        } else {
+            rolling = None;
+        }
+    }
+    if literal_start < data.len() {
+        ops.push(Op::Literal(Bytes::copy_from_slice(&data[literal_start..])));
+    }
+    ops
+}
+
+/// Bytes taken from the previous version and from the delta when applying it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Applied {
+    pub bytes_reused: u64,
+    pub bytes_received: u64,
+}
+
+/// Writes the new version to `out`, passing every piece written to `sink` as
+/// well. A copy of a block past the end of `base` fails with `InvalidData`.
+pub async fn apply(
+    base: &Path,
+    block_size: usize,
+    ops: &[Op],
+    out: &Path,
+    mut sink: impl FnMut(&[u8]),
+) -> io::Result<Applied> {
+    let mut base = tokio::fs::File::open(base).await?;
+    let base_len = base.metadata().await?.len();
+    let mut out = tokio::io::BufWriter::new(tokio::fs::File::create(out).await?);
+    let mut buf = vec![0; block_size];
+    let mut applied = Applied::default();
+    for op in ops {
+        match op {
+            Op::Copy(block) => {
+                let offset = block
+                    .checked_mul(block_size as u64)
+                    .filter(|offset| *offset < base_len)
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Block {} is past the end of the stored file", block),
+                        )
+                    })?;
+                base.seek(io::SeekFrom::Start(offset)).await?;
+                let n = read_block(&mut base, &mut buf).await?;
+                out.write_all(&buf[..n]).await?;
+                sink(&buf[..n]);
+                applied.bytes_reused += n as u64;
+            }
+            Op::Literal(data) => {
+                out.write_all(data).await?;
+                sink(data);
+                applied.bytes_received += data.len() as u64;
+            }
+        }
+    }
+    out.flush().await?;
+    Ok(applied)
+}
+
+/// Fills `buf` unless the file ends first, returning the number of bytes read.
+async fn read_block(file: &mut tokio::fs::File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..]).await? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_rolling_checksum_matches_recomputation() {
+        let data = b"the quick brown fox jumps over the lazy dog";
+        let mut rolling = Rolling::new(&data[..8]);
+        for i in 0..data.len() - 8 {
+            rolling.roll(data[i], data[i + 8]);
+            assert_eq!(rolling.digest(), Rolling::new(&data[i + 1..i + 9]).digest());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delta_rebuilds_modified_file() {
+        let temp_dir = TempDir::new("delta").unwrap();
+        let base_path = temp_dir.path().join("base");
+        let out_path = temp_dir.path().join("out");
+        let base: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        tokio::fs::write(&base_path, &base).await.unwrap();
+
+        let mut updated = base.clone();
+        updated.splice(3000..3010, b"inserted bytes".iter().copied());
+        updated[9000] ^= 0xff;
+
+        let signature = signature(&base_path, 512).await.unwrap();
+        assert_eq!(signature.blocks.len(), 20);
+        let ops = decode(encode(&diff(&signature, &updated))).unwrap();
+
+        let mut sunk = Vec::new();
+        let applied = apply(&base_path, 512, &ops, &out_path, |piece| {
+            sunk.extend_from_slice(piece)
+        })
+        .await
+        .unwrap();
+        assert_eq!(tokio::fs::read(&out_path).await.unwrap(), updated);
+        assert_eq!(sunk, updated);
+        assert!(applied.bytes_received < 2048, "{:?}", applied);
+        assert_eq!(
+            applied.bytes_reused + applied.bytes_received,
+            updated.len() as u64
+        );
+
+        let err = apply(&base_path, 512, &[Op::Copy(20)], &out_path, |_| {})
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(decode(Bytes::from_static(b"L\0\0\0\x09abc")).is_err());
+    }
+}
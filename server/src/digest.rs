@@ -0,0 +1,365 @@
+use std::future::Future;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::Bytes;
+use hyper::{HeaderMap, header::HeaderValue};
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::{checksum::ChunkDigest, constants, crypto::Sha256, error::SliceBreadServerError};
+
+/// Bodies smaller than this are hashed inline, since handing them to the
+/// blocking pool costs more than hashing them.
+const OFFLOAD_MIN_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Algorithm {
+    Sha256,
+    Crc32c,
+    Blake3,
+    /// 64-bit XXH3, big-endian.
+    Xxh3,
+}
+
+impl Algorithm {
+    /// In order of preference. SHA-256 comes first because it is computed for
+    /// every chunk anyway; the others cost an extra pass.
+    pub const SUPPORTED: [Self; 4] = [Self::Sha256, Self::Blake3, Self::Xxh3, Self::Crc32c];
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::SUPPORTED
+            .into_iter()
+            .find(|algorithm| algorithm.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha-256",
+            Self::Crc32c => "crc32c",
+            Self::Blake3 => "blake3",
+            Self::Xxh3 => "xxh3",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpectedDigest {
+    pub algorithm: Algorithm,
+    pub value: Vec<u8>,
+}
+
+/// Digests of the chunk body, from `Content-Digest` (RFC 9530) or `Digest` (RFC 3230).
+pub fn content_digests(headers: &HeaderMap) -> Result<Vec<ExpectedDigest>, SliceBreadServerError> {
+    let mut digests = parse_header(headers, constants::HEADER_CONTENT_DIGEST)?;
+    digests.extend(parse_header(headers, constants::HEADER_DIGEST)?);
+    Ok(digests)
+}
+
+/// Digests of the whole file, from `Repr-Digest` (RFC 9530).
+pub fn repr_digests(headers: &HeaderMap) -> Result<Vec<ExpectedDigest>, SliceBreadServerError> {
+    parse_header(headers, constants::HEADER_REPR_DIGEST)
+}
+
+/// A `Content-Digest` or `Repr-Digest` value in the RFC 9530 form.
+pub fn format(algorithm: Algorithm, value: &[u8]) -> String {
+    format!("{}=:{}:", algorithm.name(), STANDARD.encode(value))
+}
+
+/// Tells clients which algorithms they can send, via the RFC 9530
+/// `Want-Content-Digest` and `Want-Repr-Digest` fields.
+pub fn advertise(headers: &mut HeaderMap) {
+    let preferences = Algorithm::SUPPORTED
+        .iter()
+        .enumerate()
+        .map(|(rank, algorithm)| format!("{}={}", algorithm.name(), 10 - rank))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let value =
+        HeaderValue::from_str(&preferences).expect("algorithm names are valid header values");
+    headers.insert(constants::HEADER_WANT_CONTENT_DIGEST, value.clone());
+    headers.insert(constants::HEADER_WANT_REPR_DIGEST, value);
+}
+
+/// Accepts both the RFC 9530 form `sha-256=:<base64>:` and the RFC 3230 form
+/// `SHA-256=<base64>`. Algorithms we don't implement are ignored, as both RFCs allow.
+fn parse_header(
+    headers: &HeaderMap,
+    key: &str,
+) -> Result<Vec<ExpectedDigest>, SliceBreadServerError> {
+    let Some(value) = headers.get(key) else {
+        return Ok(Vec::new());
+    };
+    let invalid = || SliceBreadServerError::InvalidHeader(format!("Invalid header value: {}", key));
+    let value = value.to_str().map_err(|_| invalid())?;
+
+    let mut digests = Vec::new();
+    for member in value.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+        let (name, encoded) = member.split_once('=').ok_or_else(invalid)?;
+        let Some(algorithm) = Algorithm::from_name(name.trim()) else {
+            continue;
+        };
+        let encoded = encoded.trim();
+        let encoded = encoded
+            .strip_prefix(':')
+            .and_then(|e| e.strip_suffix(':'))
+            .unwrap_or(encoded);
+        let value = STANDARD.decode(encoded).map_err(|_| invalid())?;
+        digests.push(ExpectedDigest { algorithm, value });
+    }
+    Ok(digests)
+}
+
+/// Runs CPU-bound hashing on the blocking pool, so that checksumming a big
+/// chunk doesn't stall other requests on the same reactor thread. The work
+/// starts right away, before the returned future is polled.
+pub fn offload<T: Send + 'static>(
+    hash: impl FnOnce() -> T + Send + 'static,
+) -> impl Future<Output = Result<T, SliceBreadServerError>> {
+    let handle = tokio::task::spawn_blocking(hash);
+    async move {
+        handle.await.map_err(|e| {
+            SliceBreadServerError::InternalServerError(format!("Hashing task failed: {}", e))
+        })
+    }
+}
+
+#[derive(Clone)]
+enum State {
+    Crc32c(u32),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<Xxh3>),
+}
+
+/// Computes SHA-256, which chunks are identified by, plus whichever other
+/// algorithms the client sent digests for, in one pass.
+#[derive(Clone)]
+pub struct Hasher {
+    sha256: Sha256,
+    others: Vec<(Algorithm, State)>,
+}
+
+pub struct Computed {
+    pub sha256: ChunkDigest,
+    others: Vec<(Algorithm, Vec<u8>)>,
+}
+
+impl Hasher {
+    pub fn new(expected: &[ExpectedDigest]) -> Self {
+        let mut others: Vec<(Algorithm, State)> = Vec::new();
+        for digest in expected {
+            let state = match digest.algorithm {
+                Algorithm::Sha256 => continue,
+                Algorithm::Crc32c => State::Crc32c(0),
+                Algorithm::Blake3 => State::Blake3(Box::default()),
+                Algorithm::Xxh3 => State::Xxh3(Box::default()),
+            };
+            if !others
+                .iter()
+                .any(|(algorithm, _)| *algorithm == digest.algorithm)
+            {
+                others.push((digest.algorithm, state));
+            }
+        }
+        Self {
+            sha256: Sha256::new(),
+            others,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.sha256.update(data);
+        for (_, state) in &mut self.others {
+            match state {
+                State::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, data),
+                State::Blake3(hasher) => {
+                    hasher.update(data);
+                }
+                State::Xxh3(hasher) => hasher.update(data),
+            }
+        }
+    }
+
+    /// `update` on the blocking pool. Also returns the SHA-256 of `data` on
+    /// its own, which assembly needs for the chunk's Merkle leaf.
+    pub fn update_offloaded(
+        mut self,
+        data: Bytes,
+    ) -> impl Future<Output = Result<(Self, ChunkDigest), SliceBreadServerError>> {
+        offload(move || {
+            self.update(&data);
+            let leaf = Sha256::digest(&data);
+            (self, leaf)
+        })
+    }
+
+    pub fn finalize(self) -> Computed {
+        Computed {
+            sha256: self.sha256.finalize(),
+            others: self
+                .others
+                .into_iter()
+                .map(|(algorithm, state)| {
+                    let value = match state {
+                        State::Crc32c(crc) => crc.to_be_bytes().to_vec(),
+                        State::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+                        State::Xxh3(hasher) => hasher.digest().to_be_bytes().to_vec(),
+                    };
+                    (algorithm, value)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Computed {
+    /// Hashes `data` with SHA-256 and the algorithms of `expected`.
+    pub fn of(data: &[u8], expected: &[ExpectedDigest]) -> Self {
+        let mut hasher = Hasher::new(expected);
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    /// Like `of`, but large bodies are hashed off the reactor.
+    pub async fn of_body(
+        data: &Bytes,
+        expected: &[ExpectedDigest],
+    ) -> Result<Self, SliceBreadServerError> {
+        if data.len() < OFFLOAD_MIN_BYTES {
+            return Ok(Self::of(data, expected));
+        }
+        let (data, expected) = (data.clone(), expected.to_vec());
+        offload(move || Self::of(&data, &expected)).await
+    }
+
+    fn value(&self, algorithm: Algorithm) -> Option<&[u8]> {
+        match algorithm {
+            Algorithm::Sha256 => Some(&self.sha256),
+            _ => self
+                .others
+                .iter()
+                .find(|(computed, _)| *computed == algorithm)
+                .map(|(_, value)| value.as_slice()),
+        }
+    }
+
+    pub fn verify(
+        &self,
+        expected: &[ExpectedDigest],
+        what: &str,
+    ) -> Result<(), SliceBreadServerError> {
+        for digest in expected {
+            if self.value(digest.algorithm) != Some(digest.value.as_slice()) {
+                return Err(SliceBreadServerError::DigestMismatch(format!(
+                    "{} does not match {} digest",
+                    what,
+                    digest.algorithm.name()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(key: &str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            hyper::header::HeaderName::from_bytes(key.as_bytes()).unwrap(),
+            value.parse().unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_parses_rfc9530_and_rfc3230_forms() {
+        let sha = STANDARD.encode(Computed::of(b"hello", &[]).sha256);
+        let crc = STANDARD.encode(crc32c::crc32c(b"hello").to_be_bytes());
+
+        let modern = content_digests(&headers(
+            "Content-Digest",
+            &format!("sha-256=:{}:, unknown=:AAAA:, crc32c=:{}:", sha, crc),
+        ))
+        .unwrap();
+        assert_eq!(modern.len(), 2);
+        Computed::of(b"hello", &modern)
+            .verify(&modern, "Chunk")
+            .unwrap();
+
+        let legacy = content_digests(&headers("Digest", &format!("SHA-256={}", sha))).unwrap();
+        assert_eq!(legacy[0].algorithm, Algorithm::Sha256);
+        Computed::of(b"hello", &legacy)
+            .verify(&legacy, "Chunk")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_mismatch_and_malformed() {
+        let sha = STANDARD.encode(Computed::of(b"hello", &[]).sha256);
+        let expected =
+            repr_digests(&headers("Repr-Digest", &format!("sha-256=:{}:", sha))).unwrap();
+        assert!(matches!(
+            Computed::of(b"other", &expected).verify(&expected, "File"),
+            Err(SliceBreadServerError::DigestMismatch(_))
+        ));
+
+        assert!(matches!(
+            content_digests(&headers("Content-Digest", "sha-256=:not base64!:")),
+            Err(SliceBreadServerError::InvalidHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_fast_algorithms() {
+        let data = b"The quick brown fox jumps over the lazy dog";
+        let blake3 = STANDARD.encode(blake3::hash(data).as_bytes());
+        let xxh3 = STANDARD.encode(xxhash_rust::xxh3::xxh3_64(data).to_be_bytes());
+        let expected = content_digests(&headers(
+            "Content-Digest",
+            &format!("BLAKE3=:{}:, xxh3=:{}:", blake3, xxh3),
+        ))
+        .unwrap();
+        assert_eq!(
+            expected.iter().map(|d| d.algorithm).collect::<Vec<_>>(),
+            [Algorithm::Blake3, Algorithm::Xxh3]
+        );
+
+        let mut hasher = Hasher::new(&expected);
+        for piece in data.chunks(5) {
+            hasher.update(piece);
+        }
+        hasher.finalize().verify(&expected, "Chunk").unwrap();
+        assert!(matches!(
+            Computed::of(b"tampered", &expected).verify(&expected, "Chunk"),
+            Err(SliceBreadServerError::DigestMismatch(_))
+        ));
+
+        let mut advertised = HeaderMap::new();
+        advertise(&mut advertised);
+        assert_eq!(
+            advertised["Want-Content-Digest"],
+            "sha-256=10, blake3=9, xxh3=8, crc32c=7"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_offloaded_hashing_matches_inline() {
+        let data = Bytes::from(vec![7; OFFLOAD_MIN_BYTES * 2]);
+        let blake3 = STANDARD.encode(blake3::hash(&data).as_bytes());
+        let expected =
+            content_digests(&headers("Content-Digest", &format!("blake3=:{}:", blake3))).unwrap();
+
+        let offloaded = Computed::of_body(&data, &expected).await.unwrap();
+        offloaded.verify(&expected, "Chunk").unwrap();
+        assert_eq!(offloaded.sha256, Computed::of(&data, &[]).sha256);
+
+        let (hasher, leaf) = Hasher::new(&expected)
+            .update_offloaded(data.clone())
+            .await
+            .unwrap();
+        assert_eq!(leaf, offloaded.sha256);
+        hasher.finalize().verify(&expected, "File").unwrap();
+    }
+}
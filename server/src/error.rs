@@ -0,0 +1,447 @@
+use std::time::Duration;
+
+use hyper::{Response, StatusCode, header};
+use serde::Serialize;
+
+use crate::{body::ResponseBody, constants, protocol};
+
+#[derive(Debug)]
+pub enum SliceBreadServerError {
+    InternalServerError(String),
+    BadRequest(String),
+    MissingHeader(String),
+    InvalidHeader(String),
+    ChunkOutOfRange {
+        chunk_index: usize,
+        total_chunks: usize,
+    },
+    MissingChunk(usize),
+    /// A chunk other than the last of its upload is outside the allowed size.
+    ChunkSizeOutOfRange {
+        chunk_index: usize,
+        size: u64,
+        min: u64,
+        max: Option<u64>,
+    },
+    RangeOutOfBounds {
+        offset: u64,
+        end: u64,
+        file_size: u64,
+    },
+    DigestMismatch(String),
+    LengthMismatch {
+        declared: u64,
+        received: u64,
+    },
+    LengthRequired,
+    PayloadTooLarge(String),
+    /// No valid credentials; `challenge` is sent as `WWW-Authenticate`.
+    Unauthorized {
+        message: String,
+        challenge: &'static str,
+    },
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    /// The upload ran past its maximum duration and was discarded.
+    Expired(String),
+    /// The tenant or client already has as many uploads in progress as allowed.
+    TooManySessions(String),
+    /// None of the protocol versions the client asked for is supported.
+    UnsupportedVersion {
+        requested: Vec<u32>,
+    },
+    IdempotencyKeyReused(String),
+    /// Storing the upload would exceed the tenant's quota or fill the disk.
+    /// `limit` and `used` are in bytes.
+    InsufficientStorage {
+        reason: String,
+        limit: u64,
+        used: u64,
+    },
+    ServiceUnavailable(String),
+    /// Load shedding; the client should retry after `retry_after`.
+    Overloaded {
+        reason: String,
+        retry_after: Duration,
+    },
+    /// The server is in read-only maintenance mode; writes may be retried
+    /// after `retry_after`.
+    ReadOnly {
+        retry_after: Duration,
+    },
+    IoError(std::io::Error),
+    HyperError(hyper::http::Error),
+}
+
+/// JSON body sent for every error response, e.g.
+/// `{"code":"missing_chunk","status":400,"message":"...","details":{"chunk_index":1}}`.
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub status: u16,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+impl std::error::Error for SliceBreadServerError {}
+
+impl std::fmt::Display for SliceBreadServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InternalServerError(msg) => write!(f, "Internal Server Error: {}", msg),
+            Self::BadRequest(msg) => write!(f, "Bad Request: {}", msg),
+            Self::MissingHeader(key) => write!(f, "Bad Request: Missing header: {}", key),
+            Self::InvalidHeader(msg) => write!(f, "Bad Request: {}", msg),
+            Self::ChunkOutOfRange {
+                chunk_index,
+                total_chunks,
+            } => write!(
+                f,
+                "Bad Request: Invalid {}: {} >= {}: {}",
+                constants::HEADER_CHUNK_INDEX,
+                chunk_index,
+                constants::HEADER_TOTAL_CHUNKS,
+                total_chunks
+            ),
+            Self::MissingChunk(index) => write!(f, "Bad Request: Missing chunk: {}", index),
+            Self::ChunkSizeOutOfRange {
+                chunk_index,
+                size,
+                min,
+                max,
+            } => match max {
+                Some(max) => write!(
+                    f,
+                    "Bad Request: Chunk {} is {} bytes; chunks before the last must be {} to {} bytes",
+                    chunk_index, size, min, max
+                ),
+                None => write!(
+                    f,
+                    "Bad Request: Chunk {} is {} bytes; chunks before the last must be at least {} bytes",
+                    chunk_index, size, min
+                ),
+            },
+            Self::RangeOutOfBounds {
+                offset,
+                end,
+                file_size,
+            } => write!(
+                f,
+                "Bad Request: Range {}..{} exceeds {}: {}",
+                offset,
+                end,
+                constants::HEADER_FILE_SIZE,
+                file_size
+            ),
+            Self::DigestMismatch(msg) => write!(f, "Bad Request: {}", msg),
+            Self::LengthMismatch { declared, received } => write!(
+                f,
+                "Bad Request: Content-Length {} but received {} bytes",
+                declared, received
+            ),
+            Self::LengthRequired => write!(f, "Length Required: Content-Length header is required"),
+            Self::PayloadTooLarge(msg) => write!(f, "Payload Too Large: {}", msg),
+            Self::Unauthorized { message, .. } => write!(f, "Unauthorized: {}", message),
+            Self::Forbidden(msg) => write!(f, "Forbidden: {}", msg),
+            Self::NotFound(msg) => write!(f, "Not Found: {}", msg),
+            Self::Conflict(msg) => write!(f, "Conflict: {}", msg),
+            Self::Expired(msg) => write!(f, "Gone: {}", msg),
+            Self::TooManySessions(msg) => write!(f, "Too Many Requests: {}", msg),
+            Self::UnsupportedVersion { requested } => write!(
+                f,
+                "Bad Request: Unsupported protocol version {:?}, supported: {:?}",
+                requested,
+                protocol::SUPPORTED_VERSIONS
+            ),
+            Self::IdempotencyKeyReused(msg) => write!(f, "Unprocessable Entity: {}", msg),
+            Self::InsufficientStorage { reason, .. } => {
+                write!(f, "Insufficient Storage: {}", reason)
+            }
+            Self::ServiceUnavailable(msg) => write!(f, "Service Unavailable: {}", msg),
+            Self::Overloaded { reason, .. } => write!(f, "Service Unavailable: {}", reason),
+            Self::ReadOnly { .. } => {
+                write!(
+                    f,
+                    "Service Unavailable: Server is read-only for maintenance"
+                )
+            }
+            Self::IoError(err) => write!(f, "IO Error: {}", err),
+            Self::HyperError(err) => write!(f, "Hyper Error: {}", err),
+        }
+    }
+}
+
+impl SliceBreadServerError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_)
+            | Self::MissingHeader(_)
+            | Self::InvalidHeader(_)
+            | Self::ChunkOutOfRange { .. }
+            | Self::MissingChunk(_)
+            | Self::ChunkSizeOutOfRange { .. }
+            | Self::RangeOutOfBounds { .. }
+            | Self::DigestMismatch(_)
+            | Self::LengthMismatch { .. }
+            | Self::UnsupportedVersion { .. } => StatusCode::BAD_REQUEST,
+            Self::LengthRequired => StatusCode::LENGTH_REQUIRED,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Unauthorized { .. } => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::Expired(_) => StatusCode::GONE,
+            Self::TooManySessions(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::IdempotencyKeyReused(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::InsufficientStorage { .. } => StatusCode::INSUFFICIENT_STORAGE,
+            Self::ServiceUnavailable(_) | Self::Overloaded { .. } | Self::ReadOnly { .. } => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            Self::InternalServerError(_) | Self::IoError(_) | Self::HyperError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// Stable, machine-readable identifier clients can branch on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InternalServerError(_) | Self::HyperError(_) => "internal_error",
+            Self::BadRequest(_) => "bad_request",
+            Self::MissingHeader(_) => "missing_header",
+            Self::InvalidHeader(_) => "invalid_header",
+            Self::ChunkOutOfRange { .. } => "chunk_out_of_range",
+            Self::MissingChunk(_) => "missing_chunk",
+            Self::ChunkSizeOutOfRange { .. } => "chunk_size_out_of_range",
+            Self::RangeOutOfBounds { .. } => "range_out_of_bounds",
+            Self::DigestMismatch(_) => "digest_mismatch",
+            Self::LengthMismatch { .. } => "length_mismatch",
+            Self::LengthRequired => "length_required",
+            Self::PayloadTooLarge(_) => "payload_too_large",
+            Self::Unauthorized { .. } => "unauthorized",
+            Self::Forbidden(_) => "forbidden",
+            Self::NotFound(_) => "not_found",
+            Self::Conflict(_) => "conflict",
+            Self::Expired(_) => "upload_expired",
+            Self::TooManySessions(_) => "too_many_sessions",
+            Self::UnsupportedVersion { .. } => "unsupported_version",
+            Self::IdempotencyKeyReused(_) => "idempotency_key_reused",
+            Self::InsufficientStorage { .. } => "insufficient_storage",
+            Self::ServiceUnavailable(_) => "service_unavailable",
+            Self::Overloaded { .. } => "overloaded",
+            Self::ReadOnly { .. } => "read_only",
+            Self::IoError(_) => "io_error",
+        }
+    }
+
+    fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            Self::MissingHeader(key) => Some(serde_json::json!({ "header": key })),
+            Self::ChunkOutOfRange {
+                chunk_index,
+                total_chunks,
+            } => Some(serde_json::json!({
+                "chunk_index": chunk_index,
+                "total_chunks": total_chunks,
+            })),
+            Self::MissingChunk(index) => Some(serde_json::json!({ "chunk_index": index })),
+            Self::ChunkSizeOutOfRange {
+                chunk_index,
+                size,
+                min,
+                max,
+            } => Some(serde_json::json!({
+                "chunk_index": chunk_index,
+                "size": size,
+                "min_chunk_bytes": min,
+                "max_chunk_bytes": max,
+            })),
+            Self::RangeOutOfBounds {
+                offset,
+                end,
+                file_size,
+            } => Some(serde_json::json!({
+                "offset": offset,
+                "end": end,
+                "file_size": file_size,
+            })),
+            Self::UnsupportedVersion { requested } => Some(serde_json::json!({
+                "requested": requested,
+                "supported": protocol::SUPPORTED_VERSIONS,
+            })),
+            Self::LengthMismatch { declared, received } => Some(serde_json::json!({
+                "declared": declared,
+                "received": received,
+            })),
+            Self::InsufficientStorage {
+                reason,
+                limit,
+                used,
+            } => Some(serde_json::json!({
+                "reason": reason,
+                "limit": limit,
+                "used": used,
+                "remaining": limit.saturating_sub(*used),
+            })),
+            Self::Overloaded {
+                reason,
+                retry_after,
+            } => Some(serde_json::json!({
+                "reason": reason,
+                "retry_after": retry_after_secs(*retry_after),
+            })),
+            Self::ReadOnly { retry_after } => Some(serde_json::json!({
+                "retry_after": retry_after_secs(*retry_after),
+            })),
+            _ => None,
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code(),
+            status: self.status_code().as_u16(),
+            message: self.to_string(),
+            details: self.details(),
+        }
+    }
+
+    pub fn into_response(self) -> Response<ResponseBody> {
+        let body = serde_json::to_string(&self.body()).unwrap_or_else(|_| self.to_string());
+        let mut response = Response::new(ResponseBody::from(body));
+        *response.status_mut() = self.status_code();
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("application/json"),
+        );
+        match self {
+            Self::Overloaded { retry_after, .. } | Self::ReadOnly { retry_after } => {
+                response
+                    .headers_mut()
+                    .insert(header::RETRY_AFTER, retry_after_secs(retry_after).into());
+            }
+            Self::Unauthorized { challenge, .. } => {
+                response.headers_mut().insert(
+                    header::WWW_AUTHENTICATE,
+                    header::HeaderValue::from_static(challenge),
+                );
+            }
+            Self::InsufficientStorage { limit, used, .. } => {
+                let headers = response.headers_mut();
+                headers.insert(constants::HEADER_QUOTA_LIMIT, limit.into());
+                headers.insert(constants::HEADER_QUOTA_USED, used.into());
+                headers.insert(
+                    constants::HEADER_QUOTA_REMAINING,
+                    limit.saturating_sub(used).into(),
+                );
+            }
+            // Lists what the client can retry with.
+            Self::UnsupportedVersion { .. } => {
+                let supported = protocol::SUPPORTED_VERSIONS
+                    .iter()
+                    .map(u32::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                if let Ok(supported) = header::HeaderValue::from_str(&supported) {
+                    response
+                        .headers_mut()
+                        .insert(constants::HEADER_PROTOCOL_VERSION, supported);
+                }
+            }
+            _ => {}
+        }
+        response
+    }
+}
+
+/// `Retry-After` takes whole seconds; round up so clients never retry early.
+fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_millis().div_ceil(1000) as u64
+}
+
+impl From<std::io::Error> for SliceBreadServerError {
+    fn from(value: std::io::Error) -> Self {
+        tracing::error!(%value, "Internal server error during upload");
+        SliceBreadServerError::IoError(value)
+    }
+}
+
+impl From<hyper::http::Error> for SliceBreadServerError {
+    fn from(value: hyper::http::Error) -> Self {
+        tracing::error!(%value, "Internal server error during upload");
+        SliceBreadServerError::HyperError(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_response_is_json_with_code() {
+        let response = SliceBreadServerError::ChunkOutOfRange {
+            chunk_index: 3,
+            total_chunks: 3,
+        }
+        .into_response();
+
+        assert_eq!(response.status(), 400);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["code"], "chunk_out_of_range");
+        assert_eq!(body["status"], 400);
+        assert_eq!(
+            body["message"],
+            "Bad Request: Invalid X-Chunk-Index: 3 >= X-Total-Chunks: 3"
+        );
+        assert_eq!(body["details"]["chunk_index"], 3);
+        assert_eq!(body["details"]["total_chunks"], 3);
+    }
+
+    #[test]
+    fn test_details_omitted_when_absent() {
+        let response = SliceBreadServerError::Conflict("mismatch".to_string()).into_response();
+
+        assert_eq!(response.status(), 409);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["code"], "conflict");
+        assert!(body.get("details").is_none());
+    }
+
+    #[test]
+    fn test_overloaded_sets_retry_after() {
+        let response = SliceBreadServerError::Overloaded {
+            reason: "Too many uploads in flight".to_string(),
+            retry_after: Duration::from_millis(1500),
+        }
+        .into_response();
+
+        assert_eq!(response.status(), 503);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["code"], "overloaded");
+        assert_eq!(body["details"]["retry_after"], 2);
+        assert_eq!(body["details"]["reason"], "Too many uploads in flight");
+    }
+
+    #[test]
+    fn test_insufficient_storage_reports_quota() {
+        let response = SliceBreadServerError::InsufficientStorage {
+            reason: "Tenant quota exceeded".to_string(),
+            limit: 100,
+            used: 80,
+        }
+        .into_response();
+
+        assert_eq!(response.status(), 507);
+        assert_eq!(response.headers()[constants::HEADER_QUOTA_LIMIT], "100");
+        assert_eq!(response.headers()[constants::HEADER_QUOTA_USED], "80");
+        assert_eq!(response.headers()[constants::HEADER_QUOTA_REMAINING], "20");
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["code"], "insufficient_storage");
+        assert_eq!(body["details"]["remaining"], 20);
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{error::SliceBreadServerError, output};
+
+/// Caps on what one archive may expand to, against zip bombs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtractLimits {
+    /// Total uncompressed bytes across all entries.
+    pub max_bytes: u64,
+    pub max_entries: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveKind {
+    /// Kind of archive and the file name without its archive extension, which
+    /// names the directory it is unpacked into and so must be a safe path
+    /// segment: `..zip` is not an archive.
+    pub fn detect(file_name: &str) -> Option<(Self, &str)> {
+        let lower = file_name.to_ascii_lowercase();
+        [
+            (".zip", Self::Zip),
+            (".tar.gz", Self::TarGz),
+            (".tgz", Self::TarGz),
+        ]
+        .into_iter()
+        .find(|(ext, _)| lower.ends_with(ext))
+        .map(|(ext, kind)| (kind, &file_name[..file_name.len() - ext.len()]))
+        .filter(|(_, stem)| output::is_safe_segment(stem))
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Extracted {
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+/// Unpacks `archive` into `dest`. Entries that would land outside `dest` are
+/// rejected and links are skipped. On error whatever was extracted is removed.
+pub async fn extract(
+    archive: PathBuf,
+    dest: PathBuf,
+    kind: ArchiveKind,
+    limits: ExtractLimits,
+) -> Result<Extracted, SliceBreadServerError> {
+    tokio::task::spawn_blocking(move || {
+        let result = match kind {
+            ArchiveKind::Zip => extract_zip(&archive, &dest, limits),
+            ArchiveKind::TarGz => extract_tar_gz(&archive, &dest, limits),
+        };
+        if result.is_err() {
+            let _ = std::fs::remove_dir_all(&dest);
+        }
+        result
+    })
+    .await
+    .map_err(|e| SliceBreadServerError::InternalServerError(e.to_string()))?
+}
+
+fn extract_zip(
+    archive: &Path,
+    dest: &Path,
+    limits: ExtractLimits,
+) -> Result<Extracted, SliceBreadServerError> {
+    let mut zip = zip::ZipArchive::new(File::open(archive)?).map_err(invalid)?;
+    let mut extracted = Extracted::default();
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).map_err(invalid)?;
+        if entry.is_symlink() {
+            continue;
+        }
+        let path = entry_path(dest, Path::new(entry.name()))?;
+        extracted.add_entry(limits)?;
+        if entry.is_dir() {
+            std::fs::create_dir_all(&path)?;
+        } else {
+            write_entry(&mut entry, &path, &mut extracted, limits)?;
+        }
+    }
+    Ok(extracted)
+}
+
+fn extract_tar_gz(
+    archive: &Path,
+    dest: &Path,
+    limits: ExtractLimits,
+) -> Result<Extracted, SliceBreadServerError> {
+    let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(File::open(archive)?));
+    let mut extracted = Extracted::default();
+    for entry in tar.entries().map_err(invalid)? {
+        let mut entry = entry.map_err(invalid)?;
+        let entry_type = entry.header().entry_type();
+        if !entry_type.is_file() && !entry_type.is_dir() {
+            continue;
+        }
+        let path = entry_path(dest, &entry.path().map_err(invalid)?)?;
+        extracted.add_entry(limits)?;
+        if entry_type.is_dir() {
+            std::fs::create_dir_all(&path)?;
+        } else {
+            write_entry(&mut entry, &path, &mut extracted, limits)?;
+        }
+    }
+    Ok(extracted)
+}
+
+impl Extracted {
+    fn add_entry(&mut self, limits: ExtractLimits) -> Result<(), SliceBreadServerError> {
+        self.entries += 1;
+        if self.entries > limits.max_entries {
+            return Err(SliceBreadServerError::PayloadTooLarge(format!(
+                "Archive has more than {} entries",
+                limits.max_entries
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Copies an entry out, trusting the bytes actually read rather than the size
+/// the archive declares for it.
+fn write_entry(
+    entry: &mut impl Read,
+    path: &Path,
+    extracted: &mut Extracted,
+    limits: ExtractLimits,
+) -> Result<(), SliceBreadServerError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let budget = limits.max_bytes - extracted.bytes;
+    let mut file = File::create(path)?;
+    let copied = std::io::copy(&mut entry.take(budget + 1), &mut file)?;
+    file.flush()?;
+    if copied > budget {
+        return Err(SliceBreadServerError::PayloadTooLarge(format!(
+            "Archive expands to more than {} bytes",
+            limits.max_bytes
+        )));
+    }
+    extracted.bytes += copied;
+    Ok(())
+}
+
+/// Where an entry goes under `dest`, refusing absolute paths and `..` (zip slip).
+fn entry_path(dest: &Path, name: &Path) -> Result<PathBuf, SliceBreadServerError> {
+    let mut path = dest.to_path_buf();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => {
+                return Err(SliceBreadServerError::BadRequest(format!(
+                    "Archive entry escapes the extraction directory: {}",
+                    name.display()
+                )));
+            }
+        }
+    }
+    Ok(path)
+}
+
+fn invalid(err: impl std::fmt::Display) -> SliceBreadServerError {
+    SliceBreadServerError::BadRequest(format!("Invalid archive: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    const LIMITS: ExtractLimits = ExtractLimits {
+        max_bytes: 1024,
+        max_entries: 10,
+    };
+
+    fn tar_gz(path: &Path, entries: &[(&str, &[u8])]) {
+        let gz = flate2::write::GzEncoder::new(
+            File::create(path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut tar = tar::Builder::new(gz);
+        for (name, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            // set_path refuses `..`, so the name is written raw to build a malicious archive.
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_cksum();
+            tar.append(&header, *data).unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_extracts_archives_within_limits() {
+        let temp_dir = TempDir::new("extract_test").unwrap();
+        let archive = temp_dir.path().join("site.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&archive).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        zip.add_directory("docs/", options).unwrap();
+        zip.start_file("docs/index.html", options).unwrap();
+        zip.write_all(b"<h1>hi</h1>").unwrap();
+        zip.finish().unwrap();
+
+        let dest = temp_dir.path().join("site");
+        let extracted = extract(archive, dest.clone(), ArchiveKind::Zip, LIMITS)
+            .await
+            .unwrap();
+        assert_eq!(
+            extracted,
+            Extracted {
+                entries: 2,
+                bytes: 11
+            }
+        );
+        assert_eq!(
+            std::fs::read(dest.join("docs/index.html")).unwrap(),
+            b"<h1>hi</h1>"
+        );
+
+        let archive = temp_dir.path().join("big.tar.gz");
+        tar_gz(&archive, &[("a", &[0; 1000]), ("b", &[0; 100])]);
+        let dest = temp_dir.path().join("big");
+        let err = extract(archive, dest.clone(), ArchiveKind::TarGz, LIMITS)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::PayloadTooLarge(_)));
+        assert!(!dest.exists());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_entries_outside_the_destination() {
+        let temp_dir = TempDir::new("extract_test").unwrap();
+        let archive = temp_dir.path().join("evil.tar.gz");
+        tar_gz(&archive, &[("ok.txt", b"fine"), ("../evil.txt", b"pwned")]);
+
+        let dest = temp_dir.path().join("evil");
+        let err = extract(archive, dest, ArchiveKind::TarGz, LIMITS)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::BadRequest(_)));
+        assert!(!temp_dir.path().join("evil.txt").exists());
+
+        assert_eq!(
+            ArchiveKind::detect("Site.TGZ"),
+            Some((ArchiveKind::TarGz, "Site"))
+        );
+        assert_eq!(ArchiveKind::detect(".zip"), None);
+        assert_eq!(ArchiveKind::detect("..zip"), None);
+        assert_eq!(ArchiveKind::detect("...zip"), None);
+        assert_eq!(ArchiveKind::detect("...tar.gz"), None);
+        assert_eq!(ArchiveKind::detect("notes.txt"), None);
+    }
+}
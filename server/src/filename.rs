@@ -0,0 +1,145 @@
+use hyper::header::HeaderValue;
+
+use crate::{constants, error::SliceBreadServerError};
+
+const MAX_FILE_NAME_BYTES: usize = 255;
+
+/// Decodes `X-File-Name`, which may be plain ASCII, percent-encoded UTF-8,
+/// raw UTF-8 bytes, or an RFC 5987 ext-value such as `UTF-8''na%C3%AFve.txt`
+/// (optionally prefixed with `filename*=`). A literal `%` must be sent as `%25`.
+pub fn parse_file_name(value: &HeaderValue) -> Result<String, SliceBreadServerError> {
+    let raw = std::str::from_utf8(value.as_bytes()).map_err(|_| invalid("not valid UTF-8"))?;
+    let raw = raw.trim();
+    let raw = raw.strip_prefix("filename*=").unwrap_or(raw);
+
+    let name = match parse_ext_value(raw)? {
+        Some(name) => name,
+        None if raw.contains('%') => percent_decode(raw).unwrap_or_else(|| raw.to_string()),
+        None => raw.to_string(),
+    };
+
+    validate(&name)?;
+    Ok(name)
+}
+
+/// Parses `charset'language'value`; returns `None` when `raw` isn't in that form.
+fn parse_ext_value(raw: &str) -> Result<Option<String>, SliceBreadServerError> {
+    let mut parts = raw.splitn(3, '\'');
+    let (Some(charset), Some(_language), Some(encoded)) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return Ok(None);
+    };
+
+    if !charset.eq_ignore_ascii_case("UTF-8") {
+        return Err(invalid(&format!("unsupported charset {}", charset)));
+    }
+
+    percent_decode(encoded)
+        .map(Some)
+        .ok_or_else(|| invalid("malformed percent-encoding"))
+}
+
+fn percent_decode(encoded: &str) -> Option<String> {
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn validate(name: &str) -> Result<(), SliceBreadServerError> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(invalid("empty or reserved name"));
+    }
+    if name.len() > MAX_FILE_NAME_BYTES {
+        return Err(invalid("name too long"));
+    }
+    if name
+        .chars()
+        .any(|c| c == '/' || c == '\\' || c.is_control())
+    {
+        return Err(invalid("contains path separators or control characters"));
+    }
+    Ok(())
+}
+
+fn invalid(reason: &str) -> SliceBreadServerError {
+    SliceBreadServerError::InvalidHeader(format!(
+        "Invalid header value: {} ({})",
+        constants::HEADER_FILE_NAME,
+        reason
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: &[u8]) -> Result<String, SliceBreadServerError> {
+        parse_file_name(&HeaderValue::from_bytes(value).unwrap())
+    }
+
+    #[test]
+    fn test_plain_ascii_name() {
+        assert_eq!(parse(b"report.pdf").unwrap(), "report.pdf");
+    }
+
+    #[test]
+    fn test_rfc5987_ext_value() {
+        assert_eq!(
+            parse(b"UTF-8''na%C3%AFve%20r%C3%A9sum%C3%A9.txt").unwrap(),
+            "naïve résumé.txt"
+        );
+        assert_eq!(
+            parse(b"filename*=utf-8'ja'%E5%A0%B1%E5%91%8A.pdf").unwrap(),
+            "報告.pdf"
+        );
+    }
+
+    #[test]
+    fn test_percent_encoded_name() {
+        assert_eq!(parse(b"%E6%96%87%E4%BB%B6.txt").unwrap(), "文件.txt");
+        assert_eq!(parse(b"50%25.txt").unwrap(), "50%.txt");
+    }
+
+    #[test]
+    fn test_literal_percent_kept_when_not_decodable() {
+        assert_eq!(parse(b"100%.txt").unwrap(), "100%.txt");
+    }
+
+    #[test]
+    fn test_raw_utf8_bytes() {
+        assert_eq!(parse("café.txt".as_bytes()).unwrap(), "café.txt");
+    }
+
+    #[test]
+    fn test_rejects_unsafe_names() {
+        for value in [
+            &b".."[..],
+            b"../etc/passwd",
+            b"dir/file.txt",
+            b"UTF-8''..%2F..%2Fetc%2Fpasswd",
+            b"a%5Cb.txt",
+            b"bad%00name",
+            b"UTF-8''%E6%96",
+            b"ISO-8859-1''caf%E9.txt",
+            b"\xff\xfe",
+        ] {
+            assert!(
+                matches!(parse(value), Err(SliceBreadServerError::InvalidHeader(_))),
+                "{:?} should be rejected",
+                value
+            );
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::str::FromStr;
+
+use hyper::{HeaderMap, header::HeaderName};
+
+use crate::constants;
+
+/// Deployment names for the protocol's `X-` headers, for networks whose proxies
+/// strip headers they don't know. Requests are translated to the standard names
+/// in `constants` as they come in, and responses back as they go out, so the
+/// rest of the server only ever sees the standard names.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderNames {
+    /// Pairs of standard and deployment name.
+    renamed: Vec<(HeaderName, HeaderName)>,
+}
+
+impl HeaderNames {
+    pub fn to_standard(&self, headers: &mut HeaderMap) {
+        for (standard, deployed) in &self.renamed {
+            rename(headers, deployed, standard);
+        }
+    }
+
+    pub fn to_deployed(&self, headers: &mut HeaderMap) {
+        for (standard, deployed) in &self.renamed {
+            rename(headers, standard, deployed);
+        }
+    }
+}
+
+fn rename(headers: &mut HeaderMap, from: &HeaderName, to: &HeaderName) {
+    let values: Vec<_> = headers.get_all(from).iter().cloned().collect();
+    if values.is_empty() {
+        return;
+    }
+    headers.remove(from);
+    headers.remove(to);
+    for value in values {
+        headers.append(to.clone(), value);
+    }
+}
+
+/// Parses comma-separated `<standard>=<deployed>` pairs, e.g.
+/// `X-File-Id=File-Id,X-Chunk-Index=Chunk-Index`.
+impl FromStr for HeaderNames {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |name: &str| {
+            HeaderName::from_str(name.trim()).map_err(|_| format!("Invalid header name: {}", name))
+        };
+        let mut names = Self::default();
+        for pair in s.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (standard, deployed) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("Expected <standard>=<deployed>, got {}", pair))?;
+            let (standard, deployed) = (parse(standard)?, parse(deployed)?);
+            if !constants::RENAMEABLE_HEADERS
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(standard.as_str()))
+            {
+                return Err(format!("{} is not a renameable header", standard));
+            }
+            if constants::RENAMEABLE_HEADERS
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(deployed.as_str()))
+                || names
+                    .renamed
+                    .iter()
+                    .any(|(s, d)| *s == standard || *d == deployed)
+            {
+                return Err(format!("{} is renamed more than once", standard));
+            }
+            names.renamed.push((standard, deployed));
+        }
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers_are_translated_both_ways() {
+        let names: HeaderNames = "X-File-Id=File-Id, X-Chunk-Sha256=Chunk-Sha256"
+            .parse()
+            .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("file-id", "abc".parse().unwrap());
+        headers.insert("x-chunk-index", "1".parse().unwrap());
+        names.to_standard(&mut headers);
+        assert_eq!(headers["x-file-id"], "abc");
+        assert_eq!(headers["x-chunk-index"], "1");
+        assert!(!headers.contains_key("file-id"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-chunk-sha256", "00".parse().unwrap());
+        names.to_deployed(&mut headers);
+        assert_eq!(headers["chunk-sha256"], "00");
+        assert!(!headers.contains_key("x-chunk-sha256"));
+
+        assert!("Content-Type=Type".parse::<HeaderNames>().is_err());
+        assert!("X-File-Id=X-File-Name".parse::<HeaderNames>().is_err());
+        assert!("X-File-Id=A,X-File-Name=A".parse::<HeaderNames>().is_err());
+        assert!("X-File-Id".parse::<HeaderNames>().is_err());
+        assert_eq!("".parse::<HeaderNames>(), Ok(HeaderNames::default()));
+    }
+}
@@ -0,0 +1,381 @@
+use std::{collections::VecDeque, fmt::Write, sync::Mutex, time::Instant};
+
+use crate::constants;
+
+const KIB: f64 = 1024.0;
+
+/// Bucket upper bounds for sizes, in bytes: powers of 4 from 1 KiB to 64 GiB.
+const SIZE_BOUNDS: &[f64] = &[
+    KIB,
+    4.0 * KIB,
+    16.0 * KIB,
+    64.0 * KIB,
+    256.0 * KIB,
+    KIB * KIB,
+    4.0 * KIB * KIB,
+    16.0 * KIB * KIB,
+    64.0 * KIB * KIB,
+    256.0 * KIB * KIB,
+    KIB * KIB * KIB,
+    4.0 * KIB * KIB * KIB,
+    16.0 * KIB * KIB * KIB,
+    64.0 * KIB * KIB * KIB,
+];
+
+/// Bucket upper bounds for durations, in seconds, up to a day.
+const DURATION_BOUNDS: &[f64] = &[
+    0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 600.0, 1800.0, 3600.0, 7200.0, 21600.0, 86400.0,
+];
+
+const RETRY_BOUNDS: &[f64] = &[0.0, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0];
+
+/// Observations per bucket, the last counting those above every bound.
+#[derive(Debug, Clone)]
+struct Counts {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+    max: f64,
+}
+
+impl Counts {
+    fn new(bounds: &[f64]) -> Self {
+        Self {
+            buckets: vec![0; bounds.len() + 1],
+            count: 0,
+            sum: 0.0,
+            max: 0.0,
+        }
+    }
+
+    fn add(&mut self, bucket: usize, value: f64) {
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += value;
+        self.max = self.max.max(value);
+    }
+
+    fn merge(&mut self, other: &Self) {
+        for (bucket, count) in self.buckets.iter_mut().zip(&other.buckets) {
+            *bucket += count;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        self.max = self.max.max(other.max);
+    }
+}
+
+struct Inner {
+    /// Everything since startup, as Prometheus expects.
+    total: Counts,
+    /// Per `HISTOGRAM_SLICE`, by slice number, covering the last `HISTOGRAM_WINDOW`.
+    slices: VecDeque<(u64, Counts)>,
+}
+
+/// A histogram with fixed buckets, kept both in total and over a rolling
+/// window so that percentiles reflect recent traffic.
+pub struct Histogram {
+    bounds: &'static [f64],
+    started: Instant,
+    inner: Mutex<Inner>,
+}
+
+/// Percentiles over the rolling window, estimated from the buckets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub count: u64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            started: Instant::now(),
+            inner: Mutex::new(Inner {
+                total: Counts::new(bounds),
+                slices: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        self.observe_at(value, Instant::now());
+    }
+
+    fn observe_at(&self, value: f64, now: Instant) {
+        let bucket = self.bounds.partition_point(|&bound| bound < value);
+        let slice = self.slice(now);
+        let mut inner = self.inner.lock().expect("histogram lock poisoned");
+        inner.total.add(bucket, value);
+        if inner.slices.back().is_none_or(|(last, _)| *last != slice) {
+            inner.slices.push_back((slice, Counts::new(self.bounds)));
+        }
+        if let Some((_, counts)) = inner.slices.back_mut() {
+            counts.add(bucket, value);
+        }
+        let oldest = slice.saturating_sub(slices_per_window() - 1);
+        while inner
+            .slices
+            .front()
+            .is_some_and(|(first, _)| *first < oldest)
+        {
+            inner.slices.pop_front();
+        }
+    }
+
+    fn slice(&self, now: Instant) -> u64 {
+        now.duration_since(self.started).as_secs() / constants::HISTOGRAM_SLICE.as_secs()
+    }
+
+    pub fn summary(&self) -> Summary {
+        self.summary_at(Instant::now())
+    }
+
+    fn summary_at(&self, now: Instant) -> Summary {
+        let oldest = self.slice(now).saturating_sub(slices_per_window() - 1);
+        let mut recent = Counts::new(self.bounds);
+        let inner = self.inner.lock().expect("histogram lock poisoned");
+        for (_, counts) in inner.slices.iter().filter(|(slice, _)| *slice >= oldest) {
+            recent.merge(counts);
+        }
+        drop(inner);
+        Summary {
+            count: recent.count,
+            mean: if recent.count == 0 {
+                0.0
+            } else {
+                recent.sum / recent.count as f64
+            },
+            p50: self.percentile(&recent, 0.50),
+            p90: self.percentile(&recent, 0.90),
+            p99: self.percentile(&recent, 0.99),
+            max: recent.max,
+        }
+    }
+
+    /// Interpolates linearly within the bucket the rank falls in; above the
+    /// last bound, up to the largest value seen.
+    fn percentile(&self, counts: &Counts, quantile: f64) -> f64 {
+        let rank = quantile * counts.count as f64;
+        let mut below = 0;
+        for (bucket, &count) in counts.buckets.iter().enumerate() {
+            if count > 0 && (below + count) as f64 >= rank {
+                let low = bucket.checked_sub(1).map_or(0.0, |i| self.bounds[i]);
+                let high = self.bounds.get(bucket).copied().unwrap_or(counts.max);
+                let within = (rank - below as f64) / count as f64;
+                return (low + (high - low) * within).min(counts.max);
+            }
+            below += count;
+        }
+        0.0
+    }
+
+    /// The totals in the Prometheus text format.
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let total = self
+            .inner
+            .lock()
+            .expect("histogram lock poisoned")
+            .total
+            .clone();
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&total.buckets) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, total.count);
+        let _ = writeln!(out, "{}_sum {}", name, total.sum);
+        let _ = writeln!(out, "{}_count {}", name, total.count);
+    }
+}
+
+fn slices_per_window() -> u64 {
+    (constants::HISTOGRAM_WINDOW.as_secs() / constants::HISTOGRAM_SLICE.as_secs()).max(1)
+}
+
+/// Distributions of completed uploads, for capacity planning.
+pub struct UploadHistograms {
+    /// Bytes of each completed upload.
+    pub upload_size: Histogram,
+    /// Bytes of each chunk written.
+    pub chunk_size: Histogram,
+    /// Seconds from an upload's first chunk until it was assembled.
+    pub duration: Histogram,
+    /// Times chunks of each completed upload were sent again after the first attempt.
+    pub retries: Histogram,
+}
+
+impl Default for UploadHistograms {
+    fn default() -> Self {
+        Self {
+            upload_size: Histogram::new(SIZE_BOUNDS),
+            chunk_size: Histogram::new(SIZE_BOUNDS),
+            duration: Histogram::new(DURATION_BOUNDS),
+            retries: Histogram::new(RETRY_BOUNDS),
+        }
+    }
+}
+
+impl UploadHistograms {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Served by `GET /metrics`.
+    pub fn render(&self, out: &mut String) {
+        self.upload_size.render(
+            out,
+            "slicedbread_upload_size_bytes",
+            "Size of completed uploads.",
+        );
+        self.chunk_size.render(
+            out,
+            "slicedbread_chunk_size_bytes",
+            "Size of chunks written.",
+        );
+        self.duration.render(
+            out,
+            "slicedbread_upload_duration_seconds",
+            "Time from the first chunk of an upload until it was assembled.",
+        );
+        self.retries.render(
+            out,
+            "slicedbread_upload_retries",
+            "Times chunks of a completed upload were sent again after the first attempt.",
+        );
+    }
+
+    /// Served by `GET /admin/stats/summary`: one line per histogram, with
+    /// sizes and durations in readable units.
+    pub fn summary(&self) -> String {
+        let mut out = format!(
+            "Over the last {}:\n",
+            duration(constants::HISTOGRAM_WINDOW.as_secs_f64())
+        );
+        row(&mut out, "upload size", self.upload_size.summary(), bytes);
+        row(&mut out, "chunk size", self.chunk_size.summary(), bytes);
+        row(
+            &mut out,
+            "upload duration",
+            self.duration.summary(),
+            duration,
+        );
+        row(&mut out, "retries", self.retries.summary(), |n| {
+            format!("{:.1}", n)
+        });
+        out
+    }
+}
+
+fn row(out: &mut String, label: &str, summary: Summary, unit: fn(f64) -> String) {
+    let _ = writeln!(
+        out,
+        "{:<16} count {:<6} mean {:<10} p50 {:<10} p90 {:<10} p99 {:<10} max {}",
+        label,
+        summary.count,
+        unit(summary.mean),
+        unit(summary.p50),
+        unit(summary.p90),
+        unit(summary.p99),
+        unit(summary.max),
+    );
+}
+
+fn bytes(value: f64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = value;
+    let mut unit = 0;
+    while value >= KIB && unit < units.len() - 1 {
+        value /= KIB;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{:.0} {}", value, units[unit])
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+fn duration(secs: f64) -> String {
+    if secs < 60.0 {
+        format!("{:.1}s", secs)
+    } else if secs < 3600.0 {
+        format!("{:.1}m", secs / 60.0)
+    } else {
+        format!("{:.1}h", secs / 3600.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles_are_estimated_from_buckets() {
+        let histogram = Histogram::new(DURATION_BOUNDS);
+        assert_eq!(histogram.summary().count, 0);
+        assert_eq!(histogram.summary().p99, 0.0);
+
+        // 90 quick uploads and 10 slow ones.
+        for _ in 0..90 {
+            histogram.observe(2.0);
+        }
+        for _ in 0..10 {
+            histogram.observe(400.0);
+        }
+        let summary = histogram.summary();
+        assert_eq!(summary.count, 100);
+        assert!((summary.mean - 41.8).abs() < 1e-9);
+        assert!(summary.p50 > 1.0 && summary.p50 <= 5.0, "{}", summary.p50);
+        assert!(summary.p90 <= 5.0, "{}", summary.p90);
+        assert!(
+            summary.p99 > 300.0 && summary.p99 <= 400.0,
+            "{}",
+            summary.p99
+        );
+        assert_eq!(summary.max, 400.0);
+    }
+
+    #[test]
+    fn test_window_forgets_old_observations_but_totals_keep_them() {
+        let histogram = Histogram::new(RETRY_BOUNDS);
+        let start = histogram.started;
+        histogram.observe_at(3.0, start);
+        histogram.observe_at(0.0, start + constants::HISTOGRAM_WINDOW / 2);
+        assert_eq!(
+            histogram
+                .summary_at(start + constants::HISTOGRAM_WINDOW / 2)
+                .count,
+            2
+        );
+
+        let later = start + constants::HISTOGRAM_WINDOW + constants::HISTOGRAM_SLICE;
+        let summary = histogram.summary_at(later);
+        assert_eq!(summary.count, 1);
+        assert_eq!(summary.max, 0.0);
+
+        let mut out = String::new();
+        histogram.render(&mut out, "retries", "Retries.");
+        assert!(out.contains("# TYPE retries histogram\n"));
+        assert!(out.contains("retries_bucket{le=\"0\"} 1\n"));
+        assert!(out.contains("retries_bucket{le=\"5\"} 2\n"));
+        assert!(out.contains("retries_bucket{le=\"+Inf\"} 2\n"));
+        assert!(out.contains("retries_count 2\n"));
+    }
+
+    #[test]
+    fn test_units_are_readable() {
+        assert_eq!(bytes(512.0), "512 B");
+        assert_eq!(bytes(5.0 * KIB * KIB), "5.0 MiB");
+        assert_eq!(duration(2.5), "2.5s");
+        assert_eq!(duration(90.0), "1.5m");
+        assert_eq!(duration(3600.0), "1.0h");
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use hyper_util::{
+    rt::{TokioExecutor, TokioTimer},
+    server::conn::auto,
+};
+
+const MIN_BUF_SIZE: usize = 8192;
+
+/// Protocol tuning for accepted connections; `None` keeps hyper's default.
+/// Many small chunk requests favour keep-alive and small buffers, a few huge
+/// ones favour large HTTP/2 flow-control windows.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HttpConfig {
+    pub http1_keep_alive: Option<bool>,
+    /// Most header fields an HTTP/1.1 request may have.
+    pub http1_max_headers: Option<usize>,
+    /// Largest HTTP/1.1 read buffer, which bounds the request head. Raised to
+    /// 8192 if smaller, since hyper refuses anything below that.
+    pub http1_max_buf_size: Option<usize>,
+    /// How long a client may take to send the HTTP/1.1 request head.
+    pub header_read_timeout: Option<Duration>,
+    pub http2_max_header_list_size: Option<u32>,
+    pub http2_initial_stream_window_size: Option<u32>,
+    pub http2_initial_connection_window_size: Option<u32>,
+    pub http2_adaptive_window: bool,
+    pub http2_max_concurrent_streams: Option<u32>,
+    pub http2_keep_alive_interval: Option<Duration>,
+    pub http2_keep_alive_timeout: Option<Duration>,
+}
+
+impl HttpConfig {
+    pub fn builder(&self) -> auto::Builder<TokioExecutor> {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        // hyper panics when a timeout is configured without a timer, and starts
+        // enforcing its own header timeout once one is set, so only add it when asked.
+        let timed = self.header_read_timeout.is_some()
+            || self.http2_keep_alive_interval.is_some()
+            || self.http2_keep_alive_timeout.is_some();
+
+        let mut http1 = builder.http1();
+        if timed {
+            http1.timer(TokioTimer::new());
+        }
+        if let Some(keep_alive) = self.http1_keep_alive {
+            http1.keep_alive(keep_alive);
+        }
+        if let Some(max) = self.http1_max_headers {
+            http1.max_headers(max);
+        }
+        if let Some(max) = self.http1_max_buf_size {
+            http1.max_buf_size(max.max(MIN_BUF_SIZE));
+        }
+        if let Some(timeout) = self.header_read_timeout {
+            http1.header_read_timeout(timeout);
+        }
+
+        let mut http2 = builder.http2();
+        if timed {
+            http2.timer(TokioTimer::new());
+        }
+        if let Some(max) = self.http2_max_header_list_size {
+            http2.max_header_list_size(max);
+        }
+        if let Some(size) = self.http2_initial_stream_window_size {
+            http2.initial_stream_window_size(size);
+        }
+        if let Some(size) = self.http2_initial_connection_window_size {
+            http2.initial_connection_window_size(size);
+        }
+        if self.http2_adaptive_window {
+            http2.adaptive_window(true);
+        }
+        if let Some(max) = self.http2_max_concurrent_streams {
+            http2.max_concurrent_streams(max);
+        }
+        if let Some(interval) = self.http2_keep_alive_interval {
+            http2.keep_alive_interval(interval);
+        }
+        if let Some(timeout) = self.http2_keep_alive_timeout {
+            http2.keep_alive_timeout(timeout);
+        }
+        builder
+    }
+}
@@ -0,0 +1,271 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use base64::Engine;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{Request, StatusCode, Uri, header};
+use hyper_util::rt::TokioIo;
+use serde_json::{Map, Value};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    TlsConnector,
+    rustls::{self, pki_types::ServerName},
+};
+
+use crate::{
+    auth::{DEFAULT_SCOPES, Principal, Scope},
+    constants,
+    crypto::Sha256,
+    error::SliceBreadServerError,
+};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// RFC 7662 endpoint that opaque bearer tokens are checked against.
+#[derive(Debug, Clone)]
+pub struct IntrospectionConfig {
+    pub url: Uri,
+    /// Credentials the server authenticates to the endpoint with, sent as HTTP Basic.
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    /// How long a result is reused; never past the token's `exp`.
+    pub cache_ttl: Duration,
+    /// Claim naming the tenant; tokens without it act as the default tenant.
+    pub tenant_claim: String,
+    /// Used when `url` is `https`.
+    pub tls: Arc<rustls::ClientConfig>,
+}
+
+/// Checks bearer tokens against the introspection endpoint, remembering the
+/// answer for active and inactive tokens alike.
+#[derive(Debug)]
+pub struct Introspector {
+    config: IntrospectionConfig,
+    // Keyed by the token's hash, so the cache doesn't hold live credentials.
+    cache: Mutex<HashMap<[u8; 32], Cached>>,
+}
+
+#[derive(Debug)]
+struct Cached {
+    principal: Option<Principal>,
+    expires_at: Instant,
+}
+
+impl Introspector {
+    pub fn new(config: IntrospectionConfig) -> Self {
+        Self {
+            config,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The principal `token` stands for, or `None` if it isn't active.
+    pub async fn principal(&self, token: &str) -> Result<Option<Principal>, SliceBreadServerError> {
+        let key = Sha256::digest(token);
+        if let Some(cached) = self.cache.lock().expect("cache lock poisoned").get(&key)
+            && cached.expires_at > Instant::now()
+        {
+            return Ok(cached.principal.clone());
+        }
+
+        let claims =
+            match tokio::time::timeout(constants::INTROSPECTION_TIMEOUT, self.introspect(token))
+                .await
+            {
+                Ok(Ok(claims)) => claims,
+                Ok(Err(err)) => return Err(self.unavailable(err)),
+                Err(_) => return Err(self.unavailable("timed out".into())),
+            };
+        let principal = principal(&claims, &self.config.tenant_claim);
+        let mut ttl = self.config.cache_ttl;
+        if let Some(exp) = claims.get("exp").and_then(Value::as_i64) {
+            let left = exp.saturating_sub(chrono::Utc::now().timestamp()).max(0);
+            ttl = ttl.min(Duration::from_secs(left as u64));
+        }
+        if !ttl.is_zero() {
+            let now = Instant::now();
+            let mut cache = self.cache.lock().expect("cache lock poisoned");
+            if cache.len() >= constants::MAX_CREDENTIAL_CACHE_ENTRIES {
+                cache.retain(|_, cached| cached.expires_at > now);
+                if cache.len() >= constants::MAX_CREDENTIAL_CACHE_ENTRIES {
+                    cache.clear();
+                }
+            }
+            cache.insert(
+                key,
+                Cached {
+                    principal: principal.clone(),
+                    expires_at: now + ttl,
+                },
+            );
+        }
+        Ok(principal)
+    }
+
+    fn unavailable(&self, err: BoxError) -> SliceBreadServerError {
+        tracing::warn!(url = %self.config.url, %err, "Token introspection failed");
+        SliceBreadServerError::ServiceUnavailable("Token introspection failed".to_string())
+    }
+
+    async fn introspect(&self, token: &str) -> Result<Map<String, Value>, BoxError> {
+        let url = &self.config.url;
+        let https = match url.scheme_str() {
+            Some("https") => true,
+            Some("http") => false,
+            _ => return Err("introspection URL must be http or https".into()),
+        };
+        let host = url
+            .host()
+            .ok_or("introspection URL has no host")?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let port = url.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+        let form = form_urlencoded::Serializer::new(String::new())
+            .append_pair("token", token)
+            .append_pair("token_type_hint", "access_token")
+            .finish();
+        let mut request = Request::post(url.path_and_query().map_or("/", |path| path.as_str()))
+            .header(
+                header::HOST,
+                url.authority().map_or(host, |authority| authority.as_str()),
+            )
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(header::ACCEPT, "application/json");
+        if let Some(client_id) = &self.config.client_id {
+            // RFC 6749 form-encodes the client credentials before joining them.
+            let encode =
+                |value: &str| form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>();
+            let credentials = format!(
+                "{}:{}",
+                encode(client_id),
+                encode(self.config.client_secret.as_deref().unwrap_or_default())
+            );
+            request = request.header(
+                header::AUTHORIZATION,
+                format!(
+                    "Basic {}",
+                    base64::engine::general_purpose::STANDARD.encode(credentials)
+                ),
+            );
+        }
+        let request = request.body(Full::new(Bytes::from(form)))?;
+
+        let stream = TcpStream::connect((host, port)).await?;
+        let (status, body) = if https {
+            let name = ServerName::try_from(host.to_string())?;
+            let stream = TlsConnector::from(self.config.tls.clone())
+                .connect(name, stream)
+                .await?;
+            send(stream, request).await?
+        } else {
+            send(stream, request).await?
+        };
+        if !status.is_success() {
+            return Err(format!("introspection endpoint answered {}", status).into());
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+async fn send<S>(stream: S, request: Request<Full<Bytes>>) -> Result<(StatusCode, Bytes), BoxError>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (mut sender, connection) =
+        hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        if let Err(err) = connection.await {
+            tracing::debug!(%err, "Introspection connection closed");
+        }
+    });
+    let response = sender.send_request(request).await?;
+    let status = response.status();
+    let body = Limited::new(
+        response.into_body(),
+        constants::MAX_INTROSPECTION_RESPONSE_BYTES,
+    )
+    .collect()
+    .await?
+    .to_bytes();
+    Ok((status, body))
+}
+
+/// Maps an introspection response to a principal. Scopes are read from the
+/// space-separated `scope` claim, ignoring any the server doesn't know; a
+/// token without the claim gets the default scopes.
+fn principal(claims: &Map<String, Value>, tenant_claim: &str) -> Option<Principal> {
+    if claims.get("active").and_then(Value::as_bool) != Some(true) {
+        return None;
+    }
+    if let Some(exp) = claims.get("exp").and_then(Value::as_i64)
+        && exp <= chrono::Utc::now().timestamp()
+    {
+        return None;
+    }
+    let claim = |name: &str| claims.get(name).and_then(Value::as_str);
+    let scopes = match claim("scope") {
+        Some(scope) => scope
+            .split_whitespace()
+            .filter_map(|scope| scope.parse::<Scope>().ok())
+            .collect(),
+        None => DEFAULT_SCOPES.to_vec(),
+    };
+    Some(Principal {
+        name: claim("sub")
+            .or_else(|| claim("username"))
+            .or_else(|| claim("client_id"))
+            .unwrap_or("bearer token")
+            .to_string(),
+        tenant: claim(tenant_claim)
+            .unwrap_or(constants::DEFAULT_TENANT)
+            .to_string(),
+        scopes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(json: Value) -> Map<String, Value> {
+        json.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_claims_map_to_principal() {
+        let principal = principal(
+            &claims(serde_json::json!({
+                "active": true,
+                "sub": "render-svc",
+                "scope": "openid download upload",
+                "org": "acme",
+            })),
+            "org",
+        )
+        .unwrap();
+        assert_eq!(principal.name, "render-svc");
+        assert_eq!(principal.tenant, "acme");
+        assert_eq!(principal.scopes, [Scope::Download, Scope::Upload]);
+
+        let principal = principal_for(serde_json::json!({ "active": true, "client_id": "cli" }));
+        assert_eq!(principal.unwrap().tenant, constants::DEFAULT_TENANT);
+
+        assert_eq!(principal_for(serde_json::json!({ "active": false })), None);
+        assert_eq!(
+            principal_for(serde_json::json!({ "active": true, "exp": 1 })),
+            None
+        );
+    }
+
+    fn principal_for(json: Value) -> Option<Principal> {
+        principal(&claims(json), "tenant")
+    }
+}
@@ -0,0 +1,173 @@
+use std::path::Path;
+
+use bytes::Bytes;
+
+#[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+pub async fn write_file(path: impl AsRef<Path>, data: Bytes) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut file = tokio::fs::File::create(path).await?;
+    file.write_all(&data).await?;
+    file.flush().await
+}
+
+#[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+pub async fn read_file(path: impl AsRef<Path>) -> std::io::Result<Vec<u8>> {
+    tokio::fs::read(path).await
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub async fn write_file(path: impl AsRef<Path>, data: Bytes) -> std::io::Result<()> {
+    uring::submit(|reply| uring::Op::Write {
+        path: path.as_ref().to_path_buf(),
+        data,
+        reply,
+    })
+    .await
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub async fn read_file(path: impl AsRef<Path>) -> std::io::Result<Vec<u8>> {
+    uring::submit(|reply| uring::Op::Read {
+        path: path.as_ref().to_path_buf(),
+        reply,
+    })
+    .await
+}
+
+/// Writes `data` at `offset` without truncating, leaving a hole if the file is
+/// shorter. Goes through tokio with either backend.
+pub async fn write_at(path: impl AsRef<Path>, offset: u64, data: &[u8]) -> std::io::Result<()> {
+    use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .await?;
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+    file.write_all(data).await?;
+    file.flush().await
+}
+
+/// Flushes a file's data and metadata to disk.
+pub async fn sync_file(path: impl AsRef<Path>) -> std::io::Result<()> {
+    tokio::fs::File::open(path).await?.sync_all().await
+}
+
+/// Flushes a directory's entries to disk, so that files created or renamed
+/// into it survive a crash. A no-op where directories can't be opened.
+pub async fn sync_dir(path: impl AsRef<Path>) -> std::io::Result<()> {
+    #[cfg(unix)]
+    tokio::fs::File::open(path).await?.sync_all().await?;
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Reads `len` bytes starting at `offset`.
+pub async fn read_at(path: impl AsRef<Path>, offset: u64, len: usize) -> std::io::Result<Vec<u8>> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+    let mut data = vec![0; len];
+    file.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+/// tokio-uring needs its own current-thread runtime, so file operations are
+/// shipped to a dedicated thread and the results sent back over oneshots.
+/// Reads and writes use ordinary buffers: tokio-uring 0.4 can't register
+/// fixed ones, and a chunk read for assembly is collected whole.
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring {
+    use std::{io, path::PathBuf, sync::OnceLock};
+
+    use bytes::Bytes;
+    use tokio::sync::{mpsc, oneshot};
+
+    const READ_BUF_SIZE: usize = 256 * 1024;
+
+    pub enum Op {
+        Write {
+            path: PathBuf,
+            data: Bytes,
+            reply: oneshot::Sender<io::Result<()>>,
+        },
+        Read {
+            path: PathBuf,
+            reply: oneshot::Sender<io::Result<Vec<u8>>>,
+        },
+    }
+
+    static WORKER: OnceLock<mpsc::UnboundedSender<Op>> = OnceLock::new();
+
+    fn worker() -> &'static mpsc::UnboundedSender<Op> {
+        WORKER.get_or_init(|| {
+            let (tx, mut rx) = mpsc::unbounded_channel::<Op>();
+            std::thread::Builder::new()
+                .name("slicebread-uring".to_string())
+                .spawn(move || {
+                    tokio_uring::start(async move {
+                        while let Some(op) = rx.recv().await {
+                            tokio_uring::spawn(run(op));
+                        }
+                    })
+                })
+                .expect("failed to spawn io-uring worker thread");
+            tx
+        })
+    }
+
+    pub async fn submit<T>(op: impl FnOnce(oneshot::Sender<io::Result<T>>) -> Op) -> io::Result<T> {
+        let (reply, rx) = oneshot::channel();
+        worker()
+            .send(op(reply))
+            .map_err(|_| io::Error::other("io-uring worker stopped"))?;
+        rx.await
+            .map_err(|_| io::Error::other("io-uring worker dropped request"))?
+    }
+
+    async fn run(op: Op) {
+        match op {
+            Op::Write { path, data, reply } => {
+                let _ = reply.send(write(path, data).await);
+            }
+            Op::Read { path, reply } => {
+                let _ = reply.send(read(path).await);
+            }
+        }
+    }
+
+    async fn write(path: PathBuf, data: Bytes) -> io::Result<()> {
+        let file = tokio_uring::fs::File::create(path).await?;
+        let mut written = 0;
+        while written < data.len() {
+            let (res, _) = file.write_at(data.slice(written..), written as u64).await;
+            match res? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                n => written += n,
+            }
+        }
+        file.close().await
+    }
+
+    async fn read(path: PathBuf) -> io::Result<Vec<u8>> {
+        let file = tokio_uring::fs::File::open(path).await?;
+        let mut out = Vec::new();
+        let mut buf = vec![0u8; READ_BUF_SIZE];
+        loop {
+            let (res, b) = file.read_at(buf, out.len() as u64).await;
+            let n = res?;
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&b[..n]);
+            buf = b;
+        }
+        file.close().await?;
+        Ok(out)
+    }
+}
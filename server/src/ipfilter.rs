@@ -0,0 +1,173 @@
+use std::{net::IpAddr, str::FromStr};
+
+use hyper::HeaderMap;
+
+use crate::constants::HEADER_FORWARDED_FOR;
+
+/// An address range such as `10.0.0.0/8` or `fd00::/8`; a bare address is a
+/// single-host range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let network = address
+            .parse::<IpAddr>()
+            .map_err(|_| format!("Invalid address in CIDR: {}", s))?
+            .to_canonical();
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("Invalid prefix length in CIDR: {}", s))?,
+            None => max,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+/// Which clients may talk to the server. A denied address is always refused;
+/// otherwise, if an allowlist is set, the address must be on it.
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+    /// Peers whose `X-Forwarded-For` is believed, such as a load balancer.
+    pub trusted_proxies: Vec<Cidr>,
+}
+
+impl IpFilter {
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        !self.deny.iter().any(|cidr| cidr.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip)))
+    }
+
+    /// The address the request originates from: the peer itself, or when the
+    /// peer is a trusted proxy, the rightmost `X-Forwarded-For` hop that isn't one.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let is_trusted = |ip: IpAddr| self.trusted_proxies.iter().any(|cidr| cidr.contains(ip));
+        if !is_trusted(peer) {
+            return peer;
+        }
+
+        let mut client = peer;
+        let hops = headers
+            .get_all(HEADER_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect::<Vec<_>>();
+        for hop in hops.into_iter().rev() {
+            let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = ip;
+            if !is_trusted(ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidrs(list: &[&str]) -> Vec<Cidr> {
+        list.iter().map(|cidr| cidr.parse().unwrap()).collect()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr_parsing_and_matching() {
+        let office: Cidr = "10.20.0.0/16".parse().unwrap();
+        assert!(office.contains(ip("10.20.3.4")));
+        assert!(office.contains(ip("::ffff:10.20.3.4")));
+        assert!(!office.contains(ip("10.21.0.1")));
+
+        let host: Cidr = "192.168.1.10".parse().unwrap();
+        assert!(host.contains(ip("192.168.1.10")));
+        assert!(!host.contains(ip("192.168.1.11")));
+
+        let any: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(ip("8.8.8.8")));
+        assert!(!any.contains(ip("::1")));
+
+        let v6: Cidr = "fd00::/8".parse().unwrap();
+        assert!(v6.contains(ip("fd12::1")));
+
+        for invalid in ["10.0.0.0/33", "fd00::/129", "office", "10.0.0.0/x"] {
+            assert!(
+                invalid.parse::<Cidr>().is_err(),
+                "{} should be rejected",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_deny_wins_over_allow() {
+        let filter = IpFilter {
+            allow: cidrs(&["10.0.0.0/8"]),
+            deny: cidrs(&["10.6.0.0/16"]),
+            trusted_proxies: Vec::new(),
+        };
+        assert!(filter.permits(ip("10.1.1.1")));
+        assert!(!filter.permits(ip("10.6.1.1")));
+        assert!(!filter.permits(ip("192.168.0.1")));
+        assert!(IpFilter::default().permits(ip("192.168.0.1")));
+    }
+
+    #[test]
+    fn test_forwarded_for_is_only_trusted_from_proxies() {
+        let filter = IpFilter {
+            trusted_proxies: cidrs(&["172.16.0.0/12"]),
+            ..IpFilter::default()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HEADER_FORWARDED_FOR,
+            "1.2.3.4, 10.0.0.9, 172.16.0.2".parse().unwrap(),
+        );
+
+        assert_eq!(filter.client_ip(ip("172.16.0.1"), &headers), ip("10.0.0.9"));
+        assert_eq!(filter.client_ip(ip("8.8.8.8"), &headers), ip("8.8.8.8"));
+        assert_eq!(
+            filter.client_ip(ip("172.16.0.1"), &HeaderMap::new()),
+            ip("172.16.0.1")
+        );
+    }
+}
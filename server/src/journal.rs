@@ -0,0 +1,383 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    thread::JoinHandle,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch};
+
+use crate::session::{Session, SessionSnapshot};
+
+/// One line of the chunk journal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum JournalRecord {
+    /// An upload was declared, or what was declared for it changed.
+    Session {
+        file_id: String,
+        session: Box<Session>,
+        started_at: DateTime<Utc>,
+    },
+    /// A chunk was accepted and is on disk.
+    Chunk {
+        file_id: String,
+        index: usize,
+        size: u64,
+        sha256: String,
+        at: DateTime<Utc>,
+    },
+    /// A byte range was written, as a half-open interval.
+    Range {
+        file_id: String,
+        start: u64,
+        end: u64,
+        at: DateTime<Utc>,
+    },
+    /// The upload was completed or abandoned.
+    End { file_id: String },
+}
+
+enum Command {
+    Append(JournalRecord),
+    Rewrite(Vec<JournalRecord>),
+}
+
+/// Append-only JSON Lines file of what happened to uploads in flight, so that
+/// the bytes and chunks they were credited with survive a crash that leaves
+/// no session snapshot behind.
+///
+/// Records are written by a thread of its own, so that appending never blocks
+/// the caller, who holds the session store lock to keep records in the order
+/// the changes were made. Callers that need a record on disk before going on
+/// wait for `flushed`.
+#[derive(Debug)]
+pub struct ChunkJournal {
+    commands: Option<mpsc::UnboundedSender<Command>>,
+    writer: Option<JoinHandle<()>>,
+    /// How many commands were sent, and how many the writer has carried out.
+    sent: u64,
+    done: watch::Receiver<u64>,
+    /// Records appended since the journal was last rewritten.
+    appended: u64,
+}
+
+impl ChunkJournal {
+    /// Opens the journal at `path` for appending. With `sync`, the writer
+    /// flushes each batch of records to disk before it counts as done.
+    pub fn open(path: &Path, sync: bool) -> std::io::Result<Self> {
+        let file = open_append(path)?;
+        let (commands, receiver) = mpsc::unbounded_channel();
+        let (done_sender, done) = watch::channel(0);
+        let path = path.to_path_buf();
+        let writer = std::thread::Builder::new()
+            .name("slicebread-journal".to_string())
+            .spawn(move || write(&path, file, sync, receiver, done_sender))?;
+        Ok(Self {
+            commands: Some(commands),
+            writer: Some(writer),
+            sent: 0,
+            done,
+            appended: 0,
+        })
+    }
+
+    pub fn append(&mut self, record: JournalRecord) -> std::io::Result<()> {
+        self.send(Command::Append(record))?;
+        self.appended += 1;
+        Ok(())
+    }
+
+    /// Whether anything was appended since the journal was last rewritten.
+    pub fn has_grown(&self) -> bool {
+        self.appended > 0
+    }
+
+    /// Replaces the journal's contents with `records`, which must describe
+    /// every upload in flight as of the last append.
+    pub fn rewrite(&mut self, records: Vec<JournalRecord>) -> std::io::Result<()> {
+        self.send(Command::Rewrite(records))?;
+        self.appended = 0;
+        Ok(())
+    }
+
+    /// Resolves once everything sent so far is written, and synced if the
+    /// journal syncs. Doesn't borrow the journal, so it can be awaited after
+    /// its lock is released.
+    pub fn flushed(&self) -> impl Future<Output = ()> + Send + use<> {
+        let mut done = self.done.clone();
+        let sent = self.sent;
+        async move {
+            // An error means the writer is gone, and nothing more will be written.
+            let _ = done.wait_for(|&done| done >= sent).await;
+        }
+    }
+
+    fn send(&mut self, command: Command) -> std::io::Result<()> {
+        self.commands
+            .as_ref()
+            .and_then(|commands| commands.send(command).ok())
+            .ok_or_else(|| std::io::Error::other("chunk journal writer stopped"))?;
+        self.sent += 1;
+        Ok(())
+    }
+}
+
+impl Drop for ChunkJournal {
+    /// Waits for the writer to write out what was sent, so a journal opened
+    /// on the same file afterwards reads it all.
+    fn drop(&mut self) {
+        self.commands.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn open_append(path: &Path) -> std::io::Result<File> {
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+}
+
+fn to_lines(records: &[JournalRecord]) -> std::io::Result<Vec<u8>> {
+    let mut lines = Vec::new();
+    for record in records {
+        serde_json::to_writer(&mut lines, record)?;
+        lines.push(b'\n');
+    }
+    Ok(lines)
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp_path = PathBuf::from(path).into_os_string();
+    tmp_path.push(".tmp");
+    tmp_path.into()
+}
+
+/// The writer thread: carries out commands in the order they were sent, a
+/// batch at a time, until the journal is dropped. Failures are logged and the
+/// commands counted as done, as a journal that can't be written is no reason
+/// to stop taking uploads.
+fn write(
+    path: &Path,
+    mut file: File,
+    sync: bool,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+    done: watch::Sender<u64>,
+) {
+    let mut count = 0;
+    while let Some(command) = commands.blocking_recv() {
+        let mut next = Some(command);
+        while let Some(command) = next {
+            let result = match command {
+                // Each record is appended with a single write so lines never interleave.
+                Command::Append(record) => {
+                    to_lines(std::slice::from_ref(&record)).and_then(|line| file.write_all(&line))
+                }
+                Command::Rewrite(records) => {
+                    let tmp_path = tmp_path(path);
+                    to_lines(&records)
+                        .and_then(|lines| std::fs::write(&tmp_path, lines))
+                        .and_then(|()| std::fs::rename(&tmp_path, path))
+                        .and_then(|()| open_append(path))
+                        .map(|reopened| file = reopened)
+                }
+            };
+            if let Err(err) = result {
+                tracing::warn!(%err, path = %path.display(), "Failed to write the chunk journal");
+            }
+            count += 1;
+            next = commands.try_recv().ok();
+        }
+        if sync && let Err(err) = file.sync_data() {
+            tracing::warn!(%err, path = %path.display(), "Failed to sync the chunk journal");
+        }
+        done.send_replace(count);
+    }
+}
+
+/// Uploads in flight according to the journal at `path`. A chunk recorded
+/// more than once counts once, and a line cut short by a crash is skipped.
+pub async fn replay(path: &Path) -> std::io::Result<Vec<SessionSnapshot>> {
+    let contents = match tokio::fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut uploads: Vec<SessionSnapshot> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for line in contents.lines().filter(|line| !line.is_empty()) {
+        let record: JournalRecord = match serde_json::from_str(line) {
+            Ok(record) => record,
+            Err(err) => {
+                tracing::warn!(%err, path = %path.display(), "Skipping unreadable chunk journal line");
+                continue;
+            }
+        };
+        match record {
+            JournalRecord::Session {
+                file_id,
+                session,
+                started_at,
+            } => match positions.get(&file_id) {
+                Some(&i) if uploads[i].session.generation == session.generation => {
+                    uploads[i].session = *session;
+                }
+                Some(&i) => uploads[i] = SessionSnapshot::new(file_id, *session, started_at),
+                None => {
+                    positions.insert(file_id.clone(), uploads.len());
+                    uploads.push(SessionSnapshot::new(file_id, *session, started_at));
+                }
+            },
+            JournalRecord::Chunk {
+                file_id,
+                index,
+                size,
+                sha256,
+                ..
+            } => {
+                if let Some(&i) = positions.get(&file_id) {
+                    uploads[i].add_chunk(index, sha256, size);
+                }
+            }
+            JournalRecord::Range {
+                file_id,
+                start,
+                end,
+                ..
+            } => {
+                if let Some(&i) = positions.get(&file_id) {
+                    uploads[i].add_range(start, end);
+                }
+            }
+            JournalRecord::End { file_id } => {
+                if let Some(i) = positions.remove(&file_id) {
+                    uploads.swap_remove(i);
+                    if let Some(moved) = uploads.get(i) {
+                        positions.insert(moved.file_id.clone(), i);
+                    }
+                }
+            }
+        }
+    }
+    Ok(uploads)
+}
+
+/// Rewrites the journal at `path` to hold just `uploads`, so that it doesn't
+/// grow with every upload ever made. The new journal replaces the old one in
+/// a single rename.
+pub async fn compact(path: &Path, uploads: &[SessionSnapshot]) -> std::io::Result<()> {
+    let at = Utc::now();
+    let records: Vec<JournalRecord> = uploads
+        .iter()
+        .flat_map(|upload| upload.journal_records(at))
+        .collect();
+    let tmp_path = tmp_path(path);
+    tokio::fs::write(&tmp_path, to_lines(&records)?).await?;
+    tokio::fs::rename(&tmp_path, path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::{
+        checksum, constants, session::SessionStore, tags::Tags, user_metadata::UserMetadata,
+    };
+
+    fn session(generation: u64) -> Box<Session> {
+        Box::new(Session {
+            tenant: constants::DEFAULT_TENANT.to_string(),
+            file_name: "a.txt".to_string(),
+            total_chunks: 3,
+            content_type: "text/plain".to_string(),
+            repr_digests: Vec::new(),
+            file_size: None,
+            byte_ranges: false,
+            extract: false,
+            defer_assembly: false,
+            max_duration: None,
+            client_ip: None,
+            generation,
+            bundle: None,
+            owner: None,
+            shares: Vec::new(),
+            category: None,
+            tags: Tags::new(),
+            user_metadata: UserMetadata::new(),
+            required_sha256: None,
+        })
+    }
+
+    fn chunk(file_id: &str, index: usize, size: u64) -> JournalRecord {
+        JournalRecord::Chunk {
+            file_id: file_id.to_string(),
+            index,
+            size,
+            sha256: checksum::to_hex(&[index as u8; 32]),
+            at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_counts_each_chunk_once() {
+        let dir = TempDir::new("journal").unwrap();
+        let path = dir.path().join("chunks.journal");
+        assert!(replay(&path).await.unwrap().is_empty());
+
+        let mut journal = ChunkJournal::open(&path, false).unwrap();
+        let start = |file_id: &str, generation| JournalRecord::Session {
+            file_id: file_id.to_string(),
+            session: session(generation),
+            started_at: Utc::now(),
+        };
+        for record in [
+            start("a", 0),
+            chunk("a", 0, 5),
+            chunk("a", 0, 5),
+            chunk("a", 2, 3),
+            start("b", 0),
+            chunk("b", 0, 7),
+            JournalRecord::End {
+                file_id: "b".to_string(),
+            },
+            start("c", 0),
+            chunk("c", 1, 9),
+            start("c", 1),
+            chunk("c", 0, 4),
+        ] {
+            journal.append(record).unwrap();
+        }
+        journal.flushed().await;
+        // A crash in the middle of an append.
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"op\":\"chunk\",\"file_id\":\"a\",\"ind")
+            .unwrap();
+
+        let store = SessionStore::new();
+        assert_eq!(store.restore(replay(&path).await.unwrap()), 2);
+        assert_eq!(store.progress("a").unwrap().bytes_received, 8);
+        assert_eq!(store.progress("a").unwrap().chunks_received, 2);
+        assert!(store.progress("b").is_none());
+        // The second generation replaced the first.
+        assert_eq!(store.session("c").unwrap().generation, 1);
+        assert_eq!(store.progress("c").unwrap().bytes_received, 4);
+        assert_eq!(store.stats().total.bytes_stored, 12);
+
+        // Compacting keeps what is in flight and drops the rest.
+        compact(&path, &store.snapshot()).await.unwrap();
+        let restored = SessionStore::new();
+        assert_eq!(restored.restore(replay(&path).await.unwrap()), 2);
+        assert_eq!(restored.stats().total.bytes_stored, 12);
+        assert_eq!(restored.chunk_digest("a", 2), store.chunk_digest("a", 2));
+    }
+}
@@ -0,0 +1,287 @@
+use std::{io, sync::Arc};
+
+use hyper::Uri;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    TlsConnector,
+    rustls::{self, pki_types::ServerName},
+};
+
+use crate::constants;
+
+/// Directory that Basic auth credentials are checked against, by binding as the user.
+#[derive(Debug, Clone)]
+pub struct LdapConfig {
+    /// `ldap://host[:389]` or `ldaps://host[:636]`.
+    pub url: Uri,
+    /// DN to bind as, with `{user}` standing for the username, e.g.
+    /// `uid={user},ou=people,dc=example,dc=com`.
+    pub bind_dn: String,
+    /// Used for `ldaps`.
+    pub tls: Arc<rustls::ClientConfig>,
+}
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_ENUMERATED: u8 = 0x0a;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_BIND_REQUEST: u8 = 0x60;
+const TAG_BIND_RESPONSE: u8 = 0x61;
+const TAG_UNBIND_REQUEST: u8 = 0x42;
+const TAG_SIMPLE_AUTH: u8 = 0x80;
+
+const RESULT_SUCCESS: u32 = 0;
+const RESULT_NO_SUCH_OBJECT: u32 = 32;
+const RESULT_INAPPROPRIATE_AUTHENTICATION: u32 = 48;
+const RESULT_INVALID_CREDENTIALS: u32 = 49;
+
+/// Whether the directory accepts `password` for `user`, tried with an LDAPv3
+/// simple bind. Errors mean the directory couldn't be asked.
+pub async fn bind(config: &LdapConfig, user: &str, password: &str) -> io::Result<bool> {
+    // With an empty password the bind is unauthenticated, which servers allow for any DN.
+    if user.is_empty() || password.is_empty() {
+        return Ok(false);
+    }
+    let ldaps = match config.url.scheme_str() {
+        Some("ldaps") => true,
+        Some("ldap") => false,
+        _ => return Err(invalid_input("LDAP URL must be ldap or ldaps")),
+    };
+    let host = config
+        .url
+        .host()
+        .ok_or_else(|| invalid_input("LDAP URL has no host"))?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let port = config
+        .url
+        .port_u16()
+        .unwrap_or(if ldaps { 636 } else { 389 });
+    let request = bind_request(
+        &config.bind_dn.replace("{user}", &escape_dn_value(user)),
+        password,
+    );
+
+    let stream = TcpStream::connect((host, port)).await?;
+    let result = if ldaps {
+        let name = ServerName::try_from(host.to_string()).map_err(invalid_input)?;
+        let stream = TlsConnector::from(config.tls.clone())
+            .connect(name, stream)
+            .await?;
+        exchange(stream, &request).await?
+    } else {
+        exchange(stream, &request).await?
+    };
+    match result {
+        RESULT_SUCCESS => Ok(true),
+        RESULT_NO_SUCH_OBJECT
+        | RESULT_INAPPROPRIATE_AUTHENTICATION
+        | RESULT_INVALID_CREDENTIALS => Ok(false),
+        code => Err(io::Error::other(format!(
+            "LDAP bind failed with result code {}",
+            code
+        ))),
+    }
+}
+
+/// Sends the bind request and returns the result code of the response.
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    request: &[u8],
+) -> io::Result<u32> {
+    stream.write_all(request).await?;
+    stream.flush().await?;
+
+    let (tag, message) = read_element(&mut stream).await?;
+    if tag != TAG_SEQUENCE {
+        return Err(invalid_data("LDAP response is not a message"));
+    }
+    let mut message = message.as_slice();
+    expect(&mut message, TAG_INTEGER)?;
+    let mut response = expect(&mut message, TAG_BIND_RESPONSE)?;
+    let code = expect(&mut response, TAG_ENUMERATED)?;
+    if code.is_empty() || code.len() > 4 {
+        return Err(invalid_data("Invalid LDAP result code"));
+    }
+    let code = code
+        .iter()
+        .fold(0, |code, byte| (code << 8) | u32::from(*byte));
+
+    let unbind = element(
+        TAG_SEQUENCE,
+        &[element(TAG_INTEGER, &[2]), element(TAG_UNBIND_REQUEST, &[])].concat(),
+    );
+    let _ = stream.write_all(&unbind).await;
+    let _ = stream.shutdown().await;
+    Ok(code)
+}
+
+fn bind_request(dn: &str, password: &str) -> Vec<u8> {
+    let bind = [
+        element(TAG_INTEGER, &[3]),
+        element(TAG_OCTET_STRING, dn.as_bytes()),
+        element(TAG_SIMPLE_AUTH, password.as_bytes()),
+    ]
+    .concat();
+    element(
+        TAG_SEQUENCE,
+        &[element(TAG_INTEGER, &[1]), element(TAG_BIND_REQUEST, &bind)].concat(),
+    )
+}
+
+/// BER encoding with a definite length.
+fn element(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    if value.len() < 0x80 {
+        out.push(value.len() as u8);
+    } else {
+        let len = value.len().to_be_bytes();
+        let skip = len.iter().take_while(|byte| **byte == 0).count();
+        out.push(0x80 | (len.len() - skip) as u8);
+        out.extend_from_slice(&len[skip..]);
+    }
+    out.extend_from_slice(value);
+    out
+}
+
+async fn read_element<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<(u8, Vec<u8>)> {
+    let tag = stream.read_u8().await?;
+    let first = stream.read_u8().await?;
+    let len = if first < 0x80 {
+        usize::from(first)
+    } else {
+        let count = usize::from(first & 0x7f);
+        if count == 0 || count > 4 {
+            return Err(invalid_data("Unsupported LDAP length encoding"));
+        }
+        let mut len = 0;
+        for _ in 0..count {
+            len = (len << 8) | usize::from(stream.read_u8().await?);
+        }
+        len
+    };
+    if len > constants::MAX_LDAP_RESPONSE_BYTES {
+        return Err(invalid_data("LDAP response too large"));
+    }
+    let mut value = vec![0; len];
+    stream.read_exact(&mut value).await?;
+    Ok((tag, value))
+}
+
+/// Takes the next element off `input`, which must have tag `tag`.
+fn expect<'a>(input: &mut &'a [u8], tag: u8) -> io::Result<&'a [u8]> {
+    let truncated = || invalid_data("Truncated LDAP response");
+    let (&found, rest) = input.split_first().ok_or_else(truncated)?;
+    if found != tag {
+        return Err(invalid_data("Unexpected element in LDAP response"));
+    }
+    let (&first, mut rest) = rest.split_first().ok_or_else(truncated)?;
+    let len = if first < 0x80 {
+        usize::from(first)
+    } else {
+        let count = usize::from(first & 0x7f);
+        if count == 0 || count > 4 || rest.len() < count {
+            return Err(truncated());
+        }
+        let (len, after) = rest.split_at(count);
+        rest = after;
+        len.iter()
+            .fold(0, |len, byte| (len << 8) | usize::from(*byte))
+    };
+    if rest.len() < len {
+        return Err(truncated());
+    }
+    let (value, rest) = rest.split_at(len);
+    *input = rest;
+    Ok(value)
+}
+
+/// Escapes a username for use as an attribute value in a DN (RFC 4514), so it
+/// can't change which entry is bound to.
+fn escape_dn_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let last = value.chars().count().saturating_sub(1);
+    for (i, c) in value.chars().enumerate() {
+        match c {
+            '\\' | ',' | '+' | '"' | '<' | '>' | ';' | '=' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '#' if i == 0 => escaped.push_str("\\#"),
+            ' ' if i == 0 || i == last => escaped.push_str("\\ "),
+            '\0' => escaped.push_str("\\00"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn invalid_input(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usernames_cannot_change_the_dn() {
+        assert_eq!(escape_dn_value("alice"), "alice");
+        assert_eq!(escape_dn_value("x,ou=admins"), "x\\,ou\\=admins");
+        assert_eq!(escape_dn_value("#a b "), "\\#a b\\ ");
+    }
+
+    #[tokio::test]
+    async fn test_bind_against_directory() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ldap://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let (_, request) = read_element(&mut stream).await.unwrap();
+                let mut request = request.as_slice();
+                expect(&mut request, TAG_INTEGER).unwrap();
+                let mut bind = expect(&mut request, TAG_BIND_REQUEST).unwrap();
+                expect(&mut bind, TAG_INTEGER).unwrap();
+                let dn = expect(&mut bind, TAG_OCTET_STRING).unwrap().to_vec();
+                let password = expect(&mut bind, TAG_SIMPLE_AUTH).unwrap();
+                let code = if dn == b"uid=alice,dc=example" && password == b"s3cret" {
+                    RESULT_SUCCESS
+                } else {
+                    RESULT_INVALID_CREDENTIALS
+                };
+                let response = [
+                    element(TAG_ENUMERATED, &[code as u8]),
+                    element(TAG_OCTET_STRING, b""),
+                    element(TAG_OCTET_STRING, b""),
+                ]
+                .concat();
+                let message = element(
+                    TAG_SEQUENCE,
+                    &[
+                        element(TAG_INTEGER, &[1]),
+                        element(TAG_BIND_RESPONSE, &response),
+                    ]
+                    .concat(),
+                );
+                stream.write_all(&message).await.unwrap();
+            }
+        });
+
+        let config = LdapConfig {
+            url: url.parse().unwrap(),
+            bind_dn: "uid={user},dc=example".to_string(),
+            tls: Arc::new(crate::tls::load_client_config(None).unwrap()),
+        };
+        assert!(bind(&config, "alice", "s3cret").await.unwrap());
+        assert!(!bind(&config, "alice", "wrong").await.unwrap());
+        assert!(!bind(&config, "alice", "").await.unwrap());
+    }
+}
@@ -0,0 +1,65 @@
+pub mod alerts;
+pub mod archive;
+pub mod audit;
+pub mod auth;
+pub mod backpressure;
+pub mod basic_auth;
+pub mod bitmap;
+pub mod body;
+pub mod bundle;
+pub mod catalog;
+pub mod cdc;
+pub mod chaos;
+pub mod checksum;
+pub mod chunk_size;
+pub mod cluster;
+pub mod compression;
+pub mod config;
+pub mod constants;
+pub mod crypto;
+pub mod debug_log;
+pub mod delta;
+pub mod digest;
+pub mod error;
+pub mod extract;
+pub mod filename;
+pub mod headers;
+pub mod histogram;
+pub mod http;
+pub mod introspection;
+pub mod io;
+pub mod ipfilter;
+pub mod journal;
+pub mod layout;
+pub mod ldap;
+pub mod listener;
+pub mod merkle;
+pub mod multipart;
+pub mod output;
+pub mod peer;
+pub mod policy;
+pub mod pool;
+#[cfg(feature = "postgres")]
+pub mod postgres_store;
+pub mod protocol;
+pub mod purge;
+pub mod ranges;
+#[cfg(feature = "redis")]
+pub mod redis_store;
+pub mod replication;
+pub mod retention;
+pub mod runtime;
+pub mod sandbox;
+pub mod server;
+pub mod session;
+pub mod share;
+pub mod sidecar;
+pub mod stats;
+pub mod tags;
+pub mod throttle;
+pub mod tls;
+#[cfg(feature = "ui")]
+pub mod ui;
+pub mod user_metadata;
+
+pub use listener::serve;
@@ -0,0 +1,80 @@
+use std::{convert::Infallible, sync::Arc};
+
+use hyper::{body::Incoming, service::Service};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{server::SliceBreadServer, tls};
+
+/// Accepts connections until the listener fails, serving HTTP/1.1 and HTTP/2
+/// (h2c or ALPN over TLS) on each one.
+pub async fn serve(
+    listener: TcpListener,
+    server: Arc<SliceBreadServer<Incoming>>,
+    tls: Option<TlsAcceptor>,
+) -> std::io::Result<()> {
+    let builder = server.http_config().builder();
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let server = server.for_connection(peer.ip());
+        let tls = tls.clone();
+        let builder = builder.clone();
+
+        tokio::task::spawn(async move {
+            let result = match tls {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => {
+                        let identities = stream
+                            .get_ref()
+                            .1
+                            .peer_certificates()
+                            .and_then(|certs| certs.first())
+                            .map(tls::certificate_identities);
+                        let server = match identities {
+                            Some(identities) => server.with_client_identities(identities),
+                            None => server,
+                        };
+                        serve_connection(&builder, stream, server).await
+                    }
+                    Err(err) => {
+                        tracing::warn!(%peer, %err, "TLS handshake failed");
+                        return;
+                    }
+                },
+                None => serve_connection(&builder, stream, server).await,
+            };
+
+            if let Err(err) = result {
+                tracing::error!("Failed to serve connection: {:?}", err);
+            }
+        });
+    }
+}
+
+async fn serve_connection<I>(
+    builder: &auto::Builder<TokioExecutor>,
+    io: I,
+    server: SliceBreadServer<Incoming>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let server = Arc::new(server);
+    let service = hyper::service::service_fn(move |req| {
+        let server = server.clone();
+        async move {
+            Ok::<_, Infallible>(match server.call(req).await {
+                Ok(response) => response,
+                Err(err) => err.into_response(),
+            })
+        }
+    });
+    builder.serve_connection(TokioIo::new(io), service).await
+}
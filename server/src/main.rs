@@ -1,17 +1,39 @@
-use hyper::server::conn::http1;
-use hyper_util::rt::TokioIo;
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, num::NonZeroUsize, path::PathBuf, sync::Arc, time::Duration};
 use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
 
 use dotenvy::dotenv;
 
-use crate::server::SliceBreadServer;
+use server::{
+    alerts::{AlertConfig, AlertRules},
+    auth::IdentityRule,
+    backpressure::BackpressureConfig,
+    basic_auth::{BasicAuthConfig, Htpasswd},
+    chaos::ChaosConfig,
+    cluster::{ClusterConfig, StoreLocation},
+    config::{BodyLimits, Durability, ServerConfig},
+    constants, crypto,
+    debug_log::DebugLogFilter,
+    extract::ExtractLimits,
+    headers::HeaderNames,
+    http::HttpConfig,
+    introspection::IntrospectionConfig,
+    ipfilter::{Cidr, IpFilter},
+    layout::ChunkLayout,
+    ldap::LdapConfig,
+    output::OutputTemplate,
+    peer::PeerConfig,
+    retention::{RetentionAction, RetentionRule},
+    runtime::{RuntimeConfig, RuntimeFlavor},
+    sandbox::{self, Privileges},
+    server::{SliceBreadServer, Surface},
+    session::SessionLimits,
+    throttle::ThrottleConfig,
+    tls,
+};
 use tracing_subscriber::filter::EnvFilter;
 
-mod constants;
-mod server;
-
-use clap::{Parser, command};
+use clap::Parser;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -19,10 +41,353 @@ pub struct Args {
     /// Server address of the person to greet
     #[arg(long, env = "API_PORT")]
     port: u16,
+
+    /// Serve /admin/* only on this address (e.g. 127.0.0.1:9090) instead of the upload port
+    #[arg(long, env = "ADMIN_ADDR")]
+    admin_addr: Option<SocketAddr>,
+
+    /// PEM certificate chain; enables TLS together with --tls-key
+    #[arg(long, env = "TLS_CERT_PATH", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, env = "TLS_KEY_PATH", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// PEM CA bundle; requires every TLS client to present a certificate signed by it
+    #[arg(long, env = "TLS_CLIENT_CA_PATH", requires = "tls_cert")]
+    tls_client_ca: Option<PathBuf>,
+
+    /// Maps a client certificate CN/SAN to a tenant, as `name=tenant` or `name=tenant:scope+scope` (upload, download, delete, admin); repeat for several
+    #[arg(long, env = "CLIENT_IDENTITIES", value_delimiter = ',')]
+    client_identity: Vec<IdentityRule>,
+
+    /// RFC 7662 token introspection endpoint; clients without a mapped certificate must send an active bearer token
+    #[arg(long, env = "INTROSPECTION_URL")]
+    introspection_url: Option<hyper::Uri>,
+
+    /// Client id the server authenticates to the introspection endpoint with
+    #[arg(long, env = "INTROSPECTION_CLIENT_ID", requires = "introspection_url")]
+    introspection_client_id: Option<String>,
+
+    /// Client secret for --introspection-client-id
+    #[arg(
+        long,
+        env = "INTROSPECTION_CLIENT_SECRET",
+        hide_env_values = true,
+        requires = "introspection_client_id"
+    )]
+    introspection_client_secret: Option<String>,
+
+    /// Seconds an introspection result is reused, at most until the token expires
+    #[arg(long, env = "INTROSPECTION_CACHE_TTL", default_value_t = constants::DEFAULT_INTROSPECTION_CACHE_TTL.as_secs())]
+    introspection_cache_ttl: u64,
+
+    /// Introspection claim holding the tenant; tokens without it act as the default tenant
+    #[arg(long, env = "INTROSPECTION_TENANT_CLAIM", default_value = "tenant")]
+    introspection_tenant_claim: String,
+
+    /// PEM CA bundle the introspection endpoint's certificate is checked against, instead of the public roots
+    #[arg(long, env = "INTROSPECTION_CA_PATH", requires = "introspection_url")]
+    introspection_ca: Option<PathBuf>,
+
+    /// htpasswd file of bcrypt hashes (htpasswd -B); clients without a mapped certificate may log in with HTTP Basic auth
+    #[arg(long, env = "HTPASSWD_PATH", conflicts_with = "ldap_url")]
+    htpasswd: Option<PathBuf>,
+
+    /// LDAP server Basic auth credentials are checked against by binding, e.g. ldaps://ldap.internal
+    #[arg(long, env = "LDAP_URL", requires = "ldap_bind_dn")]
+    ldap_url: Option<hyper::Uri>,
+
+    /// DN to bind as, with {user} replaced by the username, e.g. uid={user},ou=people,dc=example,dc=com
+    #[arg(long, env = "LDAP_BIND_DN", requires = "ldap_url")]
+    ldap_bind_dn: Option<String>,
+
+    /// PEM CA bundle the LDAP server's certificate is checked against, instead of the public roots
+    #[arg(long, env = "LDAP_CA_PATH", requires = "ldap_url")]
+    ldap_ca: Option<PathBuf>,
+
+    /// HMAC secret for X-Upload-Policy tokens; when set, every upload must carry a valid policy
+    #[arg(long, env = "UPLOAD_POLICY_SECRET", hide_env_values = true)]
+    upload_policy_secret: Option<String>,
+
+    /// HMAC secret share links are signed with; owners can create links only when set
+    #[arg(long, env = "SHARE_LINK_SECRET", hide_env_values = true)]
+    share_link_secret: Option<String>,
+
+    /// User (name or uid) to switch to once the port is bound
+    #[arg(long, env = "RUN_AS_USER")]
+    user: Option<String>,
+
+    /// Group (name or gid) to switch to; defaults to the user's primary group
+    #[arg(long, env = "RUN_AS_GROUP", requires = "user")]
+    group: Option<String>,
+
+    /// Confine file access to the upload, replication, archive and audit directories (Linux, Landlock)
+    #[arg(long, env = "SANDBOX")]
+    sandbox: bool,
+
+    /// Dev-only fault injection for chunk writes, e.g. `p_fail=0.1,latency=200ms`
+    #[arg(long, env = "CHAOS")]
+    chaos: Option<ChaosConfig>,
+
+    /// Largest X-Total-Chunks a client may declare
+    #[arg(long, env = "MAX_TOTAL_CHUNKS", default_value_t = constants::DEFAULT_MAX_TOTAL_CHUNKS)]
+    max_total_chunks: usize,
+
+    /// Reject chunk uploads without a Content-Length header with 411
+    #[arg(long, env = "REQUIRE_CONTENT_LENGTH")]
+    require_content_length: bool,
+
+    /// Largest chunk request body, in bytes
+    #[arg(long, env = "MAX_CHUNK_BODY_BYTES")]
+    max_chunk_body_bytes: Option<u64>,
+
+    /// Smallest chunk accepted other than the last of an upload, in bytes
+    #[arg(long, env = "MIN_CHUNK_BYTES")]
+    min_chunk_bytes: Option<u64>,
+
+    /// Largest byte range request body, in bytes
+    #[arg(long, env = "MAX_RANGE_BODY_BYTES")]
+    max_range_body_bytes: Option<u64>,
+
+    /// Largest delta request body, in bytes
+    #[arg(long, env = "MAX_DELTA_BODY_BYTES")]
+    max_delta_body_bytes: Option<u64>,
+
+    /// Largest batch request body, in bytes
+    #[arg(long, env = "MAX_BATCH_BODY_BYTES")]
+    max_batch_body_bytes: Option<u64>,
+
+    /// Compress JSON responses with zstd or gzip, as negotiated by Accept-Encoding
+    #[arg(long, env = "COMPRESS_RESPONSES")]
+    compress_responses: bool,
+
+    /// Log headers and responses of requests for these file ids, to troubleshoot a client; repeat or comma-separate for several
+    #[arg(long, env = "DEBUG_LOG_FILE_IDS", value_delimiter = ',')]
+    debug_log_file_id: Vec<String>,
+
+    /// Log headers and responses of requests from these tenants; repeat or comma-separate for several
+    #[arg(long, env = "DEBUG_LOG_TENANTS", value_delimiter = ',')]
+    debug_log_tenant: Vec<String>,
+
+    /// Most bytes of each response body written to the debug log
+    #[arg(long, env = "DEBUG_LOG_BODY_BYTES")]
+    debug_log_body_bytes: Option<usize>,
+
+    /// WORM mode: make assembled files read-only and refuse deletes outside the admin API
+    #[arg(long, env = "IMMUTABLE")]
+    immutable: bool,
+
+    /// Where assembled files land relative to the upload directory, e.g. `{tenant}/{date}/{file_id}/{file_name}`
+    #[arg(long, env = "OUTPUT_TEMPLATE", default_value = "{file_id}/{file_name}")]
+    output_template: OutputTemplate,
+
+    /// How chunk files are named while an upload is in progress, e.g. `pad=6,fanout=1000`
+    #[arg(long, env = "CHUNK_LAYOUT", default_value = "flat")]
+    chunk_layout: ChunkLayout,
+
+    /// Other names for the protocol's X- headers, e.g. `X-File-Id=File-Id,X-Chunk-Index=Chunk-Index`
+    #[arg(long, env = "HEADER_NAMES")]
+    header_names: Option<HeaderNames>,
+
+    /// What is fsynced before a write is acknowledged: `none`, `file` (the assembled file) or `chunk` (every chunk too)
+    #[arg(long, env = "DURABILITY", default_value = "file")]
+    durability: Durability,
+
+    /// Most bytes an archive uploaded with `X-Extract` may expand to
+    #[arg(long, env = "EXTRACT_MAX_BYTES", default_value_t = constants::DEFAULT_EXTRACT_MAX_BYTES)]
+    extract_max_bytes: u64,
+
+    /// Most entries an archive uploaded with `X-Extract` may have
+    #[arg(long, env = "EXTRACT_MAX_ENTRIES", default_value_t = constants::DEFAULT_EXTRACT_MAX_ENTRIES)]
+    extract_max_entries: usize,
+
+    /// Seconds an upload may take from its first chunk before it is expired and its chunks deleted
+    #[arg(long, env = "MAX_UPLOAD_DURATION")]
+    max_upload_duration: Option<u64>,
+
+    /// Most uploads one client IP may have in progress at once
+    #[arg(long, env = "MAX_SESSIONS_PER_CLIENT")]
+    max_sessions_per_client: Option<usize>,
+
+    /// Most uploads one tenant may have in progress at once
+    #[arg(long, env = "MAX_SESSIONS_PER_TENANT")]
+    max_sessions_per_tenant: Option<usize>,
+
+    /// Most bytes one tenant may store; uploads past it are refused with 507
+    #[arg(long, env = "TENANT_QUOTA_BYTES")]
+    tenant_quota_bytes: Option<u64>,
+
+    /// Webhook alerts are posted to, generic or Slack incoming; repeat or comma-separate for several
+    #[arg(long, env = "ALERT_WEBHOOKS", value_delimiter = ',')]
+    alert_webhook: Vec<hyper::Uri>,
+
+    /// PEM CA bundle alert webhooks' certificates are checked against, instead of the public roots
+    #[arg(long, env = "ALERT_WEBHOOK_CA_PATH")]
+    alert_webhook_ca: Option<PathBuf>,
+
+    /// Alert when more than this percentage of requests fail with a server error over --alert-error-window
+    #[arg(long, env = "ALERT_ERROR_RATE_PERCENT")]
+    alert_error_rate_percent: Option<f64>,
+
+    /// Seconds over which the error rate is measured
+    #[arg(long, env = "ALERT_ERROR_WINDOW", default_value_t = constants::DEFAULT_ALERT_ERROR_WINDOW.as_secs())]
+    alert_error_window: u64,
+
+    /// Alert when less than this percentage of the upload disk is free
+    #[arg(long, env = "ALERT_DISK_FREE_PERCENT")]
+    alert_disk_free_percent: Option<f64>,
+
+    /// Alert when a tenant has used this percentage of --tenant-quota-bytes
+    #[arg(long, env = "ALERT_QUOTA_PERCENT", requires = "tenant_quota_bytes")]
+    alert_quota_percent: Option<f64>,
+
+    /// Total chunk ingest bandwidth across all connections, in bytes per second
+    #[arg(long, env = "MAX_INGEST_RATE")]
+    max_ingest_rate: Option<u64>,
+
+    /// Chunk ingest bandwidth per connection, in bytes per second
+    #[arg(long, env = "MAX_CONNECTION_INGEST_RATE")]
+    max_connection_ingest_rate: Option<u64>,
+
+    /// Shed chunk uploads with 503 once this many are in flight
+    #[arg(long, env = "MAX_IN_FLIGHT_UPLOADS")]
+    max_in_flight_uploads: Option<usize>,
+
+    /// Shed final chunks with 503 once this many files are being assembled
+    #[arg(long, env = "MAX_PENDING_ASSEMBLIES")]
+    max_pending_assemblies: Option<usize>,
+
+    /// Shed chunk uploads with 503 when the upload volume has less free space than this, in bytes
+    #[arg(long, env = "MIN_FREE_DISK_BYTES")]
+    min_free_disk_bytes: Option<u64>,
+
+    /// Start in read-only maintenance mode: downloads and status work, writes get 503 until PUT /admin/maintenance turns it off
+    #[arg(long, env = "READ_ONLY")]
+    read_only: bool,
+
+    /// Shed chunk uploads with 503 while request bodies buffered in memory would exceed this many bytes
+    #[arg(long, env = "MAX_BUFFERED_BYTES")]
+    max_buffered_bytes: Option<u64>,
+
+    /// Directories completed files are copied to in the background; repeat or comma-separate for several
+    #[arg(long, env = "REPLICATE_TO", value_delimiter = ',')]
+    replicate_to: Vec<PathBuf>,
+
+    /// How long completed files are kept, as `scope=period[:archive]` with scope `*`, `tenant:<name>` or `category:<name>`, e.g. `tenant:acme=30d`; repeat or comma-separate for several
+    #[arg(long, env = "RETENTION", value_delimiter = ',')]
+    retention: Vec<RetentionRule>,
+
+    /// Cold storage directory files are moved to when an `archive` retention rule expires them
+    #[arg(long, env = "ARCHIVE_TO")]
+    archive_to: Option<PathBuf>,
+
+    /// Append-only audit trail (JSON Lines); defaults to .audit.jsonl in the upload directory
+    #[arg(long, env = "AUDIT_LOG")]
+    audit_log: Option<PathBuf>,
+
+    /// Joins a cluster under this id: instances sharing the upload directory and cluster store may each take any chunk of an upload
+    #[arg(long, env = "CLUSTER_NODE_ID")]
+    cluster_node_id: Option<String>,
+
+    /// Where cluster state is kept, as `dir:<path>`, or `redis://<host>` or `postgres://<host>/<db>` with the matching feature; defaults to .cluster in the upload directory
+    #[arg(long, env = "CLUSTER_STORE")]
+    cluster_store: Option<StoreLocation>,
+
+    /// Base URL of another instance (repeatable), for clusters whose instances keep chunks on their own disks; the assembling instance fetches missing chunks from them
+    #[arg(
+        long,
+        env = "CLUSTER_PEERS",
+        value_delimiter = ',',
+        requires = "cluster_peer_secret"
+    )]
+    cluster_peer: Vec<hyper::Uri>,
+
+    /// Secret shared by every instance, signing requests to the internal endpoints peers fetch chunks from
+    #[arg(long, env = "CLUSTER_PEER_SECRET", requires = "cluster_node_id")]
+    cluster_peer_secret: Option<String>,
+
+    /// PEM file of CAs trusted for https peers; defaults to the public web PKI roots
+    #[arg(long, env = "CLUSTER_PEER_CA", requires = "cluster_peer_secret")]
+    cluster_peer_ca: Option<PathBuf>,
+
+    /// Tokio scheduler: `multi_thread`, or `current_thread` to run on a single thread
+    #[arg(long, env = "RUNTIME_FLAVOR", default_value = "multi_thread")]
+    runtime_flavor: RuntimeFlavor,
+
+    /// Async worker threads for the multi_thread runtime; defaults to one per CPU
+    #[arg(long, env = "WORKER_THREADS")]
+    worker_threads: Option<NonZeroUsize>,
+
+    /// Most threads for file I/O and hashing, on top of the workers; defaults to 512
+    #[arg(long, env = "MAX_BLOCKING_THREADS")]
+    max_blocking_threads: Option<NonZeroUsize>,
+
+    /// Seconds an idle blocking thread is kept before it exits; defaults to 10
+    #[arg(long, env = "BLOCKING_THREAD_KEEP_ALIVE")]
+    blocking_thread_keep_alive: Option<u64>,
+
+    /// Set to false to close HTTP/1.1 connections after each request
+    #[arg(long, env = "HTTP1_KEEP_ALIVE")]
+    http1_keep_alive: Option<bool>,
+
+    /// Most header fields an HTTP/1.1 request may have
+    #[arg(long, env = "HTTP1_MAX_HEADERS")]
+    http1_max_headers: Option<usize>,
+
+    /// Largest HTTP/1.1 read buffer in bytes, which also bounds the request head (min 8192)
+    #[arg(long, env = "HTTP1_MAX_BUF_SIZE")]
+    http1_max_buf_size: Option<usize>,
+
+    /// Seconds a client may take to send the HTTP/1.1 request head
+    #[arg(long, env = "HEADER_READ_TIMEOUT")]
+    header_read_timeout: Option<u64>,
+
+    /// Largest HTTP/2 header list in bytes
+    #[arg(long, env = "HTTP2_MAX_HEADER_LIST_SIZE")]
+    http2_max_header_list_size: Option<u32>,
+
+    /// Initial HTTP/2 flow-control window per stream, in bytes
+    #[arg(long, env = "HTTP2_STREAM_WINDOW_SIZE")]
+    http2_stream_window_size: Option<u32>,
+
+    /// Initial HTTP/2 flow-control window per connection, in bytes
+    #[arg(long, env = "HTTP2_CONNECTION_WINDOW_SIZE")]
+    http2_connection_window_size: Option<u32>,
+
+    /// Grow HTTP/2 windows based on measured bandwidth-delay product; overrides the window sizes
+    #[arg(long, env = "HTTP2_ADAPTIVE_WINDOW")]
+    http2_adaptive_window: bool,
+
+    /// Most concurrent HTTP/2 streams per connection
+    #[arg(long, env = "HTTP2_MAX_CONCURRENT_STREAMS")]
+    http2_max_concurrent_streams: Option<u32>,
+
+    /// Seconds between HTTP/2 keep-alive pings
+    #[arg(long, env = "HTTP2_KEEP_ALIVE_INTERVAL")]
+    http2_keep_alive_interval: Option<u64>,
+
+    /// Seconds to wait for a keep-alive ping acknowledgement before closing the connection
+    #[arg(long, env = "HTTP2_KEEP_ALIVE_TIMEOUT")]
+    http2_keep_alive_timeout: Option<u64>,
+
+    /// Only accept clients in these CIDR ranges; repeat or comma-separate for several
+    #[arg(long, env = "ALLOW_CIDRS", value_delimiter = ',')]
+    allow_cidr: Vec<Cidr>,
+
+    /// Refuse clients in these CIDR ranges, even if allowed by --allow-cidr
+    #[arg(long, env = "DENY_CIDRS", value_delimiter = ',')]
+    deny_cidr: Vec<Cidr>,
+
+    /// Proxies whose X-Forwarded-For header identifies the real client
+    #[arg(long, env = "TRUSTED_PROXIES", value_delimiter = ',')]
+    trusted_proxy: Vec<Cidr>,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// Privileges are dropped and the sandbox applied before the runtime starts, so
+/// that they cover every thread.
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     dotenv().ok();
 
     tracing_subscriber::fmt()
@@ -33,19 +398,325 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let args = Args::parse();
     let addr = SocketAddr::from(([127, 0, 0, 1], args.port));
 
-    let listener = TcpListener::bind(addr).await?;
-    tracing::info!("Listening on http://{}", addr);
+    let tls = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => Some(TlsAcceptor::from(Arc::new(tls::load_server_config(
+            cert,
+            key,
+            args.tls_client_ca.as_deref(),
+        )?))),
+        _ => None,
+    };
+
+    let introspection = match args.introspection_url {
+        Some(url) => Some(IntrospectionConfig {
+            url,
+            client_id: args.introspection_client_id,
+            client_secret: args.introspection_client_secret,
+            cache_ttl: Duration::from_secs(args.introspection_cache_ttl),
+            tenant_claim: args.introspection_tenant_claim,
+            tls: Arc::new(tls::load_client_config(args.introspection_ca.as_deref())?),
+        }),
+        None => None,
+    };
+
+    let alert_rules = AlertRules {
+        error_rate_percent: args.alert_error_rate_percent,
+        error_window: Duration::from_secs(args.alert_error_window),
+        disk_free_percent: args.alert_disk_free_percent,
+        quota_percent: args.alert_quota_percent,
+    };
+    let alerts = if [
+        alert_rules.error_rate_percent,
+        alert_rules.disk_free_percent,
+        alert_rules.quota_percent,
+    ]
+    .iter()
+    .any(Option::is_some)
+    {
+        Some(AlertConfig {
+            webhooks: args.alert_webhook,
+            rules: alert_rules,
+            tls: Arc::new(tls::load_client_config(args.alert_webhook_ca.as_deref())?),
+        })
+    } else if !args.alert_webhook.is_empty() {
+        return Err("--alert-webhook needs at least one alert rule".into());
+    } else {
+        None
+    };
+
+    let basic_auth = match (args.htpasswd, args.ldap_url, args.ldap_bind_dn) {
+        (Some(path), _, _) => Some(BasicAuthConfig::Htpasswd(Arc::new(Htpasswd::load(&path)?))),
+        (None, Some(url), Some(bind_dn)) => Some(BasicAuthConfig::Ldap(LdapConfig {
+            url,
+            bind_dn,
+            tls: Arc::new(tls::load_client_config(args.ldap_ca.as_deref())?),
+        })),
+        _ => None,
+    };
+
+    if args.archive_to.is_none()
+        && args
+            .retention
+            .iter()
+            .any(|rule| rule.action == RetentionAction::Archive)
+    {
+        return Err("Retention rules that archive files need --archive-to".into());
+    }
+
+    if let (Some(min), Some(max)) = (args.min_chunk_bytes, args.max_chunk_body_bytes)
+        && min > max
+    {
+        return Err("--min-chunk-bytes is larger than --max-chunk-body-bytes".into());
+    }
+
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    tracing::info!(fips = crypto::FIPS, "Listening on {}://{}", scheme, addr);
+    // Bound here too, so that a privileged port still works after dropping privileges.
+    let admin_listener = match args.admin_addr {
+        Some(admin_addr) => {
+            let listener = std::net::TcpListener::bind(admin_addr)?;
+            listener.set_nonblocking(true)?;
+            tracing::info!("Admin API listening on {}://{}", scheme, admin_addr);
+            Some(listener)
+        }
+        None => None,
+    };
 
-    let server = Arc::new(SliceBreadServer::new(String::from("/uploads/")));
+    if let Some(chaos) = &args.chaos {
+        tracing::warn!(
+            ?chaos,
+            "Chaos mode enabled, chunk writes will be randomly delayed or failed"
+        );
+    }
+
+    let upload_dir = PathBuf::from("/uploads/");
+    if args.cluster_store.is_some() && args.cluster_node_id.is_none() {
+        return Err("--cluster-store needs --cluster-node-id".into());
+    }
+    let cluster_store = args
+        .cluster_store
+        .unwrap_or_else(|| StoreLocation::Dir(upload_dir.join(constants::CLUSTER_DIR)));
+    let cluster = match args.cluster_node_id {
+        Some(node_id) => Some(ClusterConfig {
+            node_id,
+            store: cluster_store.open()?,
+            peers: match args.cluster_peer_secret {
+                Some(secret) => Some(PeerConfig {
+                    peers: args.cluster_peer,
+                    secret: secret.into_bytes(),
+                    tls: Arc::new(tls::load_client_config(args.cluster_peer_ca.as_deref())?),
+                }),
+                None => None,
+            },
+        }),
+        None => None,
+    };
+    if let Some(cluster) = &cluster {
+        tracing::info!(
+            node_id = %cluster.node_id,
+            store = %cluster.store.name(),
+            peers = cluster.peers.as_ref().map_or(0, |peers| peers.peers.len()),
+            "Cluster mode enabled"
+        );
+    }
+
+    let config = ServerConfig {
+        chaos: args.chaos,
+        max_total_chunks: args.max_total_chunks,
+        require_content_length: args.require_content_length,
+        body_limits: BodyLimits {
+            chunk: args.max_chunk_body_bytes,
+            range: args.max_range_body_bytes,
+            delta: args.max_delta_body_bytes,
+            batch: args.max_batch_body_bytes,
+        },
+        min_chunk_bytes: args.min_chunk_bytes,
+        immutable: args.immutable,
+        output_template: args.output_template,
+        chunk_layout: args.chunk_layout,
+        header_names: args.header_names.unwrap_or_default(),
+        compress_responses: args.compress_responses,
+        debug_log: DebugLogFilter {
+            file_ids: args.debug_log_file_id,
+            tenants: args.debug_log_tenant,
+            max_body_bytes: args.debug_log_body_bytes,
+        },
+        durability: args.durability,
+        extract_limits: ExtractLimits {
+            max_bytes: args.extract_max_bytes,
+            max_entries: args.extract_max_entries,
+        },
+        max_upload_duration: args.max_upload_duration.map(Duration::from_secs),
+        session_limits: SessionLimits {
+            max_per_client: args.max_sessions_per_client,
+            max_per_tenant: args.max_sessions_per_tenant,
+        },
+        tenant_quota_bytes: args.tenant_quota_bytes,
+        throttle: ThrottleConfig {
+            global_bytes_per_sec: args.max_ingest_rate,
+            connection_bytes_per_sec: args.max_connection_ingest_rate,
+        },
+        backpressure: BackpressureConfig {
+            max_in_flight_uploads: args.max_in_flight_uploads,
+            max_pending_assemblies: args.max_pending_assemblies,
+            min_free_disk_bytes: args.min_free_disk_bytes,
+            max_buffered_bytes: args.max_buffered_bytes,
+            read_only: args.read_only,
+        },
+        replicate_to: args.replicate_to,
+        retention: args.retention,
+        archive_to: args.archive_to,
+        audit_log: args.audit_log,
+        cluster,
+        ip_filter: IpFilter {
+            allow: args.allow_cidr,
+            deny: args.deny_cidr,
+            trusted_proxies: args.trusted_proxy,
+        },
+        client_identities: args.client_identity,
+        introspection,
+        alerts,
+        basic_auth,
+        upload_policy_secret: args.upload_policy_secret,
+        share_link_secret: args.share_link_secret,
+        http: HttpConfig {
+            http1_keep_alive: args.http1_keep_alive,
+            http1_max_headers: args.http1_max_headers,
+            http1_max_buf_size: args.http1_max_buf_size,
+            header_read_timeout: args.header_read_timeout.map(Duration::from_secs),
+            http2_max_header_list_size: args.http2_max_header_list_size,
+            http2_initial_stream_window_size: args.http2_stream_window_size,
+            http2_initial_connection_window_size: args.http2_connection_window_size,
+            http2_adaptive_window: args.http2_adaptive_window,
+            http2_max_concurrent_streams: args.http2_max_concurrent_streams,
+            http2_keep_alive_interval: args.http2_keep_alive_interval.map(Duration::from_secs),
+            http2_keep_alive_timeout: args.http2_keep_alive_timeout.map(Duration::from_secs),
+        },
+    };
+    let mut sandbox_dirs = vec![upload_dir.clone()];
+    sandbox_dirs.extend(config.replicate_to.iter().cloned());
+    sandbox_dirs.extend(config.archive_to.iter().cloned());
+    if config.cluster.is_some()
+        && let StoreLocation::Dir(dir) = &cluster_store
+    {
+        sandbox_dirs.push(dir.clone());
+    }
+    if let Some(parent) = config.audit_log.as_deref().and_then(|path| path.parent()) {
+        sandbox_dirs.push(parent.to_path_buf());
+    }
+    let enforce_retention = !config.retention.is_empty();
+    let check_alerts = config.alerts.is_some();
+    let server = Arc::new(SliceBreadServer::with_config(
+        upload_dir.to_string_lossy().into_owned(),
+        config,
+    ));
 
-    loop {
-        let (stream, _) = listener.accept().await?;
-        let server = server.clone();
-        let io = TokioIo::new(stream);
-        tokio::task::spawn(async move {
-            if let Err(err) = http1::Builder::new().serve_connection(io, server).await {
-                tracing::error!("Failed to serve connection: {:?}", err);
+    if let Some(user) = args.user {
+        sandbox::drop_privileges(&Privileges {
+            user,
+            group: args.group,
+        })?;
+    }
+    if args.sandbox {
+        sandbox::restrict_filesystem(&sandbox_dirs)?;
+    }
+
+    let runtime = RuntimeConfig {
+        flavor: args.runtime_flavor,
+        worker_threads: args.worker_threads,
+        max_blocking_threads: args.max_blocking_threads,
+        thread_keep_alive: args.blocking_thread_keep_alive.map(Duration::from_secs),
+    };
+    tracing::info!(?runtime, "Starting runtime");
+    runtime.build()?.block_on(async move {
+        match server.restore_usage().await {
+            Ok(0) => {}
+            Ok(counted) => tracing::info!(counted, "Counted completed files towards usage"),
+            Err(err) => tracing::error!(%err, "Failed to count completed files towards usage"),
+        }
+        match server.restore_sessions().await {
+            Ok(0) => {}
+            Ok(restored) => tracing::info!(restored, "Restored uploads in progress"),
+            Err(err) => tracing::error!(%err, "Failed to restore uploads in progress"),
+        }
+        let sweeper = server.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(constants::EXPIRY_SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                match sweeper.expire_overdue().await {
+                    Ok(0) => {}
+                    Ok(expired) => tracing::info!(expired, "Expired overdue uploads"),
+                    Err(err) => tracing::error!(%err, "Failed to expire overdue uploads"),
+                }
             }
         });
+        if enforce_retention {
+            let reaper = server.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(constants::RETENTION_SWEEP_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    match reaper.enforce_retention().await {
+                        Ok(0) => {}
+                        Ok(retired) => tracing::info!(retired, "Retired files past retention"),
+                        Err(err) => tracing::error!(%err, "Failed to enforce retention"),
+                    }
+                }
+            });
+        }
+        if check_alerts {
+            let watcher = server.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(constants::ALERT_CHECK_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    watcher.check_alerts().await;
+                }
+            });
+        }
+        let listener = TcpListener::from_std(listener)?;
+        let serving = async {
+            let Some(admin_listener) = admin_listener else {
+                return server::serve(listener, server.clone(), tls).await;
+            };
+            let admin_listener = TcpListener::from_std(admin_listener)?;
+            let public = Arc::new(server.with_surface(Surface::Public));
+            let admin = Arc::new(server.with_surface(Surface::Admin));
+            tokio::try_join!(
+                server::serve(listener, public, tls.clone()),
+                server::serve(admin_listener, admin, tls),
+            )
+            .map(|_| ())
+        };
+        tokio::select! {
+            result = serving => result?,
+            signal = shutdown_signal() => {
+                signal?;
+                tracing::info!("Shutting down");
+            }
+        }
+        // The listeners are closed; requests already accepted finish first.
+        let persisted = server.shutdown().await?;
+        tracing::info!(persisted, "Saved uploads in progress");
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+    })?;
+    Ok(())
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM as sent by orchestrators for a rolling deploy.
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
     }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
 }
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    checksum::{self, ChunkDigest},
+    crypto::Sha256,
+};
+
+/// Binary Merkle tree whose leaves are the SHA-256 digests of the chunks, in
+/// order. A parent is `SHA-256(0x01 || left || right)`; an odd node out is
+/// carried up to the next level unchanged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleTree {
+    levels: Vec<Vec<ChunkDigest>>,
+}
+
+impl MerkleTree {
+    pub fn from_leaves(leaves: Vec<ChunkDigest>) -> Self {
+        let mut levels = vec![leaves];
+        while levels.last().is_some_and(|level| level.len() > 1) {
+            let next = levels
+                .last()
+                .expect("levels is never empty")
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => parent(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+        Self { levels }
+    }
+
+    /// Root of the tree; an upload always has at least one chunk, but an empty
+    /// tree hashes to the digest of no data.
+    pub fn root(&self) -> ChunkDigest {
+        self.levels
+            .last()
+            .and_then(|level| level.first())
+            .copied()
+            .unwrap_or_else(|| checksum::sha256(&[]))
+    }
+
+    /// `chunk_sizes` are the lengths of the leaves' chunks, in order.
+    pub fn manifest(&self, file_id: &str, chunk_sizes: Vec<u64>) -> Manifest {
+        Manifest {
+            file_id: file_id.to_string(),
+            algorithm: "sha-256".to_string(),
+            chunk_count: self.levels[0].len(),
+            chunk_sizes,
+            root: checksum::to_hex(&self.root()),
+            levels: self
+                .levels
+                .iter()
+                .map(|level| level.iter().map(checksum::to_hex).collect())
+                .collect(),
+        }
+    }
+}
+
+fn parent(left: &ChunkDigest, right: &ChunkDigest) -> ChunkDigest {
+    let mut hasher = Sha256::new();
+    hasher.update([0x01]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize()
+}
+
+/// Served by `GET /files/{id}/manifest`; `levels[0]` are the chunk digests and
+/// the last level holds only the root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub file_id: String,
+    pub algorithm: String,
+    pub chunk_count: usize,
+    /// Locates each chunk within the assembled file. Missing from manifests
+    /// written before chunks could be downloaded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunk_sizes: Vec<u64>,
+    pub root: String,
+    pub levels: Vec<Vec<String>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_chunk_root_is_chunk_digest() {
+        let leaf = checksum::sha256(b"only");
+        assert_eq!(MerkleTree::from_leaves(vec![leaf]).root(), leaf);
+    }
+
+    #[test]
+    fn test_odd_leaf_is_promoted() {
+        let leaves: Vec<_> = [b"a", b"b", b"c"]
+            .iter()
+            .map(|d| checksum::sha256(*d))
+            .collect();
+        let tree = MerkleTree::from_leaves(leaves.clone());
+
+        let ab = parent(&leaves[0], &leaves[1]);
+        assert_eq!(tree.root(), parent(&ab, &leaves[2]));
+
+        let manifest = tree.manifest("id", vec![1, 1, 1]);
+        assert_eq!(manifest.chunk_count, 3);
+        assert_eq!(manifest.levels.len(), 3);
+        assert_eq!(manifest.levels[1][1], checksum::to_hex(&leaves[2]));
+        assert_eq!(manifest.levels[2], vec![manifest.root.clone()]);
+    }
+}
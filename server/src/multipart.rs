@@ -0,0 +1,145 @@
+use bytes::Bytes;
+use hyper::{
+    HeaderMap,
+    header::{CONTENT_DISPOSITION, HeaderName, HeaderValue},
+};
+
+/// One part of a `multipart/form-data` body.
+#[derive(Debug)]
+pub struct Part {
+    pub headers: HeaderMap,
+    pub data: Bytes,
+}
+
+impl Part {
+    /// The `name` parameter of the part's `Content-Disposition`.
+    pub fn name(&self) -> Option<&str> {
+        self.disposition_param("name")
+    }
+
+    /// The `filename` parameter of the part's `Content-Disposition`.
+    pub fn filename(&self) -> Option<&str> {
+        self.disposition_param("filename")
+    }
+
+    fn disposition_param(&self, key: &str) -> Option<&str> {
+        let disposition = self.headers.get(CONTENT_DISPOSITION)?.to_str().ok()?;
+        disposition.split(';').skip(1).find_map(|param| {
+            let (name, value) = param.split_once('=')?;
+            (name.trim().eq_ignore_ascii_case(key)).then(|| value.trim().trim_matches('"'))
+        })
+    }
+}
+
+/// The boundary of a `multipart/form-data` content type, or `None` for any
+/// other content type.
+pub fn boundary(content_type: &str) -> Option<&str> {
+    let mut params = content_type.split(';');
+    if !params
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+    params.find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        let value = value.trim().trim_matches('"');
+        (name.trim().eq_ignore_ascii_case("boundary") && !value.is_empty()).then_some(value)
+    })
+}
+
+/// Splits a `multipart/form-data` body into its parts. Part bodies are slices
+/// of `body` rather than copies.
+pub fn parse(body: &Bytes, boundary: &str) -> Result<Vec<Part>, String> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut pos = find(body, &delimiter, 0)
+        .ok_or_else(|| "Multipart body has no opening boundary".to_string())?
+        + delimiter.len();
+    let mut parts = Vec::new();
+    loop {
+        if body[pos..].starts_with(b"--") {
+            return Ok(parts);
+        }
+        if !body[pos..].starts_with(b"\r\n") {
+            return Err("Malformed multipart boundary line".to_string());
+        }
+        pos += 2;
+        let head_end = find(body, b"\r\n\r\n", pos)
+            .ok_or_else(|| "Unterminated multipart part headers".to_string())?;
+        let headers = parse_headers(&body[pos..head_end])?;
+        let data_start = head_end + 4;
+
+        let mut close = b"\r\n".to_vec();
+        close.extend_from_slice(&delimiter);
+        let data_end = find(body, &close, data_start)
+            .ok_or_else(|| "Multipart body has no closing boundary".to_string())?;
+        parts.push(Part {
+            headers,
+            data: body.slice(data_start..data_end),
+        });
+        pos = data_end + close.len();
+    }
+}
+
+fn parse_headers(head: &[u8]) -> Result<HeaderMap, String> {
+    let mut headers = HeaderMap::new();
+    for line in head.split(|b| *b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            continue;
+        }
+        let colon = line
+            .iter()
+            .position(|b| *b == b':')
+            .ok_or_else(|| "Malformed multipart part header".to_string())?;
+        let name = HeaderName::from_bytes(&line[..colon])
+            .map_err(|_| "Invalid multipart part header name".to_string())?;
+        let value = HeaderValue::from_bytes(line[colon + 1..].trim_ascii())
+            .map_err(|_| format!("Invalid value for multipart part header {}", name))?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|i| from + i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_form_data() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=\"xyz\""),
+            Some("xyz")
+        );
+        assert_eq!(boundary("application/json; boundary=xyz"), None);
+
+        let body = Bytes::from_static(
+            b"preamble\r\n--xyz\r\n\
+              Content-Disposition: form-data; name=\"a\"; filename=\"a.txt\"\r\n\
+              Content-Type: text/plain\r\n\r\n\
+              first\r\nline\r\n--xyz\r\n\
+              Content-Disposition: form-data; name=\"b\"\r\n\r\n\
+              \r\n--xyz--\r\n",
+        );
+        let parts = parse(&body, "xyz").unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name(), Some("a"));
+        assert_eq!(parts[0].filename(), Some("a.txt"));
+        assert_eq!(parts[0].headers["content-type"], "text/plain");
+        assert_eq!(&parts[0].data[..], b"first\r\nline");
+        assert_eq!(parts[1].name(), Some("b"));
+        assert_eq!(parts[1].filename(), None);
+        assert!(parts[1].data.is_empty());
+
+        assert!(parse(&Bytes::from_static(b"--xyz\r\n\r\nno end"), "xyz").is_err());
+    }
+}
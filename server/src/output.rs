@@ -0,0 +1,166 @@
+use std::{path::PathBuf, str::FromStr};
+
+use crate::error::SliceBreadServerError;
+
+const PLACEHOLDERS: [&str; 4] = ["tenant", "date", "file_id", "file_name"];
+
+/// Where an assembled file lands, relative to the upload directory, e.g.
+/// `{tenant}/{date}/{file_id}/{file_name}` or `completed/{file_name}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputTemplate {
+    template: String,
+}
+
+pub struct OutputVars<'a> {
+    pub tenant: &'a str,
+    pub file_id: &'a str,
+    pub file_name: &'a str,
+}
+
+impl OutputTemplate {
+    /// Fills in the placeholders; `{date}` is today's UTC date as `YYYY-MM-DD`.
+    pub fn render(&self, vars: &OutputVars) -> Result<PathBuf, SliceBreadServerError> {
+        let date = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        let mut path = PathBuf::new();
+
+        for segment in self.template.split('/') {
+            let mut rendered = segment.to_string();
+            for (placeholder, value) in [
+                ("{tenant}", vars.tenant),
+                ("{date}", date.as_str()),
+                ("{file_id}", vars.file_id),
+                ("{file_name}", vars.file_name),
+            ] {
+                if rendered.contains(placeholder) {
+                    if !is_safe_segment(value) {
+                        return Err(SliceBreadServerError::BadRequest(format!(
+                            "{} cannot be used in an output path: {}",
+                            placeholder, value
+                        )));
+                    }
+                    rendered = rendered.replace(placeholder, value);
+                }
+            }
+            path.push(rendered);
+        }
+
+        Ok(path)
+    }
+}
+
+impl Default for OutputTemplate {
+    fn default() -> Self {
+        Self {
+            template: "{file_id}/{file_name}".to_string(),
+        }
+    }
+}
+
+impl FromStr for OutputTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let template = s.trim().trim_end_matches('/');
+        if template.starts_with('/') {
+            return Err(format!("Output template must be relative: {}", s));
+        }
+        if !template.ends_with("{file_name}") {
+            return Err(format!(
+                "Output template must end with {{file_name}}: {}",
+                s
+            ));
+        }
+
+        for segment in template.split('/') {
+            if segment.is_empty() || segment == "." || segment == ".." {
+                return Err(format!("Invalid path segment in output template: {}", s));
+            }
+
+            let mut rest = segment;
+            while let Some(start) = rest.find('{') {
+                let end = rest[start..]
+                    .find('}')
+                    .ok_or_else(|| format!("Unclosed placeholder in output template: {}", s))?;
+                let name = &rest[start + 1..start + end];
+                if !PLACEHOLDERS.contains(&name) {
+                    return Err(format!("Unknown output template placeholder: {{{}}}", name));
+                }
+                rest = &rest[start + end + 1..];
+            }
+        }
+
+        Ok(Self {
+            template: template.to_string(),
+        })
+    }
+}
+
+/// Whether `value` can be used as one path segment: it can't name the
+/// directory itself, its parent, or a path below it.
+pub fn is_safe_segment(value: &str) -> bool {
+    !value.is_empty() && value != "." && value != ".." && !value.contains(['/', '\\'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> OutputVars<'static> {
+        OutputVars {
+            tenant: "acme",
+            file_id: "abc",
+            file_name: "report.pdf",
+        }
+    }
+
+    #[test]
+    fn test_default_keeps_file_next_to_chunks() {
+        let path = OutputTemplate::default().render(&vars()).unwrap();
+        assert_eq!(path, PathBuf::from("abc/report.pdf"));
+    }
+
+    #[test]
+    fn test_render_template() {
+        let template: OutputTemplate = "{tenant}/{date}/{file_id}-{file_name}".parse().unwrap();
+        let path = template.render(&vars()).unwrap();
+        let date = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        assert_eq!(path, PathBuf::from(format!("acme/{}/abc-report.pdf", date)));
+
+        let flat: OutputTemplate = "completed/{file_name}".parse().unwrap();
+        assert_eq!(
+            flat.render(&vars()).unwrap(),
+            PathBuf::from("completed/report.pdf")
+        );
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        for template in [
+            "/abs/{file_name}",
+            "{file_id}",
+            "../{file_name}",
+            "a//{file_name}",
+            "{owner}/{file_name}",
+            "{file_id/{file_name}",
+        ] {
+            assert!(
+                template.parse::<OutputTemplate>().is_err(),
+                "{} should be rejected",
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn test_unsafe_values_are_rejected() {
+        let template: OutputTemplate = "{tenant}/{file_name}".parse().unwrap();
+        let vars = OutputVars {
+            tenant: "..",
+            ..vars()
+        };
+        assert!(matches!(
+            template.render(&vars),
+            Err(SliceBreadServerError::BadRequest(_))
+        ));
+    }
+}
@@ -0,0 +1,244 @@
+use std::sync::Arc;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty, Limited};
+use hyper::{HeaderMap, Method, Request, StatusCode, Uri, header};
+use hyper_util::rt::TokioIo;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    TlsConnector,
+    rustls::{self, pki_types::ServerName},
+};
+
+use crate::{constants, crypto::hmac, digest};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The other instances of a cluster whose upload directories aren't shared.
+/// The instance that assembles an upload fetches the chunks it lacks from
+/// them, and tells them to drop their copies once the file is assembled.
+#[derive(Debug, Clone)]
+pub struct PeerConfig {
+    /// Base URLs of the other instances, e.g. `http://10.0.0.2:8080`.
+    pub peers: Vec<Uri>,
+    /// Shared by every instance; signs requests to the internal endpoints.
+    pub secret: Vec<u8>,
+    /// Used for `https` peers.
+    pub tls: Arc<rustls::ClientConfig>,
+}
+
+/// Value of `X-Cluster-Signature` for a request, as
+/// `timestamp "." base64url(HMAC-SHA256(secret, timestamp "\n" method "\n" path))`,
+/// with the timestamp in Unix seconds.
+pub fn sign(secret: &[u8], method: &Method, path: &str, timestamp: i64) -> String {
+    let tag = hmac::sign(
+        &hmac::Key::new(hmac::HMAC_SHA256, secret),
+        signed_message(method, path, timestamp).as_bytes(),
+    );
+    format!("{}.{}", timestamp, URL_SAFE_NO_PAD.encode(tag.as_ref()))
+}
+
+/// Whether `signature` was made with `secret` for this request, recently
+/// enough that a captured one can't be replayed for long.
+pub fn verify(signature: &str, secret: &[u8], method: &Method, path: &str, now: i64) -> bool {
+    let Some((timestamp, tag)) = signature.split_once('.') else {
+        return false;
+    };
+    let (Ok(timestamp), Ok(tag)) = (timestamp.parse::<i64>(), URL_SAFE_NO_PAD.decode(tag)) else {
+        return false;
+    };
+    timestamp.abs_diff(now) <= constants::PEER_SIGNATURE_MAX_AGE.as_secs()
+        && hmac::verify(
+            &hmac::Key::new(hmac::HMAC_SHA256, secret),
+            signed_message(method, path, timestamp).as_bytes(),
+            &tag,
+        )
+        .is_ok()
+}
+
+fn signed_message(method: &Method, path: &str, timestamp: i64) -> String {
+    format!("{}\n{}\n{}", timestamp, method, path)
+}
+
+/// Path of the internal endpoint serving a stored chunk.
+pub fn chunk_path(file_id: &str, chunk_index: usize) -> String {
+    format!("/internal/uploads/{}/chunks/{}", file_id, chunk_index)
+}
+
+/// Path of the internal endpoint that drops what a peer stored of an upload.
+pub fn upload_path(file_id: &str) -> String {
+    format!("/internal/uploads/{}", file_id)
+}
+
+impl PeerConfig {
+    /// Asks each peer in turn for a chunk, returning the first copy whose
+    /// `Content-Digest` matches, or `None` if no peer has it.
+    pub async fn fetch_chunk(
+        &self,
+        file_id: &str,
+        chunk_index: usize,
+        limit: usize,
+    ) -> Option<Bytes> {
+        let path = chunk_path(file_id, chunk_index);
+        for peer in &self.peers {
+            match self.request(peer, Method::GET, &path, limit).await {
+                Ok((StatusCode::OK, headers, body)) => match verified(&headers, &body) {
+                    Ok(()) => return Some(body),
+                    Err(err) => {
+                        tracing::warn!(%peer, %file_id, chunk_index, %err, "Peer sent a corrupt chunk");
+                    }
+                },
+                Ok((StatusCode::NOT_FOUND, _, _)) => {}
+                Ok((status, _, _)) => {
+                    tracing::warn!(%peer, %file_id, chunk_index, %status, "Peer refused a chunk request");
+                }
+                Err(err) => {
+                    tracing::warn!(%peer, %file_id, chunk_index, %err, "Failed to fetch chunk from peer");
+                }
+            }
+        }
+        None
+    }
+
+    /// Tells every peer to drop its working files for an upload. Failures are
+    /// only logged: leftovers are removed when the upload expires there.
+    pub async fn forget(&self, file_id: &str) {
+        let path = upload_path(file_id);
+        for peer in &self.peers {
+            match self
+                .request(
+                    peer,
+                    Method::DELETE,
+                    &path,
+                    constants::MAX_PEER_RESPONSE_BYTES,
+                )
+                .await
+            {
+                Ok((status, _, _)) if status.is_success() || status == StatusCode::NOT_FOUND => {}
+                Ok((status, _, _)) => {
+                    tracing::warn!(%peer, %file_id, %status, "Peer refused to drop an upload");
+                }
+                Err(err) => {
+                    tracing::warn!(%peer, %file_id, %err, "Failed to tell peer to drop an upload");
+                }
+            }
+        }
+    }
+
+    async fn request(
+        &self,
+        peer: &Uri,
+        method: Method,
+        path: &str,
+        limit: usize,
+    ) -> Result<(StatusCode, HeaderMap, Bytes), BoxError> {
+        let https = match peer.scheme_str() {
+            Some("https") => true,
+            Some("http") => false,
+            _ => return Err("peer URL must be http or https".into()),
+        };
+        let host = peer
+            .host()
+            .ok_or("peer URL has no host")?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let port = peer.port_u16().unwrap_or(if https { 443 } else { 80 });
+        let path = format!("{}{}", peer.path().trim_end_matches('/'), path);
+        let signature = sign(&self.secret, &method, &path, chrono::Utc::now().timestamp());
+        let request = Request::builder()
+            .method(method)
+            .uri(&path)
+            .header(
+                header::HOST,
+                peer.authority()
+                    .map_or(host, |authority| authority.as_str()),
+            )
+            .header(constants::HEADER_CLUSTER_SIGNATURE, signature)
+            .body(Empty::<Bytes>::new())?;
+
+        let exchange = async {
+            let stream = TcpStream::connect((host, port)).await?;
+            if https {
+                let name = ServerName::try_from(host.to_string())?;
+                let stream = TlsConnector::from(self.tls.clone())
+                    .connect(name, stream)
+                    .await?;
+                send(stream, request, limit).await
+            } else {
+                send(stream, request, limit).await
+            }
+        };
+        tokio::time::timeout(constants::PEER_TIMEOUT, exchange)
+            .await
+            .map_err(|_| "timed out")?
+    }
+}
+
+fn verified(headers: &HeaderMap, body: &Bytes) -> Result<(), BoxError> {
+    let expected = digest::content_digests(headers)?;
+    if expected.is_empty() {
+        return Err("no Content-Digest".into());
+    }
+    digest::Computed::of(body, &expected).verify(&expected, "Chunk")?;
+    Ok(())
+}
+
+async fn send<S>(
+    stream: S,
+    request: Request<Empty<Bytes>>,
+    limit: usize,
+) -> Result<(StatusCode, HeaderMap, Bytes), BoxError>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (mut sender, connection) =
+        hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        if let Err(err) = connection.await {
+            tracing::debug!(%err, "Peer connection closed");
+        }
+    });
+    let response = sender.send_request(request).await?;
+    let (parts, body) = response.into_parts();
+    let body = Limited::new(body, limit).collect().await?.to_bytes();
+    Ok((parts.status, parts.headers, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signatures_are_bound_to_the_request() {
+        let path = chunk_path("abc", 3);
+        let signature = sign(b"secret", &Method::GET, &path, 1_000);
+        assert!(verify(&signature, b"secret", &Method::GET, &path, 1_030));
+        assert!(!verify(&signature, b"other", &Method::GET, &path, 1_030));
+        assert!(!verify(
+            &signature,
+            b"secret",
+            &Method::DELETE,
+            &path,
+            1_030
+        ));
+        assert!(!verify(
+            &signature,
+            b"secret",
+            &Method::GET,
+            &chunk_path("abc", 4),
+            1_030
+        ));
+        assert!(!verify(
+            &signature,
+            b"secret",
+            &Method::GET,
+            &path,
+            1_000 + 3_600
+        ));
+        assert!(!verify("garbage", b"secret", &Method::GET, &path, 1_000));
+    }
+}
@@ -0,0 +1,165 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    checksum::{self, ChunkDigest},
+    crypto::hmac,
+};
+
+/// Per-upload constraints, signed by the service that authorizes an upload and
+/// sent by the client as `X-Upload-Policy` with every chunk, like an S3 POST
+/// policy. Encoded as `base64url(json) "." base64url(HMAC-SHA256(secret, base64url(json)))`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadPolicy {
+    pub expires: DateTime<Utc>,
+    /// Largest file allowed, in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    /// Allowed content types; `type/*` matches any subtype and an empty list allows any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub content_types: Vec<String>,
+    /// Restricts the policy to a single upload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Seconds an upload may take from its first chunk before it expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_duration_secs: Option<u64>,
+    /// Exact size of the file, in bytes, checked when it is assembled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Hex SHA-256 the assembled file must have.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Makes the policy single-use: it binds to the first file id it is used
+    /// with, and is refused for any other upload until it expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+}
+
+impl UploadPolicy {
+    pub fn sign(&self, secret: &[u8]) -> String {
+        let json = serde_json::to_vec(self).expect("upload policies always serialize");
+        let payload = URL_SAFE_NO_PAD.encode(json);
+        let tag = hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, secret),
+            payload.as_bytes(),
+        );
+        format!("{}.{}", payload, URL_SAFE_NO_PAD.encode(tag.as_ref()))
+    }
+
+    /// Decodes `token`, checking its signature and that it has not expired.
+    pub fn verify(token: &str, secret: &[u8], now: DateTime<Utc>) -> Result<Self, String> {
+        let malformed = || "Malformed upload policy".to_string();
+        let (payload, tag) = token.trim().split_once('.').ok_or_else(malformed)?;
+        let tag = URL_SAFE_NO_PAD.decode(tag).map_err(|_| malformed())?;
+        hmac::verify(
+            &hmac::Key::new(hmac::HMAC_SHA256, secret),
+            payload.as_bytes(),
+            &tag,
+        )
+        .map_err(|_| "Invalid upload policy signature".to_string())?;
+
+        let json = URL_SAFE_NO_PAD.decode(payload).map_err(|_| malformed())?;
+        let policy: Self = serde_json::from_slice(&json).map_err(|_| malformed())?;
+        if policy
+            .sha256
+            .as_ref()
+            .is_some_and(|sha256| checksum::from_hex(sha256).is_none())
+        {
+            return Err(malformed());
+        }
+        if policy.expires <= now {
+            return Err(format!("Upload policy expired at {}", policy.expires));
+        }
+        Ok(policy)
+    }
+
+    /// The digest from `sha256`, which `verify` has checked is well-formed.
+    pub fn sha256_digest(&self) -> Option<ChunkDigest> {
+        self.sha256.as_deref().and_then(checksum::from_hex)
+    }
+
+    /// Compares media types only, ignoring parameters such as `charset`.
+    pub fn allows_content_type(&self, content_type: &str) -> bool {
+        if self.content_types.is_empty() {
+            return true;
+        }
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        self.content_types.iter().any(|allowed| {
+            let allowed = allowed.to_ascii_lowercase();
+            match allowed.strip_suffix("/*") {
+                Some(kind) => essence.split_once('/').is_some_and(|(t, _)| t == kind),
+                None => essence == allowed,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_signed_policy_round_trips_until_expiry() {
+        let now = Utc::now();
+        let policy = UploadPolicy {
+            expires: now + Duration::minutes(5),
+            max_size: Some(1024),
+            content_types: vec!["image/*".to_string(), "application/pdf".to_string()],
+            file_id: None,
+            tenant: Some("acme".to_string()),
+            max_duration_secs: Some(3600),
+            size: Some(512),
+            sha256: Some(checksum::to_hex(&checksum::sha256(b"artifact"))),
+            nonce: Some("n-1".to_string()),
+        };
+        let token = policy.sign(b"secret");
+
+        assert_eq!(
+            UploadPolicy::verify(&token, b"secret", now),
+            Ok(policy.clone())
+        );
+        assert!(UploadPolicy::verify(&token, b"other", now).is_err());
+        assert!(UploadPolicy::verify(&token, b"secret", now + Duration::minutes(5)).is_err());
+
+        let (payload, tag) = token.split_once('.').unwrap();
+        let forged = format!("{}A.{}", payload, tag);
+        assert!(UploadPolicy::verify(&forged, b"secret", now).is_err());
+
+        let bad_digest = UploadPolicy {
+            sha256: Some("abc".to_string()),
+            ..policy
+        }
+        .sign(b"secret");
+        assert!(UploadPolicy::verify(&bad_digest, b"secret", now).is_err());
+    }
+
+    #[test]
+    fn test_content_types_match_by_essence_and_wildcard() {
+        let policy = UploadPolicy {
+            expires: Utc::now(),
+            max_size: None,
+            content_types: vec!["image/*".to_string(), "text/csv".to_string()],
+            file_id: None,
+            tenant: None,
+            max_duration_secs: None,
+            size: None,
+            sha256: None,
+            nonce: None,
+        };
+        assert!(policy.allows_content_type("image/png"));
+        assert!(policy.allows_content_type("Text/CSV; charset=utf-8"));
+        assert!(!policy.allows_content_type("text/html"));
+        assert!(!policy.allows_content_type("imagex/png"));
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+use bytes::BytesMut;
+
+/// Buffers that grew past this size while receiving a large chunk are dropped
+/// instead of going back to the pool, so one huge upload doesn't pin memory.
+const MAX_RETAINED_CAPACITY: usize = 8 * 1024 * 1024;
+
+#[derive(Clone)]
+pub struct BufferPool {
+    buffers: Arc<Mutex<Vec<BytesMut>>>,
+    max_buffers: usize,
+    buffer_capacity: usize,
+}
+
+impl BufferPool {
+    pub fn new(max_buffers: usize, buffer_capacity: usize) -> Self {
+        Self {
+            buffers: Arc::new(Mutex::new(Vec::with_capacity(max_buffers))),
+            max_buffers,
+            buffer_capacity,
+        }
+    }
+
+    pub fn get(&self) -> PooledBuffer {
+        let buf = self
+            .buffers
+            .lock()
+            .expect("buffer pool lock poisoned")
+            .pop()
+            .unwrap_or_else(|| BytesMut::with_capacity(self.buffer_capacity));
+
+        PooledBuffer {
+            buf,
+            pool: self.clone(),
+        }
+    }
+
+    fn put(&self, mut buf: BytesMut) {
+        buf.clear();
+        // Reclaims the original allocation once every `Bytes` split off from it
+        // has been dropped; otherwise this allocates a fresh buffer.
+        buf.reserve(self.buffer_capacity);
+        if buf.capacity() > MAX_RETAINED_CAPACITY {
+            return;
+        }
+
+        let mut buffers = self.buffers.lock().expect("buffer pool lock poisoned");
+        if buffers.len() < self.max_buffers {
+            buffers.push(buf);
+        }
+    }
+
+    #[cfg(test)]
+    fn available(&self) -> usize {
+        self.buffers.lock().unwrap().len()
+    }
+}
+
+pub struct PooledBuffer {
+    buf: BytesMut,
+    pool: BufferPool,
+}
+
+impl Deref for PooledBuffer {
+    type Target = BytesMut;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.put(std::mem::take(&mut self.buf));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_is_returned_and_reused() {
+        let pool = BufferPool::new(2, 1024);
+
+        let mut buf = pool.get();
+        buf.extend_from_slice(b"hello");
+        let ptr = buf.as_ptr();
+        drop(buf);
+        assert_eq!(pool.available(), 1);
+
+        let buf = pool.get();
+        assert!(buf.is_empty());
+        assert_eq!(buf.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn test_allocation_reclaimed_after_split_bytes_dropped() {
+        let pool = BufferPool::new(1, 1024);
+
+        let mut buf = pool.get();
+        buf.extend_from_slice(b"chunk data");
+        let ptr = buf.as_ptr();
+        let frozen = buf.split().freeze();
+        drop(frozen);
+        drop(buf);
+
+        let buf = pool.get();
+        assert_eq!(buf.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn test_pool_does_not_exceed_max_buffers() {
+        let pool = BufferPool::new(1, 16);
+
+        let a = pool.get();
+        let b = pool.get();
+        drop(a);
+        drop(b);
+        assert_eq!(pool.available(), 1);
+    }
+
+    #[test]
+    fn test_oversized_buffers_are_not_retained() {
+        let pool = BufferPool::new(4, 16);
+
+        let mut buf = pool.get();
+        buf.resize(MAX_RETAINED_CAPACITY + 1, 0);
+        drop(buf);
+        assert_eq!(pool.available(), 0);
+    }
+}
@@ -0,0 +1,60 @@
+use hyper::HeaderMap;
+
+use crate::{constants, error::SliceBreadServerError};
+
+/// Protocol versions this server speaks, oldest first. Clients that don't send
+/// `X-SliceBread-Version` get the oldest, so they keep working when an
+/// incompatible version is added.
+pub const SUPPORTED_VERSIONS: &[u32] = &[1];
+
+/// The newest version both sides speak. `X-SliceBread-Version` lists the
+/// versions the client accepts, e.g. `2, 1`.
+pub fn negotiate(headers: &HeaderMap) -> Result<u32, SliceBreadServerError> {
+    let Some(value) = headers.get(constants::HEADER_PROTOCOL_VERSION) else {
+        return Ok(SUPPORTED_VERSIONS[0]);
+    };
+    let invalid = || {
+        SliceBreadServerError::InvalidHeader(format!(
+            "Invalid {}",
+            constants::HEADER_PROTOCOL_VERSION
+        ))
+    };
+    let requested = value
+        .to_str()
+        .map_err(|_| invalid())?
+        .split(',')
+        .map(|version| version.trim().parse::<u32>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    requested
+        .iter()
+        .filter(|version| SUPPORTED_VERSIONS.contains(version))
+        .max()
+        .copied()
+        .ok_or(SliceBreadServerError::UnsupportedVersion { requested })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requesting(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(constants::HEADER_PROTOCOL_VERSION, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_negotiates_newest_common_version() {
+        assert_eq!(negotiate(&HeaderMap::new()).unwrap(), 1);
+        assert_eq!(negotiate(&requesting("1")).unwrap(), 1);
+        assert_eq!(negotiate(&requesting("7, 1")).unwrap(), 1);
+        assert!(matches!(
+            negotiate(&requesting("7")),
+            Err(SliceBreadServerError::UnsupportedVersion { .. })
+        ));
+        assert!(matches!(
+            negotiate(&requesting("one")),
+            Err(SliceBreadServerError::InvalidHeader(_))
+        ));
+    }
+}
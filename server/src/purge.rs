@@ -0,0 +1,53 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, Utc};
+
+use crate::crypto::hmac;
+
+/// Token an admin sends back to `POST /admin/purge` to go through with a
+/// purge, showing they asked about that file moments before. Encoded as
+/// `expires "." base64url(HMAC-SHA256(key, file_id "/" expires))`, with
+/// `expires` in Unix seconds.
+pub fn confirmation(key: &hmac::Key, file_id: &str, expires_at: DateTime<Utc>) -> String {
+    let expires = expires_at.timestamp();
+    let tag = hmac::sign(key, format!("{}/{}", file_id, expires).as_bytes());
+    format!("{}.{}", expires, URL_SAFE_NO_PAD.encode(tag.as_ref()))
+}
+
+/// Checks `token` was issued for `file_id` and hasn't expired.
+pub fn verify(
+    key: &hmac::Key,
+    token: &str,
+    file_id: &str,
+    now: DateTime<Utc>,
+) -> Result<(), String> {
+    let invalid = || format!("Invalid purge confirmation for {}", file_id);
+    let (expires, tag) = token.split_once('.').ok_or_else(invalid)?;
+    let tag = URL_SAFE_NO_PAD.decode(tag).map_err(|_| invalid())?;
+    hmac::verify(key, format!("{}/{}", file_id, expires).as_bytes(), &tag)
+        .map_err(|_| invalid())?;
+    let expires: i64 = expires.parse().map_err(|_| invalid())?;
+    if expires <= now.timestamp() {
+        return Err(format!("Purge confirmation for {} has expired", file_id));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_confirmations_are_per_file_and_expire() {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"key");
+        let now = Utc::now();
+        let token = confirmation(&key, "fileA", now + Duration::minutes(5));
+
+        assert!(verify(&key, &token, "fileA", now).is_ok());
+        assert!(verify(&key, &token, "fileB", now).is_err());
+        assert!(verify(&key, &token, "fileA", now + Duration::minutes(5)).is_err());
+        let other = hmac::Key::new(hmac::HMAC_SHA256, b"other");
+        assert!(verify(&other, &token, "fileA", now).is_err());
+    }
+}
@@ -0,0 +1,61 @@
+/// Byte ranges received so far for an upload sent as arbitrary ranges, kept
+/// as sorted, non-overlapping, non-adjacent half-open intervals.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RangeSet {
+    ranges: Vec<(u64, u64)>,
+}
+
+impl RangeSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `start..end` and returns how many of its bytes were not covered before.
+    pub fn insert(&mut self, start: u64, end: u64) -> u64 {
+        if start >= end {
+            return 0;
+        }
+        let first = self.ranges.partition_point(|&(_, e)| e < start);
+        let last = self.ranges.partition_point(|&(s, _)| s <= end);
+        let overlapped: u64 = self.ranges[first..last]
+            .iter()
+            .map(|&(s, e)| e.min(end).saturating_sub(s.max(start)))
+            .sum();
+
+        let merged = self.ranges[first..last]
+            .iter()
+            .fold((start, end), |(s, e), &(rs, re)| (s.min(rs), e.max(re)));
+        self.ranges.splice(first..last, [merged]);
+        end - start - overlapped
+    }
+
+    /// The intervals, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.ranges.iter().copied()
+    }
+
+    /// Total number of bytes covered.
+    pub fn covered(&self) -> u64 {
+        self.ranges.iter().map(|&(s, e)| e - s).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overlapping_and_adjacent_ranges_merge() {
+        let mut ranges = RangeSet::new();
+        assert_eq!(ranges.insert(10, 20), 10);
+        assert_eq!(ranges.insert(30, 40), 10);
+        assert_eq!(ranges.insert(15, 35), 10);
+        assert_eq!(ranges.ranges, [(10, 40)]);
+
+        assert_eq!(ranges.insert(0, 10), 10);
+        assert_eq!(ranges.insert(5, 25), 0);
+        assert_eq!(ranges.insert(50, 50), 0);
+        assert_eq!(ranges.ranges, [(0, 40)]);
+        assert_eq!(ranges.covered(), 40);
+    }
+}
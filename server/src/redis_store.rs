@@ -0,0 +1,218 @@
+use std::fmt;
+
+use redis::{AsyncCommands, Client, Script, aio::ConnectionManager};
+use tokio::sync::OnceCell;
+
+use crate::{
+    bitmap::ChunkBitmap,
+    cluster::{ClusterStore, StoreFuture, redact_url},
+    constants,
+    session::Session,
+};
+
+/// Keeps cluster state in Redis, for deployments without a shared volume
+/// that honours exclusive creates. Each upload has a JSON session key, a
+/// bitmap of received chunks and a lock key holding the assembling node, and
+/// a set lists the uploads with a session.
+pub struct RedisStore {
+    client: Client,
+    name: String,
+    prefix: String,
+    connection: OnceCell<ConnectionManager>,
+    lock: Script,
+    unlock: Script,
+}
+
+// Taking the lock and checking who holds it must be one step, or a lock
+// released in between would read as held by someone else.
+const LOCK_SCRIPT: &str = r#"
+if redis.call('SET', KEYS[1], ARGV[1], 'NX') then return 1 end
+if redis.call('GET', KEYS[1]) == ARGV[1] then return 1 end
+return 0
+"#;
+
+const UNLOCK_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('DEL', KEYS[1]) end
+return 0
+"#;
+
+impl RedisStore {
+    /// Parses a `redis://` URL. Nothing is connected until the store is first
+    /// used, and dropped connections are re-established.
+    pub fn new(url: &str) -> Result<Self, String> {
+        let client =
+            Client::open(url).map_err(|err| format!("Invalid Redis URL {}: {}", url, err))?;
+        Ok(Self {
+            client,
+            name: redact_url(url),
+            prefix: constants::CLUSTER_REDIS_PREFIX.to_string(),
+            connection: OnceCell::new(),
+            lock: Script::new(LOCK_SCRIPT),
+            unlock: Script::new(UNLOCK_SCRIPT),
+        })
+    }
+
+    async fn connection(&self) -> std::io::Result<ConnectionManager> {
+        self.connection
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await
+            .cloned()
+            .map_err(std::io::Error::other)
+    }
+
+    fn key(&self, file_id: &str, kind: &str) -> String {
+        format!("{}{}:{}", self.prefix, file_id, kind)
+    }
+
+    fn uploads_key(&self) -> String {
+        format!("{}uploads", self.prefix)
+    }
+}
+
+impl fmt::Debug for RedisStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisStore")
+            .field("name", &self.name)
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Reads a bitmap written with `SETBIT`, whose bit 0 is the high bit of the
+/// first byte.
+fn from_bits(bytes: &[u8], total_chunks: usize) -> ChunkBitmap {
+    let mut bitmap = ChunkBitmap::new(total_chunks);
+    for index in 0..total_chunks.min(bytes.len() * 8) {
+        if bytes[index / 8] & (0x80 >> (index % 8)) != 0 {
+            bitmap.insert(index);
+        }
+    }
+    bitmap
+}
+
+impl ClusterStore for RedisStore {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn session<'a>(&'a self, file_id: &'a str) -> StoreFuture<'a, Option<Session>> {
+        Box::pin(async move {
+            let json: Option<Vec<u8>> = self
+                .connection()
+                .await?
+                .get(self.key(file_id, "session"))
+                .await
+                .map_err(std::io::Error::other)?;
+            Ok(json.map(|json| serde_json::from_slice(&json)).transpose()?)
+        })
+    }
+
+    fn put_session<'a>(&'a self, file_id: &'a str, session: &'a Session) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let json = serde_json::to_vec(session)?;
+            redis::pipe()
+                .atomic()
+                .set(self.key(file_id, "session"), json)
+                .sadd(self.uploads_key(), file_id)
+                .query_async::<()>(&mut self.connection().await?)
+                .await
+                .map_err(std::io::Error::other)
+        })
+    }
+
+    fn mark_received<'a>(&'a self, file_id: &'a str, chunk_index: usize) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            self.connection()
+                .await?
+                .setbit::<_, bool>(self.key(file_id, "received"), chunk_index, true)
+                .await
+                .map(drop)
+                .map_err(std::io::Error::other)
+        })
+    }
+
+    fn received<'a>(
+        &'a self,
+        file_id: &'a str,
+        total_chunks: usize,
+    ) -> StoreFuture<'a, ChunkBitmap> {
+        Box::pin(async move {
+            let bits: Option<Vec<u8>> = self
+                .connection()
+                .await?
+                .get(self.key(file_id, "received"))
+                .await
+                .map_err(std::io::Error::other)?;
+            Ok(from_bits(&bits.unwrap_or_default(), total_chunks))
+        })
+    }
+
+    fn lock_assembly<'a>(&'a self, file_id: &'a str, node: &'a str) -> StoreFuture<'a, bool> {
+        Box::pin(async move {
+            let mut connection = self.connection().await?;
+            self.lock
+                .key(self.key(file_id, "lock"))
+                .arg(node)
+                .invoke_async::<bool>(&mut connection)
+                .await
+                .map_err(std::io::Error::other)
+        })
+    }
+
+    fn unlock_assembly<'a>(&'a self, file_id: &'a str, node: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let mut connection = self.connection().await?;
+            self.unlock
+                .key(self.key(file_id, "lock"))
+                .arg(node)
+                .invoke_async::<()>(&mut connection)
+                .await
+                .map_err(std::io::Error::other)
+        })
+    }
+
+    fn remove<'a>(&'a self, file_id: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let keys = ["session", "received", "lock"].map(|kind| self.key(file_id, kind));
+            redis::pipe()
+                .atomic()
+                .del(&keys)
+                .srem(self.uploads_key(), file_id)
+                .query_async::<()>(&mut self.connection().await?)
+                .await
+                .map_err(std::io::Error::other)
+        })
+    }
+
+    fn uploads(&self) -> StoreFuture<'_, Vec<String>> {
+        Box::pin(async move {
+            self.connection()
+                .await?
+                .smembers(self.uploads_key())
+                .await
+                .map_err(std::io::Error::other)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_setbit_order() {
+        // SETBIT 0, 9 and 10.
+        let bitmap = from_bits(&[0b1000_0000, 0b0110_0000], 12);
+        assert_eq!(bitmap.count(), 3);
+        assert!(bitmap.contains(0) && bitmap.contains(9) && bitmap.contains(10));
+        assert!(!from_bits(&[0xff], 4).contains(4));
+        assert_eq!(from_bits(&[], 4).count(), 0);
+    }
+
+    #[test]
+    fn test_redacts_credentials() {
+        let store = RedisStore::new("redis://user:pw@cache:6380").unwrap();
+        assert_eq!(store.name(), "redis://cache:6380");
+        assert!(RedisStore::new("http://cache").is_err());
+    }
+}
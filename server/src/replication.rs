@@ -0,0 +1,276 @@
+use std::{
+    fmt::Debug,
+    future::Future,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants,
+    sidecar::{self, FileMetadata},
+};
+
+pub type BackendFuture<'a> = Pin<Box<dyn Future<Output = std::io::Result<()>> + Send + 'a>>;
+
+/// Secondary storage a completed file is copied to. `key` is the file's path
+/// relative to the upload directory, so replicas mirror the primary layout.
+pub trait ReplicaBackend: Debug + Send + Sync {
+    fn name(&self) -> String;
+    fn put<'a>(&'a self, source: &'a Path, key: &'a Path) -> BackendFuture<'a>;
+    /// Removes the replica at `key`; removing one that isn't there succeeds.
+    fn delete<'a>(&'a self, key: &'a Path) -> BackendFuture<'a>;
+}
+
+/// Replicates into another directory, e.g. a second disk or a network mount.
+#[derive(Debug)]
+pub struct LocalDirBackend {
+    root: PathBuf,
+}
+
+impl LocalDirBackend {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl ReplicaBackend for LocalDirBackend {
+    fn name(&self) -> String {
+        format!("local:{}", self.root.display())
+    }
+
+    fn put<'a>(&'a self, source: &'a Path, key: &'a Path) -> BackendFuture<'a> {
+        Box::pin(async move {
+            let target = self.root.join(key);
+            if let Some(parent) = target.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            let tmp = target.with_extension("replica.tmp");
+            tokio::fs::copy(source, &tmp).await?;
+            tokio::fs::rename(&tmp, &target).await
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a Path) -> BackendFuture<'a> {
+        Box::pin(async move {
+            match tokio::fs::remove_file(self.root.join(key)).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplicaState {
+    Pending,
+    Replicated,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicaStatus {
+    pub backend: String,
+    pub state: ReplicaState,
+    pub attempts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replicated_at: Option<DateTime<Utc>>,
+}
+
+/// Copies completed files to every backend in the background, retrying with
+/// exponential backoff and recording progress in the file's sidecar.
+#[derive(Debug, Clone)]
+pub struct Replicator {
+    backends: Vec<Arc<dyn ReplicaBackend>>,
+    max_attempts: u32,
+    base_backoff: Duration,
+}
+
+impl Replicator {
+    pub fn new(backends: Vec<Arc<dyn ReplicaBackend>>) -> Self {
+        Self {
+            backends,
+            max_attempts: constants::REPLICATION_MAX_ATTEMPTS,
+            base_backoff: constants::REPLICATION_BASE_BACKOFF,
+        }
+    }
+
+    pub fn with_retry(mut self, max_attempts: u32, base_backoff: Duration) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self.base_backoff = base_backoff;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.backends.is_empty()
+    }
+
+    /// Status entries to record in the sidecar before replication starts.
+    pub fn pending(&self) -> Vec<ReplicaStatus> {
+        self.backends
+            .iter()
+            .map(|backend| ReplicaStatus {
+                backend: backend.name(),
+                state: ReplicaState::Pending,
+                attempts: 0,
+                last_error: None,
+                replicated_at: None,
+            })
+            .collect()
+    }
+
+    /// Removes the replicas of `key` from every backend.
+    pub async fn delete(&self, key: &Path) -> std::io::Result<()> {
+        for backend in &self.backends {
+            backend.delete(key).await?;
+            tracing::info!(backend = %backend.name(), key = %key.display(), "Deleted replica");
+        }
+        Ok(())
+    }
+
+    pub fn spawn(&self, output_path: PathBuf, key: PathBuf, metadata: FileMetadata) {
+        if !self.is_enabled() {
+            return;
+        }
+        let replicator = self.clone();
+        tokio::spawn(async move { replicator.run(&output_path, &key, metadata).await });
+    }
+
+    pub async fn run(&self, output_path: &Path, key: &Path, mut metadata: FileMetadata) {
+        for (i, backend) in self.backends.iter().enumerate() {
+            let status = self.replicate_one(backend.as_ref(), output_path, key).await;
+            if let Some(entry) = metadata.replication.get_mut(i) {
+                *entry = status;
+            }
+            if let Err(err) = sidecar::write(output_path, &metadata).await {
+                tracing::warn!(%err, "Failed to record replication status");
+            }
+        }
+    }
+
+    async fn replicate_one(
+        &self,
+        backend: &dyn ReplicaBackend,
+        source: &Path,
+        key: &Path,
+    ) -> ReplicaStatus {
+        let mut status = ReplicaStatus {
+            backend: backend.name(),
+            state: ReplicaState::Failed,
+            attempts: 0,
+            last_error: None,
+            replicated_at: None,
+        };
+
+        while status.attempts < self.max_attempts {
+            if status.attempts > 0 {
+                tokio::time::sleep(self.base_backoff * 2u32.pow(status.attempts - 1)).await;
+            }
+            status.attempts += 1;
+
+            match backend.put(source, key).await {
+                Ok(()) => {
+                    tracing::info!(backend = %status.backend, key = %key.display(), "Replicated file");
+                    status.state = ReplicaState::Replicated;
+                    status.last_error = None;
+                    status.replicated_at = Some(Utc::now());
+                    return status;
+                }
+                Err(err) => {
+                    tracing::warn!(backend = %status.backend, attempt = status.attempts, %err, "Replication attempt failed");
+                    status.last_error = Some(err.to_string());
+                }
+            }
+        }
+
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct Flaky {
+        failures: u32,
+        calls: AtomicU32,
+    }
+
+    impl ReplicaBackend for Flaky {
+        fn name(&self) -> String {
+            "flaky".to_string()
+        }
+
+        fn put<'a>(&'a self, _source: &'a Path, _key: &'a Path) -> BackendFuture<'a> {
+            Box::pin(async move {
+                if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                    Err(std::io::Error::other("unavailable"))
+                } else {
+                    Ok(())
+                }
+            })
+        }
+
+        fn delete<'a>(&'a self, _key: &'a Path) -> BackendFuture<'a> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_until_success_or_exhaustion() {
+        let recovers = Flaky {
+            failures: 2,
+            calls: AtomicU32::new(0),
+        };
+        let replicator = Replicator::new(vec![]).with_retry(3, Duration::from_millis(1));
+        let status = replicator
+            .replicate_one(&recovers, Path::new("a"), Path::new("a"))
+            .await;
+        assert_eq!(status.state, ReplicaState::Replicated);
+        assert_eq!(status.attempts, 3);
+
+        let broken = Flaky {
+            failures: u32::MAX,
+            calls: AtomicU32::new(0),
+        };
+        let status = replicator
+            .replicate_one(&broken, Path::new("a"), Path::new("a"))
+            .await;
+        assert_eq!(status.state, ReplicaState::Failed);
+        assert_eq!(status.last_error.as_deref(), Some("unavailable"));
+    }
+
+    #[tokio::test]
+    async fn test_local_backend_mirrors_layout() {
+        let dir = TempDir::new("replica").unwrap();
+        let source = dir.path().join("source.bin");
+        tokio::fs::write(&source, b"data").await.unwrap();
+
+        let backend = LocalDirBackend::new(dir.path().join("replica"));
+        backend
+            .put(&source, Path::new("id/file.bin"))
+            .await
+            .unwrap();
+
+        let copied = tokio::fs::read(dir.path().join("replica/id/file.bin"))
+            .await
+            .unwrap();
+        assert_eq!(copied, b"data");
+
+        backend.delete(Path::new("id/file.bin")).await.unwrap();
+        assert!(!dir.path().join("replica/id/file.bin").exists());
+        backend.delete(Path::new("id/file.bin")).await.unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+use std::{str::FromStr, time::Duration};
+
+use chrono::{DateTime, Utc};
+
+/// Which completed files a retention rule covers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetentionScope {
+    All,
+    Tenant(String),
+    /// Files uploaded with this `X-Upload-Category`.
+    Category(String),
+}
+
+/// What happens to a file once its retention period is over.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RetentionAction {
+    #[default]
+    Delete,
+    /// Move it to the archive directory before deleting it here.
+    Archive,
+}
+
+/// How long completed files are kept, parsed from `scope=period[:archive]`
+/// where scope is `*`, `tenant:<name>` or `category:<name>`, e.g.
+/// `tenant:acme=30d` or `category:logs=7d:archive`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionRule {
+    pub scope: RetentionScope,
+    pub keep_for: Duration,
+    pub action: RetentionAction,
+}
+
+impl RetentionRule {
+    pub fn is_over(&self, completed_at: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        chrono::Duration::from_std(self.keep_for)
+            .ok()
+            .and_then(|keep_for| completed_at.checked_add_signed(keep_for))
+            .is_some_and(|until| until <= now)
+    }
+}
+
+/// The most specific rule for a file: a category rule beats a tenant rule,
+/// which beats `*`.
+pub fn rule_for<'a>(
+    rules: &'a [RetentionRule],
+    tenant: &str,
+    category: Option<&str>,
+) -> Option<&'a RetentionRule> {
+    let find =
+        |matches: &dyn Fn(&RetentionScope) -> bool| rules.iter().find(|rule| matches(&rule.scope));
+    category
+        .and_then(|category| {
+            find(&|scope| matches!(scope, RetentionScope::Category(c) if c == category))
+        })
+        .or_else(|| find(&|scope| matches!(scope, RetentionScope::Tenant(t) if t == tenant)))
+        .or_else(|| find(&|scope| *scope == RetentionScope::All))
+}
+
+impl FromStr for RetentionRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scope, rest) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected scope=period, got: {}", s))?;
+        let scope = match scope.trim() {
+            "*" => RetentionScope::All,
+            scope => match scope.split_once(':') {
+                Some(("tenant", name)) if !name.is_empty() => {
+                    RetentionScope::Tenant(name.to_string())
+                }
+                Some(("category", name)) if !name.is_empty() => {
+                    RetentionScope::Category(name.to_string())
+                }
+                _ => {
+                    return Err(format!(
+                        "Invalid retention scope: {} (expected *, tenant:<name> or category:<name>)",
+                        scope
+                    ));
+                }
+            },
+        };
+        let (period, action) = match rest.trim().split_once(':') {
+            Some((period, "archive")) => (period, RetentionAction::Archive),
+            Some((period, "delete")) => (period, RetentionAction::Delete),
+            Some((_, other)) => {
+                return Err(format!(
+                    "Unknown retention action: {} (expected delete or archive)",
+                    other
+                ));
+            }
+            None => (rest.trim(), RetentionAction::Delete),
+        };
+        Ok(Self {
+            scope,
+            keep_for: parse_period(period)?,
+            action,
+        })
+    }
+}
+
+fn parse_period(value: &str) -> Result<Duration, String> {
+    let (number, unit) = value
+        .find(|c: char| !c.is_ascii_digit())
+        .map(|i| value.split_at(i))
+        .unwrap_or((value, "s"));
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid retention period: {}", value))?;
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("Invalid retention period unit: {}", value)),
+    };
+    number
+        .checked_mul(secs)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("Retention period too long: {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rules() {
+        assert_eq!(
+            "tenant:acme=30d".parse::<RetentionRule>().unwrap(),
+            RetentionRule {
+                scope: RetentionScope::Tenant("acme".to_string()),
+                keep_for: Duration::from_secs(30 * 24 * 60 * 60),
+                action: RetentionAction::Delete,
+            }
+        );
+        assert_eq!(
+            "category:logs=12h:archive"
+                .parse::<RetentionRule>()
+                .unwrap(),
+            RetentionRule {
+                scope: RetentionScope::Category("logs".to_string()),
+                keep_for: Duration::from_secs(12 * 60 * 60),
+                action: RetentionAction::Archive,
+            }
+        );
+        assert_eq!(
+            "*=90".parse::<RetentionRule>().unwrap().keep_for,
+            Duration::from_secs(90)
+        );
+        for invalid in ["acme=30d", "tenant:=30d", "*=30w", "*=30d:shred", "*"] {
+            assert!(invalid.parse::<RetentionRule>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_most_specific_rule_wins() {
+        let rules: Vec<RetentionRule> = ["*=90d", "tenant:acme=30d", "category:logs=7d"]
+            .iter()
+            .map(|rule| rule.parse().unwrap())
+            .collect();
+        assert_eq!(rule_for(&rules, "acme", Some("logs")), Some(&rules[2]));
+        assert_eq!(rule_for(&rules, "acme", Some("invoices")), Some(&rules[1]));
+        assert_eq!(rule_for(&rules, "other", None), Some(&rules[0]));
+        assert_eq!(rule_for(&rules[1..2], "other", None), None);
+
+        let now = Utc::now();
+        assert!(rules[2].is_over(now - chrono::Duration::days(7), now));
+        assert!(!rules[2].is_over(now - chrono::Duration::days(6), now));
+    }
+}
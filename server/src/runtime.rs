@@ -0,0 +1,113 @@
+use std::{num::NonZeroUsize, str::FromStr, time::Duration};
+
+use tokio::runtime::{Builder, Runtime};
+
+use crate::constants;
+
+/// Which tokio scheduler drives the server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RuntimeFlavor {
+    /// Everything on the main thread, plus the blocking pool for file I/O and
+    /// hashing: the smallest footprint, for containers with a single CPU.
+    CurrentThread,
+    /// A work-stealing pool of worker threads.
+    #[default]
+    MultiThread,
+}
+
+impl FromStr for RuntimeFlavor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "current_thread" => Ok(Self::CurrentThread),
+            "multi_thread" => Ok(Self::MultiThread),
+            other => Err(format!(
+                "Unknown runtime flavor: {} (expected current_thread or multi_thread)",
+                other
+            )),
+        }
+    }
+}
+
+/// Sizing of the tokio runtime; `None` keeps tokio's default of one worker
+/// per CPU and 512 blocking threads.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuntimeConfig {
+    pub flavor: RuntimeFlavor,
+    /// Only for the multi-threaded flavor.
+    pub worker_threads: Option<NonZeroUsize>,
+    /// Threads for file I/O and offloaded hashing, on top of the workers.
+    pub max_blocking_threads: Option<NonZeroUsize>,
+    /// How long an idle blocking thread is kept before it exits.
+    pub thread_keep_alive: Option<Duration>,
+}
+
+impl RuntimeConfig {
+    pub fn build(&self) -> std::io::Result<Runtime> {
+        let mut builder = match self.flavor {
+            RuntimeFlavor::CurrentThread if self.worker_threads.is_some() => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "Worker threads can only be set for the multi_thread runtime",
+                ));
+            }
+            RuntimeFlavor::CurrentThread => Builder::new_current_thread(),
+            RuntimeFlavor::MultiThread => Builder::new_multi_thread(),
+        };
+        builder
+            .enable_all()
+            .thread_name(constants::RUNTIME_THREAD_NAME);
+        if let Some(threads) = self.worker_threads {
+            builder.worker_threads(threads.get());
+        }
+        if let Some(threads) = self.max_blocking_threads {
+            builder.max_blocking_threads(threads.get());
+        }
+        if let Some(keep_alive) = self.thread_keep_alive {
+            builder.thread_keep_alive(keep_alive);
+        }
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builds_the_configured_runtime() {
+        assert_eq!(
+            "current_thread".parse::<RuntimeFlavor>(),
+            Ok(RuntimeFlavor::CurrentThread)
+        );
+        assert!("threaded".parse::<RuntimeFlavor>().is_err());
+
+        let runtime = RuntimeConfig {
+            worker_threads: NonZeroUsize::new(3),
+            max_blocking_threads: NonZeroUsize::new(4),
+            ..RuntimeConfig::default()
+        }
+        .build()
+        .unwrap();
+        assert_eq!(runtime.metrics().num_workers(), 3);
+
+        let runtime = RuntimeConfig {
+            flavor: RuntimeFlavor::CurrentThread,
+            ..RuntimeConfig::default()
+        }
+        .build()
+        .unwrap();
+        assert_eq!(runtime.metrics().num_workers(), 1);
+        assert_eq!(runtime.block_on(async { 1 + 1 }), 2);
+
+        let err = RuntimeConfig {
+            flavor: RuntimeFlavor::CurrentThread,
+            worker_threads: NonZeroUsize::new(2),
+            ..RuntimeConfig::default()
+        }
+        .build()
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,175 @@
+use std::{io, path::PathBuf};
+
+/// Account to switch to once the port is bound, given as a name or numeric id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Privileges {
+    pub user: String,
+    /// Defaults to the user's primary group.
+    pub group: Option<String>,
+}
+
+/// Switches the process to `privileges`. Must run before any other thread is
+/// started, since setuid only applies to the calling thread on Linux.
+#[cfg(unix)]
+pub fn drop_privileges(privileges: &Privileges) -> io::Result<()> {
+    let (uid, primary_gid) = lookup_user(&privileges.user)?;
+    let gid = match &privileges.group {
+        Some(group) => lookup_group(group)?,
+        None => primary_gid,
+    };
+
+    // SAFETY: plain syscalls without pointers, apart from the empty group list.
+    unsafe {
+        if libc::setgroups(0, std::ptr::null()) != 0
+            || libc::setgid(gid) != 0
+            || libc::setuid(uid) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    // Regaining root must be impossible now.
+    if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+        return Err(io::Error::other("Privileges could not be dropped"));
+    }
+    tracing::info!(uid, gid, "Dropped privileges");
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges(_privileges: &Privileges) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Dropping privileges is only supported on Unix",
+    ))
+}
+
+#[cfg(unix)]
+fn lookup_user(user: &str) -> io::Result<(libc::uid_t, libc::gid_t)> {
+    let name = std::ffi::CString::new(user)?;
+    // SAFETY: `name` is NUL-terminated; the returned record is copied out before
+    // any other lookup can overwrite it, and this runs before other threads exist.
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if !passwd.is_null() {
+        return Ok(unsafe { ((*passwd).pw_uid, (*passwd).pw_gid) });
+    }
+    let uid: libc::uid_t = user
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::NotFound, format!("Unknown user {}", user)))?;
+    // SAFETY: as above.
+    let passwd = unsafe { libc::getpwuid(uid) };
+    let gid = if passwd.is_null() {
+        uid
+    } else {
+        unsafe { (*passwd).pw_gid }
+    };
+    Ok((uid, gid))
+}
+
+#[cfg(unix)]
+fn lookup_group(group: &str) -> io::Result<libc::gid_t> {
+    let name = std::ffi::CString::new(group)?;
+    // SAFETY: see `lookup_user`.
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if !entry.is_null() {
+        return Ok(unsafe { (*entry).gr_gid });
+    }
+    group
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::NotFound, format!("Unknown group {}", group)))
+}
+
+/// Uses Landlock to confine every later file operation of the process to
+/// `dirs`, as defense in depth against path handling bugs. Like
+/// `drop_privileges`, it must run before other threads are started.
+#[cfg(target_os = "linux")]
+pub fn restrict_filesystem(dirs: &[PathBuf]) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+    // Every filesystem right of Landlock ABI v1 (execute through make_sym).
+    const ACCESS_FS: u64 = (1 << 13) - 1;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    let attr = RulesetAttr {
+        handled_access_fs: ACCESS_FS,
+    };
+    // SAFETY: `attr` outlives the call and its size is passed along.
+    let ruleset = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr as *const RulesetAttr,
+            std::mem::size_of::<RulesetAttr>(),
+            0,
+        )
+    };
+    if ruleset < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the syscall returned a fresh descriptor that nothing else owns.
+    let ruleset =
+        unsafe { <std::os::fd::OwnedFd as std::os::fd::FromRawFd>::from_raw_fd(ruleset as i32) };
+
+    for dir in dirs {
+        std::fs::create_dir_all(dir)?;
+        let dir = std::fs::File::open(dir)?;
+        let rule = PathBeneathAttr {
+            allowed_access: ACCESS_FS,
+            parent_fd: dir.as_raw_fd(),
+        };
+        // SAFETY: both descriptors are open and `rule` outlives the call.
+        if unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset.as_raw_fd(),
+                LANDLOCK_RULE_PATH_BENEATH,
+                &rule as *const PathBeneathAttr,
+                0,
+            )
+        } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    // SAFETY: plain syscalls on a descriptor we own.
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+            || libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    tracing::info!(?dirs, "Filesystem access restricted");
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn restrict_filesystem(_dirs: &[PathBuf]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Filesystem sandboxing is only supported on Linux",
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_users_and_groups_by_name_or_id() {
+        assert_eq!(lookup_user("root").unwrap(), (0, 0));
+        assert_eq!(lookup_user("0").unwrap().0, 0);
+        assert_eq!(lookup_group("0").unwrap(), 0);
+        assert!(lookup_user("no-such-user-slicebread").is_err());
+    }
+}
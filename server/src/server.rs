@@ -1,58 +1,749 @@
-use std::marker::PhantomData;
-
+use std::{
+    marker::PhantomData,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::{Buf, BufMut, Bytes};
+use chrono::{DateTime, Utc};
+use futures_util::FutureExt;
 use http_body_util::BodyExt;
-use hyper::{Request, Response, service::Service};
-use tokio::io::AsyncWriteExt;
-
-use crate::constants;
+use hyper::{Method, Request, Response, service::Service};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tracing::field::Empty;
+
+pub use crate::error::SliceBreadServerError;
+use crate::{
+    alerts::AlertMonitor,
+    archive::{self, ArchiveEntry, ArchiveFormat},
+    audit::{AuditEntry, AuditLog},
+    auth::{self, Credentials, Principal, Scope},
+    backpressure::{self, LoadShedder, Maintenance},
+    basic_auth::BasicAuthenticator,
+    bitmap::ChunkBitmap,
+    body::ResponseBody,
+    bundle::{self, BundleFile, BundleManifest, BundleMember},
+    catalog::{self, CatalogEntry},
+    checksum::{self, ChunkDigest},
+    chunk_size::{self, ChunkSizeLimits, RequestCost},
+    compression::{self, Encoding},
+    config::{Durability, ServerConfig},
+    constants,
+    crypto::hmac,
+    debug_log::{self, DebugLog, DebugLogFilter},
+    delta::{self, Applied, Signature},
+    digest::{self, Computed},
+    error::ErrorBody,
+    extract::{self, ArchiveKind},
+    filename,
+    http::HttpConfig,
+    introspection::Introspector,
+    io,
+    journal::{self, ChunkJournal},
+    layout::ChunkLayout,
+    merkle::{Manifest, MerkleTree},
+    multipart::{self, Part},
+    output::{self, OutputVars},
+    peer,
+    policy::UploadPolicy,
+    pool::BufferPool,
+    protocol, purge,
+    replication::{LocalDirBackend, ReplicaBackend, Replicator},
+    retention::{self, RetentionAction},
+    session::{ChunkClaim, IdempotencyState, Progress, Session, SessionSnapshot, SessionStore},
+    share::{self, Share, ShareAccess},
+    sidecar::{self, FileMetadata},
+    tags::{self, Tags},
+    throttle::TokenBucket,
+    user_metadata::{self, UserMetadata},
+};
+
+/// Which routes a listener serves, so that the admin API can be bound to a
+/// separate, e.g. localhost-only, address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Surface {
+    #[default]
+    All,
+    /// Everything except `/admin/*`.
+    Public,
+    /// Only `/admin/*`.
+    Admin,
+}
 
-#[derive(Clone)]
 pub struct SliceBreadServer<B> {
-    _phantom: PhantomData<B>,
+    _phantom: PhantomData<fn() -> B>,
     base_files_dir: String,
+    buffer_pool: BufferPool,
+    config: Arc<ServerConfig>,
+    sessions: Arc<SessionStore>,
+    global_throttle: Option<Arc<TokenBucket>>,
+    connection_throttle: Option<Arc<TokenBucket>>,
+    load: Arc<LoadShedder>,
+    debug_log: Arc<DebugLog>,
+    /// Timings of chunk uploads, from which chunk sizes are recommended.
+    request_cost: Arc<RequestCost>,
+    alerts: Option<Arc<AlertMonitor>>,
+    replicator: Replicator,
+    /// Cold storage for files whose retention rule archives them.
+    archive: Option<Arc<dyn ReplicaBackend>>,
+    audit: AuditLog,
+    introspector: Option<Arc<Introspector>>,
+    basic_auth: Option<Arc<BasicAuthenticator>>,
+    /// Signs purge confirmations, which only need to outlive a round trip.
+    purge_key: Arc<hmac::Key>,
+    client_ip: Option<IpAddr>,
+    client_identities: Option<Vec<String>>,
+    surface: Surface,
+}
+
+impl<B> Clone for SliceBreadServer<B> {
+    fn clone(&self) -> Self {
+        Self {
+            _phantom: PhantomData,
+            base_files_dir: self.base_files_dir.clone(),
+            buffer_pool: self.buffer_pool.clone(),
+            config: self.config.clone(),
+            sessions: self.sessions.clone(),
+            global_throttle: self.global_throttle.clone(),
+            connection_throttle: self.connection_throttle.clone(),
+            load: self.load.clone(),
+            debug_log: self.debug_log.clone(),
+            request_cost: self.request_cost.clone(),
+            alerts: self.alerts.clone(),
+            replicator: self.replicator.clone(),
+            archive: self.archive.clone(),
+            audit: self.audit.clone(),
+            introspector: self.introspector.clone(),
+            basic_auth: self.basic_auth.clone(),
+            purge_key: self.purge_key.clone(),
+            client_ip: self.client_ip,
+            client_identities: self.client_identities.clone(),
+            surface: self.surface,
+        }
+    }
 }
 
 impl<B> SliceBreadServer<B> {
     pub fn new(dir: String) -> Self {
+        Self::with_config(dir, ServerConfig::default())
+    }
+
+    pub fn with_config(dir: String, config: ServerConfig) -> Self {
+        let global_throttle = config
+            .throttle
+            .global_bytes_per_sec
+            .map(|rate| Arc::new(TokenBucket::new(rate)));
+        let load = Arc::new(LoadShedder::new(config.backpressure));
+        let sessions = Arc::new(SessionStore::with_limits(config.session_limits));
+        let debug_log = Arc::new(DebugLog::new(config.debug_log.clone()));
+        let alerts = config
+            .alerts
+            .clone()
+            .map(|alerts| Arc::new(AlertMonitor::new(alerts)));
+        let replicator = Replicator::new(
+            config
+                .replicate_to
+                .iter()
+                .map(|dir| Arc::new(LocalDirBackend::new(dir)) as Arc<dyn ReplicaBackend>)
+                .collect(),
+        );
+        let archive = config
+            .archive_to
+            .as_ref()
+            .map(|dir| Arc::new(LocalDirBackend::new(dir)) as Arc<dyn ReplicaBackend>);
+        let audit = AuditLog::new(
+            config
+                .audit_log
+                .clone()
+                .unwrap_or_else(|| Path::new(&dir).join(constants::AUDIT_LOG_FILE)),
+        );
+        let introspector = config
+            .introspection
+            .clone()
+            .map(|introspection| Arc::new(Introspector::new(introspection)));
+        let basic_auth = config
+            .basic_auth
+            .clone()
+            .map(|basic_auth| Arc::new(BasicAuthenticator::new(basic_auth)));
         Self {
             _phantom: PhantomData,
             base_files_dir: dir,
+            buffer_pool: BufferPool::new(
+                constants::DEFAULT_POOL_BUFFERS,
+                constants::DEFAULT_POOL_BUFFER_CAPACITY,
+            ),
+            config: Arc::new(config),
+            sessions,
+            global_throttle,
+            connection_throttle: None,
+            load,
+            debug_log,
+            request_cost: Arc::new(RequestCost::new()),
+            alerts,
+            replicator,
+            archive,
+            audit,
+            introspector,
+            basic_auth,
+            purge_key: Arc::new(hmac::Key::new(
+                hmac::HMAC_SHA256,
+                &rand::random::<[u8; 32]>(),
+            )),
+            client_ip: None,
+            client_identities: None,
+            surface: Surface::All,
         }
     }
-}
 
-#[derive(Debug)]
-pub enum SliceBreadServerError {
-    InternalServerError(String),
-    BadRequest(String),
-    IoError(std::io::Error),
-    HyperError(hyper::http::Error),
-}
+    /// Clone for a newly accepted connection, with its own bandwidth budget.
+    pub fn for_connection(&self, client_ip: IpAddr) -> Self {
+        let mut server = self.clone();
+        server.client_ip = Some(client_ip);
+        server.connection_throttle = self
+            .config
+            .throttle
+            .connection_bytes_per_sec
+            .map(|rate| Arc::new(TokenBucket::new(rate)));
+        server
+    }
 
-impl std::error::Error for SliceBreadServerError {}
+    /// Identities from the client certificate the connection was authenticated with.
+    pub fn with_client_identities(mut self, identities: Vec<String>) -> Self {
+        self.client_identities = Some(identities);
+        self
+    }
 
-impl std::fmt::Display for SliceBreadServerError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::InternalServerError(msg) => write!(f, "Internal Server Error: {}", msg),
-            Self::BadRequest(msg) => write!(f, "Bad Request: {}", msg),
-            Self::IoError(err) => write!(f, "IO Error: {}", err),
-            Self::HyperError(err) => write!(f, "Hyper Error: {}", err),
+    pub fn http_config(&self) -> &HttpConfig {
+        &self.config.http
+    }
+
+    /// Clone that answers `404` for routes outside `surface`. It shares all state,
+    /// so uploads made through one listener show up in the other's admin API.
+    pub fn with_surface(&self, surface: Surface) -> Self {
+        let mut server = self.clone();
+        server.surface = surface;
+        server
+    }
+
+    fn body_limit(&self, body_route: Option<&Route>) -> Option<u64> {
+        let limits = &self.config.body_limits;
+        match body_route {
+            Some(Route::RangeUpload { .. }) => limits.range,
+            Some(Route::DeltaUpload { .. }) => limits.delta,
+            Some(Route::BatchUpload) => limits.batch,
+            _ => limits.chunk,
         }
     }
-}
 
-impl From<std::io::Error> for SliceBreadServerError {
-    fn from(value: std::io::Error) -> Self {
-        tracing::error!(%value, "Internal server error during upload");
-        SliceBreadServerError::IoError(value)
+    /// The connection's peer, or the client behind a trusted proxy.
+    fn resolve_client_ip(&self, headers: &hyper::HeaderMap) -> Option<IpAddr> {
+        self.client_ip
+            .map(|peer| self.config.ip_filter.client_ip(peer, headers))
+    }
+
+    /// Checks the client against the IP filter and, for mTLS connections, the
+    /// identity rules. An authenticated client's tenant is written into `X-Tenant-Id`,
+    /// and its `Principal` added to the request's extensions.
+    /// Returns the credentials still to be checked with authenticate(), if any.
+    fn admit(
+        &self,
+        client_ip: Option<IpAddr>,
+        route: Option<&Route>,
+        req: &mut Request<B>,
+    ) -> Result<Option<Credentials>, SliceBreadServerError> {
+        if let Some(ip) = client_ip
+            && !self.config.ip_filter.permits(ip)
+        {
+            tracing::warn!(client_ip = %ip, "Refused client outside the IP filter");
+            return Err(SliceBreadServerError::Forbidden(format!(
+                "Client {} is not allowed",
+                ip
+            )));
+        }
+
+        let admin = route.is_some_and(Route::is_admin);
+        let served = match self.surface {
+            Surface::All => true,
+            Surface::Public => !admin,
+            Surface::Admin => admin,
+        };
+        if !served {
+            return Err(SliceBreadServerError::NotFound(
+                "No such route on this listener".to_string(),
+            ));
+        }
+
+        if route.is_some_and(Route::is_internal) {
+            return self.admit_peer(req).map(|()| None);
+        }
+
+        if route.is_some_and(Route::accepts_share_links)
+            && let Some(token) = query_param(req.uri().query(), "share")
+        {
+            return Ok(Some(Credentials::ShareLink(token.to_string())));
+        }
+
+        if let Some(identities) = &self.client_identities
+            && !self.config.client_identities.is_empty()
+        {
+            let Some(principal) = auth::resolve(&self.config.client_identities, identities) else {
+                tracing::warn!(
+                    ?identities,
+                    "Refused client certificate without an identity rule"
+                );
+                return Err(SliceBreadServerError::Forbidden(
+                    "Client certificate is not mapped to a tenant".to_string(),
+                ));
+            };
+            return self.authorize(principal, route, req).map(|()| None);
+        }
+
+        if self.introspector.is_none() && self.basic_auth.is_none() {
+            return Ok(None);
+        }
+        let credentials = req
+            .headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Credentials::parse);
+        match credentials {
+            Some(Credentials::Bearer(token)) if self.introspector.is_some() => {
+                Ok(Some(Credentials::Bearer(token)))
+            }
+            Some(Credentials::Basic { user, password }) if self.basic_auth.is_some() => {
+                Ok(Some(Credentials::Basic { user, password }))
+            }
+            _ => Err(self.unauthorized("Credentials are required")),
+        }
+    }
+
+    /// Checks the `X-Cluster-Signature` of a request from another instance.
+    /// The internal routes don't exist unless peers are configured.
+    fn admit_peer(&self, req: &Request<B>) -> Result<(), SliceBreadServerError> {
+        let Some(peers) = self
+            .config
+            .cluster
+            .as_ref()
+            .and_then(|cluster| cluster.peers.as_ref())
+        else {
+            return Err(SliceBreadServerError::NotFound("No such route".to_string()));
+        };
+        let signed = req
+            .headers()
+            .get(constants::HEADER_CLUSTER_SIGNATURE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|signature| {
+                peer::verify(
+                    signature,
+                    &peers.secret,
+                    req.method(),
+                    req.uri().path(),
+                    Utc::now().timestamp(),
+                )
+            });
+        if !signed {
+            tracing::warn!(path = %req.uri().path(), "Refused internal request without a valid cluster signature");
+            return Err(SliceBreadServerError::Forbidden(
+                "Invalid cluster signature".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the credentials admit() passed on and authorizes the principal
+    /// they stand for. Basic auth users are mapped to tenants by the identity
+    /// rules, and get the default tenant and scopes without one.
+    async fn authenticate(
+        &self,
+        credentials: Credentials,
+        route: Option<&Route>,
+        req: &mut Request<B>,
+    ) -> Result<(), SliceBreadServerError> {
+        let principal = match credentials {
+            Credentials::Bearer(token) => {
+                let Some(introspector) = &self.introspector else {
+                    return Err(self.unauthorized("Bearer tokens are not accepted"));
+                };
+                introspector
+                    .principal(&token)
+                    .await?
+                    .ok_or_else(|| self.unauthorized("Bearer token is not active"))?
+            }
+            Credentials::Basic { user, password } => {
+                let Some(basic_auth) = &self.basic_auth else {
+                    return Err(self.unauthorized("Basic auth is not accepted"));
+                };
+                if !basic_auth.verify(&user, &password).await? {
+                    tracing::warn!(%user, "Refused Basic auth credentials");
+                    return Err(self.unauthorized("Invalid username or password"));
+                }
+                auth::resolve(&self.config.client_identities, std::slice::from_ref(&user))
+                    .unwrap_or_else(|| Principal {
+                        name: user,
+                        tenant: constants::DEFAULT_TENANT.to_string(),
+                        scopes: auth::DEFAULT_SCOPES.to_vec(),
+                    })
+            }
+            Credentials::ShareLink(token) => {
+                return self.follow_share_link(&token, route, req).await;
+            }
+        };
+        self.authorize(principal, route, req)
+    }
+
+    /// Admits a request by a link share on the file it names, acting as the
+    /// tenant that uploaded the file. The link must be signed for the file and
+    /// still be listed in its sidecar, so removing the share revokes it.
+    async fn follow_share_link(
+        &self,
+        token: &str,
+        route: Option<&Route>,
+        req: &mut Request<B>,
+    ) -> Result<(), SliceBreadServerError> {
+        let invalid = || SliceBreadServerError::Forbidden("Invalid share link".to_string());
+        let (Some(secret), Some(route)) = (&self.config.share_link_secret, route) else {
+            return Err(invalid());
+        };
+        let file_id = route.file_id().ok_or_else(invalid)?;
+        let share_id = share::verify_link(token, file_id, secret.as_bytes()).ok_or_else(invalid)?;
+        let base_dir = Path::new(&self.base_files_dir);
+        let entry = catalog::lookup(base_dir, file_id)
+            .await?
+            .ok_or_else(invalid)?;
+        let metadata = sidecar::read(&base_dir.join(&entry.path)).await?;
+        let share = metadata
+            .shares
+            .iter()
+            .find(|share| share.id == share_id && share.principal.is_none())
+            .ok_or_else(invalid)?;
+        if !share.allows(route.modifies(), Utc::now()) {
+            return Err(SliceBreadServerError::Forbidden(format!(
+                "Share link does not allow {} {}",
+                if route.modifies() {
+                    "changing"
+                } else {
+                    "reading"
+                },
+                file_id
+            )));
+        }
+        let tenant = hyper::header::HeaderValue::from_str(&metadata.uploader).map_err(|_| {
+            SliceBreadServerError::InternalServerError("Invalid tenant".to_string())
+        })?;
+        req.headers_mut()
+            .insert(constants::HEADER_TENANT_ID, tenant);
+        Ok(())
+    }
+
+    /// `401` challenging the client for whichever credentials the server accepts.
+    fn unauthorized(&self, message: &str) -> SliceBreadServerError {
+        let challenge = match (self.introspector.is_some(), self.basic_auth.is_some()) {
+            (true, true) => "Bearer, Basic realm=\"slicebread\"",
+            (true, false) => "Bearer",
+            _ => "Basic realm=\"slicebread\"",
+        };
+        SliceBreadServerError::Unauthorized {
+            message: message.to_string(),
+            challenge,
+        }
+    }
+
+    /// Checks `principal` holds a scope for the route and pins the request to its tenant.
+    fn authorize(
+        &self,
+        principal: Principal,
+        route: Option<&Route>,
+        req: &mut Request<B>,
+    ) -> Result<(), SliceBreadServerError> {
+        // Anything that isn't a known route is a chunk upload.
+        let scopes = route.map_or(&[Scope::Upload][..], Route::scopes);
+        if !principal.allows(scopes) {
+            return Err(SliceBreadServerError::Forbidden(format!(
+                "{} lacks the {} scope",
+                principal.name,
+                scopes
+                    .iter()
+                    .map(|scope| scope.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" or ")
+            )));
+        }
+        if let Some(tenant) = req.headers().get(constants::HEADER_TENANT_ID)
+            && tenant.as_bytes() != principal.tenant.as_bytes()
+        {
+            return Err(SliceBreadServerError::Forbidden(format!(
+                "{} may only act as tenant {}",
+                principal.name, principal.tenant
+            )));
+        }
+        let tenant = hyper::header::HeaderValue::from_str(&principal.tenant).map_err(|_| {
+            SliceBreadServerError::InternalServerError(format!(
+                "Invalid tenant for {}",
+                principal.name
+            ))
+        })?;
+        req.headers_mut()
+            .insert(constants::HEADER_TENANT_ID, tenant);
+        req.extensions_mut().insert(principal);
+        Ok(())
+    }
+
+    /// Refuses access to a file by anyone but its owner, an admin, or for
+    /// reads, a principal it is shared with.
+    async fn check_access(
+        &self,
+        file_id: &str,
+        principal: Option<&Principal>,
+        write: bool,
+    ) -> Result<(), SliceBreadServerError> {
+        if self.may_access(file_id, principal, write).await? {
+            return Ok(());
+        }
+        Err(SliceBreadServerError::Forbidden(format!(
+            "{} may not {} {}",
+            principal.map_or("Client", |principal| principal.name.as_str()),
+            if write { "change" } else { "read" },
+            file_id
+        )))
+    }
+
+    /// The owner is looked up on the upload in progress, or else on the
+    /// completed file's sidecar.
+    async fn may_access(
+        &self,
+        file_id: &str,
+        principal: Option<&Principal>,
+        write: bool,
+    ) -> Result<bool, SliceBreadServerError> {
+        if principal.is_none() {
+            return Ok(true);
+        }
+        let (owner, shares) = match self.sessions.session(file_id) {
+            Some(session) => (session.owner, session.shares),
+            None => {
+                let base_dir = Path::new(&self.base_files_dir);
+                let Some(entry) = catalog::lookup(base_dir, file_id).await? else {
+                    return Ok(true);
+                };
+                match sidecar::read(&base_dir.join(&entry.path)).await {
+                    Ok(metadata) => (metadata.owner, metadata.shares),
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => (None, Vec::new()),
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        };
+        Ok(auth::may_access(
+            principal,
+            owner.as_deref(),
+            &shares,
+            write,
+        ))
+    }
+
+    /// Enforces the signed upload policy when the server requires one. `size`
+    /// is how large the file will be, as far as is known yet.
+    fn check_policy(
+        &self,
+        headers: &hyper::HeaderMap,
+        file_id: &str,
+        tenant: &str,
+        content_type: &str,
+        size: u64,
+    ) -> Result<Option<UploadPolicy>, SliceBreadServerError> {
+        let Some(secret) = &self.config.upload_policy_secret else {
+            return Ok(None);
+        };
+        let token = headers
+            .get(constants::HEADER_UPLOAD_POLICY)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| {
+                SliceBreadServerError::Forbidden("An upload policy is required".to_string())
+            })?;
+        let policy = UploadPolicy::verify(token, secret.as_bytes(), Utc::now())
+            .map_err(SliceBreadServerError::Forbidden)?;
+
+        if policy.file_id.as_ref().is_some_and(|id| id != file_id) {
+            return Err(SliceBreadServerError::Forbidden(format!(
+                "Upload policy does not cover file id {}",
+                file_id
+            )));
+        }
+        if policy.tenant.as_ref().is_some_and(|t| t != tenant) {
+            return Err(SliceBreadServerError::Forbidden(format!(
+                "Upload policy does not cover tenant {}",
+                tenant
+            )));
+        }
+        if !policy.allows_content_type(content_type) {
+            return Err(SliceBreadServerError::Forbidden(format!(
+                "Upload policy does not allow {}",
+                content_type
+            )));
+        }
+        if let Some(max_size) = policy.max_size
+            && size > max_size
+        {
+            return Err(SliceBreadServerError::PayloadTooLarge(format!(
+                "Upload policy allows at most {} bytes, got {}",
+                max_size, size
+            )));
+        }
+        if let Some(required) = policy.size
+            && size > required
+        {
+            return Err(SliceBreadServerError::PayloadTooLarge(format!(
+                "Upload policy requires a file of {} bytes, got {}",
+                required, size
+            )));
+        }
+        if let Some(nonce) = &policy.nonce
+            && !self
+                .sessions
+                .claim_policy_nonce(nonce, file_id, policy.expires, Utc::now())
+        {
+            return Err(SliceBreadServerError::Forbidden(
+                "Upload policy has already been used for another upload".to_string(),
+            ));
+        }
+        Ok(Some(policy))
+    }
+
+    /// Refuses an upload that would take the tenant past its storage quota.
+    fn check_quota(&self, tenant: &str, incoming: u64) -> Result<(), SliceBreadServerError> {
+        let Some(limit) = self.config.tenant_quota_bytes else {
+            return Ok(());
+        };
+        let used = self.sessions.usage(tenant).bytes_stored;
+        if used.saturating_add(incoming) > limit {
+            return Err(SliceBreadServerError::InsufficientStorage {
+                reason: format!(
+                    "Storing {} more bytes would exceed the quota of tenant {}",
+                    incoming, tenant
+                ),
+                limit,
+                used,
+            });
+        }
+        Ok(())
+    }
+
+    /// Handles `GET /quota`: the tenant's storage quota and usage, so a client
+    /// can check before starting a large upload.
+    fn quota(
+        &self,
+        headers: &hyper::HeaderMap,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let tenant = get_tenant(headers)?;
+        let used = self.sessions.usage(&tenant).bytes_stored;
+        let limit = self.config.tenant_quota_bytes;
+        let remaining = limit.map(|limit| limit.saturating_sub(used));
+        let mut res = json_response(&Quota {
+            tenant,
+            limit,
+            used,
+            remaining,
+        })?;
+        let headers = res.headers_mut();
+        headers.insert(constants::HEADER_QUOTA_USED, used.into());
+        if let (Some(limit), Some(remaining)) = (limit, remaining) {
+            headers.insert(constants::HEADER_QUOTA_LIMIT, limit.into());
+            headers.insert(constants::HEADER_QUOTA_REMAINING, remaining.into());
+        }
+        Ok(res)
+    }
+
+    /// Handles `GET /capabilities`, recommending a chunk size for the
+    /// `file_size` query parameter if given.
+    fn capabilities(
+        &self,
+        file_size: Option<&str>,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let config = &self.config;
+        let file_size = file_size
+            .map(|size| {
+                size.parse::<u64>().map_err(|e| {
+                    SliceBreadServerError::BadRequest(format!("Invalid file_size: {}", e))
+                })
+            })
+            .transpose()?;
+        let recommended_chunk_size = chunk_size::recommend(
+            file_size,
+            self.request_cost.estimate(),
+            ChunkSizeLimits {
+                min_chunk_bytes: config.min_chunk_bytes,
+                max_chunk_bytes: config.body_limits.chunk,
+                max_total_chunks: config.max_total_chunks,
+            },
+        );
+        json_response(&Capabilities {
+            protocol_versions: protocol::SUPPORTED_VERSIONS,
+            checksum_algorithms: digest::Algorithm::SUPPORTED
+                .into_iter()
+                .map(digest::Algorithm::name)
+                .collect(),
+            upload_modes: &["chunks", "byte_ranges", "delta", "batch", "bundles"],
+            response_encodings: if config.compress_responses {
+                Encoding::SUPPORTED
+                    .into_iter()
+                    .map(Encoding::name)
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            features: CapabilityFeatures {
+                upload_policy_required: config.upload_policy_secret.is_some(),
+                share_links: config.share_link_secret.is_some(),
+                immutable: config.immutable,
+                read_only: self.load.maintenance().read_only,
+                cluster: config.cluster.is_some(),
+            },
+            limits: CapabilityLimits {
+                max_total_chunks: config.max_total_chunks,
+                min_chunk_bytes: config.min_chunk_bytes,
+                max_chunk_bytes: config.body_limits.chunk,
+                max_range_bytes: config.body_limits.range,
+                max_delta_bytes: config.body_limits.delta,
+                max_batch_bytes: config.body_limits.batch,
+                max_delta_block_size: constants::MAX_DELTA_BLOCK_SIZE,
+                max_upload_duration_secs: config.max_upload_duration.map(|d| d.as_secs()),
+                max_sessions_per_client: config.session_limits.max_per_client,
+                max_sessions_per_tenant: config.session_limits.max_per_tenant,
+                tenant_quota_bytes: config.tenant_quota_bytes,
+            },
+            recommended_chunk_size,
+            recommended_total_chunks: file_size
+                .map(|size| size.div_ceil(recommended_chunk_size).max(1)),
+        })
+    }
+
+    /// The tightest of `X-Upload-Max-Duration`, the policy's and the server's limit.
+    fn max_duration(
+        &self,
+        headers: &hyper::HeaderMap,
+        policy: Option<&UploadPolicy>,
+    ) -> Result<Option<Duration>, SliceBreadServerError> {
+        let requested = get_optional_header(headers, constants::HEADER_UPLOAD_MAX_DURATION)?;
+        Ok([
+            requested.map(Duration::from_secs),
+            policy
+                .and_then(|policy| policy.max_duration_secs)
+                .map(Duration::from_secs),
+            self.config.max_upload_duration,
+        ]
+        .into_iter()
+        .flatten()
+        .min())
     }
-}
 
-impl From<hyper::http::Error> for SliceBreadServerError {
-    fn from(value: hyper::http::Error) -> Self {
-        tracing::error!(%value, "Internal server error during upload");
-        SliceBreadServerError::HyperError(value)
+    async fn throttle(&self, bytes: usize) {
+        for bucket in [&self.global_throttle, &self.connection_throttle]
+            .into_iter()
+            .flatten()
+        {
+            bucket.acquire(bytes).await;
+        }
     }
 }
 
@@ -62,663 +753,9078 @@ fn get_header<T: std::str::FromStr>(
 ) -> Result<T, SliceBreadServerError> {
     headers
         .get(key)
-        .ok_or_else(|| SliceBreadServerError::BadRequest(format!("Missing header: {}", key)))?
+        .ok_or_else(|| SliceBreadServerError::MissingHeader(key.to_string()))?
         .to_str()
-        .map_err(|_| SliceBreadServerError::BadRequest(format!("Invalid header format: {}", key)))?
+        .map_err(|_| {
+            SliceBreadServerError::InvalidHeader(format!("Invalid header format: {}", key))
+        })?
         .parse::<T>()
-        .map_err(|_| SliceBreadServerError::BadRequest(format!("Invalid header value: {}", key)))
+        .map_err(|_| SliceBreadServerError::InvalidHeader(format!("Invalid header value: {}", key)))
 }
 
-impl<B> Service<Request<B>> for SliceBreadServer<B>
-where
-    B: hyper::body::Body + Send + 'static,
-    B::Data: Send,
-    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
-{
-    type Response = Response<String>;
-    type Error = SliceBreadServerError;
-    type Future = std::pin::Pin<
-        Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>> + Send>,
-    >;
+fn get_optional_header<T: std::str::FromStr>(
+    headers: &hyper::HeaderMap,
+    key: &str,
+) -> Result<Option<T>, SliceBreadServerError> {
+    if !headers.contains_key(key) {
+        return Ok(None);
+    }
+    get_header(headers, key).map(Some)
+}
 
-    fn call(&self, req: Request<B>) -> Self::Future {
-        let headers = req.headers().clone();
-        let base_files_dir = self.base_files_dir.clone();
+/// File ids name directories under the upload directory, so each must be a
+/// single path segment.
+fn get_file_id(headers: &hyper::HeaderMap) -> Result<String, SliceBreadServerError> {
+    let file_id: String = get_header(headers, constants::HEADER_FILE_ID)?;
+    if !output::is_safe_segment(&file_id) {
+        return Err(SliceBreadServerError::InvalidHeader(format!(
+            "Invalid header value: {}",
+            constants::HEADER_FILE_ID
+        )));
+    }
+    Ok(file_id)
+}
 
-        Box::pin(async move {
-            let body = req
-                .collect()
-                .await
-                .map_err(|e| {
-                    SliceBreadServerError::InternalServerError(format!(
-                        "Failed to read body: {}",
-                        e.into()
-                    ))
-                })?
-                .to_bytes();
-            let file_id: String = get_header(&headers, constants::HEADER_FILE_ID)?;
-            let chunk_index: usize = get_header(&headers, constants::HEADER_CHUNK_INDEX)?;
-            let total_chunks: usize = get_header(&headers, constants::HEADER_TOTAL_CHUNKS)?;
-            let file_name: String = get_header(&headers, constants::HEADER_FILE_NAME)?;
-
-            tracing::info!(file_id = %file_id, "Received chunk");
-            tracing::debug!("Received chunk index: {}", chunk_index);
-
-            if chunk_index > total_chunks {
-                tracing::warn!(chunk_index, total_chunks, "Invalid chunk index");
-                return Err(SliceBreadServerError::BadRequest(format!(
-                    "Invalid {}: {} > {}: {}",
-                    constants::HEADER_CHUNK_INDEX,
-                    chunk_index,
-                    constants::HEADER_TOTAL_CHUNKS,
-                    total_chunks
-                )));
-            }
+/// Tenant ids end up in output paths, so only a conservative character set is allowed.
+fn get_tenant(headers: &hyper::HeaderMap) -> Result<String, SliceBreadServerError> {
+    let Some(tenant) = get_optional_header::<String>(headers, constants::HEADER_TENANT_ID)? else {
+        return Ok(constants::DEFAULT_TENANT.to_string());
+    };
+    let valid = !tenant.is_empty()
+        && tenant.len() <= 64
+        && tenant
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(SliceBreadServerError::InvalidHeader(format!(
+            "Invalid header value: {}",
+            constants::HEADER_TENANT_ID
+        )));
+    }
+    Ok(tenant)
+}
 
-            if total_chunks == 0 {
-                return Err(SliceBreadServerError::BadRequest(
-                    "Total chunks must be at least 1".to_string(),
-                ));
-            }
+/// Whether `X-Extract` asks for the file to be unpacked, which only works for archives.
+fn get_extract(headers: &hyper::HeaderMap, file_name: &str) -> Result<bool, SliceBreadServerError> {
+    let extract = get_optional_header(headers, constants::HEADER_EXTRACT)?.unwrap_or(false);
+    if extract && ArchiveKind::detect(file_name).is_none() {
+        return Err(SliceBreadServerError::BadRequest(format!(
+            "{} needs a .zip, .tar.gz or .tgz file, got {}",
+            constants::HEADER_EXTRACT,
+            file_name
+        )));
+    }
+    Ok(extract)
+}
 
-            let upload_dir = format!("{}/{}/", base_files_dir, file_id);
-            tracing::debug!(upload_dir = %upload_dir, "Creating upload directory");
-            tokio::fs::create_dir_all(upload_dir).await?;
+/// `X-Bundle-Id` and `X-Bundle-Path`, which are sent together or not at all.
+fn get_bundle_member(
+    headers: &hyper::HeaderMap,
+) -> Result<Option<BundleMember>, SliceBreadServerError> {
+    let bundle_id: Option<String> = get_optional_header(headers, constants::HEADER_BUNDLE_ID)?;
+    let path: Option<String> = get_optional_header(headers, constants::HEADER_BUNDLE_PATH)?;
+    match (bundle_id, path) {
+        (None, None) => Ok(None),
+        (Some(bundle_id), Some(path)) => Ok(Some(BundleMember {
+            bundle_id: bundle::parse_id(&bundle_id)?.to_string(),
+            path: bundle::parse_path(&path)?,
+        })),
+        (Some(_), None) => Err(SliceBreadServerError::MissingHeader(
+            constants::HEADER_BUNDLE_PATH.to_string(),
+        )),
+        (None, Some(_)) => Err(SliceBreadServerError::MissingHeader(
+            constants::HEADER_BUNDLE_ID.to_string(),
+        )),
+    }
+}
 
-            let chunk_file = format!("{}/{}/chunk_{}.bin", base_files_dir, file_id, chunk_index);
-            let mut file = tokio::fs::File::create(chunk_file).await?;
-            file.write_all(&body).await?;
-            file.flush().await?;
+/// Read-only shares for the principals named in `X-Shared-With`, comma-separated.
+fn get_shares(headers: &hyper::HeaderMap) -> Vec<Share> {
+    headers
+        .get_all(constants::HEADER_SHARED_WITH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| Share::new(Some(name.to_string()), ShareAccess::Read, None))
+        .collect()
+}
 
-            let is_last_chunk = chunk_index == total_chunks - 1;
+fn get_category(headers: &hyper::HeaderMap) -> Result<Option<String>, SliceBreadServerError> {
+    let Some(category) = get_optional_header::<String>(headers, constants::HEADER_UPLOAD_CATEGORY)?
+    else {
+        return Ok(None);
+    };
+    let category = category.trim();
+    if category.is_empty() || category.len() > 64 {
+        return Err(SliceBreadServerError::InvalidHeader(format!(
+            "{} must be 1 to 64 characters",
+            constants::HEADER_UPLOAD_CATEGORY
+        )));
+    }
+    Ok(Some(category.to_string()))
+}
 
-            if is_last_chunk {
-                for i in 0..total_chunks {
-                    let chunk_file = format!("{}/{}/chunk_{}.bin", base_files_dir, file_id, i);
+fn get_tags(headers: &hyper::HeaderMap) -> Result<Tags, SliceBreadServerError> {
+    match get_optional_header::<String>(headers, constants::HEADER_UPLOAD_TAGS)? {
+        Some(value) => tags::parse(&value).map_err(|e| {
+            SliceBreadServerError::InvalidHeader(format!(
+                "{}: {}",
+                constants::HEADER_UPLOAD_TAGS,
+                e
+            ))
+        }),
+        None => Ok(Tags::new()),
+    }
+}
 
-                    if !tokio::fs::try_exists(&chunk_file).await? {
-                        tracing::warn!(%file_id, missing_chunk = i, "Missing chunk during finalization");
-                        return Err(SliceBreadServerError::BadRequest(format!(
-                            "Missing chunk: {}",
-                            i
-                        )));
-                    }
-                }
+fn get_user_metadata(headers: &hyper::HeaderMap) -> Result<UserMetadata, SliceBreadServerError> {
+    user_metadata::from_headers(headers).map_err(SliceBreadServerError::InvalidHeader)
+}
 
-                tracing::info!(%file_id, "All chunks received, assembling final file");
-                let mut file = tokio::fs::File::create(format!(
-                    "{}/{}/{}",
-                    base_files_dir, file_id, file_name
-                ))
-                .await?;
-                println!("fileoutput: {:?}", file);
-                for i in 0..total_chunks {
-                    let chunk_file = format!("{}/{}/chunk_{}.bin", base_files_dir, file_id, i);
-                    let chunk_bytes = tokio::fs::read(&chunk_file).await?;
-                    file.write_all(&chunk_bytes).await?;
-                    tokio::fs::remove_file(chunk_file).await?;
-                }
-                file.flush().await?;
+/// Holds an upload to the exact size and digest its policy requires, which
+/// are checked once the file is assembled.
+fn bind_to_policy(
+    declared: &mut Session,
+    policy: Option<&UploadPolicy>,
+) -> Result<(), SliceBreadServerError> {
+    let Some(policy) = policy else {
+        return Ok(());
+    };
+    if let Some(size) = policy.size {
+        if let Some(declared_size) = declared.file_size
+            && declared_size != size
+        {
+            return Err(SliceBreadServerError::Forbidden(format!(
+                "Upload policy requires a file of {} bytes, but {} is {}",
+                size,
+                constants::HEADER_FILE_SIZE,
+                declared_size
+            )));
+        }
+        declared.file_size = Some(size);
+    }
+    declared.required_sha256 = policy.sha256_digest();
+    Ok(())
+}
 
-                tracing::info!(%file_id, file_name = %file_name, "Upload complete and file assembled");
-            }
+/// Checks a finished file against the size and SHA-256 it was bound to.
+fn check_finished(
+    what: &str,
+    size: u64,
+    sha256: &ChunkDigest,
+    expected_size: Option<u64>,
+    required_sha256: Option<ChunkDigest>,
+) -> Result<(), SliceBreadServerError> {
+    if let Some(declared) = expected_size
+        && declared != size
+    {
+        return Err(SliceBreadServerError::LengthMismatch {
+            declared,
+            received: size,
+        });
+    }
+    if required_sha256.is_some_and(|required| required != *sha256) {
+        return Err(SliceBreadServerError::DigestMismatch(format!(
+            "{} does not match the SHA-256 of its upload policy",
+            what
+        )));
+    }
+    Ok(())
+}
 
-            Ok(Response::builder()
-                .status(201)
-                .body("File uploaded successfuly".to_string())?)
-        })
+fn get_bounded_header(
+    headers: &hyper::HeaderMap,
+    key: &str,
+    max: usize,
+) -> Result<usize, SliceBreadServerError> {
+    let value: usize = get_header(headers, key)?;
+    if value > max {
+        return Err(SliceBreadServerError::InvalidHeader(format!(
+            "Invalid header value: {} exceeds maximum of {}",
+            key, max
+        )));
     }
+    Ok(value)
 }
 
-#[cfg(test)]
-mod tests {
-    use std::sync::Arc;
+impl<B> SliceBreadServer<B> {
+    fn chunk_path(&self, file_id: &str, layout: &ChunkLayout, chunk_index: usize) -> PathBuf {
+        Path::new(&self.base_files_dir)
+            .join(file_id)
+            .join(layout.chunk_path(chunk_index))
+    }
 
-    use bytes::Bytes;
-    use http_body_util::Full;
-    use hyper::{Request, service::Service};
-    use tempdir::TempDir;
-    use tokio::fs;
+    fn layout_path(&self, file_id: &str) -> PathBuf {
+        Path::new(&self.base_files_dir)
+            .join(file_id)
+            .join(constants::CHUNK_LAYOUT_FILE)
+    }
 
-    use crate::server::{SliceBreadServer, SliceBreadServerError};
+    /// Layout an upload's chunks were stored with, kept next to them so that
+    /// changing `--chunk-layout` doesn't strand uploads in progress.
+    async fn chunk_layout(&self, file_id: &str) -> Result<ChunkLayout, SliceBreadServerError> {
+        match tokio::fs::read(self.layout_path(file_id)).await {
+            Ok(json) => serde_json::from_slice(&json)
+                .map_err(|e| SliceBreadServerError::InternalServerError(e.to_string())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(self.config.chunk_layout),
+            Err(err) => Err(err.into()),
+        }
+    }
 
-    #[tokio::test]
-    async fn test_full_upload_success() {
-        let temp_dir = TempDir::new("upload_test").expect("create temp dir failed");
-        let upload_dir = temp_dir.path().join("uploads");
-        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+    /// Records the configured layout for a new upload, or returns the one it already has.
+    async fn pin_chunk_layout(&self, file_id: &str) -> Result<ChunkLayout, SliceBreadServerError> {
+        let path = self.layout_path(file_id);
+        if tokio::fs::try_exists(&path).await? {
+            return self.chunk_layout(file_id).await;
+        }
+        // Renamed into place so concurrent chunks never read it half-written.
+        // Racing writers all write the configured layout, so either rename may win.
+        let layout = self.config.chunk_layout;
+        let json = serde_json::to_vec(&layout)
+            .map_err(|e| SliceBreadServerError::InternalServerError(e.to_string()))?;
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(format!(".{:016x}.tmp", rand::random::<u64>()));
+        tokio::fs::write(&tmp_path, json).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+        Ok(layout)
+    }
 
-        let service =
-            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+    fn range_path(&self, file_id: &str) -> PathBuf {
+        Path::new(&self.base_files_dir)
+            .join(file_id)
+            .join(constants::RANGES_FILE)
+    }
 
-        let file_id = "test123";
-        let file_name = "hello.txt";
+    fn part_path(&self, file_id: &str, layout: &ChunkLayout, chunk_index: usize) -> PathBuf {
+        let mut path = self
+            .chunk_path(file_id, layout, chunk_index)
+            .into_os_string();
+        path.push(".part");
+        path.into()
+    }
 
-        // Upload chunk 0
-        let req0 = Request::builder()
-            .method("POST")
-            .header("X-File-Id", file_id)
-            .header("X-File-Name", file_name)
-            .header("X-Chunk-Index", "0")
-            .header("X-Total-Chunks", "2")
-            .body(Full::new(Bytes::from("Hello, ".to_string())).into())
-            .unwrap();
+    /// Length of the `.part` file left by an interrupted upload of this chunk, if any.
+    async fn partial_len(
+        &self,
+        file_id: &str,
+        layout: &ChunkLayout,
+        chunk_index: usize,
+    ) -> Result<Option<u64>, SliceBreadServerError> {
+        match tokio::fs::metadata(self.part_path(file_id, layout, chunk_index)).await {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
 
-        let res = service.call(req0).await.unwrap();
-        assert_eq!(res.status(), 201);
+    /// Checks that `X-Chunk-Offset` matches what was persisted so far for this chunk.
+    async fn check_offset(
+        &self,
+        file_id: &str,
+        layout: &ChunkLayout,
+        chunk_index: usize,
+        offset: u64,
+    ) -> Result<(), SliceBreadServerError> {
+        let persisted = self
+            .partial_len(file_id, layout, chunk_index)
+            .await?
+            .unwrap_or(0);
+        if offset != persisted {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "{} {} does not match the {} bytes persisted for chunk {}",
+                constants::HEADER_CHUNK_OFFSET,
+                offset,
+                persisted,
+                chunk_index
+            )));
+        }
+        Ok(())
+    }
 
-        let chunk_path = upload_dir.join(file_id).join("chunk_0.bin");
-        let written = tokio::fs::read_to_string(chunk_path).await.unwrap();
-        assert_eq!(written, "Hello, ");
+    /// Persists the bytes received before a chunk body was cut off, so the client
+    /// can resume from `X-Chunk-Offset` instead of resending the whole chunk.
+    async fn save_partial(
+        &self,
+        headers: &hyper::HeaderMap,
+        received: Bytes,
+    ) -> Result<(), SliceBreadServerError> {
+        let file_id = get_file_id(headers)?;
+        let chunk_index = get_bounded_header(
+            headers,
+            constants::HEADER_CHUNK_INDEX,
+            self.config.max_total_chunks.saturating_sub(1),
+        )?;
+        let offset = get_optional_header(headers, constants::HEADER_CHUNK_OFFSET)?.unwrap_or(0);
+        if received.is_empty() {
+            return Ok(());
+        }
+        tokio::fs::create_dir_all(Path::new(&self.base_files_dir).join(&file_id)).await?;
+        let layout = self.pin_chunk_layout(&file_id).await?;
+        if tokio::fs::try_exists(self.chunk_path(&file_id, &layout, chunk_index)).await? {
+            return Ok(());
+        }
+        self.check_offset(&file_id, &layout, chunk_index, offset)
+            .await?;
 
-        // Upload chunk 1 (final chunk)
-        let req1 = Request::builder()
-            .method("POST")
-            .header("X-File-Id", file_id)
-            .header("X-File-Name", file_name)
-            .header("X-Chunk-Index", "1")
-            .header("X-Total-Chunks", "2")
-            .body(Full::new(Bytes::from("World!".to_string())).into())
-            .unwrap();
+        let part_file = self.part_path(&file_id, &layout, chunk_index);
+        if let Some(parent) = part_file.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut part = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&part_file)
+            .await?;
+        part.write_all(&received).await?;
+        part.flush().await?;
+        tracing::info!(%file_id, chunk_index, persisted = offset + received.len() as u64, "Saved partial chunk");
+        Ok(())
+    }
 
-        let res = service.call(req1).await.unwrap();
-        assert_eq!(res.status(), 201);
+    fn manifest_path(&self, file_id: &str) -> PathBuf {
+        Path::new(&self.base_files_dir)
+            .join(constants::MANIFEST_DIR)
+            .join(format!("{}.json", file_id))
+    }
 
-        // Check final file content
-        let final_path = upload_dir.join(file_id).join(file_name);
-        let content = fs::read_to_string(final_path).await.unwrap();
-        assert_eq!(content, "Hello, World!");
+    async fn write_manifest(
+        &self,
+        file_id: &str,
+        manifest: &Manifest,
+    ) -> Result<(), SliceBreadServerError> {
+        let path = self.manifest_path(file_id);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let json = serde_json::to_vec(manifest)
+            .map_err(|e| SliceBreadServerError::InternalServerError(e.to_string()))?;
+        tokio::fs::write(path, json).await?;
+        Ok(())
     }
 
-    #[tokio::test]
-    async fn test_single_chunk_file_upload() {
-        let temp_dir = TempDir::new("upload_test").expect("create temp dir failed");
-        let upload_dir = temp_dir.path().join("uploads");
-        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+    async fn get_manifest(
+        &self,
+        file_id: &str,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        match tokio::fs::read_to_string(self.manifest_path(file_id)).await {
+            Ok(json) => Ok(Response::builder()
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(json.into())?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Err(
+                SliceBreadServerError::NotFound(format!("Manifest for {}", file_id)),
+            ),
+            Err(err) => Err(err.into()),
+        }
+    }
 
-        let service =
-            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+    /// Handles `HEAD /files/{file_id}`: a completed file's metadata as headers,
+    /// so it can be checked for without downloading it. The `ETag` is the
+    /// file's SHA-256 and changes with every new version.
+    async fn file_info(
+        &self,
+        file_id: &str,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let base_dir = Path::new(&self.base_files_dir);
+        let Some(entry) = catalog::lookup(base_dir, file_id).await? else {
+            return Err(SliceBreadServerError::NotFound(format!("File {}", file_id)));
+        };
+        let metadata = sidecar::read(&base_dir.join(&entry.path)).await?;
+
+        let mut res = Response::builder()
+            .status(200)
+            .header(hyper::header::CONTENT_LENGTH, metadata.size)
+            .header(hyper::header::CONTENT_TYPE, &metadata.content_type)
+            .header(hyper::header::ETAG, format!("\"{}\"", metadata.sha256))
+            .header(
+                hyper::header::LAST_MODIFIED,
+                metadata
+                    .completed_at
+                    .format("%a, %d %b %Y %H:%M:%S GMT")
+                    .to_string(),
+            )
+            .header(constants::HEADER_FILE_SHA256, &metadata.sha256)
+            .header(constants::HEADER_MERKLE_ROOT, &metadata.merkle_root)
+            .header(
+                constants::HEADER_UPLOAD_STARTED_AT,
+                metadata.started_at.to_rfc3339(),
+            )
+            .header(
+                constants::HEADER_UPLOAD_COMPLETED_AT,
+                metadata.completed_at.to_rfc3339(),
+            )
+            .body(ResponseBody::default())?;
+        user_metadata::to_headers(&metadata.user_metadata, res.headers_mut());
+        Ok(res)
+    }
 
-        let file_id = "test1238";
-        let file_name = "hello.txt";
+    /// Removes a completed file with its sidecar and manifest. In immutable mode
+    /// only the admin route may do this.
+    async fn delete_file(
+        &self,
+        file_id: &str,
+        admin: bool,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let base_dir = Path::new(&self.base_files_dir);
+        let Some(entry) = catalog::lookup(base_dir, file_id).await? else {
+            return Err(SliceBreadServerError::NotFound(format!("File {}", file_id)));
+        };
+        if self.config.immutable && !admin {
+            return Err(SliceBreadServerError::Forbidden(format!(
+                "File {} is immutable",
+                file_id
+            )));
+        }
 
-        // Upload chunk 0
-        let req0 = Request::builder()
-            .method("POST")
-            .header("X-File-Id", file_id)
-            .header("X-File-Name", file_name)
-            .header("X-Chunk-Index", "0")
-            .header("X-Total-Chunks", "1")
-            .body(Full::new(Bytes::from("Hello, World!".to_string())).into())
-            .unwrap();
+        self.remove_completed(&entry).await?;
 
-        let res = service.call(req0).await.unwrap();
-        assert_eq!(res.status(), 201);
+        tracing::info!(%file_id, admin, "Deleted file");
+        Ok(Response::builder()
+            .status(204)
+            .body(ResponseBody::default())?)
+    }
 
-        // Check final file content
-        let final_path = upload_dir.join(file_id).join(file_name);
-        let content = fs::read_to_string(final_path).await.unwrap();
-        assert_eq!(content, "Hello, World!");
+    /// Removes a completed file with its sidecar, manifest, extraction and
+    /// catalog entry, releasing its bytes from the tenant's usage. The file's
+    /// chunk directory goes too once nothing else is left in it.
+    async fn remove_completed(&self, entry: &CatalogEntry) -> Result<(), SliceBreadServerError> {
+        let base_dir = Path::new(&self.base_files_dir);
+        let output_path = base_dir.join(&entry.path);
+        let metadata = match sidecar::read(&output_path).await {
+            Ok(metadata) => Some(metadata),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        for path in [
+            output_path.clone(),
+            sidecar::path_for(&output_path),
+            self.manifest_path(&entry.file_id),
+        ] {
+            match tokio::fs::remove_file(&path).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        if let Some(dir) = metadata.as_ref().and_then(|m| m.extracted.as_deref()) {
+            match tokio::fs::remove_dir_all(output_path.with_file_name(dir)).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        match tokio::fs::remove_dir(base_dir.join(&entry.file_id)).await {
+            Err(err)
+                if !matches!(
+                    err.kind(),
+                    std::io::ErrorKind::NotFound | std::io::ErrorKind::DirectoryNotEmpty
+                ) =>
+            {
+                return Err(err.into());
+            }
+            _ => {}
+        }
+        catalog::remove(base_dir, &entry.file_id).await?;
+        if let Some(metadata) = metadata {
+            self.sessions
+                .file_removed(&metadata.uploader, metadata.size);
+        }
+        Ok(())
     }
 
-    #[tokio::test]
-    async fn test_missing_x_file_id_header() {
-        let service = SliceBreadServer::<Full<Bytes>>::new(String::from("uploads"));
+    /// Counts the completed files on disk towards their tenants' usage, which
+    /// only lives in memory, so quotas hold across restarts. Call once at
+    /// startup. Returns how many files were counted.
+    pub async fn restore_usage(&self) -> Result<usize, SliceBreadServerError> {
+        let base_dir = Path::new(&self.base_files_dir);
+        let mut counted = 0;
+        for entry in catalog::list(base_dir).await? {
+            match sidecar::read(&base_dir.join(&entry.path)).await {
+                Ok(metadata) => {
+                    self.sessions.file_stored(&metadata.uploader, metadata.size);
+                    counted += 1;
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(counted)
+    }
+
+    /// Deletes, or archives and then deletes, every completed file whose
+    /// retention period is over, returning how many there were. Archived files
+    /// keep their path under the archive directory, next to their sidecar.
+    /// Nothing is retired in read-only mode.
+    pub async fn enforce_retention(&self) -> Result<usize, SliceBreadServerError> {
+        if self.config.retention.is_empty() || self.load.check_writable().is_err() {
+            return Ok(0);
+        }
+        let base_dir = Path::new(&self.base_files_dir);
+        let now = Utc::now();
+        let mut retired = 0;
+        for entry in catalog::list(base_dir).await? {
+            let output_path = base_dir.join(&entry.path);
+            let metadata = match sidecar::read(&output_path).await {
+                Ok(metadata) => metadata,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+            let Some(rule) = retention::rule_for(
+                &self.config.retention,
+                &metadata.uploader,
+                metadata.category.as_deref(),
+            )
+            .filter(|rule| rule.is_over(metadata.completed_at, now)) else {
+                continue;
+            };
+            if rule.action == RetentionAction::Archive {
+                let Some(archive) = &self.archive else {
+                    tracing::warn!(file_id = %entry.file_id, "No archive to move expired file to");
+                    continue;
+                };
+                archive.put(&output_path, &entry.path).await?;
+                archive
+                    .put(
+                        &sidecar::path_for(&output_path),
+                        &sidecar::path_for(&entry.path),
+                    )
+                    .await?;
+            }
+            self.remove_completed(&entry).await?;
+            tracing::info!(file_id = %entry.file_id, action = ?rule.action, "Retention period over");
+            retired += 1;
+        }
+        Ok(retired)
+    }
+
+    /// Handles `POST /admin/purge`, which erases every trace of a file: the
+    /// upload in progress, the assembled file, its sidecar and manifest, and
+    /// its replicas. Without `confirmation` nothing is deleted; the response
+    /// carries the token to send back within a few minutes to go ahead.
+    async fn purge(
+        &self,
+        file_id: &str,
+        confirmation: Option<&str>,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        #[derive(serde::Serialize)]
+        struct PurgeRequest<'a> {
+            file_id: &'a str,
+            confirmation: String,
+            expires_at: DateTime<Utc>,
+        }
+
+        let base_dir = Path::new(&self.base_files_dir);
+        let chunk_dir = base_dir.join(file_id);
+        let entry = catalog::lookup(base_dir, file_id).await?;
+        let session = self.sessions.session(file_id);
+        if entry.is_none() && session.is_none() && !tokio::fs::try_exists(&chunk_dir).await? {
+            return Err(SliceBreadServerError::NotFound(format!("File {}", file_id)));
+        }
+        let Some(confirmation) = confirmation else {
+            let expires_at = Utc::now() + constants::PURGE_CONFIRMATION_TTL;
+            return json_response(&PurgeRequest {
+                file_id,
+                confirmation: purge::confirmation(&self.purge_key, file_id, expires_at),
+                expires_at,
+            });
+        };
+        purge::verify(&self.purge_key, confirmation, file_id, Utc::now())
+            .map_err(SliceBreadServerError::Forbidden)?;
+        if self
+            .sessions
+            .progress(file_id)
+            .is_some_and(|progress| progress.assembly_percent.is_some())
+        {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "File {} is being assembled; cancel the assembly first",
+                file_id
+            )));
+        }
+
+        match session {
+            Some(session) => self.expire_upload(file_id, &session).await?,
+            None => self.discard_upload(file_id, 0).await?,
+        }
+        if let Some(entry) = entry {
+            self.replicator.delete(&entry.path).await?;
+            self.remove_completed(&entry).await?;
+        }
+        // Whatever is left: chunks without a session, e.g. after a crash, and
+        // files the catalog lost track of.
+        let manifest_path = self.manifest_path(file_id);
+        for removed in [
+            tokio::fs::remove_dir_all(&chunk_dir).await,
+            tokio::fs::remove_file(&manifest_path).await,
+        ] {
+            match removed {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        for path in [chunk_dir, manifest_path] {
+            if tokio::fs::try_exists(&path).await? {
+                return Err(SliceBreadServerError::InternalServerError(format!(
+                    "{} is still there after purging {}",
+                    path.display(),
+                    file_id
+                )));
+            }
+        }
+        self.sessions.forget_idempotency_keys(file_id);
+
+        tracing::info!(%file_id, "Purged file");
+        Ok(Response::builder()
+            .status(204)
+            .body(ResponseBody::default())?)
+    }
+
+    /// Handles `GET /admin/export`: everything stored for one principal, to
+    /// answer a data subject access request. Completed files are listed with
+    /// their sidecar metadata and uploads in flight with their progress. With
+    /// `files`, a tar of that listing and the files themselves is sent instead.
+    async fn export_owner(
+        &self,
+        owner: &str,
+        files: bool,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        #[derive(serde::Serialize)]
+        struct OwnerExport<'a> {
+            owner: &'a str,
+            exported_at: DateTime<Utc>,
+            files: Vec<FileMetadata>,
+            uploads: Vec<ExportedUpload>,
+        }
+
+        #[derive(serde::Serialize)]
+        struct ExportedUpload {
+            file_id: String,
+            file_name: String,
+            tenant: String,
+            content_type: String,
+            #[serde(flatten)]
+            progress: Progress,
+        }
+
+        let base_dir = Path::new(&self.base_files_dir);
+        let mut completed = catalog::list(base_dir).await?;
+        completed.sort_by(|a, b| a.file_id.cmp(&b.file_id));
+        let mut export = OwnerExport {
+            owner,
+            exported_at: Utc::now(),
+            files: Vec::new(),
+            uploads: Vec::new(),
+        };
+        let mut entries = Vec::new();
+        for entry in completed {
+            let output_path = base_dir.join(&entry.path);
+            let metadata = match sidecar::read(&output_path).await {
+                Ok(metadata) => metadata,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+            if metadata.owner.as_deref() != Some(owner) {
+                continue;
+            }
+            entries.push(ArchiveEntry::File {
+                name: Path::new("files")
+                    .join(&metadata.file_id)
+                    .join(&metadata.file_name),
+                path: output_path,
+            });
+            export.files.push(metadata);
+        }
+
+        let mut in_progress = self.sessions.in_progress();
+        in_progress.sort_by(|a, b| a.0.cmp(&b.0));
+        for (file_id, progress) in in_progress {
+            let Some(session) = self
+                .sessions
+                .session(&file_id)
+                .filter(|session| session.owner.as_deref() == Some(owner))
+            else {
+                continue;
+            };
+            export.uploads.push(ExportedUpload {
+                file_id,
+                file_name: session.file_name,
+                tenant: session.tenant,
+                content_type: session.content_type,
+                progress,
+            });
+        }
+
+        tracing::info!(
+            %owner,
+            files = export.files.len(),
+            uploads = export.uploads.len(),
+            "Exported owner data"
+        );
+        if !files {
+            return json_response(&export);
+        }
+        let index = serde_json::to_vec_pretty(&export)
+            .map_err(|e| SliceBreadServerError::InternalServerError(e.to_string()))?;
+        entries.insert(
+            0,
+            ArchiveEntry::Data {
+                name: PathBuf::from(constants::EXPORT_INDEX_FILE),
+                contents: index,
+            },
+        );
+        let (sender, body) = ResponseBody::channel(constants::ARCHIVE_FRAMES_BUFFERED);
+        archive::spawn(
+            format!("export of {}", owner),
+            entries,
+            ArchiveFormat::Tar,
+            sender,
+        );
+        Ok(Response::builder()
+            .header(
+                hyper::header::CONTENT_TYPE,
+                ArchiveFormat::Tar.content_type(),
+            )
+            .header(
+                hyper::header::CONTENT_DISPOSITION,
+                "attachment; filename=\"export.tar\"",
+            )
+            .body(body)?)
+    }
+
+    /// Handles `POST /files/{file_id}/shares`: the owner of a completed file
+    /// grants a principal, or whoever holds the returned link, access to it.
+    async fn create_share(
+        &self,
+        file_id: &str,
+        principal: Option<&Principal>,
+        body: &[u8],
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let request: ShareRequest = serde_json::from_slice(body).map_err(|e| {
+            SliceBreadServerError::BadRequest(format!("Invalid share request: {}", e))
+        })?;
+        let base_dir = Path::new(&self.base_files_dir);
+        let Some(entry) = catalog::lookup(base_dir, file_id).await? else {
+            if self.sessions.session(file_id).is_some() {
+                return Err(SliceBreadServerError::Conflict(format!(
+                    "File {} is still being uploaded",
+                    file_id
+                )));
+            }
+            return Err(SliceBreadServerError::NotFound(format!("File {}", file_id)));
+        };
+        let output_path = base_dir.join(&entry.path);
+        let mut metadata = sidecar::read(&output_path).await?;
+        if !auth::is_owner(principal, metadata.owner.as_deref()) {
+            return Err(SliceBreadServerError::Forbidden(format!(
+                "Only the owner may share {}",
+                file_id
+            )));
+        }
+        let secret = match (&request.principal, &self.config.share_link_secret) {
+            (Some(_), _) => None,
+            (None, Some(secret)) => Some(secret),
+            (None, None) => {
+                return Err(SliceBreadServerError::BadRequest(
+                    "Share links are not enabled on this server".to_string(),
+                ));
+            }
+        };
+
+        let expires_at = request
+            .expires_in_secs
+            .map(|secs| Utc::now() + chrono::Duration::seconds(secs.min(i64::MAX as u64) as i64));
+        let share = Share::new(request.principal, request.access, expires_at);
+        let token = secret.map(|secret| share::sign_link(file_id, &share.id, secret.as_bytes()));
+        metadata.shares.push(share.clone());
+        sidecar::write(&output_path, &metadata).await?;
+
+        tracing::info!(%file_id, share_id = %share.id, principal = ?share.principal, "Shared file");
+        let body = serde_json::to_string(&CreatedShare { share, token })
+            .map_err(|e| SliceBreadServerError::InternalServerError(e.to_string()))?;
+        Ok(Response::builder()
+            .status(201)
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(body.into())?)
+    }
+
+    /// Handles `POST /uploads/{file_id}/complete`: assembles an upload once the
+    /// client says it has sent everything, optionally checking `X-Total-Chunks`
+    /// and `Repr-Digest` against it. Completing a completed upload is a no-op.
+    async fn complete_upload(
+        &self,
+        file_id: &str,
+        headers: &hyper::HeaderMap,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let tenant = get_tenant(headers)?;
+        self.sync_shared(file_id).await?;
+        let Some(session) = self
+            .sessions
+            .session(file_id)
+            .filter(|session| session.tenant == tenant)
+        else {
+            return self.upload_status(file_id).await;
+        };
+        if let Some(member) = &session.bundle {
+            return Err(SliceBreadServerError::BadRequest(format!(
+                "Upload {} is part of bundle {}, which is completed by committing it",
+                file_id, member.bundle_id
+            )));
+        }
+        if let Some(total_chunks) =
+            get_optional_header::<usize>(headers, constants::HEADER_TOTAL_CHUNKS)?
+            && !session.byte_ranges
+            && total_chunks != session.total_chunks
+        {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "Total chunks mismatch for {}: expected {}, got {}",
+                file_id, session.total_chunks, total_chunks
+            )));
+        }
+        let session = self.sessions.register(
+            file_id,
+            Session {
+                repr_digests: digest::repr_digests(headers)?,
+                ..session
+            },
+        )?;
+        self.publish_session(file_id, &session).await?;
+
+        match self.claim_assembly(file_id).await? {
+            AssemblyClaim::Claimed => {
+                self.assemble_claimed(file_id, &session).await?;
+                return self.created(file_id).await;
+            }
+            AssemblyClaim::Elsewhere => return self.assembling_elsewhere(file_id).await,
+            AssemblyClaim::Unclaimed => {}
+        }
+        if let Some(progress) = self.sessions.progress(file_id)
+            && progress.assembly_percent.is_none()
+        {
+            return Err(match self.sessions.missing_chunk(file_id) {
+                Some(index) if !session.byte_ranges => SliceBreadServerError::MissingChunk(index),
+                _ => SliceBreadServerError::BadRequest(format!(
+                    "Upload {} is missing bytes",
+                    file_id
+                )),
+            });
+        }
+        self.upload_status(file_id).await
+    }
+
+    /// Handles `POST /bundles/{bundle_id}/commit`: assembles every file of a
+    /// bundle into a staging directory, then moves the whole tree into place
+    /// with one rename, so readers see all of it or none. Files staged by an
+    /// earlier attempt are listed in the staged manifest and kept.
+    async fn commit_bundle(
+        &self,
+        bundle_id: &str,
+        headers: &hyper::HeaderMap,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let bundle_id = bundle::parse_id(bundle_id)?;
+        let tenant = get_tenant(headers)?;
+        let total_files: usize = get_header(headers, constants::HEADER_TOTAL_FILES)?;
+        let base_dir = Path::new(&self.base_files_dir);
+        let committed_dir = base_dir.join(bundle::committed_dir(bundle_id));
+        if let Some(manifest) = bundle::read(&committed_dir).await?
+            && manifest.tenant == tenant
+        {
+            return json_response(&manifest);
+        }
+        if tokio::fs::try_exists(&committed_dir).await? {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "{} already exists",
+                bundle_id
+            )));
+        }
+
+        let staging_dir = bundle::staging_dir(base_dir, bundle_id);
+        let mut manifest = bundle::read(&staging_dir)
+            .await?
+            .unwrap_or_else(|| BundleManifest::new(bundle_id, &tenant));
+        let members = self.sessions.bundle_members(bundle_id);
+        if manifest.tenant != tenant || members.iter().any(|(_, session)| session.tenant != tenant)
+        {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "Bundle {} belongs to a different tenant",
+                bundle_id
+            )));
+        }
+        let files = manifest.files.len() + members.len();
+        if files != total_files {
+            return Err(SliceBreadServerError::BadRequest(format!(
+                "Bundle {} has {} of {} files",
+                bundle_id, files, total_files
+            )));
+        }
+        bundle::check_paths(
+            manifest.files.iter().map(|file| file.path.as_path()).chain(
+                members
+                    .iter()
+                    .filter_map(|(_, session)| session.bundle.as_ref())
+                    .map(|member| member.path.as_path()),
+            ),
+        )?;
+
+        // Every file must be complete before any is assembled, so a missing
+        // chunk doesn't leave the bundle half staged.
+        let mut claimed = Vec::with_capacity(members.len());
+        for (file_id, session) in &members {
+            if self.claim_assembly(file_id).await? != AssemblyClaim::Claimed {
+                for (file_id, _) in claimed {
+                    self.release_assembly(file_id).await?;
+                }
+                return Err(match self.sessions.missing_chunk(file_id) {
+                    Some(index) => SliceBreadServerError::BadRequest(format!(
+                        "Upload {} is missing chunk {}",
+                        file_id, index
+                    )),
+                    None => SliceBreadServerError::Conflict(format!(
+                        "Upload {} is being assembled",
+                        file_id
+                    )),
+                });
+            }
+            claimed.push((file_id, session));
+        }
+        let mut claimed = claimed.into_iter();
+        while let Some((file_id, session)) = claimed.next() {
+            let staged = async {
+                self.assemble_claimed(file_id, session).await?;
+                let member = session
+                    .bundle
+                    .as_ref()
+                    .expect("bundle members have a bundle");
+                let metadata = sidecar::read(&staging_dir.join(&member.path)).await?;
+                manifest.files.push(BundleFile {
+                    path: member.path.clone(),
+                    file_id: file_id.to_string(),
+                    size: metadata.size,
+                    sha256: metadata.sha256,
+                });
+                bundle::write(&staging_dir, &manifest).await?;
+                Ok::<_, SliceBreadServerError>(())
+            }
+            .await;
+            if let Err(err) = staged {
+                for (file_id, _) in claimed {
+                    self.release_assembly(file_id).await?;
+                }
+                return Err(err);
+            }
+        }
+
+        manifest.committed_at = Some(Utc::now());
+        bundle::write(&staging_dir, &manifest).await?;
+        if self.config.durability >= Durability::File {
+            io::sync_dir(&staging_dir).await?;
+        }
+        tokio::fs::rename(&staging_dir, &committed_dir).await?;
+        if self.config.durability >= Durability::File {
+            io::sync_dir(base_dir).await?;
+        }
+
+        // Bundled files are only catalogued and replicated once they are in place.
+        for file in &manifest.files {
+            let relative_path = bundle::committed_dir(bundle_id).join(&file.path);
+            let output_path = base_dir.join(&relative_path);
+            catalog::record(
+                base_dir,
+                &CatalogEntry {
+                    file_id: file.file_id.clone(),
+                    path: relative_path.clone(),
+                },
+            )
+            .await?;
+            let metadata = sidecar::read(&output_path).await?;
+            self.replicator.spawn(output_path, relative_path, metadata);
+        }
+        tracing::info!(%bundle_id, files = manifest.files.len(), "Bundle committed");
+
+        let mut res = json_response(&manifest)?;
+        *res.status_mut() = hyper::StatusCode::CREATED;
+        Ok(res)
+    }
+
+    /// Handles `GET /uploads/{id}/archive.tar` (or `.tar.gz`): the completed
+    /// files under `{id}/`, such as a committed bundle or an extracted archive,
+    /// as one archive built while it is sent.
+    async fn download_archive(
+        &self,
+        id: &str,
+        name: &str,
+        headers: &hyper::HeaderMap,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let not_found = || SliceBreadServerError::NotFound(format!("Archive of {}", id));
+        let format = ArchiveFormat::from_name(name).ok_or_else(not_found)?;
+        if id.is_empty() || id.starts_with('.') {
+            return Err(not_found());
+        }
+        if self.sessions.session(id).is_some() {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "Upload {} is still in progress",
+                id
+            )));
+        }
+        let dir = Path::new(&self.base_files_dir).join(id);
+        if !tokio::fs::metadata(&dir)
+            .await
+            .is_ok_and(|metadata| metadata.is_dir())
+        {
+            return Err(not_found());
+        }
+        let tenant = get_tenant(headers)?;
+        if let Some(manifest) = bundle::read(&dir).await?
+            && manifest.tenant != tenant
+        {
+            return Err(not_found());
+        }
+
+        let listed = dir.clone();
+        let files = tokio::task::spawn_blocking(move || archive::list(&listed))
+            .await
+            .map_err(|e| SliceBreadServerError::InternalServerError(e.to_string()))??;
+        if files.is_empty() {
+            return Err(not_found());
+        }
+        tracing::info!(%id, files = files.len(), "Streaming archive");
+        let (sender, body) = ResponseBody::channel(constants::ARCHIVE_FRAMES_BUFFERED);
+        archive::spawn(
+            id.to_string(),
+            ArchiveEntry::files_under(&dir, files),
+            format,
+            sender,
+        );
+        Ok(Response::builder()
+            .header(hyper::header::CONTENT_TYPE, format.content_type())
+            .header(
+                hyper::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.{}\"", id, format.extension()),
+            )
+            .body(body)?)
+    }
+
+    /// Handles `DELETE /admin/uploads/{file_id}/assembly`. The assembler stops
+    /// before its next chunk and removes the partial output; chunks are kept so
+    /// the upload can be assembled again by resending any chunk.
+    fn cancel_assembly(
+        &self,
+        file_id: &str,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        if !self.sessions.cancel_assembly(file_id) {
+            return Err(SliceBreadServerError::NotFound(format!(
+                "No assembly in progress for {}",
+                file_id
+            )));
+        }
+        tracing::info!(%file_id, "Assembly cancellation requested");
+        Ok(Response::builder()
+            .status(202)
+            .body(ResponseBody::default())?)
+    }
+
+    /// Block checksums of a stored file, for clients preparing a delta upload.
+    async fn get_signature(
+        &self,
+        file_id: &str,
+        block_size: Option<&str>,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        #[derive(serde::Serialize)]
+        struct FileSignature<'a> {
+            file_id: &'a str,
+            #[serde(flatten)]
+            signature: Signature,
+        }
+
+        let block_size = match block_size {
+            Some(block_size) => parse_block_size(block_size)?,
+            None => constants::DEFAULT_DELTA_BLOCK_SIZE,
+        };
+        let base_dir = Path::new(&self.base_files_dir);
+        let Some(entry) = catalog::lookup(base_dir, file_id).await? else {
+            return Err(SliceBreadServerError::NotFound(format!("File {}", file_id)));
+        };
+        let signature = delta::signature(&base_dir.join(&entry.path), block_size).await?;
+        json_response(&FileSignature { file_id, signature })
+    }
+
+    /// Patches a stored file with a delta against its signature, so re-uploads
+    /// of a large file only send the blocks that changed. The new version
+    /// replaces the old one once it is complete and matches `Repr-Digest`.
+    #[tracing::instrument(
+        name = "upload_delta",
+        skip_all,
+        fields(file_id = %file_id, bytes = body.len())
+    )]
+    async fn upload_delta(
+        &self,
+        file_id: &str,
+        headers: &hyper::HeaderMap,
+        body: Bytes,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        #[derive(serde::Serialize)]
+        struct Patched<'a> {
+            file_id: &'a str,
+            size: u64,
+            sha256: &'a str,
+            #[serde(flatten)]
+            applied: Applied,
+        }
+
+        let base_dir = Path::new(&self.base_files_dir);
+        let Some(entry) = catalog::lookup(base_dir, file_id).await? else {
+            return Err(SliceBreadServerError::NotFound(format!("File {}", file_id)));
+        };
+        if self.config.immutable {
+            return Err(SliceBreadServerError::Forbidden(format!(
+                "File {} is immutable",
+                file_id
+            )));
+        }
+        let block_size = parse_block_size(&get_header::<String>(
+            headers,
+            constants::HEADER_BLOCK_SIZE,
+        )?)?;
+        let tenant = get_tenant(headers)?;
+        let repr_digests = digest::repr_digests(headers)?;
+        let ops = delta::decode(body).map_err(SliceBreadServerError::BadRequest)?;
+
+        let output_path = base_dir.join(&entry.path);
+        let previous = sidecar::read(&output_path).await?;
+        // Another tenant's file is reported as missing, as for downloads.
+        if previous.uploader != tenant {
+            return Err(SliceBreadServerError::NotFound(format!("File {}", file_id)));
+        }
+        let started_at = Utc::now();
+        // Concurrent deltas for the same file each build their own version; the last one to finish wins.
+        let mut tmp_name = output_path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(format!(".{:016x}.delta.tmp", rand::random::<u64>()));
+        let tmp_path = output_path.with_file_name(tmp_name);
+
+        let mut hasher = digest::Hasher::new(&repr_digests);
+        let applied = match delta::apply(&output_path, block_size, &ops, &tmp_path, |piece| {
+            hasher.update(piece)
+        })
+        .await
+        {
+            Ok(applied) => applied,
+            Err(err) => {
+                let _ = tokio::fs::remove_file(&tmp_path).await;
+                return Err(match err.kind() {
+                    std::io::ErrorKind::InvalidData => {
+                        SliceBreadServerError::BadRequest(err.to_string())
+                    }
+                    _ => err.into(),
+                });
+            }
+        };
+        let size = applied.bytes_reused + applied.bytes_received;
+        let policy =
+            match self.check_policy(headers, file_id, &tenant, &previous.content_type, size) {
+                Ok(policy) => policy,
+                Err(err) => {
+                    tokio::fs::remove_file(&tmp_path).await?;
+                    return Err(err);
+                }
+            };
+        let computed = hasher.finalize();
+        let verified = computed
+            .verify(&repr_digests, "Patched file")
+            .and_then(|()| {
+                check_finished(
+                    "Patched file",
+                    size,
+                    &computed.sha256,
+                    policy.as_ref().and_then(|policy| policy.size),
+                    policy.as_ref().and_then(UploadPolicy::sha256_digest),
+                )
+            });
+        if let Err(err) = verified {
+            tracing::warn!(%err, "Patched file failed digest verification");
+            tokio::fs::remove_file(&tmp_path).await?;
+            return Err(err);
+        }
+        if let Err(err) = self.check_quota(&tenant, size.saturating_sub(previous.size)) {
+            tokio::fs::remove_file(&tmp_path).await?;
+            return Err(err);
+        }
+        tokio::fs::rename(&tmp_path, &output_path).await?;
+        self.sessions.file_removed(&tenant, previous.size);
+        self.sessions.file_stored(&tenant, size);
+
+        let tree = MerkleTree::from_leaves(vec![computed.sha256]);
+        self.write_manifest(file_id, &tree.manifest(file_id, vec![size]))
+            .await?;
+        let sha256 = checksum::to_hex(&computed.sha256);
+        let metadata = FileMetadata {
+            size,
+            sha256: sha256.clone(),
+            merkle_root: checksum::to_hex(&tree.root()),
+            started_at,
+            completed_at: Utc::now(),
+            replication: self.replicator.pending(),
+            ..previous
+        };
+        sidecar::write(&output_path, &metadata).await?;
+        self.replicator.spawn(output_path, entry.path, metadata);
+
+        tracing::info!(
+            bytes_reused = applied.bytes_reused,
+            bytes_received = applied.bytes_received,
+            "File patched with delta"
+        );
+        json_response(&Patched {
+            file_id,
+            size,
+            sha256: &sha256,
+            applied,
+        })
+    }
+
+    /// Reports how far an upload has got; completed uploads are reported from their sidecar.
+    async fn upload_status(
+        &self,
+        file_id: &str,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        self.sync_shared(file_id).await?;
+        if let Some(progress) = self.sessions.progress(file_id) {
+            return json_response(&self.in_progress_status(file_id.to_string(), progress));
+        }
+
+        let Some(entry) = catalog::lookup(Path::new(&self.base_files_dir), file_id).await? else {
+            return Err(SliceBreadServerError::NotFound(format!(
+                "Upload {}",
+                file_id
+            )));
+        };
+        json_response(&self.completed_status(entry).await?)
+    }
+
+    fn in_progress_status(&self, file_id: String, progress: Progress) -> UploadStatus {
+        let mut status = UploadStatus::in_progress(file_id, progress);
+        if let Some(session) = self.sessions.session(&status.file_id) {
+            status.tags = session.tags;
+            status.user_metadata = session.user_metadata;
+        }
+        status
+    }
+
+    /// Every upload in flight followed by every completed one, for dashboards
+    /// such as the built-in UI. Uploads the client may not read are left out,
+    /// as are those without every tag in `tag_filters`.
+    async fn list_uploads(
+        &self,
+        principal: Option<&Principal>,
+        tag_filters: &[String],
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        if let Some(cluster) = &self.config.cluster {
+            for file_id in cluster.store.uploads().await? {
+                self.sync_shared(&file_id).await?;
+            }
+        }
+        let mut uploads = Vec::new();
+        for (file_id, progress) in self.sessions.in_progress() {
+            if self.may_access(&file_id, principal, false).await? {
+                uploads.push(self.in_progress_status(file_id, progress));
+            }
+        }
+        uploads.sort_by(|a, b| a.file_id.cmp(&b.file_id));
+
+        let mut completed = catalog::list(Path::new(&self.base_files_dir)).await?;
+        completed.sort_by(|a, b| a.file_id.cmp(&b.file_id));
+        for entry in completed {
+            if self.may_access(&entry.file_id, principal, false).await? {
+                uploads.push(self.completed_status(entry).await?);
+            }
+        }
+        uploads.retain(|upload| tags::matches(&upload.tags, tag_filters));
+        json_response(&uploads)
+    }
+
+    /// Handles `POST /uploads/status`: the status of many uploads in one
+    /// request, for clients tracking too many files to poll each one.
+    async fn bulk_status(
+        &self,
+        principal: Option<&Principal>,
+        body: &[u8],
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let query: StatusQuery = serde_json::from_slice(body).map_err(|e| {
+            SliceBreadServerError::BadRequest(format!("Invalid status query: {}", e))
+        })?;
+        if query.file_ids.len() > constants::MAX_STATUS_QUERY_IDS {
+            return Err(SliceBreadServerError::PayloadTooLarge(format!(
+                "Status query has more than {} file ids",
+                constants::MAX_STATUS_QUERY_IDS
+            )));
+        }
+
+        let mut statuses = BulkStatus::default();
+        for file_id in query.file_ids {
+            // Uploads the client may not read are reported like missing ones.
+            if !output::is_safe_segment(&file_id)
+                || !self.may_access(&file_id, principal, false).await?
+            {
+                statuses.not_found.push(file_id);
+                continue;
+            }
+            self.sync_shared(&file_id).await?;
+            if let Some(progress) = self.sessions.progress(&file_id) {
+                statuses
+                    .uploads
+                    .push(self.in_progress_status(file_id, progress));
+                continue;
+            }
+            match catalog::lookup(Path::new(&self.base_files_dir), &file_id).await? {
+                Some(entry) => statuses.uploads.push(self.completed_status(entry).await?),
+                None => statuses.not_found.push(file_id),
+            }
+        }
+        json_response(&statuses)
+    }
+
+    async fn completed_status(
+        &self,
+        entry: CatalogEntry,
+    ) -> Result<UploadStatus, SliceBreadServerError> {
+        let metadata = sidecar::read(&Path::new(&self.base_files_dir).join(&entry.path)).await?;
+        let manifest: Manifest =
+            serde_json::from_slice(&tokio::fs::read(self.manifest_path(&entry.file_id)).await?)
+                .map_err(|e| SliceBreadServerError::InternalServerError(e.to_string()))?;
+        Ok(UploadStatus {
+            file_id: entry.file_id,
+            state: "completed",
+            progress: Progress {
+                chunks_received: manifest.chunk_count,
+                total_chunks: manifest.chunk_count,
+                bytes_received: metadata.size,
+                bytes_total: metadata.size,
+                bytes_total_estimated: false,
+                eta_seconds: None,
+                assembly_percent: None,
+                expires_at: None,
+            },
+            tags: metadata.tags,
+            user_metadata: metadata.user_metadata,
+        })
+    }
+
+    /// Serves one chunk, so consumers can process the start of a large upload
+    /// while later chunks are still arriving. Chunks come from their own file
+    /// until assembly, then from the assembled file at the offset the manifest gives.
+    async fn get_chunk(
+        &self,
+        file_id: &str,
+        chunk_index: &str,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let not_found =
+            || SliceBreadServerError::NotFound(format!("Chunk {} of {}", chunk_index, file_id));
+        let chunk_index: usize = chunk_index.parse().map_err(|_| not_found())?;
+
+        // Assembly may remove the chunk file at any point, in which case the
+        // assembled file is consulted instead.
+        let layout = self.chunk_layout(file_id).await?;
+        let (data, digest, user_metadata) =
+            match io::read_file(self.chunk_path(file_id, &layout, chunk_index)).await {
+                Ok(data) => {
+                    let digest = self
+                        .sessions
+                        .chunk_digest(file_id, chunk_index)
+                        .unwrap_or_else(|| checksum::sha256(&data));
+                    let user_metadata = self
+                        .sessions
+                        .session(file_id)
+                        .map(|session| session.user_metadata)
+                        .unwrap_or_default();
+                    (data, checksum::to_hex(&digest), user_metadata)
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    let base_dir = Path::new(&self.base_files_dir);
+                    let Some(entry) = catalog::lookup(base_dir, file_id).await? else {
+                        return Err(not_found());
+                    };
+                    let manifest: Manifest = serde_json::from_slice(
+                        &tokio::fs::read(self.manifest_path(file_id)).await?,
+                    )
+                    .map_err(|e| SliceBreadServerError::InternalServerError(e.to_string()))?;
+                    let Some(&size) = manifest.chunk_sizes.get(chunk_index) else {
+                        return Err(not_found());
+                    };
+                    let offset = manifest.chunk_sizes[..chunk_index].iter().sum();
+                    let output_path = base_dir.join(&entry.path);
+                    let data = io::read_at(&output_path, offset, size as usize).await?;
+                    let user_metadata = sidecar::read(&output_path).await?.user_metadata;
+                    (data, manifest.levels[0][chunk_index].clone(), user_metadata)
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+        let mut res = Response::builder()
+            .status(200)
+            .header(hyper::header::CONTENT_TYPE, "application/octet-stream")
+            .header(constants::HEADER_CHUNK_SIZE, data.len())
+            .header(constants::HEADER_CHUNK_SHA256, digest)
+            .body(data.into())?;
+        user_metadata::to_headers(&user_metadata, res.headers_mut());
+        Ok(res)
+    }
+
+    /// Answers `HEAD /uploads/{file_id}/chunks/{index}` so a resuming client can
+    /// check a single chunk without fetching the whole upload status.
+    async fn probe_chunk(
+        &self,
+        file_id: &str,
+        chunk_index: &str,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let not_found =
+            || SliceBreadServerError::NotFound(format!("Chunk {} of {}", chunk_index, file_id));
+        let chunk_index: usize = chunk_index.parse().map_err(|_| not_found())?;
+
+        let layout = self.chunk_layout(file_id).await?;
+        let chunk_file = self.chunk_path(file_id, &layout, chunk_index);
+        let size = match tokio::fs::metadata(&chunk_file).await {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let persisted = self.partial_len(file_id, &layout, chunk_index).await?;
+                return match persisted {
+                    Some(persisted) => Ok(Response::builder()
+                        .status(200)
+                        .header(constants::HEADER_CHUNK_OFFSET, persisted)
+                        .body(ResponseBody::default())?),
+                    None => Err(not_found()),
+                };
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let digest = match self.sessions.chunk_digest(file_id, chunk_index) {
+            Some(digest) => digest,
+            None => checksum::sha256(&io::read_file(&chunk_file).await?),
+        };
+
+        Ok(Response::builder()
+            .status(200)
+            .header(constants::HEADER_CHUNK_SIZE, size)
+            .header(constants::HEADER_CHUNK_OFFSET, size)
+            .header(constants::HEADER_CHUNK_SHA256, checksum::to_hex(&digest))
+            .body(ResponseBody::default())?)
+    }
+
+    /// Serves a stored chunk to the cluster peer assembling its upload, with
+    /// a `Content-Digest` the peer checks it against.
+    async fn peer_chunk(
+        &self,
+        file_id: &str,
+        chunk_index: &str,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let not_found =
+            || SliceBreadServerError::NotFound(format!("Chunk {} of {}", chunk_index, file_id));
+        let chunk_index: usize = chunk_index.parse().map_err(|_| not_found())?;
+
+        let layout = self.chunk_layout(file_id).await?;
+        let data = match io::read_file(self.chunk_path(file_id, &layout, chunk_index)).await {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Err(not_found()),
+            Err(err) => return Err(err.into()),
+        };
+        let digest = self
+            .sessions
+            .chunk_digest(file_id, chunk_index)
+            .unwrap_or_else(|| checksum::sha256(&data));
+        Ok(Response::builder()
+            .status(200)
+            .header(hyper::header::CONTENT_TYPE, "application/octet-stream")
+            .header(
+                constants::HEADER_CONTENT_DIGEST,
+                digest::format(digest::Algorithm::Sha256, &digest),
+            )
+            .body(data.into())?)
+    }
+
+    /// Drops this instance's copy of an upload a cluster peer has assembled.
+    async fn peer_forget(
+        &self,
+        file_id: &str,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let Some(session) = self.sessions.session(file_id) else {
+            return Err(SliceBreadServerError::NotFound(format!(
+                "Upload {}",
+                file_id
+            )));
+        };
+        self.sessions.remove(file_id);
+        self.discard_upload(file_id, session.total_chunks).await?;
+        tracing::info!(%file_id, "Dropped upload assembled by a peer");
+        Ok(Response::builder()
+            .status(204)
+            .body(ResponseBody::default())?)
+    }
+
+    async fn upload_chunk_idempotent(
+        &self,
+        headers: &hyper::HeaderMap,
+        principal: Option<&Principal>,
+        body: Bytes,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let Some(key) = headers.get(constants::HEADER_IDEMPOTENCY_KEY) else {
+            return self.upload_chunk(headers, principal, body).await;
+        };
+        let key = key.to_str().map_err(|_| {
+            SliceBreadServerError::InvalidHeader(format!(
+                "Invalid header format: {}",
+                constants::HEADER_IDEMPOTENCY_KEY
+            ))
+        })?;
+
+        let fingerprint = request_fingerprint(headers, &body);
+        let tenant = get_tenant(headers)?;
+        let file_id = get_file_id(headers)?;
+        let pending = match self
+            .sessions
+            .begin_idempotent(&tenant, key, &file_id, fingerprint)
+        {
+            IdempotencyState::Started(pending) => pending,
+            IdempotencyState::Replay(stored) => {
+                tracing::info!(idempotency_key = %key, "Replaying stored response");
+                return Ok(stored.into_response()?);
+            }
+            IdempotencyState::InProgress => {
+                return Err(SliceBreadServerError::Conflict(format!(
+                    "A request with {} {} is still in progress",
+                    constants::HEADER_IDEMPOTENCY_KEY,
+                    key
+                )));
+            }
+            IdempotencyState::Mismatch => {
+                return Err(SliceBreadServerError::IdempotencyKeyReused(format!(
+                    "{} {} was already used for a different request",
+                    constants::HEADER_IDEMPOTENCY_KEY,
+                    key
+                )));
+            }
+        };
+
+        let response = self.upload_chunk(headers, principal, body).await?;
+        pending.complete(&response);
+        Ok(response)
+    }
+
+    /// Stores every part of a `multipart/form-data` body as a complete
+    /// single-chunk upload, so thousands of tiny files don't each need a
+    /// request. A part's `name` is its file id; failures are reported per file.
+    #[tracing::instrument(name = "upload_batch", skip_all, fields(bytes = body.len()))]
+    async fn upload_batch(
+        &self,
+        headers: &hyper::HeaderMap,
+        principal: Option<&Principal>,
+        body: Bytes,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let boundary = headers
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(multipart::boundary)
+            .ok_or_else(|| {
+                SliceBreadServerError::BadRequest(
+                    "Batch uploads must be multipart/form-data with a boundary".to_string(),
+                )
+            })?;
+        let parts = multipart::parse(&body, boundary).map_err(SliceBreadServerError::BadRequest)?;
+        tracing::info!(files = parts.len(), "Received batch");
+
+        let mut files = Vec::with_capacity(parts.len());
+        for part in parts {
+            let file_id = part.name().unwrap_or_default().to_string();
+            let stored = match batch_file_headers(headers, &part) {
+                Ok(file_headers) => self.upload_chunk(&file_headers, principal, part.data).await,
+                Err(err) => Err(err),
+            };
+            files.push(match stored {
+                Ok(response) => BatchFileResult {
+                    file_id,
+                    status: response.status().as_u16(),
+                    error: None,
+                },
+                Err(err) => BatchFileResult {
+                    file_id,
+                    status: err.status_code().as_u16(),
+                    error: Some(err.body()),
+                },
+            });
+        }
+
+        let status = if files.iter().any(|file| file.error.is_some()) {
+            hyper::StatusCode::MULTI_STATUS
+        } else if files.iter().all(|file| file.status == 201) {
+            hyper::StatusCode::CREATED
+        } else {
+            hyper::StatusCode::OK
+        };
+        let mut response = json_response(&BatchResult { files })?;
+        *response.status_mut() = status;
+        Ok(response)
+    }
+
+    #[tracing::instrument(
+        name = "upload_chunk",
+        skip_all,
+        fields(file_id = Empty, chunk_index = Empty, total_chunks = Empty)
+    )]
+    async fn upload_chunk(
+        &self,
+        headers: &hyper::HeaderMap,
+        principal: Option<&Principal>,
+        body: Bytes,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let file_id = get_file_id(headers)?;
+        let chunk_index = get_bounded_header(
+            headers,
+            constants::HEADER_CHUNK_INDEX,
+            self.config.max_total_chunks.saturating_sub(1),
+        )?;
+        let total_chunks = get_bounded_header(
+            headers,
+            constants::HEADER_TOTAL_CHUNKS,
+            self.config.max_total_chunks,
+        )?;
+        let file_name =
+            filename::parse_file_name(headers.get(constants::HEADER_FILE_NAME).ok_or_else(
+                || SliceBreadServerError::MissingHeader(constants::HEADER_FILE_NAME.to_string()),
+            )?)?;
+
+        let span = tracing::Span::current();
+        span.record("file_id", file_id.as_str());
+        span.record("chunk_index", chunk_index);
+        span.record("total_chunks", total_chunks);
+        let tenant = get_tenant(headers)?;
+        tracing::info!("Received chunk");
+
+        if total_chunks == 0 {
+            return Err(SliceBreadServerError::InvalidHeader(
+                "Total chunks must be at least 1".to_string(),
+            ));
+        }
+
+        if chunk_index >= total_chunks {
+            tracing::warn!("Invalid chunk index");
+            return Err(SliceBreadServerError::ChunkOutOfRange {
+                chunk_index,
+                total_chunks,
+            });
+        }
+
+        let offset = get_optional_header(headers, constants::HEADER_CHUNK_OFFSET)?.unwrap_or(0);
+        if let Some(min) = self.config.min_chunk_bytes
+            && chunk_index + 1 < total_chunks
+            && offset + (body.len() as u64) < min
+        {
+            return Err(SliceBreadServerError::ChunkSizeOutOfRange {
+                chunk_index,
+                size: offset + body.len() as u64,
+                min,
+                max: self.config.body_limits.chunk,
+            });
+        }
+
+        self.check_access(&file_id, principal, true).await?;
+        let generation =
+            get_optional_header(headers, constants::HEADER_UPLOAD_GENERATION)?.unwrap_or(0);
+        self.switch_generation(&file_id, generation).await?;
+
+        let upload_dir = format!("{}/{}/", self.base_files_dir, file_id);
+        tracing::debug!(upload_dir = %upload_dir, "Creating upload directory");
+        tokio::fs::create_dir_all(upload_dir).await?;
+        let layout = self.pin_chunk_layout(&file_id).await?;
+
+        let content_type = headers
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        let content_digests = digest::content_digests(headers)?;
+        let extract = get_extract(headers, &file_name)?;
+        let mut declared = Session {
+            tenant,
+            file_name,
+            total_chunks,
+            content_type,
+            repr_digests: digest::repr_digests(headers)?,
+            file_size: get_optional_header(headers, constants::HEADER_FILE_SIZE)?,
+            byte_ranges: false,
+            extract,
+            defer_assembly: get_optional_header(headers, constants::HEADER_DEFER_ASSEMBLY)?
+                .unwrap_or(false),
+            max_duration: None,
+            client_ip: self.resolve_client_ip(headers),
+            generation,
+            bundle: get_bundle_member(headers)?,
+            owner: principal.map(|principal| principal.name.clone()),
+            shares: get_shares(headers),
+            category: get_category(headers)?,
+            tags: get_tags(headers)?,
+            user_metadata: get_user_metadata(headers)?,
+            required_sha256: None,
+        };
+
+        // The policy is checked against what the first chunk declared, and a
+        // retransmitted chunk adds nothing to the upload's size.
+        self.sync_shared(&file_id).await?;
+        let incoming = match self.sessions.chunk_digest(&file_id, chunk_index) {
+            Some(_) => 0,
+            None => offset + body.len() as u64,
+        };
+        let stored = self
+            .sessions
+            .progress(&file_id)
+            .map_or(0, |progress| progress.bytes_received);
+        let existing = self.sessions.session(&file_id);
+        let current = existing.as_ref().unwrap_or(&declared);
+        let policy = self.check_policy(
+            headers,
+            &file_id,
+            &current.tenant,
+            &current.content_type,
+            current.file_size.unwrap_or(0).max(stored + incoming),
+        )?;
+        self.check_quota(&current.tenant, incoming)?;
+        declared.max_duration = self.max_duration(headers, policy.as_ref())?;
+        bind_to_policy(&mut declared, policy.as_ref())?;
+        let session = self.sessions.register(&file_id, declared)?;
+        if existing.is_none() {
+            // Picks up chunks stored before a restart.
+            let stored = self.load_received(&file_id, total_chunks).await?;
+            self.sessions.restore_received(&file_id, &stored);
+        }
+        self.publish_session(&file_id, &session).await?;
+        self.check_expired(&file_id, &session).await?;
+
+        let chunk_file = self.chunk_path(&file_id, &layout, chunk_index);
+        let part_file = self.part_path(&file_id, &layout, chunk_index);
+        let body = if offset > 0 {
+            self.check_offset(&file_id, &layout, chunk_index, offset)
+                .await?;
+            let mut resumed = io::read_file(&part_file).await?;
+            resumed.extend_from_slice(&body);
+            tracing::info!(offset, "Resuming partial chunk");
+            Bytes::from(resumed)
+        } else {
+            body
+        };
+        let computed = Computed::of_body(&body, &content_digests).await?;
+        computed.verify(&content_digests, "Chunk")?;
+        let digest = computed.sha256;
+
+        // A retransmit after a server restart has no recorded digest, so fall back to the chunk on disk.
+        if self.sessions.chunk_digest(&file_id, chunk_index).is_none()
+            && tokio::fs::try_exists(&chunk_file).await?
+        {
+            let stored = Bytes::from(io::read_file(&chunk_file).await?);
+            let stored_bytes = stored.len() as u64;
+            let stored = Computed::of_body(&stored, &[]).await?.sha256;
+            self.mark_received(&file_id, chunk_index).await?;
+            self.sessions
+                .record_chunk(&file_id, chunk_index, stored, stored_bytes);
+        }
+
+        // Parallel uploaders may race on the same index, so the session store decides who writes it.
+        let bytes = body.len() as u64;
+        let already_present =
+            match self
+                .sessions
+                .reserve_chunk(&file_id, chunk_index, digest, bytes)
+            {
+                ChunkClaim::Duplicate => {
+                    tracing::info!("Duplicate chunk with identical content, skipping write");
+                    true
+                }
+                ChunkClaim::InProgress => {
+                    return Err(SliceBreadServerError::Conflict(format!(
+                        "Chunk {} is being uploaded by another request",
+                        chunk_index
+                    )));
+                }
+                ChunkClaim::Conflict => {
+                    tracing::warn!("Duplicate chunk with different content");
+                    return Err(SliceBreadServerError::Conflict(format!(
+                        "Chunk {} already stored with different content",
+                        chunk_index
+                    )));
+                }
+                ChunkClaim::Reserved => {
+                    let written = match self.write_chunk(&chunk_file, body).await {
+                        Ok(()) => self.mark_received(&file_id, chunk_index).await,
+                        Err(err) => Err(err),
+                    };
+                    if let Err(err) = written {
+                        self.sessions.release_chunk(&file_id, chunk_index);
+                        return Err(err);
+                    }
+                    self.sessions.commit_chunk(&file_id, chunk_index);
+                    if self.config.durability >= Durability::Chunk {
+                        self.sessions.journal_flushed().await;
+                    }
+                    false
+                }
+            };
+
+        if offset > 0 || tokio::fs::try_exists(&part_file).await? {
+            tokio::fs::remove_file(&part_file).await?;
+        }
+        if let Some(cluster) = &self.config.cluster {
+            cluster.store.mark_received(&file_id, chunk_index).await?;
+            let received = cluster.store.received(&file_id, total_chunks).await?;
+            self.sessions.restore_received(&file_id, &received);
+        }
+
+        // Whichever request completes the set assembles it, so chunks may arrive in
+        // any order and from several clients.
+        if !session.defer_assembly && session.bundle.is_none() {
+            match self.claim_assembly(&file_id).await? {
+                AssemblyClaim::Claimed => {
+                    self.assemble_claimed(&file_id, &session).await?;
+                    return self.created(&file_id).await;
+                }
+                AssemblyClaim::Elsewhere => return self.assembling_elsewhere(&file_id).await,
+                AssemblyClaim::Unclaimed => {}
+            }
+        }
+
+        // The upload's progress, so the client knows what is left to send. A
+        // retransmitting client is also told it can skip chunks already stored.
+        let mut res = self.upload_status(&file_id).await?;
+        if already_present {
+            res.headers_mut().insert(
+                constants::HEADER_CHUNK_ALREADY_PRESENT,
+                hyper::header::HeaderValue::from_static("true"),
+            );
+        }
+        Ok(res)
+    }
+
+    /// Handles `PUT /uploads/{file_id}`: the body is written at `X-Range-Offset`
+    /// into a sparse staging file, which becomes the output once every byte up
+    /// to `X-File-Size` has arrived. Overlapping ranges are allowed; the last
+    /// write wins.
+    #[tracing::instrument(
+        name = "upload_range",
+        skip_all,
+        fields(file_id = %file_id, offset = Empty, bytes = body.len())
+    )]
+    async fn upload_range(
+        &self,
+        file_id: &str,
+        headers: &hyper::HeaderMap,
+        principal: Option<&Principal>,
+        body: Bytes,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let offset: u64 = get_header(headers, constants::HEADER_RANGE_OFFSET)?;
+        let file_size: u64 = get_header(headers, constants::HEADER_FILE_SIZE)?;
+        let file_name =
+            filename::parse_file_name(headers.get(constants::HEADER_FILE_NAME).ok_or_else(
+                || SliceBreadServerError::MissingHeader(constants::HEADER_FILE_NAME.to_string()),
+            )?)?;
+        let tenant = get_tenant(headers)?;
+        tracing::Span::current().record("offset", offset);
+        tracing::info!("Received range");
+
+        let end = offset.saturating_add(body.len() as u64);
+        if end > file_size {
+            tracing::warn!("Range beyond the declared file size");
+            return Err(SliceBreadServerError::RangeOutOfBounds {
+                offset,
+                end,
+                file_size,
+            });
+        }
+        let content_digests = digest::content_digests(headers)?;
+        Computed::of_body(&body, &content_digests)
+            .await?
+            .verify(&content_digests, "Range")?;
+
+        let content_type = headers
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        let extract = get_extract(headers, &file_name)?;
+        let mut declared = Session {
+            tenant,
+            file_name,
+            total_chunks: 1,
+            content_type,
+            repr_digests: digest::repr_digests(headers)?,
+            file_size: Some(file_size),
+            byte_ranges: true,
+            extract,
+            defer_assembly: get_optional_header(headers, constants::HEADER_DEFER_ASSEMBLY)?
+                .unwrap_or(false),
+            max_duration: None,
+            client_ip: self.resolve_client_ip(headers),
+            generation: get_optional_header(headers, constants::HEADER_UPLOAD_GENERATION)?
+                .unwrap_or(0),
+            bundle: None,
+            owner: principal.map(|principal| principal.name.clone()),
+            shares: get_shares(headers),
+            category: get_category(headers)?,
+            tags: get_tags(headers)?,
+            user_metadata: get_user_metadata(headers)?,
+            required_sha256: None,
+        };
+        self.switch_generation(file_id, declared.generation).await?;
+        let current = self.sessions.session(file_id);
+        let current = current.as_ref().unwrap_or(&declared);
+        let policy = self.check_policy(
+            headers,
+            file_id,
+            &current.tenant,
+            &current.content_type,
+            file_size,
+        )?;
+        self.check_quota(&current.tenant, body.len() as u64)?;
+        declared.max_duration = self.max_duration(headers, policy.as_ref())?;
+        bind_to_policy(&mut declared, policy.as_ref())?;
+        let session = self.sessions.register(file_id, declared)?;
+        self.check_expired(file_id, &session).await?;
+
+        tokio::fs::create_dir_all(Path::new(&self.base_files_dir).join(file_id)).await?;
+        if let Some(chaos) = &self.config.chaos {
+            chaos.inject().await?;
+        }
+        io::write_at(self.range_path(file_id), offset, &body).await?;
+        if self.config.durability >= Durability::Chunk {
+            io::sync_file(self.range_path(file_id)).await?;
+        }
+        self.sessions.record_range(file_id, offset, end);
+        if self.config.durability >= Durability::Chunk {
+            self.sessions.journal_flushed().await;
+        }
+
+        if !session.defer_assembly {
+            match self.claim_assembly(file_id).await? {
+                AssemblyClaim::Claimed => {
+                    self.assemble_claimed(file_id, &session).await?;
+                    return self.created(file_id).await;
+                }
+                AssemblyClaim::Elsewhere => return self.assembling_elsewhere(file_id).await,
+                AssemblyClaim::Unclaimed => {}
+            }
+        }
+
+        self.upload_status(file_id).await
+    }
+
+    /// Assembles a file whose assembly this request claimed, releasing the claim on failure.
+    async fn assemble_claimed(
+        &self,
+        file_id: &str,
+        session: &Session,
+    ) -> Result<(), SliceBreadServerError> {
+        let assembled = match self.load.begin_assembly() {
+            Ok(_assembly) => self.assemble(file_id, session).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = assembled {
+            self.release_assembly(file_id).await?;
+            return Err(err);
+        }
+        self.sessions.complete(file_id);
+        if let Some(cluster) = &self.config.cluster {
+            match catalog::lookup(Path::new(&self.base_files_dir), file_id).await? {
+                Some(entry) => cluster.store.complete(file_id, &entry).await?,
+                None => cluster.store.remove(file_id).await?,
+            }
+            if let Some(peers) = cluster.peers.clone() {
+                let file_id = file_id.to_string();
+                tokio::spawn(async move { peers.forget(&file_id).await });
+            }
+        }
+        Ok(())
+    }
+
+    /// `SessionStore::claim_assembly`, which in a cluster must also win the
+    /// upload's assembly lock, so only one instance assembles it.
+    async fn claim_assembly(&self, file_id: &str) -> Result<AssemblyClaim, SliceBreadServerError> {
+        if !self.sessions.claim_assembly(file_id) {
+            return Ok(AssemblyClaim::Unclaimed);
+        }
+        let Some(cluster) = &self.config.cluster else {
+            return Ok(AssemblyClaim::Claimed);
+        };
+        let locked = cluster.store.lock_assembly(file_id, &cluster.node_id).await;
+        if !matches!(locked, Ok(true)) {
+            self.sessions.release_assembly(file_id);
+        }
+        Ok(match locked? {
+            true => AssemblyClaim::Claimed,
+            false => AssemblyClaim::Elsewhere,
+        })
+    }
+
+    /// Answers a request that completed an upload another instance of the
+    /// cluster is assembling: `202`, with the status endpoint to poll in
+    /// `Location`.
+    async fn assembling_elsewhere(
+        &self,
+        file_id: &str,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let Some(progress) = self.sessions.progress(file_id) else {
+            // Already assembled.
+            return self.upload_status(file_id).await;
+        };
+        let mut status = self.in_progress_status(file_id.to_string(), progress);
+        status.state = "assembling";
+        let mut res = json_response(&status)?;
+        *res.status_mut() = hyper::StatusCode::ACCEPTED;
+        set_location(&mut res, format!("/uploads/{}", file_id));
+        Ok(res)
+    }
+
+    /// Answers the request whose assembly created a file: `201`, with the
+    /// completed status and the file in `Location`.
+    async fn created(
+        &self,
+        file_id: &str,
+    ) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+        let mut res = self.upload_status(file_id).await?;
+        *res.status_mut() = hyper::StatusCode::CREATED;
+        set_location(&mut res, format!("/files/{}", file_id));
+        Ok(res)
+    }
+
+    async fn release_assembly(&self, file_id: &str) -> Result<(), SliceBreadServerError> {
+        self.sessions.release_assembly(file_id);
+        if let Some(cluster) = &self.config.cluster {
+            cluster
+                .store
+                .unlock_assembly(file_id, &cluster.node_id)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// In a cluster, shares what this instance knows of an upload: its
+    /// declaration, as merged from every chunk so far.
+    async fn publish_session(
+        &self,
+        file_id: &str,
+        session: &Session,
+    ) -> Result<(), SliceBreadServerError> {
+        if let Some(cluster) = &self.config.cluster {
+            cluster.store.put_session(file_id, session).await?;
+        }
+        Ok(())
+    }
+
+    /// In a cluster, catches up on an upload other instances may have taken
+    /// chunks of: its declaration and which chunks are stored. An upload that
+    /// another instance assembled since this one last saw it is forgotten.
+    async fn sync_shared(&self, file_id: &str) -> Result<(), SliceBreadServerError> {
+        let Some(cluster) = &self.config.cluster else {
+            return Ok(());
+        };
+        let local = self.sessions.session(file_id);
+        let Some(shared) = cluster.store.session(file_id).await? else {
+            if let Some(started_at) = self.sessions.started_at(file_id)
+                && self
+                    .sessions
+                    .progress(file_id)
+                    .is_some_and(|progress| progress.assembly_percent.is_none())
+                && let Some(entry) =
+                    catalog::lookup(Path::new(&self.base_files_dir), file_id).await?
+                && let Ok(metadata) =
+                    sidecar::read(&Path::new(&self.base_files_dir).join(&entry.path)).await
+                && metadata.completed_at >= started_at
+            {
+                self.sessions.complete(file_id);
+            }
+            return Ok(());
+        };
+        match local {
+            // Not published yet by the request that restarted it here.
+            Some(local) if local.generation > shared.generation => return Ok(()),
+            Some(local) if local.generation < shared.generation => self.sessions.remove(file_id),
+            _ => {}
+        }
+        let session = self.sessions.register(file_id, shared)?;
+        if !session.byte_ranges {
+            let received = cluster
+                .store
+                .received(file_id, session.total_chunks)
+                .await?;
+            self.sessions.restore_received(file_id, &received);
+        }
+        Ok(())
+    }
+
+    fn received_path(&self, file_id: &str) -> PathBuf {
+        Path::new(&self.base_files_dir)
+            .join(file_id)
+            .join(constants::RECEIVED_FILE)
+    }
+
+    fn cursor_path(&self, file_id: &str) -> PathBuf {
+        Path::new(&self.base_files_dir)
+            .join(file_id)
+            .join(constants::ASSEMBLY_CURSOR_FILE)
+    }
+
+    fn generation_path(&self, file_id: &str) -> PathBuf {
+        Path::new(&self.base_files_dir)
+            .join(file_id)
+            .join(constants::GENERATION_FILE)
+    }
+
+    /// The generation of the upload stored on disk, so a restart can't revive
+    /// data an abandoned attempt left behind. Uploads that never sent one are at 0.
+    async fn stored_generation(&self, file_id: &str) -> Result<u64, SliceBreadServerError> {
+        match tokio::fs::read(self.generation_path(file_id)).await {
+            Ok(bytes) => Ok(bytes
+                .try_into()
+                .map(u64::from_be_bytes)
+                .map_err(|_| std::io::Error::other("corrupt generation file"))?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Moves an upload to the generation a request declares. A higher one
+    /// discards the session and everything stored for an older attempt before
+    /// any of the new attempt is written; a lower one is refused.
+    async fn switch_generation(
+        &self,
+        file_id: &str,
+        generation: u64,
+    ) -> Result<(), SliceBreadServerError> {
+        let replaced = self.sessions.replace_generation(file_id, generation)?;
+        let stored = self.stored_generation(file_id).await?;
+        if generation < stored {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "Upload {} is at generation {}, got {}",
+                file_id, stored, generation
+            )));
+        }
+        // In a cluster, another instance may already have moved the upload
+        // directory to this generation.
+        if generation == stored {
+            return Ok(());
+        }
+
+        // Without a session, the received file tells how many chunks were stored.
+        let total_chunks = match &replaced {
+            Some(session) => session.total_chunks,
+            None => match tokio::fs::metadata(self.received_path(file_id)).await {
+                Ok(metadata) => metadata.len() as usize,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+                Err(err) => return Err(err.into()),
+            },
+        };
+        self.discard_upload(file_id, total_chunks).await?;
+        tokio::fs::create_dir_all(Path::new(&self.base_files_dir).join(file_id)).await?;
+        io::write_at(self.generation_path(file_id), 0, &generation.to_be_bytes()).await?;
+        tracing::info!(%file_id, from = stored, to = generation, "Restarted upload");
+        Ok(())
+    }
+
+    /// Number of chunks a previous, unfinished assembly wrote to the output.
+    async fn assembly_cursor(&self, file_id: &str) -> Result<usize, SliceBreadServerError> {
+        match tokio::fs::read(self.cursor_path(file_id)).await {
+            Ok(bytes) => Ok(<[u8; 8]>::try_from(bytes.as_slice())
+                .map_or(0, |cursor| u64::from_be_bytes(cursor) as usize)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn set_assembly_cursor(
+        &self,
+        file_id: &str,
+        chunks: usize,
+    ) -> Result<(), SliceBreadServerError> {
+        io::write_at(self.cursor_path(file_id), 0, &(chunks as u64).to_be_bytes()).await?;
+        Ok(())
+    }
+
+    async fn remove_assembly_cursor(&self, file_id: &str) -> Result<(), SliceBreadServerError> {
+        match tokio::fs::remove_file(self.cursor_path(file_id)).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Expires the upload if it has run past its maximum duration.
+    async fn check_expired(
+        &self,
+        file_id: &str,
+        session: &Session,
+    ) -> Result<(), SliceBreadServerError> {
+        if !self.sessions.is_expired(file_id, Utc::now()) {
+            return Ok(());
+        }
+        self.expire_upload(file_id, session).await?;
+        Err(SliceBreadServerError::Expired(format!(
+            "Upload {} exceeded its maximum duration",
+            file_id
+        )))
+    }
+
+    /// Expires every upload past its maximum duration, returning how many there
+    /// were. Uploads are left alone in read-only mode, since they can't finish.
+    /// Expired Idempotency-Keys are forgotten and the chunk journal compacted
+    /// either way.
+    pub async fn expire_overdue(&self) -> Result<usize, SliceBreadServerError> {
+        self.sessions.expire_idempotency_keys();
+        self.sessions.compact_journal();
+        if self.load.check_writable().is_err() {
+            return Ok(0);
+        }
+        let expired = self.sessions.expired(Utc::now());
+        for (file_id, session) in &expired {
+            self.expire_upload(file_id, session).await?;
+        }
+        Ok(expired.len())
+    }
+
+    /// Evaluates the alert rules, sending the alerts that started or stopped
+    /// firing. Returns how many were sent.
+    pub async fn check_alerts(&self) -> usize {
+        let Some(alerts) = &self.alerts else {
+            return 0;
+        };
+        let changed = alerts.evaluate(
+            backpressure::disk_usage(Path::new(&self.base_files_dir)),
+            &self.sessions.stats().tenants,
+            self.config.tenant_quota_bytes,
+        );
+        alerts.notify(&changed).await;
+        changed.len()
+    }
+
+    /// Stops for a rolling deploy: waits up to `SHUTDOWN_DRAIN_TIMEOUT` for
+    /// uploads and assemblies in flight, gives up the cluster locks of
+    /// assemblies that didn't finish, and writes out the uploads in progress
+    /// for `restore_sessions`. Returns how many were written. Stop accepting
+    /// connections first.
+    pub async fn shutdown(&self) -> Result<usize, SliceBreadServerError> {
+        let deadline = Instant::now() + constants::SHUTDOWN_DRAIN_TIMEOUT;
+        while self.load.in_flight() > 0 && Instant::now() < deadline {
+            tokio::time::sleep(constants::SHUTDOWN_DRAIN_POLL_INTERVAL).await;
+        }
+        for file_id in self.sessions.assembling() {
+            tracing::warn!(%file_id, "Interrupting assembly for shutdown");
+            self.release_assembly(&file_id).await?;
+        }
+        self.persist_sessions().await
+    }
+
+    /// Writes the uploads in progress to the snapshot file, returning how many
+    /// there were.
+    pub async fn persist_sessions(&self) -> Result<usize, SliceBreadServerError> {
+        let snapshot = self.sessions.snapshot();
+        let json = serde_json::to_vec(&snapshot)
+            .map_err(|e| SliceBreadServerError::InternalServerError(e.to_string()))?;
+        let path = self.snapshot_path();
+        tokio::fs::create_dir_all(&self.base_files_dir).await?;
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        tokio::fs::write(&tmp_path, json).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+        Ok(snapshot.len())
+    }
+
+    /// Picks up the uploads written out by the last `shutdown`, and those the
+    /// chunk journal recorded before a crash, so their clients resume where
+    /// they left off. Uploads whose files have gone, or that were restarted
+    /// at another generation, are skipped. The snapshot is deleted once read,
+    /// so a later crash can't revive stale sessions, and the journal is
+    /// rewritten to hold just the uploads restored, then appended to from
+    /// then on. Call before serving requests. Returns how many uploads were
+    /// restored.
+    pub async fn restore_sessions(&self) -> Result<usize, SliceBreadServerError> {
+        let path = self.snapshot_path();
+        let mut uploads: Vec<SessionSnapshot> = match tokio::fs::read(&path).await {
+            Ok(json) => serde_json::from_slice(&json).map_err(|e| {
+                SliceBreadServerError::InternalServerError(format!(
+                    "Corrupt session snapshot {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+        // The snapshot is the more complete record of an upload in both.
+        let journal_path = Path::new(&self.base_files_dir).join(constants::CHUNK_JOURNAL_FILE);
+        uploads.extend(journal::replay(&journal_path).await?);
+
+        let mut live = Vec::with_capacity(uploads.len());
+        for upload in uploads {
+            let dir = Path::new(&self.base_files_dir).join(&upload.file_id);
+            if !tokio::fs::try_exists(&dir).await?
+                || self.stored_generation(&upload.file_id).await? != upload.session.generation
+            {
+                tracing::info!(file_id = %upload.file_id, "Skipping stale upload in session snapshot");
+                continue;
+            }
+            live.push(upload);
+        }
+        let chunked: Vec<(String, usize)> = live
+            .iter()
+            .filter(|upload| !upload.session.byte_ranges)
+            .map(|upload| (upload.file_id.clone(), upload.session.total_chunks))
+            .collect();
+        let restored = self.sessions.restore(live);
+        // Chunks may have landed on disk after their record was left out.
+        for (file_id, total_chunks) in chunked {
+            let stored = self.load_received(&file_id, total_chunks).await?;
+            self.sessions.restore_received(&file_id, &stored);
+        }
+
+        tokio::fs::create_dir_all(&self.base_files_dir).await?;
+        journal::compact(&journal_path, &self.sessions.snapshot()).await?;
+        self.sessions.set_journal(ChunkJournal::open(
+            &journal_path,
+            self.config.durability >= Durability::Chunk,
+        )?);
+        if tokio::fs::try_exists(&path).await? {
+            tokio::fs::remove_file(&path).await?;
+        }
+        Ok(restored)
+    }
+
+    fn snapshot_path(&self) -> PathBuf {
+        Path::new(&self.base_files_dir).join(constants::SESSION_SNAPSHOT_FILE)
+    }
+
+    /// Forgets an upload and deletes its chunks and bookkeeping files. Only
+    /// working files are removed, since completed files may share the directory.
+    async fn expire_upload(
+        &self,
+        file_id: &str,
+        session: &Session,
+    ) -> Result<(), SliceBreadServerError> {
+        self.sessions.remove(file_id);
+        let total_chunks = if session.byte_ranges {
+            0
+        } else {
+            session.total_chunks
+        };
+        self.discard_upload(file_id, total_chunks).await?;
+        tracing::info!(%file_id, "Expired upload");
+        Ok(())
+    }
+
+    /// Deletes the working files of an upload that will not be assembled,
+    /// leaving any completed files in its directory alone.
+    async fn discard_upload(
+        &self,
+        file_id: &str,
+        total_chunks: usize,
+    ) -> Result<(), SliceBreadServerError> {
+        if let Some(cluster) = &self.config.cluster {
+            cluster.store.remove(file_id).await?;
+        }
+        let layout = self.chunk_layout(file_id).await?;
+        let mut paths = vec![
+            self.range_path(file_id),
+            self.layout_path(file_id),
+            self.received_path(file_id),
+            self.cursor_path(file_id),
+            self.generation_path(file_id),
+        ];
+        for i in 0..total_chunks {
+            paths.push(self.chunk_path(file_id, &layout, i));
+            paths.push(self.part_path(file_id, &layout, i));
+        }
+        for path in paths {
+            match tokio::fs::remove_file(path).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        let chunk_dir = Path::new(&self.base_files_dir).join(file_id);
+        for subdir in layout.subdirs(total_chunks) {
+            let _ = tokio::fs::remove_dir(chunk_dir.join(subdir)).await;
+        }
+        // Best effort: left in place if it holds completed files.
+        let _ = tokio::fs::remove_dir(&chunk_dir).await;
+        Ok(())
+    }
+
+    /// Which chunks of an upload are on disk, as persisted by `mark_received`.
+    async fn load_received(
+        &self,
+        file_id: &str,
+        total_chunks: usize,
+    ) -> Result<ChunkBitmap, SliceBreadServerError> {
+        match tokio::fs::read(self.received_path(file_id)).await {
+            Ok(bytes) => Ok(ChunkBitmap::from_bytes(&bytes, total_chunks)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Ok(ChunkBitmap::new(total_chunks))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Persists that a chunk is on disk. The file holds a byte rather than a bit
+    /// per chunk so that parallel requests can each write their own without
+    /// reading it first.
+    async fn mark_received(
+        &self,
+        file_id: &str,
+        chunk_index: usize,
+    ) -> Result<(), SliceBreadServerError> {
+        io::write_at(self.received_path(file_id), chunk_index as u64, &[1]).await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(bytes = body.len(), elapsed_ms = Empty))]
+    async fn write_chunk(
+        &self,
+        chunk_file: &Path,
+        body: Bytes,
+    ) -> Result<(), SliceBreadServerError> {
+        if let Some(chaos) = &self.config.chaos {
+            chaos.inject().await?;
+        }
+
+        // Finalization treats an existing chunk file as complete, so it must only
+        // appear under its real name once fully written.
+        let started = Instant::now();
+        if let Some(parent) = chunk_file.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut tmp_file = chunk_file.as_os_str().to_owned();
+        tmp_file.push(".tmp");
+        io::write_file(&tmp_file, body).await?;
+        if self.config.durability >= Durability::Chunk {
+            io::sync_file(&tmp_file).await?;
+        }
+        tokio::fs::rename(&tmp_file, chunk_file).await?;
+        if self.config.durability >= Durability::Chunk
+            && let Some(parent) = chunk_file.parent()
+        {
+            io::sync_dir(parent).await?;
+        }
+        tracing::Span::current().record("elapsed_ms", started.elapsed().as_millis() as u64);
+        tracing::debug!("Chunk written");
+        Ok(())
+    }
+
+    /// Puts the only chunk of an upload in place as its output. A hard link
+    /// keeps the chunk until assembly is recorded, like any other upload; a
+    /// copy is the fallback for filesystems without links.
+    async fn link_single_chunk(
+        &self,
+        file_id: &str,
+        output_path: &Path,
+    ) -> Result<(), SliceBreadServerError> {
+        let chunk_file = self.chunk_path(file_id, &self.chunk_layout(file_id).await?, 0);
+        if self.config.durability >= Durability::File {
+            io::sync_file(&chunk_file).await?;
+        }
+        match tokio::fs::remove_file(output_path).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        if let Err(err) = tokio::fs::hard_link(&chunk_file, output_path).await {
+            tracing::debug!(%err, "Could not link chunk, copying it instead");
+            tokio::fs::copy(&chunk_file, output_path).await?;
+            if self.config.durability >= Durability::File {
+                io::sync_file(output_path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Name of the directory next to an assembled archive that it is unpacked
+    /// into, e.g. `site/` for `site.zip`. A directory already there is only
+    /// replaced if the last upload of this file id extracted it, according to
+    /// the sidecar about to be overwritten.
+    async fn extract_dir(
+        &self,
+        output_path: &Path,
+        file_id: &str,
+        file_name: &str,
+    ) -> Result<String, SliceBreadServerError> {
+        let Some((_, stem)) = ArchiveKind::detect(file_name) else {
+            return Err(SliceBreadServerError::BadRequest(format!(
+                "{} is not an archive",
+                file_name
+            )));
+        };
+        let dest = output_path.with_file_name(stem);
+        if tokio::fs::try_exists(&dest).await? {
+            let ours = sidecar::read(output_path).await.is_ok_and(|previous| {
+                previous.file_id == file_id && previous.extracted.as_deref() == Some(stem)
+            });
+            if !ours {
+                return Err(SliceBreadServerError::Conflict(format!(
+                    "{} already exists",
+                    dest.display()
+                )));
+            }
+            if self.config.immutable {
+                return Err(SliceBreadServerError::Conflict(format!(
+                    "{} already exists and is immutable",
+                    dest.display()
+                )));
+            }
+        }
+        Ok(stem.to_string())
+    }
+
+    /// Unpacks an assembled archive into `dir` next to it, from `extract_dir`,
+    /// replacing an earlier extraction. A failure leaves the archive in place.
+    async fn extract_archive(
+        &self,
+        output_path: &Path,
+        file_name: &str,
+        dir: &str,
+    ) -> Result<(), SliceBreadServerError> {
+        let Some((kind, _)) = ArchiveKind::detect(file_name) else {
+            return Ok(());
+        };
+        let dest = output_path.with_file_name(dir);
+        if tokio::fs::try_exists(&dest).await? {
+            tokio::fs::remove_dir_all(&dest).await?;
+        }
+        let extracted = extract::extract(
+            output_path.to_path_buf(),
+            dest.clone(),
+            kind,
+            self.config.extract_limits,
+        )
+        .await?;
+        tracing::info!(
+            dest = %dest.display(),
+            entries = extracted.entries,
+            bytes = extracted.bytes,
+            "Archive extracted"
+        );
+        Ok(())
+    }
+
+    /// In a cluster without a shared upload directory, copies the chunks
+    /// other instances received onto this one's disk before assembly.
+    async fn fetch_from_peers(
+        &self,
+        file_id: &str,
+        total_chunks: usize,
+    ) -> Result<(), SliceBreadServerError> {
+        let Some(peers) = self
+            .config
+            .cluster
+            .as_ref()
+            .and_then(|cluster| cluster.peers.as_ref())
+        else {
+            return Ok(());
+        };
+        let layout = self.chunk_layout(file_id).await?;
+        let limit = self
+            .config
+            .body_limits
+            .chunk
+            .map_or(usize::MAX, |limit| limit as usize);
+        let mut fetched = 0;
+        for i in 0..total_chunks {
+            let path = self.chunk_path(file_id, &layout, i);
+            if tokio::fs::try_exists(&path).await? {
+                continue;
+            }
+            let Some(chunk) = peers.fetch_chunk(file_id, i, limit).await else {
+                tracing::warn!(missing_chunk = i, "No peer could serve a chunk");
+                return Err(SliceBreadServerError::ServiceUnavailable(format!(
+                    "Chunk {} of {} is held by an instance that can't be reached",
+                    i, file_id
+                )));
+            };
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            // Renamed into place so an interrupted fetch never passes for the chunk.
+            let mut tmp_path = path.clone().into_os_string();
+            tmp_path.push(format!(".{:016x}.tmp", rand::random::<u64>()));
+            tokio::fs::write(&tmp_path, &chunk).await?;
+            tokio::fs::rename(&tmp_path, &path).await?;
+            fetched += 1;
+        }
+        if fetched > 0 {
+            tracing::info!(fetched_chunks = fetched, "Fetched chunks from peers");
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(bytes = Empty, elapsed_ms = Empty))]
+    async fn assemble(
+        &self,
+        file_id: &str,
+        session: &Session,
+    ) -> Result<(), SliceBreadServerError> {
+        let total_chunks = session.total_chunks;
+        let single_chunk = !session.byte_ranges && total_chunks == 1;
+        if !session.byte_ranges
+            && let Some(i) = self.sessions.missing_chunk(file_id)
+        {
+            tracing::warn!(missing_chunk = i, "Missing chunk during finalization");
+            return Err(SliceBreadServerError::MissingChunk(i));
+        }
+        if !session.byte_ranges {
+            self.fetch_from_peers(file_id, total_chunks).await?;
+        }
+
+        let relative_path = match &session.bundle {
+            Some(member) => Path::new(constants::BUNDLE_STAGING_DIR)
+                .join(&member.bundle_id)
+                .join(&member.path),
+            None => self.config.output_template.render(&OutputVars {
+                tenant: &session.tenant,
+                file_id,
+                file_name: &session.file_name,
+            })?,
+        };
+        let output_path = Path::new(&self.base_files_dir).join(&relative_path);
+        let resumable = if session.byte_ranges {
+            0
+        } else {
+            self.assembly_cursor(file_id).await?.min(total_chunks)
+        };
+        // A partial output left by a failed attempt may be resumed even in immutable mode.
+        if self.config.immutable && resumable == 0 && tokio::fs::try_exists(&output_path).await? {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "{} already exists and is immutable",
+                relative_path.display()
+            )));
+        }
+        if let Some(parent) = output_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        tracing::info!(output_path = %output_path.display(), "All chunks received, assembling final file");
+        let started = Instant::now();
+        let mut bytes = 0;
+        let mut hasher = digest::Hasher::new(&session.repr_digests);
+        let mut leaves = Vec::with_capacity(total_chunks);
+        let mut chunk_sizes = Vec::with_capacity(total_chunks);
+        // Progress is reported and cancellation checked between chunks (or buffers).
+        let cancelled = || {
+            tracing::info!("Assembly cancelled");
+            SliceBreadServerError::Conflict(format!("Assembly of {} was cancelled", file_id))
+        };
+        if session.byte_ranges {
+            // The staging file already is the whole file; it is hashed here and moved into place.
+            let mut staging = tokio::fs::File::open(self.range_path(file_id)).await?;
+            let mut buf = bytes::BytesMut::new();
+            loop {
+                buf.reserve(constants::DEFAULT_POOL_BUFFER_CAPACITY);
+                let n = staging.read_buf(&mut buf).await?;
+                if n == 0 {
+                    break;
+                }
+                let piece = buf.split().freeze();
+                hasher = digest::offload(move || {
+                    hasher.update(&piece);
+                    hasher
+                })
+                .await?;
+                bytes += n;
+                if !self.sessions.advance_assembly(file_id, bytes as u64) {
+                    return Err(cancelled());
+                }
+            }
+            if self.config.durability >= Durability::File {
+                staging.sync_all().await?;
+            }
+        } else if single_chunk {
+            // Nothing to concatenate: the chunk is hashed here and linked into place.
+            if !self.sessions.advance_assembly(file_id, 0) {
+                return Err(cancelled());
+            }
+            let layout = self.chunk_layout(file_id).await?;
+            let chunk_bytes =
+                Bytes::from(io::read_file(self.chunk_path(file_id, &layout, 0)).await?);
+            chunk_sizes.push(chunk_bytes.len() as u64);
+            bytes += chunk_bytes.len();
+            let (next, leaf) = hasher.update_offloaded(chunk_bytes).await?;
+            hasher = next;
+            leaves.push(leaf);
+        } else {
+            let layout = self.chunk_layout(file_id).await?;
+            let mut file = tokio::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&output_path)
+                .await?;
+            // The cursor only says how far a failed attempt claimed to get, so
+            // those chunks are checked against their digests rather than trusted,
+            // and the output is cut back to the last one that matches.
+            let mut resumed = 0;
+            while resumed < resumable {
+                let chunk_file = self.chunk_path(file_id, &layout, resumed);
+                let mut written = vec![0; tokio::fs::metadata(&chunk_file).await?.len() as usize];
+                match file.read_exact(&mut written).await {
+                    Ok(_) => {}
+                    Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                    Err(err) => return Err(err.into()),
+                }
+                let expected = match self.sessions.chunk_digest(file_id, resumed) {
+                    Some(digest) => digest,
+                    None => checksum::sha256(&io::read_file(&chunk_file).await?),
+                };
+                let len = written.len();
+                let (candidate, digest) = hasher.clone().update_offloaded(written.into()).await?;
+                if digest != expected {
+                    break;
+                }
+                hasher = candidate;
+                leaves.push(digest);
+                chunk_sizes.push(len as u64);
+                bytes += len;
+                resumed += 1;
+            }
+            if resumed > 0 {
+                tracing::info!(resumed_chunks = resumed, "Resuming assembly");
+            }
+            file.set_len(bytes as u64).await?;
+            file.seek(std::io::SeekFrom::Start(bytes as u64)).await?;
+
+            for i in resumed..total_chunks {
+                if !self.sessions.advance_assembly(file_id, i as u64) {
+                    drop(file);
+                    tokio::fs::remove_file(&output_path).await?;
+                    self.remove_assembly_cursor(file_id).await?;
+                    return Err(cancelled());
+                }
+                let chunk_bytes =
+                    Bytes::from(io::read_file(self.chunk_path(file_id, &layout, i)).await?);
+                // Hashing runs on the blocking pool while the chunk is written.
+                let hashing = hasher.update_offloaded(chunk_bytes.clone());
+                file.write_all(&chunk_bytes).await?;
+                let (next, leaf) = hashing.await?;
+                hasher = next;
+                leaves.push(leaf);
+                chunk_sizes.push(chunk_bytes.len() as u64);
+                bytes += chunk_bytes.len();
+                self.set_assembly_cursor(file_id, i + 1).await?;
+            }
+            file.flush().await?;
+            if self.config.durability >= Durability::File {
+                file.sync_all().await?;
+            }
+        }
+
+        let computed = hasher.finalize();
+        let verified = computed
+            .verify(&session.repr_digests, "Assembled file")
+            .and_then(|()| {
+                check_finished(
+                    "Assembled file",
+                    bytes as u64,
+                    &computed.sha256,
+                    session.file_size,
+                    session.required_sha256,
+                )
+            });
+        if let Err(err) = verified {
+            tracing::warn!(%err, "Assembled file failed digest verification");
+            if !session.byte_ranges && !single_chunk {
+                tokio::fs::remove_file(&output_path).await?;
+                self.remove_assembly_cursor(file_id).await?;
+            }
+            return Err(err);
+        }
+        if session.byte_ranges {
+            leaves.push(computed.sha256);
+            chunk_sizes.push(bytes as u64);
+            tokio::fs::rename(self.range_path(file_id), &output_path).await?;
+        } else if single_chunk {
+            self.link_single_chunk(file_id, &output_path).await?;
+        }
+        // The chunks are the only other copy, so the output and its directory
+        // entry must be durable before they go.
+        if self.config.durability >= Durability::File
+            && let Some(parent) = output_path.parent()
+        {
+            io::sync_dir(parent).await?;
+        }
+        let tree = MerkleTree::from_leaves(leaves);
+        self.write_manifest(file_id, &tree.manifest(file_id, chunk_sizes))
+            .await?;
+
+        let extracted = if session.extract {
+            Some(
+                self.extract_dir(&output_path, file_id, &session.file_name)
+                    .await?,
+            )
+        } else {
+            None
+        };
+        let completed_at = Utc::now();
+        let metadata = FileMetadata {
+            file_id: file_id.to_string(),
+            file_name: session.file_name.clone(),
+            size: bytes as u64,
+            sha256: checksum::to_hex(&computed.sha256),
+            merkle_root: checksum::to_hex(&tree.root()),
+            content_type: session.content_type.clone(),
+            uploader: session.tenant.clone(),
+            started_at: self.sessions.started_at(file_id).unwrap_or(completed_at),
+            completed_at,
+            owner: session.owner.clone(),
+            shares: session.shares.clone(),
+            category: session.category.clone(),
+            tags: session.tags.clone(),
+            user_metadata: session.user_metadata.clone(),
+            replication: self.replicator.pending(),
+            extracted: extracted.clone(),
+        };
+        sidecar::write(&output_path, &metadata).await?;
+        if session.bundle.is_none() {
+            catalog::record(
+                Path::new(&self.base_files_dir),
+                &CatalogEntry {
+                    file_id: file_id.to_string(),
+                    path: relative_path.clone(),
+                },
+            )
+            .await?;
+        }
+        if self.config.immutable {
+            let mut permissions = tokio::fs::metadata(&output_path).await?.permissions();
+            permissions.set_readonly(true);
+            tokio::fs::set_permissions(&output_path, permissions).await?;
+        }
+        if session.bundle.is_none() {
+            self.replicator
+                .spawn(output_path.clone(), relative_path, metadata);
+        }
+
+        // Chunks go last, so a failure before this point can always assemble again.
+        if !session.byte_ranges {
+            let layout = self.chunk_layout(file_id).await?;
+            for i in 0..total_chunks {
+                tokio::fs::remove_file(self.chunk_path(file_id, &layout, i)).await?;
+            }
+            let chunk_dir = Path::new(&self.base_files_dir).join(file_id);
+            for subdir in layout.subdirs(total_chunks) {
+                // Best effort, like the chunk directory below.
+                let _ = tokio::fs::remove_dir(chunk_dir.join(subdir)).await;
+            }
+        }
+        self.remove_assembly_cursor(file_id).await?;
+        for path in [
+            self.layout_path(file_id),
+            self.received_path(file_id),
+            self.generation_path(file_id),
+        ] {
+            match tokio::fs::remove_file(path).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+
+        let chunk_dir = Path::new(&self.base_files_dir).join(file_id);
+        if output_path.parent() != Some(chunk_dir.as_path()) {
+            // Best effort: the chunk directory is empty now unless something else was put there.
+            let _ = tokio::fs::remove_dir(&chunk_dir).await;
+        }
+
+        if let Some(dir) = &extracted {
+            self.extract_archive(&output_path, &session.file_name, dir)
+                .await?;
+        }
+
+        let span = tracing::Span::current();
+        span.record("bytes", bytes);
+        span.record("elapsed_ms", started.elapsed().as_millis() as u64);
+        tracing::info!(file_name = %session.file_name, "Upload complete and file assembled");
+        Ok(())
+    }
+}
+
+fn parse_block_size(value: &str) -> Result<usize, SliceBreadServerError> {
+    value
+        .parse()
+        .ok()
+        .filter(|size| (1..=constants::MAX_DELTA_BLOCK_SIZE).contains(size))
+        .ok_or_else(|| {
+            SliceBreadServerError::BadRequest(format!(
+                "Block size must be between 1 and {} bytes",
+                constants::MAX_DELTA_BLOCK_SIZE
+            ))
+        })
+}
+
+/// What came of trying to claim an upload's assembly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AssemblyClaim {
+    Claimed,
+    /// Incomplete, or already being assembled by this instance.
+    Unclaimed,
+    /// Complete, but another instance of the cluster holds the assembly lock.
+    Elsewhere,
+}
+
+#[derive(serde::Serialize)]
+struct UploadStatus {
+    file_id: String,
+    state: &'static str,
+    #[serde(flatten)]
+    progress: Progress,
+    #[serde(skip_serializing_if = "Tags::is_empty")]
+    tags: Tags,
+    #[serde(skip_serializing_if = "UserMetadata::is_empty")]
+    user_metadata: UserMetadata,
+}
+
+impl UploadStatus {
+    fn in_progress(file_id: String, progress: Progress) -> Self {
+        let state = match progress.assembly_percent {
+            Some(_) => "assembling",
+            None => "uploading",
+        };
+        Self {
+            file_id,
+            state,
+            progress,
+            tags: Tags::new(),
+            user_metadata: UserMetadata::new(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct StatusQuery {
+    file_ids: Vec<String>,
+}
+
+/// Body of `POST /files/{file_id}/shares`; without a principal, a link is made.
+#[derive(serde::Deserialize)]
+struct ShareRequest {
+    principal: Option<String>,
+    #[serde(default = "read_access")]
+    access: ShareAccess,
+    expires_in_secs: Option<u64>,
+}
+
+fn read_access() -> ShareAccess {
+    ShareAccess::Read
+}
+
+/// Response of `POST /files/{file_id}/shares`. `token` is only returned for
+/// links, and goes in the `share` query parameter of requests for the file.
+#[derive(serde::Serialize)]
+struct CreatedShare {
+    #[serde(flatten)]
+    share: Share,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+}
+
+/// Response of `GET /quota`; `limit` and `remaining` are absent without a quota.
+#[derive(serde::Serialize)]
+struct Quota {
+    tenant: String,
+    limit: Option<u64>,
+    used: u64,
+    remaining: Option<u64>,
+}
+
+/// Response of `GET /capabilities`, from which clients pick chunk sizes and
+/// integrity options without out-of-band configuration.
+#[derive(serde::Serialize)]
+struct Capabilities {
+    protocol_versions: &'static [u32],
+    /// For `Content-Digest` and `Repr-Digest`, in order of preference.
+    checksum_algorithms: Vec<&'static str>,
+    upload_modes: &'static [&'static str],
+    /// Codings JSON responses may be sent with; empty when compression is off.
+    response_encodings: Vec<&'static str>,
+    features: CapabilityFeatures,
+    limits: CapabilityLimits,
+    /// Large enough that per-request overhead is a small share of each
+    /// upload, as measured from recent chunks, within `limits`.
+    recommended_chunk_size: u64,
+    /// How many chunks of that size the declared file takes.
+    recommended_total_chunks: Option<u64>,
+}
+
+#[derive(serde::Serialize)]
+struct CapabilityFeatures {
+    upload_policy_required: bool,
+    share_links: bool,
+    immutable: bool,
+    read_only: bool,
+    cluster: bool,
+}
+
+/// Limits that apply to the caller; `null` when there is none.
+#[derive(serde::Serialize)]
+struct CapabilityLimits {
+    max_total_chunks: usize,
+    /// For every chunk but the last.
+    min_chunk_bytes: Option<u64>,
+    max_chunk_bytes: Option<u64>,
+    max_range_bytes: Option<u64>,
+    max_delta_bytes: Option<u64>,
+    max_batch_bytes: Option<u64>,
+    max_delta_block_size: usize,
+    max_upload_duration_secs: Option<u64>,
+    max_sessions_per_client: Option<usize>,
+    max_sessions_per_tenant: Option<usize>,
+    tenant_quota_bytes: Option<u64>,
+}
+
+/// Response of `POST /uploads/status`, with statuses in the order asked for.
+#[derive(Default, serde::Serialize)]
+struct BulkStatus {
+    uploads: Vec<UploadStatus>,
+    not_found: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+struct BatchResult {
+    files: Vec<BatchFileResult>,
+}
+
+#[derive(serde::Serialize)]
+struct BatchFileResult {
+    file_id: String,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorBody>,
+}
+
+fn check_body_limit(limit: u64, size: u64) -> Result<(), SliceBreadServerError> {
+    if size > limit {
+        tracing::warn!(limit, size, "Request body too large");
+        return Err(SliceBreadServerError::PayloadTooLarge(format!(
+            "Request body exceeds {} bytes",
+            limit
+        )));
+    }
+    Ok(())
+}
+
+/// Chunk upload headers for one file of a batch: identity and policy come from
+/// the request, content type and digests from the part.
+fn batch_file_headers(
+    headers: &hyper::HeaderMap,
+    part: &Part,
+) -> Result<hyper::HeaderMap, SliceBreadServerError> {
+    let file_id = part.name().filter(|name| !name.is_empty()).ok_or_else(|| {
+        SliceBreadServerError::BadRequest("Batch part has no name to use as file id".to_string())
+    })?;
+    let file_name = part.filename().unwrap_or(file_id);
+    let value = |value: &str| {
+        hyper::header::HeaderValue::from_str(value).map_err(|_| {
+            SliceBreadServerError::InvalidHeader(format!("Invalid batch part value: {}", value))
+        })
+    };
+
+    let mut file_headers = hyper::HeaderMap::new();
+    for key in [
+        constants::HEADER_TENANT_ID,
+        constants::HEADER_UPLOAD_POLICY,
+        constants::HEADER_FORWARDED_FOR,
+    ] {
+        if let Some(v) = headers.get(key) {
+            file_headers.insert(key, v.clone());
+        }
+    }
+    for key in [
+        hyper::header::CONTENT_TYPE.as_str(),
+        constants::HEADER_CONTENT_DIGEST,
+        constants::HEADER_REPR_DIGEST,
+        constants::HEADER_DIGEST,
+    ] {
+        for v in part.headers.get_all(key) {
+            file_headers.append(key, v.clone());
+        }
+    }
+    file_headers.insert(constants::HEADER_FILE_ID, value(file_id)?);
+    file_headers.insert(constants::HEADER_FILE_NAME, value(file_name)?);
+    file_headers.insert(constants::HEADER_CHUNK_INDEX, value("0")?);
+    file_headers.insert(constants::HEADER_TOTAL_CHUNKS, value("1")?);
+    Ok(file_headers)
+}
+
+/// Identifies what a request asked for, so a reused Idempotency-Key with a
+/// different chunk or body can be told apart from a genuine retry.
+fn request_fingerprint(headers: &hyper::HeaderMap, body: &[u8]) -> ChunkDigest {
+    let header = |key: &str| headers.get(key).map(|v| v.as_bytes()).unwrap_or_default();
+    checksum::sha256_parts(&[
+        header(constants::HEADER_FILE_ID),
+        header(constants::HEADER_FILE_NAME),
+        header(constants::HEADER_CHUNK_INDEX),
+        header(constants::HEADER_TOTAL_CHUNKS),
+        header(constants::HEADER_CHUNK_OFFSET),
+        body,
+    ])
+}
+
+/// Lets clients that hash while streaming send the digest after the body.
+/// Only digest fields are taken from trailers; they override header values.
+fn merge_digest_trailers(headers: &mut hyper::HeaderMap, trailers: hyper::HeaderMap) {
+    for key in [
+        constants::HEADER_CONTENT_DIGEST,
+        constants::HEADER_DIGEST,
+        constants::HEADER_REPR_DIGEST,
+    ] {
+        if let Some(value) = trailers.get(key) {
+            let name = hyper::header::HeaderName::from_bytes(key.as_bytes())
+                .expect("digest header names are valid");
+            headers.insert(name, value.clone());
+        }
+    }
+}
+
+/// Endpoints other than the chunk upload, which every unmatched request falls through to.
+enum Route {
+    Stats,
+    /// Percentiles of the upload histograms, as text.
+    StatsSummary,
+    /// The upload histograms in the Prometheus text format.
+    Metrics,
+    Throttle,
+    Maintenance,
+    SetMaintenance,
+    DebugLog,
+    SetDebugLog,
+    Audit,
+    Uploads {
+        tags: Vec<String>,
+    },
+    BulkStatus,
+    Quota,
+    Capabilities {
+        file_size: Option<String>,
+    },
+    #[cfg(feature = "ui")]
+    Ui {
+        asset: String,
+    },
+    Manifest {
+        file_id: String,
+    },
+    FileInfo {
+        file_id: String,
+    },
+    CancelAssembly {
+        file_id: String,
+    },
+    DeleteFile {
+        file_id: String,
+        admin: bool,
+    },
+    UploadStatus {
+        file_id: String,
+    },
+    CompleteUpload {
+        file_id: String,
+    },
+    CommitBundle {
+        bundle_id: String,
+    },
+    Archive {
+        file_id: String,
+        name: String,
+    },
+    ChunkProbe {
+        file_id: String,
+        chunk_index: String,
+    },
+    ChunkDownload {
+        file_id: String,
+        chunk_index: String,
+    },
+    RangeUpload {
+        file_id: String,
+    },
+    Signature {
+        file_id: String,
+        block_size: Option<String>,
+    },
+    DeltaUpload {
+        file_id: String,
+    },
+    CreateShare {
+        file_id: String,
+    },
+    Purge {
+        file_id: Option<String>,
+        confirmation: Option<String>,
+    },
+    Export {
+        owner: Option<String>,
+        files: bool,
+    },
+    BatchUpload,
+    /// A cluster peer fetching a chunk this instance stored.
+    PeerChunk {
+        file_id: String,
+        chunk_index: String,
+    },
+    /// A cluster peer that assembled an upload, releasing this instance's copy.
+    PeerForget {
+        file_id: String,
+    },
+}
+
+impl Route {
+    /// The route for a request, if it isn't a chunk upload. Routes naming a
+    /// file id that isn't a single path segment don't match.
+    fn parse(method: &Method, path: &str, query: Option<&str>) -> Option<Self> {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let route = match (method, segments.as_slice()) {
+            (&Method::GET, ["admin", "stats"]) => Some(Self::Stats),
+            (&Method::GET, ["admin", "stats", "summary"]) => Some(Self::StatsSummary),
+            (&Method::GET, ["metrics"]) => Some(Self::Metrics),
+            (&Method::GET, ["admin", "throttle"]) => Some(Self::Throttle),
+            (&Method::GET, ["admin", "maintenance"]) => Some(Self::Maintenance),
+            (&Method::PUT, ["admin", "maintenance"]) => Some(Self::SetMaintenance),
+            (&Method::GET, ["admin", "debug-log"]) => Some(Self::DebugLog),
+            (&Method::PUT, ["admin", "debug-log"]) => Some(Self::SetDebugLog),
+            (&Method::GET, ["admin", "audit"]) => Some(Self::Audit),
+            (&Method::POST, ["admin", "purge"]) => Some(Self::Purge {
+                file_id: query_param(query, "file_id").map(str::to_string),
+                confirmation: query_param(query, "confirmation").map(str::to_string),
+            }),
+            (&Method::GET, ["admin", "export"]) => Some(Self::Export {
+                owner: decoded_query_param(query, "owner"),
+                files: query_param(query, "files") == Some("true"),
+            }),
+            (&Method::GET, ["uploads"]) => Some(Self::Uploads {
+                tags: decoded_query_params(query, "tag"),
+            }),
+            (&Method::POST, ["uploads", "status"]) => Some(Self::BulkStatus),
+            (&Method::GET, ["quota"]) => Some(Self::Quota),
+            (&Method::GET, ["capabilities"]) => Some(Self::Capabilities {
+                file_size: query_param(query, "file_size").map(str::to_string),
+            }),
+            (&Method::POST, ["batch"]) => Some(Self::BatchUpload),
+            #[cfg(feature = "ui")]
+            (&Method::GET, ["ui"]) => Some(Self::Ui {
+                asset: String::new(),
+            }),
+            #[cfg(feature = "ui")]
+            (&Method::GET, ["ui", asset]) => Some(Self::Ui {
+                asset: asset.to_string(),
+            }),
+            (&Method::GET, ["files", file_id, "manifest"]) => Some(Self::Manifest {
+                file_id: file_id.to_string(),
+            }),
+            (&Method::GET, ["files", file_id, "signature"]) => Some(Self::Signature {
+                file_id: file_id.to_string(),
+                block_size: query_param(query, "block_size").map(str::to_string),
+            }),
+            (&Method::POST, ["files", file_id, "delta"]) => Some(Self::DeltaUpload {
+                file_id: file_id.to_string(),
+            }),
+            (&Method::POST, ["files", file_id, "shares"]) => Some(Self::CreateShare {
+                file_id: file_id.to_string(),
+            }),
+            (&Method::GET, ["files", file_id, "chunks", chunk_index]) => {
+                Some(Self::ChunkDownload {
+                    file_id: file_id.to_string(),
+                    chunk_index: chunk_index.to_string(),
+                })
+            }
+            (&Method::HEAD, ["files", file_id]) => Some(Self::FileInfo {
+                file_id: file_id.to_string(),
+            }),
+            (&Method::DELETE, ["files", file_id]) => Some(Self::DeleteFile {
+                file_id: file_id.to_string(),
+                admin: false,
+            }),
+            (&Method::DELETE, ["admin", "uploads", file_id, "assembly"]) => {
+                Some(Self::CancelAssembly {
+                    file_id: file_id.to_string(),
+                })
+            }
+            (&Method::DELETE, ["admin", "files", file_id]) => Some(Self::DeleteFile {
+                file_id: file_id.to_string(),
+                admin: true,
+            }),
+            (&Method::GET, ["uploads", file_id]) => Some(Self::UploadStatus {
+                file_id: file_id.to_string(),
+            }),
+            (&Method::POST, ["uploads", file_id, "complete"]) => Some(Self::CompleteUpload {
+                file_id: file_id.to_string(),
+            }),
+            (&Method::POST, ["bundles", bundle_id, "commit"]) => Some(Self::CommitBundle {
+                bundle_id: bundle_id.to_string(),
+            }),
+            (&Method::GET, ["uploads", file_id, name]) if name.starts_with("archive.") => {
+                Some(Self::Archive {
+                    file_id: file_id.to_string(),
+                    name: name.to_string(),
+                })
+            }
+            (&Method::PUT, ["uploads", file_id]) => Some(Self::RangeUpload {
+                file_id: file_id.to_string(),
+            }),
+            (&Method::HEAD, ["uploads", file_id, "chunks", chunk_index]) => {
+                Some(Self::ChunkProbe {
+                    file_id: file_id.to_string(),
+                    chunk_index: chunk_index.to_string(),
+                })
+            }
+            (&Method::GET, ["internal", "uploads", file_id, "chunks", chunk_index]) => {
+                Some(Self::PeerChunk {
+                    file_id: file_id.to_string(),
+                    chunk_index: chunk_index.to_string(),
+                })
+            }
+            (&Method::DELETE, ["internal", "uploads", file_id]) => Some(Self::PeerForget {
+                file_id: file_id.to_string(),
+            }),
+            _ => None,
+        }?;
+        route
+            .file_id()
+            .is_none_or(output::is_safe_segment)
+            .then_some(route)
+    }
+
+    fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            Self::Stats
+                | Self::StatsSummary
+                | Self::Metrics
+                | Self::Throttle
+                | Self::Maintenance
+                | Self::SetMaintenance
+                | Self::DebugLog
+                | Self::SetDebugLog
+                | Self::Audit
+                | Self::Purge { .. }
+                | Self::Export { .. }
+                | Self::CancelAssembly { .. }
+                | Self::DeleteFile { admin: true, .. }
+        )
+    }
+
+    /// Routes that send stored file contents, which go out as they were
+    /// uploaded rather than compressed.
+    fn downloads(&self) -> bool {
+        matches!(
+            self,
+            Self::FileInfo { .. }
+                | Self::Archive { .. }
+                | Self::ChunkDownload { .. }
+                | Self::PeerChunk { .. }
+        )
+    }
+
+    /// Routes other instances of a cluster call, authenticated by their
+    /// signature rather than client credentials.
+    fn is_internal(&self) -> bool {
+        matches!(self, Self::PeerChunk { .. } | Self::PeerForget { .. })
+    }
+
+    /// Scopes any one of which lets a credential use the route.
+    fn scopes(&self) -> &'static [Scope] {
+        match self {
+            _ if self.is_admin() => &[Scope::Admin],
+            Self::RangeUpload { .. }
+            | Self::DeltaUpload { .. }
+            | Self::BatchUpload
+            | Self::CompleteUpload { .. }
+            | Self::CommitBundle { .. } => &[Scope::Upload],
+            // Uploaders check progress to resume, so these serve either side.
+            Self::Uploads { .. }
+            | Self::BulkStatus
+            | Self::Quota
+            | Self::Capabilities { .. }
+            | Self::UploadStatus { .. }
+            | Self::CreateShare { .. }
+            | Self::ChunkProbe { .. } => &[Scope::Upload, Scope::Download],
+            Self::DeleteFile { .. } => &[Scope::Delete],
+            _ => &[Scope::Download],
+        }
+    }
+
+    fn action(&self) -> &'static str {
+        match self {
+            Self::Stats => "admin_stats",
+            Self::StatsSummary => "admin_stats_summary",
+            Self::Metrics => "metrics",
+            Self::Throttle => "admin_throttle",
+            Self::Maintenance => "admin_maintenance",
+            Self::SetMaintenance => "admin_set_maintenance",
+            Self::DebugLog => "admin_debug_log",
+            Self::SetDebugLog => "admin_set_debug_log",
+            Self::Audit => "admin_audit",
+            Self::Purge {
+                confirmation: None, ..
+            } => "admin_purge_request",
+            Self::Purge { .. } => "admin_purge",
+            Self::Export { .. } => "admin_export",
+            Self::Uploads { .. } => "list_uploads",
+            Self::BulkStatus => "bulk_status",
+            Self::Quota => "read_quota",
+            Self::Capabilities { .. } => "read_capabilities",
+            #[cfg(feature = "ui")]
+            Self::Ui { .. } => "ui",
+            Self::Manifest { .. } => "read_manifest",
+            Self::FileInfo { .. } => "file_info",
+            Self::DeleteFile { admin: false, .. } => "delete",
+            Self::DeleteFile { admin: true, .. } => "admin_delete",
+            Self::CancelAssembly { .. } => "cancel_assembly",
+            Self::UploadStatus { .. } => "upload_status",
+            Self::CompleteUpload { .. } => "complete_upload",
+            Self::CommitBundle { .. } => "commit_bundle",
+            Self::Archive { .. } => "download_archive",
+            Self::ChunkProbe { .. } => "probe_chunk",
+            Self::ChunkDownload { .. } => "download_chunk",
+            Self::RangeUpload { .. } => "upload_range",
+            Self::Signature { .. } => "read_signature",
+            Self::DeltaUpload { .. } => "upload_delta",
+            Self::CreateShare { .. } => "create_share",
+            Self::BatchUpload => "upload_batch",
+            Self::PeerChunk { .. } => "peer_chunk",
+            Self::PeerForget { .. } => "peer_forget",
+        }
+    }
+
+    /// Whether the route stores or removes data, which read-only mode refuses.
+    /// The admin API stays available to operators.
+    fn writes(&self) -> bool {
+        matches!(
+            self,
+            Self::CompleteUpload { .. }
+                | Self::CommitBundle { .. }
+                | Self::RangeUpload { .. }
+                | Self::DeltaUpload { .. }
+                | Self::BatchUpload
+                | Self::CreateShare { .. }
+                | Self::DeleteFile { admin: false, .. }
+        )
+    }
+
+    /// Whether the route changes the file it names, rather than only reading it.
+    fn modifies(&self) -> bool {
+        matches!(
+            self,
+            Self::DeleteFile { .. }
+                | Self::CancelAssembly { .. }
+                | Self::CompleteUpload { .. }
+                | Self::RangeUpload { .. }
+                | Self::DeltaUpload { .. }
+                | Self::CreateShare { .. }
+                | Self::Purge { .. }
+        )
+    }
+
+    /// Whether a share link may stand in for credentials on the route. Links
+    /// can't reach the admin API or create further shares.
+    fn accepts_share_links(&self) -> bool {
+        self.file_id().is_some()
+            && !self.is_admin()
+            && !self.is_internal()
+            && !matches!(self, Self::CreateShare { .. })
+    }
+
+    fn file_id(&self) -> Option<&str> {
+        match self {
+            Self::Manifest { file_id }
+            | Self::FileInfo { file_id }
+            | Self::UploadStatus { file_id }
+            | Self::CompleteUpload { file_id }
+            | Self::Archive { file_id, .. }
+            | Self::DeleteFile { file_id, .. }
+            | Self::CancelAssembly { file_id }
+            | Self::ChunkProbe { file_id, .. }
+            | Self::ChunkDownload { file_id, .. }
+            | Self::RangeUpload { file_id }
+            | Self::Signature { file_id, .. }
+            | Self::DeltaUpload { file_id }
+            | Self::CreateShare { file_id }
+            | Self::PeerChunk { file_id, .. }
+            | Self::PeerForget { file_id } => Some(file_id),
+            Self::Purge { file_id, .. } => file_id.as_deref(),
+            Self::Stats
+            | Self::StatsSummary
+            | Self::Metrics
+            | Self::Throttle
+            | Self::Maintenance
+            | Self::SetMaintenance
+            | Self::DebugLog
+            | Self::SetDebugLog
+            | Self::Audit
+            | Self::Export { .. }
+            | Self::Uploads { .. }
+            | Self::BulkStatus
+            | Self::Quota
+            | Self::Capabilities { .. }
+            | Self::CommitBundle { .. }
+            | Self::BatchUpload => None,
+            #[cfg(feature = "ui")]
+            Self::Ui { .. } => None,
+        }
+    }
+}
+
+/// Reads a body small enough to handle in one piece, such as a JSON request.
+async fn collect_small_body<B>(
+    body: B,
+    limit: usize,
+    what: &str,
+) -> Result<Bytes, SliceBreadServerError>
+where
+    B: hyper::body::Body,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let collected = http_body_util::Limited::new(body, limit)
+        .collect()
+        .await
+        .map_err(|e| {
+            if e.is::<http_body_util::LengthLimitError>() {
+                SliceBreadServerError::PayloadTooLarge(format!("{} exceeds {} bytes", what, limit))
+            } else {
+                SliceBreadServerError::BadRequest(format!("Failed to read body: {}", e))
+            }
+        })?;
+    Ok(collected.to_bytes())
+}
+
+/// Value of `key` in a URL query string, without percent-decoding.
+fn query_param<'a>(query: Option<&'a str>, key: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))
+}
+
+/// Value of `key` in a URL query string, percent-decoded, for values such as
+/// principal names that may hold reserved characters.
+fn decoded_query_param(query: Option<&str>, key: &str) -> Option<String> {
+    decoded_query_params(query, key).into_iter().next()
+}
+
+/// Every value of a repeated `key`, percent-decoded.
+fn decoded_query_params(query: Option<&str>, key: &str) -> Vec<String> {
+    form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+        .filter(|(name, _)| name == key)
+        .map(|(_, value)| value.into_owned())
+        .collect()
+}
+
+fn json_response<T: serde::Serialize>(
+    value: &T,
+) -> Result<Response<ResponseBody>, SliceBreadServerError> {
+    let body = serde_json::to_string(value)
+        .map_err(|e| SliceBreadServerError::InternalServerError(e.to_string()))?;
+    Ok(Response::builder()
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(body.into())?)
+}
+
+/// Points `Location` at `path`, which holds a file id from the request and
+/// so is only set when it is a valid header value.
+fn set_location(res: &mut Response<ResponseBody>, path: String) {
+    if let Ok(location) = hyper::header::HeaderValue::from_str(&path) {
+        res.headers_mut().insert(hyper::header::LOCATION, location);
+    }
+}
+
+impl<B> Service<Request<B>> for SliceBreadServer<B>
+where
+    B: hyper::body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    type Response = Response<ResponseBody>;
+    type Error = SliceBreadServerError;
+    type Future = std::pin::Pin<
+        Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>> + Send>,
+    >;
+
+    /// Refuses clients that aren't admitted, handles the request (turning a panic
+    /// into a 500), then records who did what and how it ended in the audit log.
+    fn call(&self, mut req: Request<B>) -> Self::Future {
+        self.config.header_names.to_standard(req.headers_mut());
+        let method = req.method().clone();
+        let path = req.uri().path().to_string();
+        let route = Route::parse(&method, &path, req.uri().query());
+        let (action, file_id) = match &route {
+            Some(route) => (route.action(), route.file_id().map(str::to_string)),
+            None => (
+                "upload_chunk",
+                get_optional_header(req.headers(), constants::HEADER_FILE_ID)
+                    .ok()
+                    .flatten(),
+            ),
+        };
+        let client_ip = self.resolve_client_ip(req.headers());
+        let encoding = self
+            .config
+            .compress_responses
+            .then(|| Encoding::negotiate(req.headers()))
+            .flatten()
+            .filter(|_| !route.as_ref().is_some_and(Route::downloads));
+        let admitted = protocol::negotiate(req.headers()).and_then(|version| {
+            self.admit(client_ip, route.as_ref(), &mut req)
+                .map(|credentials| (version, credentials))
+        });
+        let version = admitted.as_ref().ok().map(|(version, _)| *version);
+        let audit = self.audit.clone();
+        let config = self.config.clone();
+        let server = self.clone();
+
+        Box::pin(async move {
+            let admitted = match admitted {
+                Ok((_, Some(credentials))) => {
+                    server
+                        .authenticate(credentials, route.as_ref(), &mut req)
+                        .await
+                }
+                Ok((_, None)) => Ok(()),
+                Err(err) => Err(err),
+            };
+            let access = route
+                .as_ref()
+                .filter(|route| !route.is_internal())
+                .and_then(|route| Some((route.file_id()?, route.modifies())));
+            let admitted = match (admitted, access) {
+                (Ok(()), Some((file_id, write))) => {
+                    server
+                        .check_access(file_id, req.extensions().get::<Principal>(), write)
+                        .await
+                }
+                (admitted, _) => admitted,
+            };
+            // Read after authentication, which fills in the client's tenant.
+            let actor =
+                get_tenant(req.headers()).unwrap_or_else(|_| constants::DEFAULT_TENANT.to_string());
+            let debug_body_limit = server.debug_log.body_limit(file_id.as_deref(), &actor);
+            if debug_body_limit.is_some() {
+                tracing::info!(
+                    %method,
+                    %path,
+                    query = %debug_log::query(req.uri().query().unwrap_or_default()),
+                    headers = %debug_log::headers(req.headers()),
+                    "Debug log: request"
+                );
+            }
+            let handled = match admitted {
+                Ok(()) => server.handle(route, req),
+                Err(err) => Box::pin(async move { Err(err) }),
+            };
+            // A panicking handler would otherwise take the whole connection down without a response.
+            let result = match std::panic::AssertUnwindSafe(handled).catch_unwind().await {
+                Ok(result) => result,
+                Err(panic) => {
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .map(|message| message.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    tracing::error!(%method, %path, ?file_id, %actor, panic = %message, "Request handler panicked");
+                    Err(SliceBreadServerError::InternalServerError(
+                        "Request handler panicked".to_string(),
+                    ))
+                }
+            };
+            if let Some(max) = debug_body_limit {
+                match &result {
+                    Ok(response) => tracing::info!(
+                        %method,
+                        %path,
+                        status = response.status().as_u16(),
+                        headers = %debug_log::headers(response.headers()),
+                        body = %if response.body().is_streamed() {
+                            "[streamed]".to_string()
+                        } else {
+                            debug_log::body(response.body(), max)
+                        },
+                        "Debug log: response"
+                    ),
+                    Err(err) => tracing::info!(
+                        %method,
+                        %path,
+                        status = err.status_code().as_u16(),
+                        code = err.code(),
+                        %err,
+                        "Debug log: response"
+                    ),
+                }
+            }
+            let result = match (result, encoding) {
+                (Ok(mut response), Some(encoding)) => {
+                    compression::compress(&mut response, encoding)
+                        .await
+                        .map(|()| response)
+                }
+                (result, _) => result,
+            };
+            let entry = AuditEntry {
+                timestamp: Utc::now(),
+                actor,
+                action: action.to_string(),
+                file_id,
+                status: match &result {
+                    Ok(response) => response.status().as_u16(),
+                    Err(err) => err.status_code().as_u16(),
+                },
+                client_ip,
+            };
+            if let Some(alerts) = &server.alerts
+                && let Ok(status) = hyper::StatusCode::from_u16(entry.status)
+            {
+                alerts.record(status);
+            }
+            if let Err(err) = audit.record(&entry).await {
+                tracing::warn!(%err, "Could not write audit entry");
+            }
+            result.map(|mut response| {
+                if let Some(version) = version {
+                    response
+                        .headers_mut()
+                        .insert(constants::HEADER_PROTOCOL_VERSION, version.into());
+                }
+                config.header_names.to_deployed(response.headers_mut());
+                response
+            })
+        })
+    }
+}
+
+impl<B> SliceBreadServer<B>
+where
+    B: hyper::body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    fn handle(
+        &self,
+        route: Option<Route>,
+        req: Request<B>,
+    ) -> <Self as Service<Request<B>>>::Future {
+        let server = self.clone();
+        // Chunk uploads have no route.
+        if route.as_ref().is_none_or(Route::writes)
+            && let Err(err) = self.load.check_writable()
+        {
+            return Box::pin(async move { Err(err) });
+        }
+        // Range, delta and batch uploads share the body handling below with chunk uploads.
+        let body_route = match route {
+            Some(Route::Audit) => {
+                let file_id = query_param(req.uri().query(), "file_id").map(str::to_string);
+                return Box::pin(async move {
+                    let entries = server.audit.read(file_id.as_deref()).await?;
+                    let mut body = String::new();
+                    for entry in entries {
+                        body.push_str(&serde_json::to_string(&entry).map_err(|e| {
+                            SliceBreadServerError::InternalServerError(e.to_string())
+                        })?);
+                        body.push('\n');
+                    }
+                    Ok(Response::builder()
+                        .header(hyper::header::CONTENT_TYPE, "application/x-ndjson")
+                        .body(body.into())?)
+                });
+            }
+            Some(Route::Stats) => {
+                let stats = self.sessions.stats();
+                return Box::pin(async move { json_response(&stats) });
+            }
+            Some(Route::StatsSummary) => {
+                let summary = self.sessions.histograms().summary();
+                return Box::pin(async move {
+                    Ok(Response::builder()
+                        .header(hyper::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+                        .body(summary.into())?)
+                });
+            }
+            Some(Route::Metrics) => {
+                let mut metrics = String::new();
+                self.sessions.histograms().render(&mut metrics);
+                return Box::pin(async move {
+                    Ok(Response::builder()
+                        .header(hyper::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+                        .body(metrics.into())?)
+                });
+            }
+            Some(Route::Throttle) => {
+                let throttle = self.config.throttle;
+                return Box::pin(async move { json_response(&throttle) });
+            }
+            Some(Route::Maintenance) => {
+                let maintenance = self.load.maintenance();
+                return Box::pin(async move { json_response(&maintenance) });
+            }
+            Some(Route::SetMaintenance) => {
+                return Box::pin(async move {
+                    let body = collect_small_body(
+                        req.into_body(),
+                        constants::MAX_MAINTENANCE_REQUEST_BYTES,
+                        "Maintenance request",
+                    )
+                    .await?;
+                    let maintenance: Maintenance = serde_json::from_slice(&body).map_err(|e| {
+                        SliceBreadServerError::BadRequest(format!(
+                            "Invalid maintenance request: {}",
+                            e
+                        ))
+                    })?;
+                    server.load.set_maintenance(maintenance);
+                    tracing::warn!(
+                        read_only = maintenance.read_only,
+                        "Maintenance mode changed"
+                    );
+                    json_response(&server.load.maintenance())
+                });
+            }
+            Some(Route::DebugLog) => {
+                let filter = self.debug_log.filter();
+                return Box::pin(async move { json_response(&filter) });
+            }
+            Some(Route::SetDebugLog) => {
+                return Box::pin(async move {
+                    let body = collect_small_body(
+                        req.into_body(),
+                        constants::MAX_DEBUG_LOG_REQUEST_BYTES,
+                        "Debug log request",
+                    )
+                    .await?;
+                    let filter: DebugLogFilter = serde_json::from_slice(&body).map_err(|e| {
+                        SliceBreadServerError::BadRequest(format!(
+                            "Invalid debug log request: {}",
+                            e
+                        ))
+                    })?;
+                    tracing::warn!(
+                        file_ids = ?filter.file_ids,
+                        tenants = ?filter.tenants,
+                        "Debug logging changed"
+                    );
+                    server.debug_log.set_filter(filter);
+                    json_response(&server.debug_log.filter())
+                });
+            }
+            Some(Route::Manifest { file_id }) => {
+                return Box::pin(async move { server.get_manifest(&file_id).await });
+            }
+            Some(Route::FileInfo { file_id }) => {
+                return Box::pin(async move { server.file_info(&file_id).await });
+            }
+            Some(Route::Uploads { tags }) => {
+                return Box::pin(async move {
+                    server
+                        .list_uploads(req.extensions().get::<Principal>(), &tags)
+                        .await
+                });
+            }
+            Some(Route::BulkStatus) => {
+                return Box::pin(async move {
+                    let principal = req.extensions().get::<Principal>().cloned();
+                    let body = collect_small_body(
+                        req.into_body(),
+                        constants::MAX_STATUS_QUERY_BYTES,
+                        "Status query",
+                    )
+                    .await?;
+                    server.bulk_status(principal.as_ref(), &body).await
+                });
+            }
+            Some(Route::CreateShare { file_id }) => {
+                return Box::pin(async move {
+                    let principal = req.extensions().get::<Principal>().cloned();
+                    let body = collect_small_body(
+                        req.into_body(),
+                        constants::MAX_SHARE_REQUEST_BYTES,
+                        "Share request",
+                    )
+                    .await?;
+                    server
+                        .create_share(&file_id, principal.as_ref(), &body)
+                        .await
+                });
+            }
+            #[cfg(feature = "ui")]
+            Some(Route::Ui { asset }) => {
+                return Box::pin(async move {
+                    let (content_type, contents) = crate::ui::asset(&asset).ok_or_else(|| {
+                        SliceBreadServerError::NotFound(format!("UI asset {}", asset))
+                    })?;
+                    Ok(Response::builder()
+                        .header(hyper::header::CONTENT_TYPE, content_type)
+                        .body(Bytes::from_static(contents).into())?)
+                });
+            }
+            Some(Route::Capabilities { file_size }) => {
+                return Box::pin(async move { server.capabilities(file_size.as_deref()) });
+            }
+            Some(Route::Quota) => {
+                let headers = req.headers().clone();
+                return Box::pin(async move { server.quota(&headers) });
+            }
+            Some(Route::UploadStatus { file_id }) => {
+                return Box::pin(async move { server.upload_status(&file_id).await });
+            }
+            Some(Route::CompleteUpload { file_id }) => {
+                let headers = req.headers().clone();
+                return Box::pin(async move {
+                    let _upload = server.load.begin_upload()?;
+                    server.complete_upload(&file_id, &headers).await
+                });
+            }
+            Some(Route::CommitBundle { bundle_id }) => {
+                let headers = req.headers().clone();
+                return Box::pin(async move {
+                    let _upload = server.load.begin_upload()?;
+                    server.commit_bundle(&bundle_id, &headers).await
+                });
+            }
+            Some(Route::Archive { file_id, name }) => {
+                let headers = req.headers().clone();
+                return Box::pin(async move {
+                    server.download_archive(&file_id, &name, &headers).await
+                });
+            }
+            Some(Route::DeleteFile { file_id, admin }) => {
+                return Box::pin(async move { server.delete_file(&file_id, admin).await });
+            }
+            Some(Route::CancelAssembly { file_id }) => {
+                return Box::pin(async move { server.cancel_assembly(&file_id) });
+            }
+            Some(Route::Purge {
+                file_id,
+                confirmation,
+            }) => {
+                return Box::pin(async move {
+                    let file_id = file_id.ok_or_else(|| {
+                        SliceBreadServerError::BadRequest("file_id is required".to_string())
+                    })?;
+                    server.purge(&file_id, confirmation.as_deref()).await
+                });
+            }
+            Some(Route::Export { owner, files }) => {
+                return Box::pin(async move {
+                    let owner = owner.ok_or_else(|| {
+                        SliceBreadServerError::BadRequest("owner is required".to_string())
+                    })?;
+                    server.export_owner(&owner, files).await
+                });
+            }
+            Some(Route::ChunkProbe {
+                file_id,
+                chunk_index,
+            }) => {
+                return Box::pin(async move { server.probe_chunk(&file_id, &chunk_index).await });
+            }
+            Some(Route::ChunkDownload {
+                file_id,
+                chunk_index,
+            }) => {
+                return Box::pin(async move { server.get_chunk(&file_id, &chunk_index).await });
+            }
+            Some(Route::Signature {
+                file_id,
+                block_size,
+            }) => {
+                return Box::pin(async move {
+                    server.get_signature(&file_id, block_size.as_deref()).await
+                });
+            }
+            Some(Route::PeerChunk {
+                file_id,
+                chunk_index,
+            }) => {
+                return Box::pin(async move { server.peer_chunk(&file_id, &chunk_index).await });
+            }
+            Some(Route::PeerForget { file_id }) => {
+                return Box::pin(async move { server.peer_forget(&file_id).await });
+            }
+            Some(
+                route
+                @ (Route::RangeUpload { .. } | Route::DeltaUpload { .. } | Route::BatchUpload),
+            ) => Some(route),
+            None => None,
+        };
+
+        let mut buffer = self.buffer_pool.get();
+
+        Box::pin(async move {
+            let _upload = server.load.begin_upload()?;
+            server.load.check_disk(Path::new(&server.base_files_dir))?;
+
+            let started = Instant::now();
+            let (parts, req_body) = req.into_parts();
+            let content_length: Option<u64> =
+                get_optional_header(&parts.headers, hyper::header::CONTENT_LENGTH.as_str())?;
+            if content_length.is_none() && server.config.require_content_length {
+                return Err(SliceBreadServerError::LengthRequired);
+            }
+            let limit = server.body_limit(body_route.as_ref());
+            if let (Some(limit), Some(declared)) = (limit, content_length) {
+                check_body_limit(limit, declared)?;
+            }
+            let mut held = server.load.begin_body(content_length.unwrap_or(0))?;
+
+            let mut req_body = std::pin::pin!(req_body);
+            let mut failure: Option<Box<dyn std::error::Error + Send + Sync>> = None;
+            let mut trailers = hyper::HeaderMap::new();
+            loop {
+                let data = match req_body.frame().await {
+                    None => break,
+                    Some(Ok(frame)) => match frame.into_data() {
+                        Ok(data) => data,
+                        Err(frame) => {
+                            if let Ok(received) = frame.into_trailers() {
+                                trailers.extend(received);
+                            }
+                            continue;
+                        }
+                    },
+                    Some(Err(e)) => {
+                        failure = Some(e.into());
+                        break;
+                    }
+                };
+                // Dropping the body unread makes hyper close the connection.
+                if let Some(limit) = limit {
+                    check_body_limit(limit, (buffer.len() + data.remaining()) as u64)?;
+                }
+                held.hold((buffer.len() + data.remaining()) as u64)?;
+                server.throttle(data.remaining()).await;
+                buffer.put(data);
+            }
+            if let Some(e) = failure {
+                let received = buffer.split().freeze();
+                if body_route.is_none()
+                    && let Err(err) = server.save_partial(&parts.headers, received).await
+                {
+                    tracing::warn!(%err, "Could not save partial chunk");
+                }
+                return Err(SliceBreadServerError::InternalServerError(format!(
+                    "Failed to read body: {}",
+                    e
+                )));
+            }
+            let body = buffer.split().freeze();
+            // Catches truncated transfers before anything is committed.
+            if let Some(declared) = content_length
+                && declared != body.len() as u64
+            {
+                tracing::warn!(declared, received = body.len(), "Chunk length mismatch");
+                return Err(SliceBreadServerError::LengthMismatch {
+                    declared,
+                    received: body.len() as u64,
+                });
+            }
+
+            let mut headers = parts.headers;
+            merge_digest_trailers(&mut headers, trailers);
+            let principal = parts.extensions.get::<Principal>();
+            let mut response = match body_route {
+                Some(Route::RangeUpload { file_id }) => {
+                    server
+                        .upload_range(&file_id, &headers, principal, body)
+                        .await
+                }
+                Some(Route::DeltaUpload { file_id }) => {
+                    server.upload_delta(&file_id, &headers, body).await
+                }
+                Some(Route::BatchUpload) => server.upload_batch(&headers, principal, body).await,
+                _ => {
+                    let size = body.len() as u64;
+                    let response = server
+                        .upload_chunk_idempotent(&headers, principal, body)
+                        .await?;
+                    // Assembly time would count against the last chunk only.
+                    if response.status() == hyper::StatusCode::OK {
+                        server.request_cost.record(size, started.elapsed());
+                    }
+                    Ok(response)
+                }
+            }?;
+            digest::advertise(response.headers_mut());
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use http_body_util::Full;
+    use hyper::{Request, service::Service};
+    use tempdir::TempDir;
+    use tokio::fs;
+
+    use crate::{
+        basic_auth::BasicAuthConfig,
+        chaos::ChaosConfig,
+        checksum,
+        cluster::{ClusterConfig, ClusterStore, DirStore},
+        config::{BodyLimits, Durability, ServerConfig},
+        introspection::IntrospectionConfig,
+        merkle::Manifest,
+        policy::UploadPolicy,
+        server::{SliceBreadServer, SliceBreadServerError, Surface},
+        session::SessionLimits,
+        sidecar::FileMetadata,
+        throttle::ThrottleConfig,
+    };
+
+    #[tokio::test]
+    async fn test_full_upload_success() {
+        let temp_dir = TempDir::new("upload_test").expect("create temp dir failed");
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let file_id = "test123";
+        let file_name = "hello.txt";
+
+        // Upload chunk 0
+        let req0 = Request::builder()
+            .method("POST")
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", file_name)
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "2")
+            .body(Full::new(Bytes::from("Hello, ".to_string())))
+            .unwrap();
+
+        let res = service.call(req0).await.unwrap();
+        assert_eq!(res.status(), 200);
+
+        let chunk_path = upload_dir.join(file_id).join("chunk_0.bin");
+        let written = tokio::fs::read_to_string(chunk_path).await.unwrap();
+        assert_eq!(written, "Hello, ");
+
+        // Upload chunk 1 (final chunk)
+        let req1 = Request::builder()
+            .method("POST")
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", file_name)
+            .header("X-Chunk-Index", "1")
+            .header("X-Total-Chunks", "2")
+            .body(Full::new(Bytes::from("World!".to_string())))
+            .unwrap();
+
+        let res = service.call(req1).await.unwrap();
+        assert_eq!(res.status(), 201);
+        assert_eq!(res.headers()["location"], "/files/test123");
+
+        // Check final file content
+        let final_path = upload_dir.join(file_id).join(file_name);
+        let content = fs::read_to_string(final_path).await.unwrap();
+        assert_eq!(content, "Hello, World!");
+
+        let sidecar = upload_dir.join(file_id).join("hello.txt.meta.json");
+        let metadata: FileMetadata =
+            serde_json::from_str(&fs::read_to_string(sidecar).await.unwrap()).unwrap();
+        assert_eq!(metadata.file_name, file_name);
+        assert_eq!(metadata.size, 13);
+        assert_eq!(
+            metadata.sha256,
+            checksum::to_hex(&checksum::sha256(b"Hello, World!"))
+        );
+        assert_eq!(metadata.content_type, "application/octet-stream");
+        assert_eq!(metadata.uploader, "default");
+        assert!(metadata.started_at <= metadata.completed_at);
+    }
+
+    #[tokio::test]
+    async fn test_single_chunk_file_upload() {
+        let temp_dir = TempDir::new("upload_test").expect("create temp dir failed");
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let file_id = "test1238";
+        let file_name = "hello.txt";
+
+        // Upload chunk 0
+        let req0 = Request::builder()
+            .method("POST")
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", file_name)
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from("Hello, World!".to_string())))
+            .unwrap();
+
+        let res = service.call(req0).await.unwrap();
+        assert_eq!(res.status(), 201);
+
+        // Check final file content
+        let final_path = upload_dir.join(file_id).join(file_name);
+        let content = fs::read_to_string(final_path).await.unwrap();
+        assert_eq!(content, "Hello, World!");
+    }
+
+    #[tokio::test]
+    async fn test_missing_x_file_id_header() {
+        let service = SliceBreadServer::<Full<Bytes>>::new(String::from("uploads"));
+
+        let file_id = "test12321";
+        let file_name = "hello.txt";
+
+        // Upload chunk 0
+        let req0 = Request::builder()
+            .method("POST")
+            .header("X-File-Name", file_name)
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1    ")
+            .body(Full::new(Bytes::from("Hello, World!".to_string())))
+            .unwrap();
+
+        let res = service.call(req0).await;
+        assert!(matches!(
+            res.unwrap_err(),
+            SliceBreadServerError::MissingHeader(_)
+        ));
+
+        // Check final file content
+        let final_path = format!("uploads/{}/{}", file_id, file_name);
+        let exists = fs::try_exists(final_path).await.unwrap();
+        assert!(!exists);
+    }
+
+    #[tokio::test]
+    async fn test_missing_x_file_name_header() {
+        let service = SliceBreadServer::<Full<Bytes>>::new(String::from("uploads"));
+
+        let file_id = "test12321";
+        let file_name = "hello.txt";
+
+        // Upload chunk 0
+        let req0 = Request::builder()
+            .header("X-File-Id", file_id)
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from("Hello, World!".to_string())))
+            .unwrap();
+
+        let res = service.call(req0).await;
+        assert!(matches!(
+            res.unwrap_err(),
+            SliceBreadServerError::MissingHeader(_)
+        ));
+
+        // Check final file content
+        let final_path = format!("uploads/{}/{}", file_id, file_name);
+        let exists = fs::try_exists(final_path).await.unwrap();
+        assert!(!exists);
+    }
+
+    #[tokio::test]
+    async fn test_missing_x_chunk_index_header() {
+        let service = SliceBreadServer::<Full<Bytes>>::new(String::from("uploads"));
+
+        let file_id = "test12321";
+        let file_name = "hello.txt";
+
+        // Upload chunk 0
+        let req0 = Request::builder()
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", file_name)
+            .header("X-Total-Chunks", "2")
+            .body(Full::new(Bytes::from("Hello, World!".to_string())))
+            .unwrap();
+
+        let res = service.call(req0).await;
+        assert!(matches!(
+            res.unwrap_err(),
+            SliceBreadServerError::MissingHeader(_)
+        ));
+
+        // Check final file content
+        let final_path = format!("uploads/{}/{}", file_id, file_name);
+        let exists = fs::try_exists(final_path).await.unwrap();
+        assert!(!exists);
+    }
+
+    #[tokio::test]
+    async fn test_missing_x_total_chunks_header() {
+        let service = SliceBreadServer::<Full<Bytes>>::new(String::from("uploads"));
+
+        let file_id = "test12321";
+        let file_name = "hello.txt";
+
+        // Upload chunk 0
+        let req0 = Request::builder()
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", file_name)
+            .header("X-Chunk-Index", "0")
+            .body(Full::new(Bytes::from("Hello, World!".to_string())))
+            .unwrap();
+
+        let res = service.call(req0).await;
+        assert!(matches!(
+            res.unwrap_err(),
+            SliceBreadServerError::MissingHeader(_)
+        ));
+
+        // Check final file content
+        let final_path = format!("uploads/{}/{}", file_id, file_name);
+        let exists = fs::try_exists(final_path).await.unwrap();
+        assert!(!exists);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_chunk_index_format() {
+        let service = SliceBreadServer::<Full<Bytes>>::new(String::from("uploads"));
+
+        let file_id = "test12321";
+        let file_name = "hello.txt";
+
+        // Upload chunk 0
+        let req0 = Request::builder()
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", file_name)
+            .header("X-Chunk-Index", "one")
+            .header("X-Total-Chunks", "2")
+            .body(Full::new(Bytes::from("Hello, World!".to_string())))
+            .unwrap();
+
+        let res = service.call(req0).await;
+        assert!(
+            matches!(res.unwrap_err(), SliceBreadServerError::InvalidHeader(ref msg) if msg.contains("Invalid header"))
+        );
+
+        // Check final file content
+        let final_path = format!("uploads/{}/{}", file_id, file_name);
+        let exists = fs::try_exists(final_path).await.unwrap();
+        assert!(!exists);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_total_chunks_format() {
+        let service = SliceBreadServer::<Full<Bytes>>::new(String::from("uploads"));
+
+        let file_id = "test12321";
+        let file_name = "hello.txt";
+
+        // Upload chunk 0
+        let req0 = Request::builder()
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", file_name)
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "one")
+            .body(Full::new(Bytes::from("Hello, World!".to_string())))
+            .unwrap();
+
+        let res = service.call(req0).await;
+        assert!(
+            matches!(res.unwrap_err(), SliceBreadServerError::InvalidHeader(ref msg) if msg.contains("Invalid header"))
+        );
+
+        // Check final file content
+        let final_path = format!("uploads/{}/{}", file_id, file_name);
+        let exists = fs::try_exists(final_path).await.unwrap();
+        assert!(!exists);
+    }
+
+    #[tokio::test]
+    async fn test_chunk_index_greater_than_total_chunks() {
+        let service = SliceBreadServer::<Full<Bytes>>::new(String::from("uploads"));
+
+        let file_id = "test123";
+        let file_name = "hello.txt";
+
+        // Upload chunk 0
+        let req0 = Request::builder()
+            .method("POST")
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", file_name)
+            .header("X-Chunk-Index", "2")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from("Hello, ".to_string())))
+            .unwrap();
+
+        let res = service.call(req0).await;
+        assert!(matches!(
+            res.unwrap_err(),
+            SliceBreadServerError::ChunkOutOfRange {
+                chunk_index: 2,
+                total_chunks: 1
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_chunk_index_equal_to_total_chunks() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let file_id = "fileBoundary";
+
+        let req = Request::builder()
+            .method("POST")
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", "boundary.txt")
+            .header("X-Chunk-Index", "2")
+            .header("X-Total-Chunks", "2")
+            .body(Full::new(Bytes::from("orphan")))
+            .unwrap();
+
+        let err = service.call(req).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Bad Request: Invalid X-Chunk-Index: 2 >= X-Total-Chunks: 2"
+        );
+        assert!(matches!(
+            err,
+            SliceBreadServerError::ChunkOutOfRange {
+                chunk_index: 2,
+                total_chunks: 2
+            }
+        ));
+
+        let chunk_path = upload_dir.join(file_id).join("chunk_2.bin");
+        assert!(!chunk_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_zero_total_chunks_rejected() {
+        let service = SliceBreadServer::<Full<Bytes>>::new(String::from("uploads"));
+
+        let req = Request::builder()
+            .method("POST")
+            .header("X-File-Id", "fileZero")
+            .header("X-File-Name", "zero.txt")
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "0")
+            .body(Full::new(Bytes::from("data")))
+            .unwrap();
+
+        let res = service.call(req).await;
+        assert!(
+            matches!(res.unwrap_err(), SliceBreadServerError::InvalidHeader(ref msg) if msg.eq("Total chunks must be at least 1"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_intermediate_chunk_on_finalization() {
+        let temp_dir = TempDir::new("upload_test").expect("create temp dir failed");
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let file_id = "test12663";
+        let file_name = "hello.txt";
+
+        // Upload chunk 0
+        let req0 = Request::builder()
+            .method("POST")
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", file_name)
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "3")
+            .body(Full::new(Bytes::from("Hello, ".to_string())))
+            .unwrap();
+
+        let res = service.call(req0).await.unwrap();
+        assert_eq!(res.status(), 200);
+
+        // Upload final chunk
+        let req1 = Request::builder()
+            .method("POST")
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", file_name)
+            .header("X-Chunk-Index", "2")
+            .header("X-Total-Chunks", "3")
+            .body(Full::new(Bytes::from("World!".to_string())))
+            .unwrap();
+
+        // The final chunk is kept, but nothing is assembled until chunk 1 arrives.
+        let res = service.call(req1).await.unwrap();
+        assert_eq!(res.status(), 200);
+        assert!(!upload_dir.join(file_id).join(file_name).exists());
+        assert!(upload_dir.join(file_id).join("chunk_2.bin").exists());
+
+        let req2 = Request::builder()
+            .method("POST")
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", file_name)
+            .header("X-Chunk-Index", "1")
+            .header("X-Total-Chunks", "3")
+            .body(Full::new(Bytes::from("dear ".to_string())))
+            .unwrap();
+        service.call(req2).await.unwrap();
+
+        let content = tokio::fs::read_to_string(upload_dir.join(file_id).join(file_name))
+            .await
+            .unwrap();
+        assert_eq!(content, "Hello, dear World!");
+    }
+
+    #[tokio::test]
+    async fn test_write_failure_returns_internal_server_error() {
+        let temp_dir = TempDir::new("upload_test").expect("create temp dir failed");
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let file_id = "test123";
+        let file_name = "hello.txt";
+
+        let conflict_path = upload_dir.join(file_id).join(file_name);
+        tokio::fs::create_dir_all(&conflict_path).await.unwrap();
+
+        // Upload chunk 0
+        let req0 = Request::builder()
+            .method("POST")
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", file_name)
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from("Hello, ".to_string())))
+            .unwrap();
+
+        let res = service.call(req0).await;
+        assert!(
+            matches!(res.unwrap_err(), SliceBreadServerError::IoError(ref e) if e.to_string().contains("Is a directory"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_output_file_creation_failure() {
+        let temp_dir = TempDir::new("upload_test").expect("create temp dir failed");
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let file_id = "test445562";
+        let file_name = "hello.txt";
+        let file_id_path = upload_dir.join(file_id);
+        tokio::fs::create_dir_all(&file_id_path).await.unwrap();
+
+        let chunk_file = file_id_path.join(file_name);
+        tokio::fs::create_dir_all(&chunk_file).await.unwrap(); // <— key change
+
+        // Upload chunk 0
+        let req = Request::builder()
+            .method("POST")
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", file_name)
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from("Hello, ".to_string())))
+            .unwrap();
+
+        let res = service.call(req).await;
+
+        assert!(
+            matches!(res.unwrap_err(), SliceBreadServerError::IoError(ref e) if e.to_string().contains("Is a directory"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunks_are_deleted_after_merge() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let file_id = "file123";
+        let file_name = "final.txt";
+
+        // Send single chunk (merge will happen)
+        let req = Request::builder()
+            .method("POST")
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", file_name)
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from("Hello, world!")))
+            .unwrap();
+
+        let res = service.call(req).await.unwrap();
+        assert_eq!(res.status(), 201);
+
+        let chunk_path = upload_dir.join(file_id).join("chunk_0.bin");
+        assert!(!chunk_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_final_file_has_correct_content() {}
+
+    #[tokio::test]
+    async fn test_empty_chunk_upload() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let file_id = "fileEmpty";
+        let file_name = "empty.txt";
+
+        let req = Request::builder()
+            .method("POST")
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", file_name)
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+
+        let res = service.call(req).await.unwrap();
+        assert_eq!(res.status(), 201);
+
+        let final_path = upload_dir.join(file_id).join(file_name);
+        let written = tokio::fs::read(final_path).await.unwrap();
+        assert_eq!(written.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_chunk_upload() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let file_id = "fileDup";
+        let file_name = "dup.txt";
+
+        let req = |data: &str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", file_name)
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "1")
+                .body(Full::new(Bytes::from(data.to_string())))
+                .unwrap()
+        };
+
+        // First upload
+        let res1 = service.call(req("first")).await.unwrap();
+        assert_eq!(res1.status(), 201);
+
+        // Second upload with same chunk index
+        let res2 = service.call(req("second")).await.unwrap();
+        assert_eq!(res2.status(), 201);
+
+        let final_path = upload_dir.join(file_id).join(file_name);
+        let content = tokio::fs::read_to_string(final_path).await.unwrap();
+
+        // Depending on implementation: expect "second" or "first"
+        assert!(["first", "second"].contains(&content.as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_large_chunk_upload() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let file_id = "fileLarge";
+        let file_name = "large.txt";
+
+        let big_data = "A".repeat(10 * 1024 * 1024); // 10 MB
+
+        let req = Request::builder()
+            .method("POST")
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", file_name)
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from(big_data.clone())))
+            .unwrap();
+
+        let res = service.call(req).await.unwrap();
+        assert_eq!(res.status(), 201);
+
+        let final_path = upload_dir.join(file_id).join(file_name);
+        let written = tokio::fs::read_to_string(final_path).await.unwrap();
+        assert_eq!(written.len(), big_data.len());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_uploads_same_file_id() {
+        use futures_util::future::join_all;
+
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let service = Arc::new(SliceBreadServer::<Full<Bytes>>::new(
+            upload_dir.to_str().unwrap().to_string(),
+        ));
+
+        let file_id = "fileConcurrent";
+        let file_name = "multi.txt";
+
+        let chunks = vec!["One", "Two", "Three", "Four"];
+
+        let futures = chunks.iter().enumerate().map(|(i, chunk)| {
+            let service = Arc::clone(&service);
+            let req = Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", file_name)
+                .header("X-Chunk-Index", i.to_string())
+                .header("X-Total-Chunks", chunks.len().to_string())
+                .body(Full::new(Bytes::from(chunk.to_string())))
+                .unwrap();
+            async move { service.call(req).await }
+        });
+
+        // Only the request that completed the set created the file.
+        let statuses: Vec<u16> = join_all(futures)
+            .await
+            .into_iter()
+            .map(|result| result.unwrap().status().as_u16())
+            .collect();
+        assert_eq!(statuses.iter().filter(|&&status| status == 201).count(), 1);
+        assert!(
+            statuses
+                .iter()
+                .all(|&status| status == 200 || status == 201)
+        );
+
+        let final_path = upload_dir.join(file_id).join(file_name);
+        let result = tokio::fs::read_to_string(final_path).await.unwrap();
+
+        for chunk in &chunks {
+            assert!(result.contains(chunk));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chaos_mode_fails_chunk_write() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let config = ServerConfig {
+            chaos: Some(ChaosConfig {
+                p_fail: 1.0,
+                latency: std::time::Duration::ZERO,
+                ..ChaosConfig::default()
+            }),
+            ..Default::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+
+        let file_id = "fileChaos";
+        let req = Request::builder()
+            .method("POST")
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", "chaos.txt")
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "2")
+            .body(Full::new(Bytes::from("data")))
+            .unwrap();
+
+        let err = service.call(req).await.unwrap_err();
+        assert!(err.status_code().is_server_error());
+
+        let chunk_path = upload_dir.join(file_id).join("chunk_0.bin");
+        assert!(!chunk_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_mismatched_metadata_across_chunks_is_rejected() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let file_id = "fileMismatch";
+        let req = |file_name: &str, index: &str, total: &str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", file_name)
+                .header("X-Chunk-Index", index)
+                .header("X-Total-Chunks", total)
+                .body(Full::new(Bytes::from("data")))
+                .unwrap()
+        };
+
+        let res = service.call(req("first.txt", "0", "3")).await.unwrap();
+        assert_eq!(res.status(), 200);
+
+        let err = service.call(req("second.txt", "1", "3")).await.unwrap_err();
+        assert!(
+            matches!(err, SliceBreadServerError::Conflict(ref msg) if msg.contains("File name mismatch"))
+        );
+        assert_eq!(err.status_code(), 409);
+
+        let err = service.call(req("first.txt", "1", "2")).await.unwrap_err();
+        assert!(
+            matches!(err, SliceBreadServerError::Conflict(ref msg) if msg.contains("Total chunks mismatch"))
+        );
+
+        assert!(!upload_dir.join(file_id).join("chunk_1.bin").exists());
+    }
+
+    #[tokio::test]
+    async fn test_total_chunks_above_maximum_rejected() {
+        let config = ServerConfig {
+            max_total_chunks: 10,
+            ..Default::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(String::from("uploads"), config);
+
+        let req = |index: &str, total: &str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileHuge")
+                .header("X-File-Name", "huge.txt")
+                .header("X-Chunk-Index", index)
+                .header("X-Total-Chunks", total)
+                .body(Full::new(Bytes::from("data")))
+                .unwrap()
+        };
+
+        let res = service.call(req("0", "18446744073709551615")).await;
+        assert!(
+            matches!(res.unwrap_err(), SliceBreadServerError::InvalidHeader(ref msg) if msg.eq("Invalid header value: X-Total-Chunks exceeds maximum of 10"))
+        );
+
+        let res = service.call(req("10", "10")).await;
+        assert!(
+            matches!(res.unwrap_err(), SliceBreadServerError::InvalidHeader(ref msg) if msg.eq("Invalid header value: X-Chunk-Index exceeds maximum of 9"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_identical_retransmit_is_idempotent() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let file_id = "fileRetry";
+        let req = |index: &str, data: &'static str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", "retry.txt")
+                .header("X-Chunk-Index", index)
+                .header("X-Total-Chunks", "2")
+                .body(Full::new(Bytes::from(data)))
+                .unwrap()
+        };
+
+        let res = service.call(req("0", "Hello, ")).await.unwrap();
+        assert_eq!(res.status(), 200);
+
+        let res = service.call(req("0", "Hello, ")).await.unwrap();
+        assert_eq!(res.status(), 200);
+
+        let err = service.call(req("0", "Howdy, ")).await.unwrap_err();
+        assert!(
+            matches!(err, SliceBreadServerError::Conflict(ref msg) if msg.eq("Chunk 0 already stored with different content"))
+        );
+
+        let chunk_path = upload_dir.join(file_id).join("chunk_0.bin");
+        assert_eq!(
+            tokio::fs::read_to_string(chunk_path).await.unwrap(),
+            "Hello, "
+        );
+
+        // After a restart the stored chunk is credited with its own size,
+        // not that of a differing retransmit.
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+        let err = service.call(req("0", "Hi, ")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Conflict(_)));
+        assert_eq!(service.sessions.stats().total.bytes_stored, 7);
+        assert_eq!(
+            service.sessions.progress(file_id).unwrap().bytes_received,
+            7
+        );
+
+        let res = service.call(req("1", "World!")).await.unwrap();
+        assert_eq!(res.status(), 201);
+
+        let final_path = upload_dir.join(file_id).join("retry.txt");
+        let content = tokio::fs::read_to_string(final_path).await.unwrap();
+        assert_eq!(content, "Hello, World!");
+    }
+
+    #[tokio::test]
+    async fn test_early_final_chunk_waits_for_the_rest() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let file_id = "fileEarlyFinal";
+        let req = |index: &str, data: &'static str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", "early.txt")
+                .header("X-Chunk-Index", index)
+                .header("X-Total-Chunks", "2")
+                .body(Full::new(Bytes::from(data)))
+                .unwrap()
+        };
+
+        service.call(req("1", "World!")).await.unwrap();
+        let final_path = upload_dir.join(file_id).join("early.txt");
+        assert!(!final_path.exists());
+
+        // Whichever chunk completes the set triggers assembly.
+        service.call(req("0", "Hello, ")).await.unwrap();
+
+        let content = tokio::fs::read_to_string(final_path).await.unwrap();
+        assert_eq!(content, "Hello, World!");
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_replays_finalizing_request() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let file_id = "fileIdempotent";
+        let req = |index: &str, key: &str, data: &'static str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", "idem.txt")
+                .header("X-Chunk-Index", index)
+                .header("X-Total-Chunks", "2")
+                .header("Idempotency-Key", key)
+                .body(Full::new(Bytes::from(data)))
+                .unwrap()
+        };
+
+        service.call(req("0", "key-0", "Hello, ")).await.unwrap();
+        let res = service.call(req("1", "key-1", "World!")).await.unwrap();
+        assert_eq!(res.status(), 201);
+        assert!(res.headers().get("Idempotent-Replayed").is_none());
+
+        // Chunks are gone after assembly, so re-running the request would fail with "Missing chunk".
+        let replay = service.call(req("1", "key-1", "World!")).await.unwrap();
+        assert_eq!(replay.status(), 201);
+        assert_eq!(replay.body(), res.body());
+        assert_eq!(replay.headers()["Idempotent-Replayed"], "true");
+
+        let err = service
+            .call(req("0", "key-1", "Hello, "))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SliceBreadServerError::IdempotencyKeyReused(_)
+        ));
+        assert_eq!(err.status_code(), 422);
+
+        let final_path = upload_dir.join(file_id).join("idem.txt");
+        let content = tokio::fs::read_to_string(final_path).await.unwrap();
+        assert_eq!(content, "Hello, World!");
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_not_stored_for_failed_request() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let file_id = "fileIdempotentRetry";
+        let req = |index: &str, data: &'static str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", "idem.txt")
+                .header("X-Chunk-Index", index)
+                .header("X-Total-Chunks", "2")
+                .header("Idempotency-Key", format!("key-{}", index))
+                .body(Full::new(Bytes::from(data)))
+                .unwrap()
+        };
+
+        let mut bad_digest = req("1", "World!");
+        bad_digest.headers_mut().insert(
+            "Content-Digest",
+            "sha-256=:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=:"
+                .parse()
+                .unwrap(),
+        );
+        let err = service.call(bad_digest).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::DigestMismatch(_)));
+
+        service.call(req("0", "Hello, ")).await.unwrap();
+        let res = service.call(req("1", "World!")).await.unwrap();
+        assert!(res.headers().get("Idempotent-Replayed").is_none());
+
+        let final_path = upload_dir.join(file_id).join("idem.txt");
+        let content = tokio::fs::read_to_string(final_path).await.unwrap();
+        assert_eq!(content, "Hello, World!");
+    }
+
+    #[tokio::test]
+    async fn test_utf8_file_name_upload() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let file_id = "fileUnicode";
+        let req = Request::builder()
+            .method("POST")
+            .header("X-File-Id", file_id)
+            .header(
+                "X-File-Name",
+                "UTF-8''r%C3%A9sum%C3%A9%20%E5%A0%B1%E5%91%8A.txt",
+            )
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from("Hello, World!")))
+            .unwrap();
+
+        let res = service.call(req).await.unwrap();
+        assert_eq!(res.status(), 201);
+
+        let final_path = upload_dir.join(file_id).join("résumé 報告.txt");
+        let content = tokio::fs::read_to_string(final_path).await.unwrap();
+        assert_eq!(content, "Hello, World!");
+    }
+
+    #[tokio::test]
+    async fn test_path_traversal_file_name_rejected() {
+        let service = SliceBreadServer::<Full<Bytes>>::new(String::from("uploads"));
+
+        let req = Request::builder()
+            .method("POST")
+            .header("X-File-Id", "fileTraversal")
+            .header("X-File-Name", "..%2F..%2Fescape.txt")
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from("data")))
+            .unwrap();
+
+        let res = service.call(req).await;
+        assert!(
+            matches!(res.unwrap_err(), SliceBreadServerError::InvalidHeader(ref msg) if msg.starts_with("Invalid header value: X-File-Name"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_path_traversal_file_id_rejected() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        for file_id in ["../escape", "..", ".", "a\\b"] {
+            let req = Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", "escape.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "1")
+                .body(Full::new(Bytes::from("data")))
+                .unwrap();
+            let res = service.call(req).await;
+            assert!(
+                matches!(res.unwrap_err(), SliceBreadServerError::InvalidHeader(ref msg) if msg == "Invalid header value: X-File-Id"),
+                "{}",
+                file_id
+            );
+        }
+        assert!(!temp_dir.path().join("escape").exists());
+
+        assert!(super::Route::parse(&hyper::Method::GET, "/files/../manifest", None).is_none());
+        assert!(super::Route::parse(&hyper::Method::PUT, "/uploads/.", None).is_none());
+        assert!(
+            super::Route::parse(&hyper::Method::POST, "/admin/purge", Some("file_id=..")).is_none()
+        );
+        assert!(super::Route::parse(&hyper::Method::GET, "/files/abc/manifest", None).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_admin_stats_reports_usage_per_tenant() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let req = |file_id: &str, tenant: &str, index: &str, body: &'static str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-Tenant-Id", tenant)
+                .header("X-File-Name", "stats.txt")
+                .header("X-Chunk-Index", index)
+                .header("X-Total-Chunks", "2")
+                .body(Full::new(Bytes::from(body)))
+                .unwrap()
+        };
+
+        service
+            .call(req("statsA", "acme", "0", "Hello, "))
+            .await
+            .unwrap();
+        service
+            .call(req("statsA", "acme", "1", "World!"))
+            .await
+            .unwrap();
+        service
+            .call(req("statsB", "globex", "0", "Hi"))
+            .await
+            .unwrap();
+
+        let stats = Request::builder()
+            .uri("/admin/stats")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let res = service.call(stats).await.unwrap();
+        assert_eq!(res.status(), 200);
+
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["bytes_stored"], 15);
+        assert_eq!(body["uploads_in_progress"], 1);
+        assert_eq!(body["uploads_completed"], 1);
+        assert_eq!(body["tenants"]["acme"]["bytes_stored"], 13);
+        assert_eq!(body["tenants"]["acme"]["uploads_completed"], 1);
+        assert_eq!(body["tenants"]["globex"]["uploads_in_progress"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_upload_histograms_are_exposed_as_metrics_and_summary() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let chunk = |index: &str, body: &'static str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileHistogram")
+                .header("X-File-Name", "histogram.txt")
+                .header("X-Chunk-Index", index)
+                .header("X-Total-Chunks", "2")
+                .body(Full::new(Bytes::from(body)))
+                .unwrap()
+        };
+        service.call(chunk("0", "Hello, ")).await.unwrap();
+        // Resent, e.g. after a lost response.
+        service.call(chunk("0", "Hello, ")).await.unwrap();
+        service.call(chunk("1", "World!")).await.unwrap();
+
+        let get = |uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+        let res = service.call(get("/metrics")).await.unwrap();
+        assert_eq!(res.status(), 200);
+        let metrics = String::from_utf8(res.body().to_vec()).unwrap();
+        assert!(metrics.contains("slicedbread_upload_size_bytes_sum 13\n"));
+        assert!(metrics.contains("slicedbread_chunk_size_bytes_count 2\n"));
+        assert!(metrics.contains("slicedbread_upload_duration_seconds_count 1\n"));
+        assert!(metrics.contains("slicedbread_upload_retries_bucket{le=\"0\"} 0\n"));
+        assert!(metrics.contains("slicedbread_upload_retries_bucket{le=\"1\"} 1\n"));
+
+        let res = service.call(get("/admin/stats/summary")).await.unwrap();
+        assert_eq!(res.status(), 200);
+        let summary = String::from_utf8(res.body().to_vec()).unwrap();
+        assert!(summary.starts_with("Over the last 1.0h:\n"));
+        assert!(summary.contains("upload size      count 1 "));
+        assert!(summary.contains("max 13 B\n"));
+
+        // Scraped from the admin listener only.
+        let public = service.with_surface(Surface::Public);
+        let err = public.call(get("/metrics")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn test_output_template_places_assembled_file() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+
+        let config = ServerConfig {
+            output_template: "completed/{tenant}/{file_name}".parse().unwrap(),
+            ..ServerConfig::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+
+        let req = |tenant: &str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileTemplate")
+                .header("X-Tenant-Id", tenant)
+                .header("X-File-Name", "flat.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "1")
+                .body(Full::new(Bytes::from("Hello, World!")))
+                .unwrap()
+        };
+
+        let err = service.call(req("../acme")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::InvalidHeader(_)));
+
+        let res = service.call(req("acme")).await.unwrap();
+        assert_eq!(res.status(), 201);
+
+        let final_path = upload_dir.join("completed").join("acme").join("flat.txt");
+        let content = tokio::fs::read_to_string(final_path).await.unwrap();
+        assert_eq!(content, "Hello, World!");
+        assert!(!upload_dir.join("fileTemplate").exists());
+    }
+
+    #[tokio::test]
+    async fn test_head_probes_single_chunk() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let req = Request::builder()
+            .method("POST")
+            .header("X-File-Id", "fileProbe")
+            .header("X-File-Name", "probe.txt")
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "2")
+            .body(Full::new(Bytes::from("Hello, ")))
+            .unwrap();
+        service.call(req).await.unwrap();
+
+        let probe = |index: &str| {
+            Request::builder()
+                .method("HEAD")
+                .uri(format!("/uploads/fileProbe/chunks/{}", index))
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+
+        let res = service.call(probe("0")).await.unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()["X-Chunk-Size"], "7");
+        assert_eq!(
+            res.headers()["X-Chunk-Sha256"],
+            checksum::to_hex(&checksum::sha256(b"Hello, ")).as_str()
+        );
+
+        for index in ["1", "nope"] {
+            let err = service.call(probe(index)).await.unwrap_err();
+            assert!(matches!(err, SliceBreadServerError::NotFound(_)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_chunk_resumes_from_offset() {
+        use futures_util::stream;
+        use http_body_util::StreamBody;
+        use hyper::body::Frame;
+
+        type Body =
+            StreamBody<stream::Iter<std::vec::IntoIter<Result<Frame<Bytes>, std::io::Error>>>>;
+
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+
+        let service = SliceBreadServer::<Body>::new(upload_dir.to_str().unwrap().to_string());
+
+        let req = |offset: Option<&str>, frames: Vec<Result<Frame<Bytes>, std::io::Error>>| {
+            let mut builder = Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileResume")
+                .header("X-File-Name", "resume.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "1");
+            if let Some(offset) = offset {
+                builder = builder.header("X-Chunk-Offset", offset);
+            }
+            builder.body(StreamBody::new(stream::iter(frames))).unwrap()
+        };
+
+        let cut_off = vec![
+            Ok(Frame::data(Bytes::from("Hello, "))),
+            Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset)),
+        ];
+        assert!(service.call(req(None, cut_off)).await.is_err());
+
+        let probe = Request::builder()
+            .method("HEAD")
+            .uri("/uploads/fileResume/chunks/0")
+            .body(StreamBody::new(stream::iter(vec![])))
+            .unwrap();
+        let res = service.call(probe).await.unwrap();
+        assert_eq!(res.headers()["X-Chunk-Offset"], "7");
+        assert!(res.headers().get("X-Chunk-Sha256").is_none());
+
+        let rest = || vec![Ok(Frame::data(Bytes::from("World!")))];
+        let err = service.call(req(Some("3"), rest())).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Conflict(_)));
+
+        let res = service.call(req(Some("7"), rest())).await.unwrap();
+        assert_eq!(res.status(), 201);
+
+        let final_path = upload_dir.join("fileResume").join("resume.txt");
+        let content = tokio::fs::read_to_string(final_path).await.unwrap();
+        assert_eq!(content, "Hello, World!");
+        assert!(
+            !upload_dir
+                .join("fileResume")
+                .join("chunk_0.bin.part")
+                .exists()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_throttled_upload_and_settings_endpoint() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+
+        let config = ServerConfig {
+            throttle: ThrottleConfig {
+                global_bytes_per_sec: Some(1000),
+                connection_bytes_per_sec: None,
+            },
+            ..ServerConfig::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        )
+        .for_connection("127.0.0.1".parse().unwrap());
+
+        let started = std::time::Instant::now();
+        for index in ["0", "1"] {
+            let req = Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileThrottle")
+                .header("X-File-Name", "slow.bin")
+                .header("X-Chunk-Index", index)
+                .header("X-Total-Chunks", "2")
+                .body(Full::new(Bytes::from(vec![b'A'; 600])))
+                .unwrap();
+            service.call(req).await.unwrap();
+        }
+        // 1200 bytes against a 1000 byte burst at 1000 B/s
+        assert!(started.elapsed() >= std::time::Duration::from_millis(150));
+
+        let req = Request::builder()
+            .uri("/admin/throttle")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let res = service.call(req).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["global_bytes_per_sec"], 1000);
+        assert!(body["connection_bytes_per_sec"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_content_and_repr_digest_headers() {
+        use base64::{Engine, engine::general_purpose::STANDARD};
+
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let sha = |data: &[u8]| format!("sha-256=:{}:", STANDARD.encode(checksum::sha256(data)));
+        let req = |index: &str, body: &'static str, header: (&str, String)| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileDigest")
+                .header("X-File-Name", "digest.txt")
+                .header("X-Chunk-Index", index)
+                .header("X-Total-Chunks", "2")
+                .header(header.0, header.1)
+                .body(Full::new(Bytes::from(body)))
+                .unwrap()
+        };
+
+        let err = service
+            .call(req("0", "Hello, ", ("Content-Digest", sha(b"tampered"))))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::DigestMismatch(_)));
+
+        let crc = crc32c::crc32c(b"Hello, ").to_be_bytes();
+        let res = service
+            .call(req(
+                "0",
+                "Hello, ",
+                ("Digest", format!("CRC32c={}", STANDARD.encode(crc))),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        assert!(
+            res.headers()["Want-Repr-Digest"]
+                .to_str()
+                .unwrap()
+                .contains("blake3")
+        );
+
+        let err = service
+            .call(req("1", "World!", ("Repr-Digest", sha(b"Hello, Moon!"))))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::DigestMismatch(_)));
+        assert!(!upload_dir.join("fileDigest").join("digest.txt").exists());
+        assert!(upload_dir.join("fileDigest").join("chunk_0.bin").exists());
+        assert!(upload_dir.join("fileDigest").join("chunk_1.bin").exists());
+    }
+
+    #[tokio::test]
+    async fn test_digest_in_trailers_is_verified() {
+        use base64::{Engine, engine::general_purpose::STANDARD};
+        use futures_util::stream;
+        use http_body_util::StreamBody;
+        use hyper::{HeaderMap, body::Frame};
+
+        type Body =
+            StreamBody<stream::Iter<std::vec::IntoIter<Result<Frame<Bytes>, std::io::Error>>>>;
+
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+
+        let service = SliceBreadServer::<Body>::new(upload_dir.to_str().unwrap().to_string());
+
+        let req = |digest_of: &[u8]| {
+            let mut trailers = HeaderMap::new();
+            trailers.insert(
+                "content-digest",
+                format!("sha-256=:{}:", STANDARD.encode(checksum::sha256(digest_of)))
+                    .parse()
+                    .unwrap(),
+            );
+            // A trailer can't redirect the chunk elsewhere.
+            trailers.insert("x-chunk-index", "5".parse().unwrap());
+            let frames = vec![
+                Ok(Frame::data(Bytes::from("Hello, "))),
+                Ok(Frame::data(Bytes::from("World!"))),
+                Ok(Frame::trailers(trailers)),
+            ];
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileTrailer")
+                .header("X-File-Name", "trailer.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "1")
+                .header("Trailer", "Content-Digest")
+                .body(StreamBody::new(stream::iter(frames)))
+                .unwrap()
+        };
+
+        let err = service.call(req(b"something else")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::DigestMismatch(_)));
+
+        let res = service.call(req(b"Hello, World!")).await.unwrap();
+        assert_eq!(res.status(), 201);
+        let final_path = upload_dir.join("fileTrailer").join("trailer.txt");
+        let content = tokio::fs::read_to_string(final_path).await.unwrap();
+        assert_eq!(content, "Hello, World!");
+    }
+
+    #[tokio::test]
+    async fn test_content_length_is_enforced() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+
+        let config = ServerConfig {
+            require_content_length: true,
+            ..ServerConfig::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+
+        let req = |content_length: Option<&str>| {
+            let mut builder = Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileLength")
+                .header("X-File-Name", "length.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "1");
+            if let Some(content_length) = content_length {
+                builder = builder.header("Content-Length", content_length);
+            }
+            builder.body(Full::new(Bytes::from("Hello"))).unwrap()
+        };
+
+        let err = service.call(req(None)).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::LengthRequired));
+
+        let err = service.call(req(Some("10"))).await.unwrap_err();
+        assert!(matches!(
+            err,
+            SliceBreadServerError::LengthMismatch {
+                declared: 10,
+                received: 5
+            }
+        ));
+        assert!(!upload_dir.join("fileLength").join("chunk_0.bin").exists());
+
+        let res = service.call(req(Some("5"))).await.unwrap();
+        assert_eq!(res.status(), 201);
+    }
+
+    #[tokio::test]
+    async fn test_merkle_manifest_endpoint() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let manifest = || {
+            Request::builder()
+                .uri("/files/fileMerkle/manifest")
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+        let err = service.call(manifest()).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::NotFound(_)));
+
+        let chunks = ["one", "two", "three"];
+        for (i, chunk) in chunks.iter().enumerate() {
+            let req = Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileMerkle")
+                .header("X-File-Name", "merkle.txt")
+                .header("X-Chunk-Index", i.to_string())
+                .header("X-Total-Chunks", chunks.len().to_string())
+                .body(Full::new(Bytes::from(*chunk)))
+                .unwrap();
+            service.call(req).await.unwrap();
+        }
+
+        let res = service.call(manifest()).await.unwrap();
+        let manifest: Manifest = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(manifest.chunk_count, 3);
+        assert_eq!(
+            manifest.levels[0][1],
+            checksum::to_hex(&checksum::sha256(b"two"))
+        );
+
+        let sidecar = upload_dir.join("fileMerkle").join("merkle.txt.meta.json");
+        let metadata: FileMetadata =
+            serde_json::from_str(&fs::read_to_string(sidecar).await.unwrap()).unwrap();
+        assert_eq!(metadata.merkle_root, manifest.root);
+    }
+
+    #[tokio::test]
+    async fn test_completed_file_is_replicated() {
+        use crate::replication::ReplicaState;
+
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let replica_dir = temp_dir.path().join("replica");
+
+        let config = ServerConfig {
+            replicate_to: vec![replica_dir.clone()],
+            ..ServerConfig::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+
+        let req = Request::builder()
+            .method("POST")
+            .header("X-File-Id", "fileReplica")
+            .header("X-File-Name", "replica.txt")
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from("Hello, World!")))
+            .unwrap();
+        service.call(req).await.unwrap();
+
+        let sidecar = upload_dir.join("fileReplica").join("replica.txt.meta.json");
+        let mut metadata: FileMetadata;
+        let mut waited = 0;
+        loop {
+            metadata = serde_json::from_str(&fs::read_to_string(&sidecar).await.unwrap()).unwrap();
+            if metadata.replication[0].state != ReplicaState::Pending || waited >= 50 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            waited += 1;
+        }
+
+        assert_eq!(metadata.replication[0].state, ReplicaState::Replicated);
+        let replica = fs::read_to_string(replica_dir.join("fileReplica").join("replica.txt"))
+            .await
+            .unwrap();
+        assert_eq!(replica, "Hello, World!");
+    }
+
+    #[tokio::test]
+    async fn test_purge_needs_confirmation_and_erases_everything() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let replica_dir = temp_dir.path().join("replica");
+        let config = ServerConfig {
+            replicate_to: vec![replica_dir.clone()],
+            ..ServerConfig::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+        let req = Request::builder()
+            .method("POST")
+            .header("X-File-Id", "filePurge")
+            .header("X-File-Name", "purge.txt")
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from("personal data")))
+            .unwrap();
+        service.call(req).await.unwrap();
+        let replica = replica_dir.join("filePurge/purge.txt");
+        for _ in 0..50 {
+            if replica.exists() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(replica.exists());
+
+        let purge = |query: &str| {
+            Request::builder()
+                .method("POST")
+                .uri(format!("/admin/purge?{}", query))
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+        let res = service.call(purge("file_id=filePurge")).await.unwrap();
+        assert_eq!(res.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        let confirmation = body["confirmation"].as_str().unwrap().to_string();
+        assert!(upload_dir.join("filePurge/purge.txt").exists());
+
+        let err = service
+            .call(purge("file_id=filePurge&confirmation=1.AAAA"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+        let err = service
+            .call(purge(&format!(
+                "file_id=other&confirmation={}",
+                confirmation
+            )))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::NotFound(_)));
+
+        let res = service
+            .call(purge(&format!(
+                "file_id=filePurge&confirmation={}",
+                confirmation
+            )))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 204);
+        assert!(!upload_dir.join("filePurge").exists());
+        assert!(!replica.exists());
+        assert!(
+            !upload_dir
+                .join(crate::constants::MANIFEST_DIR)
+                .join("filePurge.json")
+                .exists()
+        );
+        let req = Request::builder()
+            .uri("/files/filePurge/manifest")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        assert!(service.call(req).await.is_err());
+
+        let req = Request::builder()
+            .uri("/admin/audit?file_id=filePurge")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let res = service.call(req).await.unwrap();
+        let body = std::str::from_utf8(res.body()).unwrap();
+        assert!(body.contains(r#""action":"admin_purge","file_id":"filePurge","status":204"#));
+
+        // Chunks left on disk without a session, e.g. by a crash, are purged
+        // too, along with the responses stored for their Idempotency-Key.
+        let chunk = || {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileStray")
+                .header("X-File-Name", "stray.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "2")
+                .header("Idempotency-Key", "stray-0")
+                .body(Full::new(Bytes::from("half")))
+                .unwrap()
+        };
+        assert_eq!(service.call(chunk()).await.unwrap().status(), 200);
+        service.sessions.remove("fileStray");
+        let res = service.call(purge("file_id=fileStray")).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        let res = service
+            .call(purge(&format!(
+                "file_id=fileStray&confirmation={}",
+                body["confirmation"].as_str().unwrap()
+            )))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 204);
+        assert!(!upload_dir.join("fileStray").exists());
+        let res = service.call(chunk()).await.unwrap();
+        assert!(
+            !res.headers()
+                .contains_key(crate::constants::HEADER_IDEMPOTENT_REPLAYED)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_collects_everything_an_owner_uploaded() {
+        use http_body_util::BodyExt;
+        use std::io::Read;
+
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let htpasswd: String = ["alice@example.com", "bob", "ops"]
+            .iter()
+            .map(|user| format!("{}:{}\n", user, bcrypt::hash("pw", 4).unwrap()))
+            .collect();
+        let config = ServerConfig {
+            client_identities: vec!["ops=default:admin".parse().unwrap()],
+            basic_auth: Some(BasicAuthConfig::Htpasswd(Arc::new(
+                htpasswd.parse().unwrap(),
+            ))),
+            ..ServerConfig::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+        let request = |method: &str, uri: &str, user: &str| {
+            use base64::Engine;
+            Request::builder().method(method).uri(uri).header(
+                "Authorization",
+                format!(
+                    "Basic {}",
+                    base64::engine::general_purpose::STANDARD.encode(format!("{}:pw", user))
+                ),
+            )
+        };
+        let upload = |user: &str, file_id: &str, total_chunks: &str, data: &'static str| {
+            request("POST", "/", user)
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", format!("{}.txt", file_id))
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", total_chunks)
+                .body(Full::new(Bytes::from(data)))
+                .unwrap()
+        };
+        service
+            .call(upload("alice@example.com", "fileAlice", "1", "alice's"))
+            .await
+            .unwrap();
+        service
+            .call(upload("alice@example.com", "fileAlicePartial", "2", "half"))
+            .await
+            .unwrap();
+        service
+            .call(upload("bob", "fileBob", "1", "bob's"))
+            .await
+            .unwrap();
+
+        let export = |query: &str, user: &str| {
+            request("GET", &format!("/admin/export?{}", query), user)
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+        let err = service
+            .call(export("owner=bob", "alice@example.com"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+
+        let res = service
+            .call(export("owner=alice%40example.com", "ops"))
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["owner"], "alice@example.com");
+        assert_eq!(body["files"].as_array().unwrap().len(), 1);
+        assert_eq!(body["files"][0]["file_id"], "fileAlice");
+        assert_eq!(body["uploads"].as_array().unwrap().len(), 1);
+        assert_eq!(body["uploads"][0]["file_id"], "fileAlicePartial");
+        assert_eq!(body["uploads"][0]["chunks_received"], 1);
+
+        let res = service
+            .call(export("owner=alice%40example.com&files=true", "ops"))
+            .await
+            .unwrap();
+        assert_eq!(res.headers()["Content-Type"], "application/x-tar");
+        let tar = res.into_body().collect().await.unwrap().to_bytes();
+        let entries = tar::Archive::new(&tar[..])
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents).unwrap();
+                (entry.path().unwrap().display().to_string(), contents)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, "export.json");
+        assert_eq!(
+            entries[1],
+            (
+                "files/fileAlice/fileAlice.txt".to_string(),
+                "alice's".to_string()
+            )
+        );
+
+        let err = service.call(export("files=true", "ops")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::BadRequest(_)));
+    }
+
+    #[tokio::test]
+    async fn test_files_past_retention_are_deleted_or_archived() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let archive_dir = temp_dir.path().join("archive");
+        let config = ServerConfig {
+            retention: vec![
+                "*=1d".parse().unwrap(),
+                "tenant:acme=0s".parse().unwrap(),
+                "category:logs=0s:archive".parse().unwrap(),
+            ],
+            archive_to: Some(archive_dir.clone()),
+            ..ServerConfig::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+        for (file_id, tenant, category) in [
+            ("fileKept", "default", None),
+            ("fileDeleted", "acme", None),
+            ("fileArchived", "acme", Some("logs")),
+        ] {
+            let mut req = Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", "data.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "1")
+                .header("X-Tenant-Id", tenant);
+            if let Some(category) = category {
+                req = req.header("X-Upload-Category", category);
+            }
+            let req = req.body(Full::new(Bytes::from(file_id))).unwrap();
+            assert_eq!(service.call(req).await.unwrap().status(), 201);
+        }
+
+        assert_eq!(service.enforce_retention().await.unwrap(), 2);
+        assert!(upload_dir.join("fileKept/data.txt").exists());
+        assert!(!upload_dir.join("fileDeleted/data.txt").exists());
+        assert!(!upload_dir.join("fileArchived/data.txt").exists());
+        assert!(!archive_dir.join("fileDeleted").exists());
+        assert_eq!(
+            std::fs::read_to_string(archive_dir.join("fileArchived/data.txt")).unwrap(),
+            "fileArchived"
+        );
+        let archived: FileMetadata = serde_json::from_slice(
+            &std::fs::read(archive_dir.join("fileArchived/data.txt.meta.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(archived.category.as_deref(), Some("logs"));
+        let req = Request::builder()
+            .uri("/uploads/fileArchived")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        assert!(matches!(
+            service.call(req).await.unwrap_err(),
+            SliceBreadServerError::NotFound(_)
+        ));
+        assert_eq!(service.enforce_retention().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_immutable_files_are_only_deleted_by_admin() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+
+        let config = ServerConfig {
+            immutable: true,
+            ..ServerConfig::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+
+        let upload = || {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileWorm")
+                .header("X-File-Name", "worm.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "1")
+                .body(Full::new(Bytes::from("Hello, World!")))
+                .unwrap()
+        };
+        let delete = |path: &str| {
+            Request::builder()
+                .method("DELETE")
+                .uri(path)
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+        service.call(upload()).await.unwrap();
+
+        let output = upload_dir.join("fileWorm").join("worm.txt");
+        assert!(
+            fs::metadata(&output)
+                .await
+                .unwrap()
+                .permissions()
+                .readonly()
+        );
+
+        let err = service.call(upload()).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Conflict(_)));
+
+        let err = service.call(delete("/files/fileWorm")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+        assert!(output.exists());
+
+        let res = service.call(delete("/admin/files/fileWorm")).await.unwrap();
+        assert_eq!(res.status(), 204);
+        assert!(!output.exists());
+        assert!(
+            !upload_dir
+                .join("fileWorm")
+                .join("worm.txt.meta.json")
+                .exists()
+        );
+
+        let err = service
+            .call(delete("/admin/files/fileWorm"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn test_delete_completed_file() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let req = Request::builder()
+            .method("POST")
+            .header("X-File-Id", "fileDelete")
+            .header("X-File-Name", "delete.txt")
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from("bye")))
+            .unwrap();
+        service.call(req).await.unwrap();
+
+        let req = Request::builder()
+            .method("DELETE")
+            .uri("/files/fileDelete")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let res = service.call(req).await.unwrap();
+        assert_eq!(res.status(), 204);
+        assert!(!upload_dir.join("fileDelete").exists());
+        assert_eq!(service.sessions.stats().total.bytes_stored, 0);
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_audited_and_exported() {
+        use crate::audit::AuditEntry;
+
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string())
+                .for_connection("10.1.2.3".parse().unwrap());
+
+        let req = Request::builder()
+            .method("POST")
+            .header("X-File-Id", "fileAudit")
+            .header("X-File-Name", "audit.txt")
+            .header("X-Tenant-Id", "acme")
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from("audited")))
+            .unwrap();
+        service.call(req).await.unwrap();
+
+        let req = Request::builder()
+            .method("DELETE")
+            .uri("/files/missing")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        service.call(req).await.unwrap_err();
+
+        let req = Request::builder()
+            .uri("/admin/audit?file_id=fileAudit")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let res = service.call(req).await.unwrap();
+        let entries: Vec<AuditEntry> = std::str::from_utf8(res.body())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].actor, "acme");
+        assert_eq!(entries[0].action, "upload_chunk");
+        assert_eq!(entries[0].status, 201);
+        assert_eq!(entries[0].client_ip, Some("10.1.2.3".parse().unwrap()));
+
+        let req = Request::builder()
+            .uri("/admin/audit")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let res = service.call(req).await.unwrap();
+        let actions: Vec<String> = std::str::from_utf8(res.body())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<AuditEntry>(line).unwrap().action)
+            .collect();
+        assert_eq!(actions, ["upload_chunk", "delete", "admin_audit"]);
+    }
+
+    #[tokio::test]
+    async fn test_ip_filter_refuses_clients_before_reading_the_body() {
+        use crate::ipfilter::IpFilter;
+
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let config = ServerConfig {
+            ip_filter: IpFilter {
+                allow: vec!["10.0.0.0/8".parse().unwrap()],
+                deny: Vec::new(),
+                trusted_proxies: vec!["127.0.0.1".parse().unwrap()],
+            },
+            ..ServerConfig::default()
+        };
+        let server = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+
+        let upload = |forwarded_for: &str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileFiltered")
+                .header("X-File-Name", "filtered.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "1")
+                .header("X-Forwarded-For", forwarded_for)
+                .body(Full::new(Bytes::from("hi")))
+                .unwrap()
+        };
+
+        let outsider = server.for_connection("192.168.1.5".parse().unwrap());
+        let err = outsider.call(upload("10.0.0.1")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+        assert!(!upload_dir.join("fileFiltered").exists());
+
+        let proxy = server.for_connection("127.0.0.1".parse().unwrap());
+        let err = proxy.call(upload("192.168.1.5")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+        let res = proxy.call(upload("10.0.0.1")).await.unwrap();
+        assert_eq!(res.status(), 201);
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate_identities_bind_tenant_and_permissions() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let config = ServerConfig {
+            client_identities: vec![
+                "render-01.internal=acme".parse().unwrap(),
+                "ops.internal=ops:admin".parse().unwrap(),
+                "reader.internal=acme:download".parse().unwrap(),
+            ],
+            ..ServerConfig::default()
+        };
+        let server = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+        let connection = |identity: &str| {
+            server
+                .for_connection("127.0.0.1".parse().unwrap())
+                .with_client_identities(vec![identity.to_string()])
+        };
+        let upload = |tenant: Option<&str>| {
+            let mut req = Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileMtls")
+                .header("X-File-Name", "mtls.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "1")
+                .header("X-Shared-With", "reader.internal");
+            if let Some(tenant) = tenant {
+                req = req.header("X-Tenant-Id", tenant);
+            }
+            req.body(Full::new(Bytes::from("hi"))).unwrap()
+        };
+        let stats = || {
+            Request::builder()
+                .uri("/admin/stats")
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+
+        let err = connection("stranger").call(upload(None)).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+
+        let render = connection("render-01.internal");
+        let err = render.call(upload(Some("ops"))).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+        let err = render.call(stats()).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+
+        let res = render.call(upload(None)).await.unwrap();
+        assert_eq!(res.status(), 201);
+
+        let res = connection("ops.internal").call(stats()).await.unwrap();
+        let stats: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(stats["tenants"]["acme"]["uploads_completed"], 1);
+
+        let reader = connection("reader.internal");
+        let err = reader.call(upload(None)).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+        let manifest = Request::builder()
+            .uri("/files/fileMtls/manifest")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        assert_eq!(reader.call(manifest).await.unwrap().status(), 200);
+        let delete = Request::builder()
+            .method("DELETE")
+            .uri("/files/fileMtls")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let err = reader.call(delete).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+    }
+
+    #[tokio::test]
+    async fn test_bearer_tokens_are_introspected_and_cached() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use http_body_util::BodyExt;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/introspect", listener.local_addr().unwrap());
+        let endpoint_calls = calls.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let calls = endpoint_calls.clone();
+                let service = hyper::service::service_fn(
+                    move |req: Request<hyper::body::Incoming>| {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        async move {
+                            assert_eq!(
+                                req.headers()["authorization"],
+                                "Basic c2xpY2VkOnMzY3JldA=="
+                            );
+                            let form = req.into_body().collect().await.unwrap().to_bytes();
+                            let token = form_urlencoded::parse(&form)
+                                .find(|(key, _)| key == "token")
+                                .map(|(_, token)| token.into_owned());
+                            let claims = match token.as_deref() {
+                                Some("uploader-token") => serde_json::json!({
+                                    "active": true, "sub": "ingest", "scope": "upload", "tenant": "acme",
+                                }),
+                                Some("reader-token") => serde_json::json!({
+                                    "active": true, "sub": "viewer", "scope": "download", "tenant": "acme",
+                                }),
+                                _ => serde_json::json!({ "active": false }),
+                            };
+                            Ok::<_, std::convert::Infallible>(hyper::Response::new(Full::new(
+                                Bytes::from(claims.to_string()),
+                            )))
+                        }
+                    },
+                );
+                tokio::spawn(
+                    hyper::server::conn::http1::Builder::new()
+                        .serve_connection(hyper_util::rt::TokioIo::new(stream), service),
+                );
+            }
+        });
+
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let config = ServerConfig {
+            introspection: Some(IntrospectionConfig {
+                url: url.parse().unwrap(),
+                client_id: Some("sliced".to_string()),
+                client_secret: Some("s3cret".to_string()),
+                cache_ttl: std::time::Duration::from_secs(60),
+                tenant_claim: "tenant".to_string(),
+                tls: Arc::new(crate::tls::load_client_config(None).unwrap()),
+            }),
+            ..ServerConfig::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+        let upload = |token: Option<&str>| {
+            let mut req = Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileBearer")
+                .header("X-File-Name", "bearer.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "1")
+                .header("X-Shared-With", "viewer");
+            if let Some(token) = token {
+                req = req.header("Authorization", format!("Bearer {}", token));
+            }
+            req.body(Full::new(Bytes::from("hi"))).unwrap()
+        };
+
+        let err = service.call(upload(None)).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Unauthorized { .. }));
+        let err = service.call(upload(Some("revoked"))).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Unauthorized { .. }));
+        let err = service
+            .call(upload(Some("reader-token")))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+
+        let res = service.call(upload(Some("uploader-token"))).await.unwrap();
+        assert_eq!(res.status(), 201);
+        let mut other_tenant = upload(Some("uploader-token"));
+        other_tenant
+            .headers_mut()
+            .insert("X-Tenant-Id", "ops".parse().unwrap());
+        let err = service.call(other_tenant).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+
+        let manifest = Request::builder()
+            .uri("/files/fileBearer/manifest")
+            .header("Authorization", "Bearer reader-token")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        assert_eq!(service.call(manifest).await.unwrap().status(), 200);
+        let err = service.call(upload(Some("revoked"))).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Unauthorized { .. }));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth_users_map_to_tenants() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let htpasswd = format!(
+            "alice:{}\nops:{}\n",
+            bcrypt::hash("alice-pw", 4).unwrap(),
+            bcrypt::hash("ops-pw", 4).unwrap()
+        );
+        let config = ServerConfig {
+            client_identities: vec!["ops=ops:admin".parse().unwrap()],
+            basic_auth: Some(BasicAuthConfig::Htpasswd(Arc::new(
+                htpasswd.parse().unwrap(),
+            ))),
+            ..ServerConfig::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+        let basic = |credentials: &str| {
+            use base64::Engine;
+            format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(credentials)
+            )
+        };
+        let stats = |credentials: &str| {
+            Request::builder()
+                .uri("/admin/stats")
+                .header("Authorization", basic(credentials))
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+
+        let upload = Request::builder()
+            .method("POST")
+            .header("X-File-Id", "fileBasic")
+            .header("X-File-Name", "basic.txt")
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1");
+        let err = service
+            .call(upload.body(Full::new(Bytes::from("hi"))).unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.into_response().headers()["www-authenticate"],
+            "Basic realm=\"slicebread\""
+        );
+        let err = service.call(stats("ops:wrong")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Unauthorized { .. }));
+
+        let err = service.call(stats("alice:alice-pw")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+        let res = service.call(stats("ops:ops-pw")).await.unwrap();
+        assert_eq!(res.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_only_owners_and_shares_reach_files() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let htpasswd: String = ["alice", "bob", "carol", "ops"]
+            .iter()
+            .map(|user| format!("{}:{}\n", user, bcrypt::hash(user, 4).unwrap()))
+            .collect();
+        let config = ServerConfig {
+            client_identities: vec!["ops=default:admin+delete".parse().unwrap()],
+            basic_auth: Some(BasicAuthConfig::Htpasswd(Arc::new(
+                htpasswd.parse().unwrap(),
+            ))),
+            ..ServerConfig::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+        let request = |method: &str, uri: &str, user: &str| {
+            use base64::Engine;
+            Request::builder().method(method).uri(uri).header(
+                "Authorization",
+                format!(
+                    "Basic {}",
+                    base64::engine::general_purpose::STANDARD.encode(format!("{0}:{0}", user))
+                ),
+            )
+        };
+
+        let upload = request("POST", "/", "alice")
+            .header("X-File-Id", "fileOwned")
+            .header("X-File-Name", "owned.txt")
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .header("X-Shared-With", "carol")
+            .body(Full::new(Bytes::from("mine")))
+            .unwrap();
+        assert_eq!(service.call(upload).await.unwrap().status(), 201);
+
+        for (method, uri) in [
+            ("GET", "/files/fileOwned/manifest"),
+            ("GET", "/uploads/fileOwned"),
+            ("DELETE", "/files/fileOwned"),
+        ] {
+            let req = request(method, uri, "bob")
+                .body(Full::new(Bytes::new()))
+                .unwrap();
+            let err = service.call(req).await.unwrap_err();
+            assert!(
+                matches!(err, SliceBreadServerError::Forbidden(_)),
+                "{}",
+                uri
+            );
+        }
+        let req = request("GET", "/uploads", "bob")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let res = service.call(req).await.unwrap();
+        assert_eq!(res.body(), "[]");
+        let req = request("POST", "/", "bob")
+            .header("X-File-Id", "fileOwned")
+            .header("X-File-Name", "owned.txt")
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from("theirs")))
+            .unwrap();
+        let err = service.call(req).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+
+        let req = request("GET", "/files/fileOwned/manifest", "carol")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        assert_eq!(service.call(req).await.unwrap().status(), 200);
+        let req = request("DELETE", "/files/fileOwned", "carol")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let err = service.call(req).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+
+        let req = request("DELETE", "/files/fileOwned", "ops")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        assert!(service.call(req).await.unwrap().status().is_success());
+    }
+
+    #[tokio::test]
+    async fn test_owners_share_files_with_principals_and_links() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let htpasswd: String = ["alice", "bob"]
+            .iter()
+            .map(|user| format!("{}:{}\n", user, bcrypt::hash(user, 4).unwrap()))
+            .collect();
+        let config = ServerConfig {
+            basic_auth: Some(BasicAuthConfig::Htpasswd(Arc::new(
+                htpasswd.parse().unwrap(),
+            ))),
+            share_link_secret: Some("secret".to_string()),
+            ..ServerConfig::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+        let request = |method: &str, uri: &str, user: Option<&str>| {
+            use base64::Engine;
+            let mut req = Request::builder().method(method).uri(uri);
+            if let Some(user) = user {
+                req = req.header(
+                    "Authorization",
+                    format!(
+                        "Basic {}",
+                        base64::engine::general_purpose::STANDARD.encode(format!("{0}:{0}", user))
+                    ),
+                );
+            }
+            req
+        };
+        let share = |user: &str, body: &str| {
+            request("POST", "/files/fileShared/shares", Some(user))
+                .body(Full::new(Bytes::from(body.to_string())))
+                .unwrap()
+        };
+
+        let upload = request("POST", "/", Some("alice"))
+            .header("X-File-Id", "fileShared")
+            .header("X-File-Name", "shared.txt")
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from("shared")))
+            .unwrap();
+        assert_eq!(service.call(upload).await.unwrap().status(), 201);
+
+        let err = service
+            .call(share("bob", r#"{"principal": "bob"}"#))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+        let res = service
+            .call(share(
+                "alice",
+                r#"{"principal": "bob", "expires_in_secs": 3600}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 201);
+        let created: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(created["access"], "read");
+        assert!(created.get("token").is_none());
+
+        let manifest = |user: Option<&str>, query: &str| {
+            request("GET", &format!("/files/fileShared/manifest{}", query), user)
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+        assert_eq!(
+            service
+                .call(manifest(Some("bob"), ""))
+                .await
+                .unwrap()
+                .status(),
+            200
+        );
+        let delete = |user: Option<&str>, query: &str| {
+            request("DELETE", &format!("/files/fileShared{}", query), user)
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+        let err = service.call(delete(Some("bob"), "")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+
+        let res = service.call(share("alice", "{}")).await.unwrap();
+        let created: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        let token = created["token"].as_str().unwrap();
+        let err = service.call(manifest(None, "")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Unauthorized { .. }));
+        let res = service
+            .call(manifest(None, &format!("?share={}", token)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let err = service
+            .call(manifest(None, &format!("?share={}x", token)))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+        let err = service
+            .call(delete(None, &format!("?share={}", token)))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+
+        let sidecar = fs::read(upload_dir.join("fileShared/shared.txt.meta.json"))
+            .await
+            .unwrap();
+        let metadata: FileMetadata = serde_json::from_slice(&sidecar).unwrap();
+        assert_eq!(metadata.shares.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_handler_panic_becomes_internal_server_error() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let config = ServerConfig {
+            chaos: Some(ChaosConfig {
+                p_panic: 1.0,
+                ..ChaosConfig::default()
+            }),
+            ..ServerConfig::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+
+        let req = Request::builder()
+            .method("POST")
+            .header("X-File-Id", "filePanic")
+            .header("X-File-Name", "panic.txt")
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from("boom")))
+            .unwrap();
+        let err = service.call(req).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::InternalServerError(_)));
+
+        let req = Request::builder()
+            .uri("/admin/stats")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let res = service.call(req).await.unwrap();
+        assert_eq!(res.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_upload_status_reports_bytes() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let status = || {
+            Request::builder()
+                .uri("/uploads/fileStatus")
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+        let chunk = |index: usize, data: &'static str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileStatus")
+                .header("X-File-Name", "status.txt")
+                .header("X-File-Size", "12")
+                .header("X-Chunk-Index", index.to_string())
+                .header("X-Total-Chunks", "2")
+                .body(Full::new(Bytes::from(data)))
+                .unwrap()
+        };
+
+        let err = service.call(status()).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::NotFound(_)));
+
+        service.call(chunk(0, "0123456789")).await.unwrap();
+        let res = service.call(status()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["state"], "uploading");
+        assert_eq!(body["chunks_received"], 1);
+        assert_eq!(body["bytes_received"], 10);
+        assert_eq!(body["bytes_total"], 12);
+        assert_eq!(body["bytes_total_estimated"], false);
+
+        service.call(chunk(1, "ab")).await.unwrap();
+        let res = service.call(status()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["state"], "completed");
+        assert_eq!(body["bytes_received"], 12);
+        assert_eq!(body["total_chunks"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_parallel_sources_upload_disjoint_shards() {
+        use futures_util::future::join_all;
+
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service = Arc::new(SliceBreadServer::<Full<Bytes>>::new(
+            upload_dir.to_str().unwrap().to_string(),
+        ));
+
+        let total = 12;
+        let chunk = |index: usize, data: String| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileShards")
+                .header("X-File-Name", "shards.txt")
+                .header("X-Chunk-Index", index.to_string())
+                .header("X-Total-Chunks", total.to_string())
+                .body(Full::new(Bytes::from(data)))
+                .unwrap()
+        };
+
+        // Three machines, each sending its own range, the last one in reverse order.
+        // Chunk 0 is held back so the overlap check below happens before assembly.
+        let shards = [
+            (1..4).collect::<Vec<_>>(),
+            (4..8).collect(),
+            (8..12).rev().collect(),
+        ];
+        let uploads = shards.into_iter().map(|shard| {
+            let service = Arc::clone(&service);
+            async move {
+                for index in shard {
+                    let res = service.call(chunk(index, format!("{:02}", index))).await;
+                    assert_eq!(res.unwrap().status(), 200);
+                    tokio::task::yield_now().await;
+                }
+            }
+        });
+        join_all(uploads).await;
+        let final_path = upload_dir.join("fileShards").join("shards.txt");
+        assert!(!final_path.exists());
+
+        // An overlapping index with different content from another source is refused.
+        let err = service.call(chunk(5, "xx".to_string())).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Conflict(_)));
+
+        service.call(chunk(0, "00".to_string())).await.unwrap();
+        let content = fs::read_to_string(final_path).await.unwrap();
+        assert_eq!(content, "000102030405060708091011");
+    }
+
+    #[tokio::test]
+    async fn test_byte_ranges_are_assembled_by_offset() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let range = |offset: u64, data: &'static str| {
+            Request::builder()
+                .method("PUT")
+                .uri("/uploads/fileRanges")
+                .header("X-File-Name", "ranges.txt")
+                .header("X-File-Size", "16")
+                .header("X-Range-Offset", offset.to_string())
+                .body(Full::new(Bytes::from(data)))
+                .unwrap()
+        };
+
+        let err = service.call(range(12, "tail!")).await.unwrap_err();
+        assert!(matches!(
+            err,
+            SliceBreadServerError::RangeOutOfBounds { end: 17, .. }
+        ));
+
+        // Variable-size pieces, out of order and partly overlapping.
+        for (offset, data) in [(10, "klmnop"), (0, "abc"), (2, "cdefg"), (7, "hij")] {
+            let res = service.call(range(offset, data)).await.unwrap();
+            assert_eq!(res.status(), if offset == 7 { 201 } else { 200 });
+        }
+
+        let final_path = upload_dir.join("fileRanges").join("ranges.txt");
+        let content = fs::read_to_string(&final_path).await.unwrap();
+        assert_eq!(content, "abcdefghijklmnop");
+        assert!(!upload_dir.join("fileRanges").join("ranges.bin").exists());
+
+        let sidecar = fs::read_to_string(upload_dir.join("fileRanges/ranges.txt.meta.json"))
+            .await
+            .unwrap();
+        let metadata: FileMetadata = serde_json::from_str(&sidecar).unwrap();
+        assert_eq!(metadata.size, 16);
+        assert_eq!(
+            metadata.sha256,
+            checksum::to_hex(&checksum::sha256(b"abcdefghijklmnop"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delta_upload_patches_stored_file() {
+        use crate::delta;
+
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            ServerConfig {
+                tenant_quota_bytes: Some(30),
+                ..Default::default()
+            },
+        );
+
+        let original = "aaaabbbbccccddddeeee";
+        let req = Request::builder()
+            .method("POST")
+            .header("X-File-Id", "fileDelta")
+            .header("X-File-Name", "delta.txt")
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from(original)))
+            .unwrap();
+        service.call(req).await.unwrap();
+
+        let req = Request::builder()
+            .uri("/files/fileDelta/signature?block_size=4")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let res = service.call(req).await.unwrap();
+        let signature: delta::Signature = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(signature.blocks.len(), 5);
+
+        let updated = "aaaabbbbXXccccddddeeeeff";
+        let ops = delta::diff(&signature, updated.as_bytes());
+        let req = Request::builder()
+            .method("POST")
+            .uri("/files/fileDelta/delta")
+            .header("X-Block-Size", "4")
+            .header(
+                "Repr-Digest",
+                format!(
+                    "sha-256=:{}:",
+                    base64::Engine::encode(
+                        &base64::engine::general_purpose::STANDARD,
+                        checksum::sha256(updated.as_bytes())
+                    )
+                ),
+            )
+            .body(Full::new(delta::encode(&ops)))
+            .unwrap();
+        let res = service.call(req).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["bytes_reused"], 20);
+        assert_eq!(body["bytes_received"], 4);
+
+        let final_path = upload_dir.join("fileDelta").join("delta.txt");
+        assert_eq!(fs::read_to_string(&final_path).await.unwrap(), updated);
+        let sidecar = fs::read_to_string(upload_dir.join("fileDelta/delta.txt.meta.json"))
+            .await
+            .unwrap();
+        let metadata: FileMetadata = serde_json::from_str(&sidecar).unwrap();
+        assert_eq!(metadata.size, 24);
+        assert_eq!(metadata.file_name, "delta.txt");
+
+        let req = Request::builder()
+            .method("POST")
+            .uri("/files/fileDelta/delta")
+            .header("X-Block-Size", "4")
+            .body(Full::new(delta::encode(&[delta::Op::Copy(9)])))
+            .unwrap();
+        let err = service.call(req).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::BadRequest(_)));
+        assert_eq!(fs::read_to_string(&final_path).await.unwrap(), updated);
+
+        // Usage follows the file's new size, and growing past the quota is refused.
+        let quota = || {
+            Request::builder()
+                .uri("/quota")
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+        let res = service.call(quota()).await.unwrap();
+        assert_eq!(res.headers()["X-Quota-Used"], "24");
+        let grow = |tenant: &str| {
+            Request::builder()
+                .method("POST")
+                .uri("/files/fileDelta/delta")
+                .header("X-Block-Size", "4")
+                .header("X-Tenant-Id", tenant)
+                .body(Full::new(delta::encode(&[
+                    delta::Op::Copy(0),
+                    delta::Op::Literal(Bytes::from("0123456789abcdefghijklmnopqrstuvwxyz")),
+                ])))
+                .unwrap()
+        };
+        let err = service
+            .call(grow(crate::constants::DEFAULT_TENANT))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SliceBreadServerError::InsufficientStorage { used: 24, .. }
+        ));
+        assert_eq!(fs::read_to_string(&final_path).await.unwrap(), updated);
+
+        // Another tenant can't patch, or take over, the file.
+        let err = service.call(grow("globex")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::NotFound(_)));
+        assert_eq!(fs::read_to_string(&final_path).await.unwrap(), updated);
+        let metadata = crate::sidecar::read(&final_path).await.unwrap();
+        assert_eq!(metadata.uploader, crate::constants::DEFAULT_TENANT);
+        let res = service.call(quota()).await.unwrap();
+        assert_eq!(res.headers()["X-Quota-Used"], "24");
+    }
+
+    #[tokio::test]
+    async fn test_chunks_can_be_downloaded_before_and_after_assembly() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let chunks: [&'static [u8]; 3] = [b"\x00\xff\xfe", b"\x80binary", b"tail"];
+        let chunk = |index: usize| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileStream")
+                .header("X-File-Name", "stream.bin")
+                .header("X-Chunk-Index", index.to_string())
+                .header("X-Total-Chunks", "3")
+                .body(Full::new(Bytes::from_static(chunks[index])))
+                .unwrap()
+        };
+        let download = |index: usize| {
+            Request::builder()
+                .uri(format!("/files/fileStream/chunks/{}", index))
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+
+        service.call(chunk(0)).await.unwrap();
+        let res = service.call(download(0)).await.unwrap();
+        assert_eq!(&res.body()[..], chunks[0]);
+        let err = service.call(download(1)).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::NotFound(_)));
+
+        service.call(chunk(2)).await.unwrap();
+        service.call(chunk(1)).await.unwrap();
+        assert!(upload_dir.join("fileStream").join("stream.bin").exists());
+
+        for (index, data) in chunks.iter().enumerate() {
+            let res = service.call(download(index)).await.unwrap();
+            assert_eq!(&res.body()[..], *data);
+            assert_eq!(
+                res.headers()["X-Chunk-Sha256"],
+                checksum::to_hex(&checksum::sha256(data)).as_str()
+            );
+        }
+        let err = service.call(download(3)).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn test_uploads_are_listed() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let chunk = |file_id: &str, total: usize| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", "list.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", total.to_string())
+                .body(Full::new(Bytes::from("data")))
+                .unwrap()
+        };
+        service.call(chunk("fileDone", 1)).await.unwrap();
+        service.call(chunk("fileOpen", 2)).await.unwrap();
+
+        let req = Request::builder()
+            .uri("/uploads")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let res = service.call(req).await.unwrap();
+        let uploads: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(uploads[0]["file_id"], "fileOpen");
+        assert_eq!(uploads[0]["state"], "uploading");
+        assert_eq!(uploads[1]["file_id"], "fileDone");
+        assert_eq!(uploads[1]["state"], "completed");
+        assert_eq!(uploads[1]["bytes_received"], 4);
+    }
+
+    #[tokio::test]
+    async fn test_uploads_are_tagged_and_filtered_by_tag() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let chunk = |file_id: &str, chunk_index: &str, tags: &str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", "tagged.txt")
+                .header("X-Chunk-Index", chunk_index)
+                .header("X-Total-Chunks", "2")
+                .header("X-Upload-Tags", tags)
+                .body(Full::new(Bytes::from("data")))
+                .unwrap()
+        };
+        service
+            .call(chunk("fileProd", "0", "env=prod, team=data"))
+            .await
+            .unwrap();
+        service
+            .call(chunk("fileProd", "1", "env=prod,team=data"))
+            .await
+            .unwrap();
+        service
+            .call(chunk("fileDev", "0", "env=dev"))
+            .await
+            .unwrap();
+        let err = service
+            .call(chunk("fileDev", "1", "env=prod"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Conflict(_)));
+        let err = service
+            .call(chunk("fileBad", "0", "env"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::InvalidHeader(_)));
+
+        let metadata = crate::sidecar::read(&upload_dir.join("fileProd/tagged.txt"))
+            .await
+            .unwrap();
+        assert_eq!(metadata.tags["team"], "data");
+
+        let list = |query: &str| {
+            Request::builder()
+                .uri(format!("/uploads?{}", query))
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+        let res = service.call(list("tag=env%3Aprod&tag=team")).await.unwrap();
+        let uploads: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(uploads.as_array().unwrap().len(), 1);
+        assert_eq!(uploads[0]["file_id"], "fileProd");
+        assert_eq!(uploads[0]["tags"]["env"], "prod");
+        let res = service.call(list("tag=env:dev")).await.unwrap();
+        let uploads: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(uploads.as_array().unwrap().len(), 1);
+        assert_eq!(uploads[0]["state"], "uploading");
+        assert_eq!(uploads[0]["tags"]["env"], "dev");
+        let res = service.call(list("tag=env:qa")).await.unwrap();
+        assert_eq!(res.body(), "[]");
+    }
 
-        let file_id = "test12321";
-        let file_name = "hello.txt";
+    #[tokio::test]
+    async fn test_user_metadata_is_kept_and_returned() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
 
-        // Upload chunk 0
-        let req0 = Request::builder()
+        let chunk = |file_id: &str, chunk_index: &str, project: &str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", "meta.txt")
+                .header("X-Chunk-Index", chunk_index)
+                .header("X-Total-Chunks", "2")
+                .header("X-Meta-Project", project)
+                .body(Full::new(Bytes::from("data")))
+                .unwrap()
+        };
+        service
+            .call(chunk("fileMeta", "0", "apollo"))
+            .await
+            .unwrap();
+        let res = service
+            .call(
+                Request::builder()
+                    .uri("/uploads/fileMeta")
+                    .body(Full::new(Bytes::new()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(status["user_metadata"]["project"], "apollo");
+        let err = service
+            .call(chunk("fileMeta", "1", "gemini"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Conflict(_)));
+        service
+            .call(chunk("fileMeta", "1", "apollo"))
+            .await
+            .unwrap();
+
+        let metadata = crate::sidecar::read(&upload_dir.join("fileMeta/meta.txt"))
+            .await
+            .unwrap();
+        assert_eq!(metadata.user_metadata["project"], "apollo");
+
+        let res = service
+            .call(
+                Request::builder()
+                    .method("HEAD")
+                    .uri("/files/fileMeta")
+                    .body(Full::new(Bytes::new()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.headers()["X-Meta-Project"], "apollo");
+    }
+
+    #[tokio::test]
+    async fn test_upload_policy_is_enforced() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let config = ServerConfig {
+            upload_policy_secret: Some("secret".to_string()),
+            ..ServerConfig::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+
+        let policy = UploadPolicy {
+            expires: chrono::Utc::now() + chrono::Duration::hours(1),
+            max_size: Some(8),
+            content_types: vec!["text/*".to_string()],
+            file_id: None,
+            tenant: None,
+            max_duration_secs: None,
+            size: None,
+            sha256: None,
+            nonce: None,
+        };
+        let chunk =
+            |file_id: &str, content_type: &str, data: &'static str, policy: Option<String>| {
+                let mut req = Request::builder()
+                    .method("POST")
+                    .header("X-File-Id", file_id)
+                    .header("X-File-Name", "policy.txt")
+                    .header("X-Chunk-Index", "0")
+                    .header("X-Total-Chunks", "1")
+                    .header("Content-Type", content_type);
+                if let Some(policy) = policy {
+                    req = req.header("X-Upload-Policy", policy);
+                }
+                req.body(Full::new(Bytes::from(data))).unwrap()
+            };
+
+        let err = service
+            .call(chunk("filePolicy", "text/plain", "data", None))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+
+        let forged = UploadPolicy {
+            max_size: None,
+            ..policy.clone()
+        }
+        .sign(b"other");
+        let err = service
+            .call(chunk("filePolicy", "text/plain", "data", Some(forged)))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+
+        let token = policy.sign(b"secret");
+        let err = service
+            .call(chunk(
+                "filePolicy",
+                "image/png",
+                "data",
+                Some(token.clone()),
+            ))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+
+        let err = service
+            .call(chunk(
+                "filePolicy",
+                "text/plain",
+                "far too large",
+                Some(token.clone()),
+            ))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::PayloadTooLarge(_)));
+
+        let res = service
+            .call(chunk("filePolicy", "text/plain", "data", Some(token)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 201);
+    }
+
+    #[tokio::test]
+    async fn test_single_use_policy_binds_size_and_digest() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let config = ServerConfig {
+            upload_policy_secret: Some("secret".to_string()),
+            ..ServerConfig::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+
+        let token = UploadPolicy {
+            expires: chrono::Utc::now() + chrono::Duration::hours(1),
+            max_size: None,
+            content_types: Vec::new(),
+            file_id: None,
+            tenant: None,
+            max_duration_secs: None,
+            size: Some(11),
+            sha256: Some(checksum::to_hex(&checksum::sha256(b"Hello world"))),
+            nonce: Some("artifact-1".to_string()),
+        }
+        .sign(b"secret");
+        let chunk = |file_id: &str, generation: &str, chunk_index: &str, data: &'static str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", "artifact.bin")
+                .header("X-Upload-Generation", generation)
+                .header("X-Chunk-Index", chunk_index)
+                .header("X-Total-Chunks", "2")
+                .header("X-Upload-Policy", token.clone())
+                .body(Full::new(Bytes::from(data)))
+                .unwrap()
+        };
+
+        service
+            .call(chunk("fileArtifact", "0", "0", "Hello "))
+            .await
+            .unwrap();
+        let err = service
+            .call(chunk("fileOther", "0", "0", "Hello "))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+        let err = service
+            .call(chunk("fileArtifact", "0", "1", "world, again"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::PayloadTooLarge(_)));
+        let err = service
+            .call(chunk("fileArtifact", "0", "1", "World"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::DigestMismatch(_)));
+        assert!(!upload_dir.join("fileArtifact/artifact.bin").exists());
+
+        // The token still covers a restart of the same upload.
+        for (chunk_index, data) in [("0", "Hello "), ("1", "world")] {
+            service
+                .call(chunk("fileArtifact", "1", chunk_index, data))
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            std::fs::read(upload_dir.join("fileArtifact/artifact.bin")).unwrap(),
+            b"Hello world"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cluster_instances_share_an_upload() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let store: Arc<dyn ClusterStore> = Arc::new(DirStore::new(upload_dir.join(".cluster")));
+        let node = |node_id: &str| {
+            SliceBreadServer::<Full<Bytes>>::with_config(
+                upload_dir.to_str().unwrap().to_string(),
+                ServerConfig {
+                    cluster: Some(ClusterConfig {
+                        node_id: node_id.to_string(),
+                        store: store.clone(),
+                        peers: None,
+                    }),
+                    ..ServerConfig::default()
+                },
+            )
+        };
+        let (a, b) = (node("a"), node("b"));
+
+        let chunk = |file_id: &str, chunk_index: &str, data: &'static str, tags: &str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", "shared.txt")
+                .header("X-Chunk-Index", chunk_index)
+                .header("X-Total-Chunks", "2")
+                .header("X-Upload-Tags", tags)
+                .body(Full::new(Bytes::from(data)))
+                .unwrap()
+        };
+        let status = |file_id: &str| {
+            Request::builder()
+                .uri(format!("/uploads/{}", file_id))
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+
+        a.call(chunk("fileShared", "0", "Hello, ", "env=prod"))
+            .await
+            .unwrap();
+        let res = b
+            .call(
+                Request::builder()
+                    .uri("/uploads")
+                    .body(Full::new(Bytes::new()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body[0]["file_id"], "fileShared");
+        assert_eq!(body[0]["chunks_received"], 1);
+        // b checks the chunk against what a's instance was told.
+        let err = b
+            .call(chunk("fileShared", "1", "world!", "env=dev"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Conflict(_)));
+        b.call(chunk("fileShared", "1", "world!", "env=prod"))
+            .await
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(upload_dir.join("fileShared/shared.txt"))
+                .await
+                .unwrap(),
+            "Hello, world!"
+        );
+        let res = a.call(status("fileShared")).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["state"], "completed");
+        assert_eq!(store.session("fileShared").await.unwrap(), None);
+
+        // Only the holder of the assembly lock assembles; the others point
+        // the client at the status endpoint.
+        assert!(store.lock_assembly("fileLocked", "c").await.unwrap());
+        a.call(chunk("fileLocked", "0", "Hello, ", ""))
+            .await
+            .unwrap();
+        let res = b
+            .call(chunk("fileLocked", "1", "world!", ""))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 202);
+        assert_eq!(res.headers()["location"], "/uploads/fileLocked");
+        assert!(!upload_dir.join("fileLocked/shared.txt").exists());
+        let complete = || {
+            Request::builder()
+                .method("POST")
+                .uri("/uploads/fileLocked/complete")
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+        let res = a.call(complete()).await.unwrap();
+        assert_eq!(res.status(), 202);
+        assert_eq!(res.headers()["location"], "/uploads/fileLocked");
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["state"], "assembling");
+        assert_eq!(body["chunks_received"], 2);
+
+        // Once the lock is free, either instance may assemble.
+        store.unlock_assembly("fileLocked", "c").await.unwrap();
+        let res = a.call(complete()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["state"], "completed");
+        assert!(upload_dir.join("fileLocked/shared.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_cluster_peers_serve_chunks_for_assembly() {
+        use http_body_util::BodyExt;
+
+        use crate::{peer::PeerConfig, tls};
+
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let store: Arc<dyn ClusterStore> = Arc::new(DirStore::new(temp_dir.path().join("cluster")));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let peer_url = format!("http://{}", listener.local_addr().unwrap());
+        let node = |node_id: &str, peers: Vec<hyper::Uri>| {
+            SliceBreadServer::<Full<Bytes>>::with_config(
+                temp_dir.path().join(node_id).to_str().unwrap().to_string(),
+                ServerConfig {
+                    cluster: Some(ClusterConfig {
+                        node_id: node_id.to_string(),
+                        store: store.clone(),
+                        peers: Some(PeerConfig {
+                            peers,
+                            secret: b"cluster secret".to_vec(),
+                            tls: Arc::new(tls::load_client_config(None).unwrap()),
+                        }),
+                    }),
+                    ..ServerConfig::default()
+                },
+            )
+        };
+        let a = node("a", Vec::new());
+        let b = node("b", vec![peer_url.parse().unwrap()]);
+
+        let served = a.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let a = served.clone();
+                let service =
+                    hyper::service::service_fn(move |req: Request<hyper::body::Incoming>| {
+                        let a = a.clone();
+                        async move {
+                            let (parts, body) = req.into_parts();
+                            let body = body.collect().await.unwrap().to_bytes();
+                            a.call(Request::from_parts(parts, Full::new(body))).await
+                        }
+                    });
+                tokio::spawn(
+                    hyper::server::conn::http1::Builder::new()
+                        .serve_connection(hyper_util::rt::TokioIo::new(stream), service),
+                );
+            }
+        });
+
+        let chunk = |chunk_index: &str, data: &'static str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "filePeer")
+                .header("X-File-Name", "peer.txt")
+                .header("X-Chunk-Index", chunk_index)
+                .header("X-Total-Chunks", "2")
+                .body(Full::new(Bytes::from(data)))
+                .unwrap()
+        };
+        a.call(chunk("0", "Hello, ")).await.unwrap();
+        assert!(temp_dir.path().join("a/filePeer/chunk_0.bin").exists());
+
+        // The internal routes only answer signed requests.
+        let unsigned = Request::builder()
+            .uri("/internal/uploads/filePeer/chunks/0")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let err = a.call(unsigned).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Forbidden(_)));
+
+        // b fetches chunk 0 from a to assemble, then has a drop its copy.
+        let res = b.call(chunk("1", "world!")).await.unwrap();
+        assert_eq!(res.status(), 201);
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("b/filePeer/peer.txt"))
+                .await
+                .unwrap(),
+            "Hello, world!"
+        );
+        for _ in 0..100 {
+            if !temp_dir.path().join("a/filePeer").exists() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(!temp_dir.path().join("a/filePeer").exists());
+
+        // Without peers, the internal routes don't exist.
+        let local = SliceBreadServer::<Full<Bytes>>::new(
+            temp_dir.path().join("c").to_str().unwrap().to_string(),
+        );
+        let err = local
+            .call(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/internal/uploads/filePeer")
+                    .body(Full::new(Bytes::new()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn test_read_only_mode_refuses_writes_until_lifted() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let chunk = |chunk_index: &str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileMaintenance")
+                .header("X-File-Name", "maintenance.txt")
+                .header("X-Chunk-Index", chunk_index)
+                .header("X-Total-Chunks", "2")
+                .body(Full::new(Bytes::from("data")))
+                .unwrap()
+        };
+        let maintenance = |body: &'static str| {
+            Request::builder()
+                .method("PUT")
+                .uri("/admin/maintenance")
+                .body(Full::new(Bytes::from(body)))
+                .unwrap()
+        };
+        service.call(chunk("0")).await.unwrap();
+
+        let res = service
+            .call(maintenance(r#"{"read_only":true,"retry_after":120}"#))
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["read_only"], true);
+        let err = service.call(chunk("1")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::ReadOnly { .. }));
+        let res = err.into_response();
+        assert_eq!(res.status(), 503);
+        assert_eq!(res.headers()["retry-after"], "120");
+
+        // Reads still work.
+        let res = service
+            .call(
+                Request::builder()
+                    .uri("/uploads/fileMaintenance")
+                    .body(Full::new(Bytes::new()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["chunks_received"], 1);
+        let res = service
+            .call(
+                Request::builder()
+                    .uri("/files/fileMaintenance/chunks/0")
+                    .body(Full::new(Bytes::new()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.body().as_ref(), b"data");
+
+        service
+            .call(maintenance(r#"{"read_only":false}"#))
+            .await
+            .unwrap();
+        let res = service.call(chunk("1")).await.unwrap();
+        assert_eq!(res.status(), 201);
+    }
+
+    #[tokio::test]
+    async fn test_json_responses_are_compressed_when_accepted() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            ServerConfig {
+                compress_responses: true,
+                ..ServerConfig::default()
+            },
+        );
+
+        // Enough uploads in flight for the listing to be worth compressing.
+        let json = format!("[{}0]", "1,".repeat(1024));
+        for i in 0..20 {
+            let req = Request::builder()
+                .method("POST")
+                .header("X-File-Id", format!("fileCompressed{}", i))
+                .header("X-File-Name", "listing.json")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "2")
+                .body(Full::new(Bytes::from(json.clone())))
+                .unwrap();
+            service.call(req).await.unwrap();
+        }
+        let get = |uri: &str, accept: &str| {
+            Request::builder()
+                .uri(uri)
+                .header("Accept-Encoding", accept)
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+
+        let res = service.call(get("/uploads", "gzip, zstd")).await.unwrap();
+        assert_eq!(res.headers()["content-encoding"], "zstd");
+        assert_eq!(res.headers()["vary"], "accept-encoding");
+        let listing: serde_json::Value =
+            serde_json::from_slice(&zstd::decode_all(res.body().as_ref()).unwrap()).unwrap();
+        assert_eq!(listing.as_array().unwrap().len(), 20);
+
+        let res = service.call(get("/uploads", "identity")).await.unwrap();
+        assert!(!res.headers().contains_key("content-encoding"));
+        serde_json::from_slice::<serde_json::Value>(res.body()).unwrap();
+
+        // Stored contents go out as they were uploaded, even if they are JSON.
+        let res = service
+            .call(get("/files/fileCompressed0/chunks/0", "gzip"))
+            .await
+            .unwrap();
+        assert!(!res.headers().contains_key("content-encoding"));
+        assert_eq!(res.body().as_ref(), json.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_uploads_in_progress_survive_a_restart() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let chunk = |chunk_index: &str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileWarm")
+                .header("X-File-Name", "warm.txt")
+                .header("X-Chunk-Index", chunk_index)
+                .header("X-Total-Chunks", "3")
+                .body(Full::new(Bytes::from(format!("part{}", chunk_index))))
+                .unwrap()
+        };
+        let status = || {
+            Request::builder()
+                .uri("/uploads/fileWarm")
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+        service.call(chunk("0")).await.unwrap();
+        service.call(chunk("2")).await.unwrap();
+        assert_eq!(service.shutdown().await.unwrap(), 1);
+        let started_at = service.sessions.started_at("fileWarm");
+
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+        assert_eq!(service.restore_sessions().await.unwrap(), 1);
+        assert!(
+            !upload_dir
+                .join(crate::constants::SESSION_SNAPSHOT_FILE)
+                .exists()
+        );
+        assert_eq!(service.sessions.started_at("fileWarm"), started_at);
+        let res = service.call(status()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["chunks_received"], 2);
+        assert_eq!(body["bytes_received"], 10);
+        assert_eq!(service.sessions.stats().total.bytes_stored, 10);
+
+        let res = service.call(chunk("2")).await.unwrap();
+        assert_eq!(res.headers()["x-chunk-already-present"], "true");
+        let res = service.call(chunk("1")).await.unwrap();
+        assert_eq!(res.status(), 201);
+        assert_eq!(
+            fs::read(upload_dir.join("fileWarm/warm.txt"))
+                .await
+                .unwrap(),
+            b"part0part1part2"
+        );
+
+        // Nothing to restore without a snapshot.
+        assert_eq!(service.restore_sessions().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_chunk_journal_restores_uploads_after_a_crash() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let chunk = |chunk_index: &str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileCrash")
+                .header("X-File-Name", "crash.txt")
+                .header("X-Chunk-Index", chunk_index)
+                .header("X-Total-Chunks", "3")
+                .body(Full::new(Bytes::from(format!("part{}", chunk_index))))
+                .unwrap()
+        };
+        let restart = || async {
+            let service =
+                SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+            let restored = service.restore_sessions().await.unwrap();
+            (service, restored)
+        };
+
+        let (service, restored) = restart().await;
+        assert_eq!(restored, 0);
+        service.call(chunk("0")).await.unwrap();
+        service.call(chunk("2")).await.unwrap();
+        let res = service.call(chunk("2")).await.unwrap();
+        assert_eq!(res.headers()["x-chunk-already-present"], "true");
+        // Killed without a shutdown, so there is no snapshot.
+        drop(service);
+
+        let (service, restored) = restart().await;
+        assert_eq!(restored, 1);
+        let progress = service.sessions.progress("fileCrash").unwrap();
+        assert_eq!(progress.chunks_received, 2);
+        assert_eq!(progress.bytes_received, 10);
+        assert_eq!(service.sessions.stats().total.bytes_stored, 10);
+        let res = service.call(chunk("2")).await.unwrap();
+        assert_eq!(res.headers()["x-chunk-already-present"], "true");
+        drop(service);
+
+        // Restored uploads are journaled again, until they complete.
+        let (service, restored) = restart().await;
+        assert_eq!(restored, 1);
+        let res = service.call(chunk("1")).await.unwrap();
+        assert_eq!(res.status(), 201);
+        assert_eq!(
+            fs::read(upload_dir.join("fileCrash/crash.txt"))
+                .await
+                .unwrap(),
+            b"part0part1part2"
+        );
+        // The sweep compacts the journal down to the uploads still in flight.
+        let journal_path = upload_dir.join(crate::constants::CHUNK_JOURNAL_FILE);
+        assert!(fs::metadata(&journal_path).await.unwrap().len() > 0);
+        service.expire_overdue().await.unwrap();
+        service.sessions.journal_flushed().await;
+        assert_eq!(fs::metadata(&journal_path).await.unwrap().len(), 0);
+        drop(service);
+
+        let (_, restored) = restart().await;
+        assert_eq!(restored, 0);
+    }
+
+    #[tokio::test]
+    async fn test_debug_logging_is_toggled_through_the_admin_api() {
+        let service = SliceBreadServer::<Full<Bytes>>::new(String::from("uploads"));
+        let set = |body: &'static str| {
+            Request::builder()
+                .method("PUT")
+                .uri("/admin/debug-log")
+                .body(Full::new(Bytes::from(body)))
+                .unwrap()
+        };
+        assert_eq!(
+            service.debug_log.body_limit(Some("fileDebug"), "acme"),
+            None
+        );
+
+        let res = service
+            .call(set(r#"{"file_ids":["fileDebug"],"max_body_bytes":256}"#))
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["file_ids"], serde_json::json!(["fileDebug"]));
+        assert_eq!(
+            service.debug_log.body_limit(Some("fileDebug"), "acme"),
+            Some(256)
+        );
+        let res = service
+            .call(
+                Request::builder()
+                    .uri("/admin/debug-log")
+                    .body(Full::new(Bytes::new()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["max_body_bytes"], 256);
+
+        let err = service.call(set("file_ids=fileDebug")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::BadRequest(_)));
+        service.call(set("{}")).await.unwrap();
+        assert_eq!(
+            service.debug_log.body_limit(Some("fileDebug"), "acme"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alerts_are_posted_to_webhooks_when_they_change() {
+        use http_body_util::BodyExt;
+
+        use crate::alerts::{AlertConfig, AlertRules};
+
+        let (sent, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hooks/alerts", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let sent = sent.clone();
+                let service =
+                    hyper::service::service_fn(move |req: Request<hyper::body::Incoming>| {
+                        let sent = sent.clone();
+                        async move {
+                            assert_eq!(req.uri().path(), "/hooks/alerts");
+                            let body = req.into_body().collect().await.unwrap().to_bytes();
+                            sent.send(serde_json::from_slice::<serde_json::Value>(&body).unwrap())
+                                .unwrap();
+                            Ok::<_, std::convert::Infallible>(hyper::Response::new(Full::new(
+                                Bytes::new(),
+                            )))
+                        }
+                    });
+                tokio::spawn(
+                    hyper::server::conn::http1::Builder::new()
+                        .serve_connection(hyper_util::rt::TokioIo::new(stream), service),
+                );
+            }
+        });
+
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            ServerConfig {
+                tenant_quota_bytes: Some(10),
+                alerts: Some(AlertConfig {
+                    webhooks: vec![url.parse().unwrap()],
+                    rules: AlertRules {
+                        quota_percent: Some(50.0),
+                        ..AlertRules::default()
+                    },
+                    tls: Arc::new(crate::tls::load_client_config(None).unwrap()),
+                }),
+                ..ServerConfig::default()
+            },
+        );
+        let req = Request::builder()
             .method("POST")
-            .header("X-File-Name", file_name)
+            .header("X-File-Id", "fileAlert")
+            .header("X-File-Name", "alert.txt")
             .header("X-Chunk-Index", "0")
-            .header("X-Total-Chunks", "1    ")
-            .body(Full::new(Bytes::from("Hello, World!".to_string())).into())
+            .header("X-Total-Chunks", "1")
+            .header("X-Tenant-Id", "acme")
+            .body(Full::new(Bytes::from("Hello!")))
             .unwrap();
-
-        let res = service.call(req0).await;
+        service.call(req).await.unwrap();
+
+        assert_eq!(service.check_alerts().await, 1);
+        let alert = received.recv().await.unwrap();
+        assert_eq!(alert["rule"], "quota_near_limit");
+        assert_eq!(alert["tenant"], "acme");
+        assert_eq!(alert["state"], "firing");
+        assert_eq!(alert["value"], 60.0);
         assert!(
-            matches!(res.unwrap_err(), SliceBreadServerError::BadRequest(ref msg) if msg.contains("Missing header"))
+            alert["text"]
+                .as_str()
+                .unwrap()
+                .starts_with("[firing] Tenant acme")
         );
 
-        // Check final file content
-        let final_path = format!("uploads/{}/{}", file_id, file_name);
-        let exists = fs::try_exists(final_path).await.unwrap();
-        assert!(!exists);
+        // Only sent again once it resolves.
+        assert_eq!(service.check_alerts().await, 0);
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_admin_routes_can_be_split_onto_their_own_listener() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let server = SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+        let public = server.with_surface(Surface::Public);
+        let admin = server.with_surface(Surface::Admin);
+
+        let chunk = || {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileSurface")
+                .header("X-File-Name", "surface.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "1")
+                .body(Full::new(Bytes::from("data")))
+                .unwrap()
+        };
+        let stats = || {
+            Request::builder()
+                .uri("/admin/stats")
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+
+        let err = admin.call(chunk()).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::NotFound(_)));
+        let res = public.call(chunk()).await.unwrap();
+        assert_eq!(res.status(), 201);
+
+        let err = public.call(stats()).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::NotFound(_)));
+        let res = admin.call(stats()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["bytes_stored"], 4);
+    }
+
+    #[tokio::test]
+    async fn test_chunk_layout_is_kept_across_config_changes() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let config = ServerConfig {
+            chunk_layout: "pad=2,fanout=2".parse().unwrap(),
+            ..ServerConfig::default()
+        };
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        );
+
+        let chunk = |index: usize| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileLayout")
+                .header("X-File-Name", "layout.txt")
+                .header("X-Chunk-Index", index.to_string())
+                .header("X-Total-Chunks", "4")
+                .body(Full::new(Bytes::from(format!("<{}>", index))))
+                .unwrap()
+        };
+        for index in 0..3 {
+            service.call(chunk(index)).await.unwrap();
+        }
+        let chunk_dir = upload_dir.join("fileLayout");
+        assert!(chunk_dir.join("00").join("chunk_01.bin").exists());
+        assert!(chunk_dir.join("01").join("chunk_02.bin").exists());
+
+        // A restarted server with the default layout still finds the chunks.
+        let restarted =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+        let res = restarted.call(chunk(3)).await.unwrap();
+        assert_eq!(res.status(), 201);
+        let content = fs::read_to_string(chunk_dir.join("layout.txt"))
+            .await
+            .unwrap();
+        assert_eq!(content, "<0><1><2><3>");
+
+        let mut entries = fs::read_dir(&chunk_dir).await.unwrap();
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            names.push(entry.file_name());
+        }
+        names.sort();
+        assert_eq!(names, ["layout.txt", "layout.txt.meta.json"]);
     }
 
     #[tokio::test]
-    async fn test_missing_x_file_name_header() {
-        let service = SliceBreadServer::<Full<Bytes>>::new(String::from("uploads"));
+    async fn test_assembly_can_be_cancelled_and_retried() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
 
-        let file_id = "test12321";
-        let file_name = "hello.txt";
+        let chunk = |index: usize| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileCancel")
+                .header("X-File-Name", "cancel.txt")
+                .header("X-Chunk-Index", index.to_string())
+                .header("X-Total-Chunks", "3")
+                .body(Full::new(Bytes::from(format!("<{}>", index))))
+                .unwrap()
+        };
+        let cancel = || {
+            Request::builder()
+                .method("DELETE")
+                .uri("/admin/uploads/fileCancel/assembly")
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+        service.call(chunk(0)).await.unwrap();
+        service.call(chunk(1)).await.unwrap();
+        let err = service.call(cancel()).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::NotFound(_)));
+
+        // Stands in for an assembly that is a third of the way through.
+        let mut received = crate::bitmap::ChunkBitmap::new(3);
+        for index in 0..3 {
+            received.insert(index);
+        }
+        service.sessions.restore_received("fileCancel", &received);
+        assert!(service.sessions.claim_assembly("fileCancel"));
+        service.sessions.advance_assembly("fileCancel", 1);
 
-        // Upload chunk 0
-        let req0 = Request::builder()
-            .header("X-File-Id", file_id)
-            .header("X-Chunk-Index", "0")
-            .header("X-Total-Chunks", "1")
-            .body(Full::new(Bytes::from("Hello, World!".to_string())).into())
+        let req = Request::builder()
+            .uri("/uploads/fileCancel")
+            .body(Full::new(Bytes::new()))
             .unwrap();
-
-        let res = service.call(req0).await;
-        assert!(
-            matches!(res.unwrap_err(), SliceBreadServerError::BadRequest(ref msg) if msg.contains("Missing header"))
-        );
-
-        // Check final file content
-        let final_path = format!("uploads/{}/{}", file_id, file_name);
-        let exists = fs::try_exists(final_path).await.unwrap();
-        assert!(!exists);
+        let res = service.call(req).await.unwrap();
+        let status: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(status["state"], "assembling");
+        assert_eq!(status["assembly_percent"], 33.3);
+
+        let res = service.call(cancel()).await.unwrap();
+        assert_eq!(res.status(), 202);
+        let session = service.sessions.session("fileCancel").unwrap();
+        let err = service
+            .assemble_claimed("fileCancel", &session)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Conflict(_)));
+        let final_path = upload_dir.join("fileCancel").join("cancel.txt");
+        assert!(!final_path.exists());
+
+        let res = service.call(chunk(2)).await.unwrap();
+        assert_eq!(res.status(), 201);
+        let content = fs::read_to_string(&final_path).await.unwrap();
+        assert_eq!(content, "<0><1><2>");
     }
 
     #[tokio::test]
-    async fn test_missing_x_chunk_index_header() {
-        let service = SliceBreadServer::<Full<Bytes>>::new(String::from("uploads"));
+    async fn test_assembly_resumes_from_last_verified_chunk() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
 
-        let file_id = "test12321";
-        let file_name = "hello.txt";
+        let chunk = |index: usize| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileResume")
+                .header("X-File-Name", "resume.txt")
+                .header("X-Chunk-Index", index.to_string())
+                .header("X-Total-Chunks", "3")
+                .body(Full::new(Bytes::from(format!("<{}>", index))))
+                .unwrap()
+        };
+        service.call(chunk(0)).await.unwrap();
+        service.call(chunk(1)).await.unwrap();
 
-        // Upload chunk 0
-        let req0 = Request::builder()
-            .header("X-File-Id", file_id)
-            .header("X-File-Name", file_name)
-            .header("X-Total-Chunks", "2")
-            .body(Full::new(Bytes::from("Hello, World!".to_string())).into())
+        // A crashed attempt claimed all three chunks but only the first made it intact.
+        let upload = upload_dir.join("fileResume");
+        fs::write(upload.join("resume.txt"), "<0><X>garbage")
+            .await
             .unwrap();
+        fs::write(
+            upload.join(crate::constants::ASSEMBLY_CURSOR_FILE),
+            3u64.to_be_bytes(),
+        )
+        .await
+        .unwrap();
+
+        let res = service.call(chunk(2)).await.unwrap();
+        assert_eq!(res.status(), 201);
+        let content = fs::read_to_string(upload.join("resume.txt")).await.unwrap();
+        assert_eq!(content, "<0><1><2>");
+        assert!(!upload.join(crate::constants::ASSEMBLY_CURSOR_FILE).exists());
+        assert!(!upload.join("chunk_0.bin").exists());
+    }
 
-        let res = service.call(req0).await;
-        assert!(
-            matches!(res.unwrap_err(), SliceBreadServerError::BadRequest(ref msg) if msg.contains("Missing header"))
-        );
-
-        // Check final file content
-        let final_path = format!("uploads/{}/{}", file_id, file_name);
-        let exists = fs::try_exists(final_path).await.unwrap();
-        assert!(!exists);
+    #[tokio::test]
+    async fn test_every_durability_level_assembles() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        for durability in ["none", "file", "chunk"] {
+            let upload_dir = temp_dir.path().join(durability);
+            let service = SliceBreadServer::<Full<Bytes>>::with_config(
+                upload_dir.to_str().unwrap().to_string(),
+                ServerConfig {
+                    durability: durability.parse().unwrap(),
+                    chunk_layout: "fanout=1".parse().unwrap(),
+                    ..Default::default()
+                },
+            );
+            for index in 0..2 {
+                let req = Request::builder()
+                    .method("POST")
+                    .header("X-File-Id", "fileDurable")
+                    .header("X-File-Name", "durable.txt")
+                    .header("X-Chunk-Index", index.to_string())
+                    .header("X-Total-Chunks", "2")
+                    .body(Full::new(Bytes::from(format!("<{}>", index))))
+                    .unwrap();
+                service.call(req).await.unwrap();
+            }
+            let content = fs::read_to_string(upload_dir.join("fileDurable").join("durable.txt"))
+                .await
+                .unwrap();
+            assert_eq!(content, "<0><1>");
+        }
+        assert!("fsync".parse::<Durability>().is_err());
     }
 
     #[tokio::test]
-    async fn test_missing_x_total_chunks_header() {
-        let service = SliceBreadServer::<Full<Bytes>>::new(String::from("uploads"));
+    async fn test_single_chunk_upload_is_linked_into_place() {
+        use base64::{Engine, engine::general_purpose::STANDARD};
 
-        let file_id = "test12321";
-        let file_name = "hello.txt";
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
 
-        // Upload chunk 0
-        let req0 = Request::builder()
-            .header("X-File-Id", file_id)
-            .header("X-File-Name", file_name)
-            .header("X-Chunk-Index", "0")
-            .body(Full::new(Bytes::from("Hello, World!".to_string())).into())
+        let req = |file_id: &str, expected: &[u8]| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", "single.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "1")
+                .header(
+                    "Repr-Digest",
+                    format!("sha-256=:{}:", STANDARD.encode(checksum::sha256(expected))),
+                )
+                .body(Full::new(Bytes::from("Hello, World!")))
+                .unwrap()
+        };
+        let err = service
+            .call(req("fileTampered", b"Hello, Moon!"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::DigestMismatch(_)));
+        let tampered = upload_dir.join("fileTampered");
+        assert!(!tampered.join("single.txt").exists());
+        assert!(tampered.join("chunk_0.bin").exists());
+
+        let output = upload_dir.join("fileSingle").join("single.txt");
+        let chunk = upload_dir.join("fileSingle").join("chunk_0.bin");
+        let res = service
+            .call(req("fileSingle", b"Hello, World!"))
+            .await
             .unwrap();
-
-        let res = service.call(req0).await;
-        assert!(
-            matches!(res.unwrap_err(), SliceBreadServerError::BadRequest(ref msg) if msg.contains("Missing header"))
-        );
-
-        // Check final file content
-        let final_path = format!("uploads/{}/{}", file_id, file_name);
-        let exists = fs::try_exists(final_path).await.unwrap();
-        assert!(!exists);
+        assert_eq!(res.status(), 201);
+        assert_eq!(fs::read_to_string(&output).await.unwrap(), "Hello, World!");
+        assert!(!chunk.exists());
+        let manifest = fs::read(service.manifest_path("fileSingle")).await.unwrap();
+        let manifest: Manifest = serde_json::from_slice(&manifest).unwrap();
+        assert_eq!(manifest.chunk_sizes, vec![13]);
     }
 
     #[tokio::test]
-    async fn test_invalid_chunk_index_format() {
-        let service = SliceBreadServer::<Full<Bytes>>::new(String::from("uploads"));
-
-        let file_id = "test12321";
-        let file_name = "hello.txt";
+    async fn test_batch_upload_stores_each_part_as_a_file() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
 
-        // Upload chunk 0
-        let req0 = Request::builder()
-            .header("X-File-Id", file_id)
-            .header("X-File-Name", file_name)
-            .header("X-Chunk-Index", "one")
-            .header("X-Total-Chunks", "2")
-            .body(Full::new(Bytes::from("Hello, World!".to_string())).into())
+        let body = "--batch\r\n\
+            Content-Disposition: form-data; name=\"fileA\"; filename=\"a.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            Hello\r\n--batch\r\n\
+            Content-Disposition: form-data; name=\"fileB\"\r\n\
+            Repr-Digest: sha-256=:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=:\r\n\r\n\
+            World\r\n--batch\r\n\
+            Content-Disposition: form-data; filename=\"anonymous.txt\"\r\n\r\n\
+            !\r\n--batch--\r\n";
+        let req = Request::builder()
+            .method("POST")
+            .uri("/batch")
+            .header("Content-Type", "multipart/form-data; boundary=batch")
+            .body(Full::new(Bytes::from(body)))
             .unwrap();
-
-        let res = service.call(req0).await;
-        assert!(
-            matches!(res.unwrap_err(), SliceBreadServerError::BadRequest(ref msg) if msg.contains("Invalid header"))
+        let res = service.call(req).await.unwrap();
+        assert_eq!(res.status(), 207);
+        let result: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        let statuses: Vec<_> = result["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|file| {
+                (
+                    file["file_id"].as_str().unwrap(),
+                    file["status"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(statuses, [("fileA", 201), ("fileB", 400), ("", 400)]);
+        assert_eq!(result["files"][1]["error"]["code"], "digest_mismatch");
+
+        let file_a = upload_dir.join("fileA");
+        assert_eq!(
+            fs::read_to_string(file_a.join("a.txt")).await.unwrap(),
+            "Hello"
         );
+        let metadata: FileMetadata =
+            serde_json::from_slice(&fs::read(file_a.join("a.txt.meta.json")).await.unwrap())
+                .unwrap();
+        assert_eq!(metadata.content_type, "text/plain");
+        assert!(!upload_dir.join("fileB").join("fileB").exists());
 
-        // Check final file content
-        let final_path = format!("uploads/{}/{}", file_id, file_name);
-        let exists = fs::try_exists(final_path).await.unwrap();
-        assert!(!exists);
+        let req = Request::builder()
+            .method("POST")
+            .uri("/batch")
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from("{}")))
+            .unwrap();
+        let err = service.call(req).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::BadRequest(_)));
     }
 
     #[tokio::test]
-    async fn test_invalid_total_chunks_format() {
-        let service = SliceBreadServer::<Full<Bytes>>::new(String::from("uploads"));
+    async fn test_archive_is_extracted_when_asked() {
+        use std::io::Write;
 
-        let file_id = "test12321";
-        let file_name = "hello.txt";
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
 
-        // Upload chunk 0
-        let req0 = Request::builder()
-            .header("X-File-Id", file_id)
-            .header("X-File-Name", file_name)
-            .header("X-Chunk-Index", "0")
-            .header("X-Total-Chunks", "one")
-            .body(Full::new(Bytes::from("Hello, World!".to_string())).into())
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zip.start_file("docs/readme.txt", zip::write::SimpleFileOptions::default())
             .unwrap();
+        zip.write_all(b"read me").unwrap();
+        let archive = Bytes::from(zip.finish().unwrap().into_inner());
+        let half = archive.len() / 2;
 
-        let res = service.call(req0).await;
-        assert!(
-            matches!(res.unwrap_err(), SliceBreadServerError::BadRequest(ref msg) if msg.contains("Invalid header"))
+        let chunk_of = |file_id: &str, file_name: &str, index: usize, body: Bytes| {
+            let req = Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", file_name)
+                .header("X-Chunk-Index", index.to_string())
+                .header("X-Total-Chunks", "2");
+            let req = if index == 0 {
+                req.header("X-Extract", "true")
+            } else {
+                req
+            };
+            req.body(Full::new(body)).unwrap()
+        };
+        let chunk = |file_name: &str, index: usize, body: Bytes| {
+            chunk_of("fileArchive", file_name, index, body)
+        };
+        let err = service
+            .call(chunk("notes.txt", 0, archive.slice(..half)))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::BadRequest(_)));
+
+        service
+            .call(chunk("site.zip", 0, archive.slice(..half)))
+            .await
+            .unwrap();
+        let res = service
+            .call(chunk("site.zip", 1, archive.slice(half..)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 201);
+        let extracted = upload_dir.join("fileArchive").join("site");
+        assert_eq!(
+            fs::read_to_string(extracted.join("docs").join("readme.txt"))
+                .await
+                .unwrap(),
+            "read me"
         );
+        assert!(upload_dir.join("fileArchive").join("site.zip").exists());
+        let sidecar = crate::sidecar::read(&upload_dir.join("fileArchive").join("site.zip"))
+            .await
+            .unwrap();
+        assert_eq!(sidecar.extracted.as_deref(), Some("site"));
 
-        // Check final file content
-        let final_path = format!("uploads/{}/{}", file_id, file_name);
-        let exists = fs::try_exists(final_path).await.unwrap();
-        assert!(!exists);
+        // Names whose stem isn't a directory of its own can't be extracted.
+        for file_name in ["..zip", "...zip"] {
+            let err = service
+                .call(chunk_of("fileDots", file_name, 0, archive.slice(..half)))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, SliceBreadServerError::BadRequest(_)));
+        }
+        assert!(extracted.join("docs").join("readme.txt").exists());
+
+        // A directory the server didn't extract for this file id is left alone.
+        let foreign = upload_dir.join("fileForeign").join("site");
+        fs::create_dir_all(&foreign).await.unwrap();
+        fs::write(foreign.join("keep.txt"), "keep").await.unwrap();
+        service
+            .call(chunk_of(
+                "fileForeign",
+                "site.zip",
+                0,
+                archive.slice(..half),
+            ))
+            .await
+            .unwrap();
+        let err = service
+            .call(chunk_of(
+                "fileForeign",
+                "site.zip",
+                1,
+                archive.slice(half..),
+            ))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Conflict(_)));
+        assert!(foreign.join("keep.txt").exists());
+
+        // Deleting the archive removes what was extracted from it.
+        let delete = Request::builder()
+            .method("DELETE")
+            .uri("/files/fileArchive")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        assert_eq!(service.call(delete).await.unwrap().status(), 204);
+        assert!(!upload_dir.join("fileArchive").exists());
     }
 
     #[tokio::test]
-    async fn test_chunk_index_greater_than_total_chunks() {
-        let service = SliceBreadServer::<Full<Bytes>>::new(String::from("uploads"));
-
-        let file_id = "test123";
-        let file_name = "hello.txt";
+    async fn test_uploads_past_their_max_duration_expire() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            ServerConfig {
+                max_upload_duration: Some(std::time::Duration::from_secs(1)),
+                ..Default::default()
+            },
+        );
 
-        // Upload chunk 0
-        let req0 = Request::builder()
-            .method("POST")
-            .header("X-File-Id", file_id)
-            .header("X-File-Name", file_name)
-            .header("X-Chunk-Index", "2")
-            .header("X-Total-Chunks", "1")
-            .body(Full::new(Bytes::from("Hello, ".to_string())).into())
-            .unwrap();
+        let req = |file_id: &str, chunk_index: &str, max_duration: Option<&str>| {
+            let mut req = Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", "slow.txt")
+                .header("X-Chunk-Index", chunk_index)
+                .header("X-Total-Chunks", "2");
+            if let Some(max_duration) = max_duration {
+                req = req.header("X-Upload-Max-Duration", max_duration);
+            }
+            req.body(Full::new(Bytes::from("Hello"))).unwrap()
+        };
 
-        let res = service.call(req0).await;
+        // A later chunk may shorten the deadline, here to one already past.
+        let res = service.call(req("fileShort", "0", None)).await.unwrap();
+        assert_eq!(res.status(), 200);
+        let err = service
+            .call(req("fileShort", "1", Some("0")))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Expired(_)));
+        assert_eq!(err.status_code(), 410);
+        assert!(!upload_dir.join("fileShort").exists());
+        assert!(service.sessions.session("fileShort").is_none());
+
+        let res = service.call(req("fileSlow", "0", None)).await.unwrap();
+        assert_eq!(res.status(), 200);
         assert!(
-            matches!(res.unwrap_err(), SliceBreadServerError::BadRequest(ref msg) if msg.eq("Invalid X-Chunk-Index: 2 > X-Total-Chunks: 1"))
+            service
+                .sessions
+                .progress("fileSlow")
+                .unwrap()
+                .expires_at
+                .is_some()
         );
+        assert_eq!(service.expire_overdue().await.unwrap(), 0);
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        assert_eq!(service.expire_overdue().await.unwrap(), 1);
+        assert!(!upload_dir.join("fileSlow").exists());
+        assert_eq!(service.sessions.stats().total.bytes_stored, 0);
     }
 
     #[tokio::test]
-    async fn test_missing_intermediate_chunk_on_finalization() {
-        let temp_dir = TempDir::new("upload_test").expect("create temp dir failed");
+    async fn test_new_uploads_past_the_client_session_limit_get_429() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
         let upload_dir = temp_dir.path().join("uploads");
-        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
-
-        let service =
-            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            ServerConfig {
+                session_limits: SessionLimits {
+                    max_per_client: Some(1),
+                    max_per_tenant: None,
+                },
+                ..Default::default()
+            },
+        );
 
-        let file_id = "test12663";
-        let file_name = "hello.txt";
+        let req = |file_id: &str, chunk_index: &str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", "busy.txt")
+                .header("X-Chunk-Index", chunk_index)
+                .header("X-Total-Chunks", "2")
+                .body(Full::new(Bytes::from("Hello")))
+                .unwrap()
+        };
+        let client = service.for_connection("10.0.0.1".parse().unwrap());
+        let other = service.for_connection("10.0.0.2".parse().unwrap());
+
+        let res = client.call(req("fileFirst", "0")).await.unwrap();
+        assert_eq!(res.status(), 200);
+        let err = client.call(req("fileSecond", "0")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::TooManySessions(_)));
+        assert_eq!(err.status_code(), 429);
+        let res = other.call(req("fileSecond", "0")).await.unwrap();
+        assert_eq!(res.status(), 200);
+
+        // Finishing an upload frees its slot.
+        let res = client.call(req("fileFirst", "1")).await.unwrap();
+        assert_eq!(res.status(), 201);
+        let res = client.call(req("fileThird", "0")).await.unwrap();
+        assert_eq!(res.status(), 200);
+    }
 
-        // Upload chunk 0
-        let req0 = Request::builder()
-            .method("POST")
-            .header("X-File-Id", file_id)
-            .header("X-File-Name", file_name)
-            .header("X-Chunk-Index", "0")
-            .header("X-Total-Chunks", "3")
-            .body(Full::new(Bytes::from("Hello, ".to_string())).into())
-            .unwrap();
+    #[tokio::test]
+    async fn test_bodies_over_the_route_limit_are_refused() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            ServerConfig {
+                body_limits: BodyLimits {
+                    chunk: Some(4),
+                    range: Some(8),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
 
-        let res = service.call(req0).await.unwrap();
-        assert_eq!(res.status(), 201);
+        let chunk = |content_length: Option<&str>| {
+            let mut req = Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileLimit")
+                .header("X-File-Name", "limit.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "1");
+            if let Some(content_length) = content_length {
+                req = req.header("Content-Length", content_length);
+            }
+            req.body(Full::new(Bytes::from("Hello"))).unwrap()
+        };
+        // Refused on the declared length alone, and while streaming without one.
+        for content_length in [Some("5"), None] {
+            let err = service.call(chunk(content_length)).await.unwrap_err();
+            assert!(matches!(err, SliceBreadServerError::PayloadTooLarge(_)));
+        }
+        assert!(!upload_dir.join("fileLimit").exists());
 
-        // Upload final chunk
-        let req1 = Request::builder()
-            .method("POST")
-            .header("X-File-Id", file_id)
-            .header("X-File-Name", file_name)
-            .header("X-Chunk-Index", "2")
-            .header("X-Total-Chunks", "3")
-            .body(Full::new(Bytes::from("World!".to_string())).into())
+        let req = Request::builder()
+            .method("PUT")
+            .uri("/uploads/fileRange")
+            .header("X-File-Name", "range.txt")
+            .header("X-File-Size", "5")
+            .header("X-Range-Offset", "0")
+            .body(Full::new(Bytes::from("Hello")))
             .unwrap();
-
-        let res = service.call(req1).await;
-        assert!(
-            matches!(res.unwrap_err(), SliceBreadServerError::BadRequest(ref msg) if msg.eq("Missing chunk: 1"))
-        );
+        let res = service.call(req).await.unwrap();
+        assert_eq!(res.status(), 201);
     }
 
     #[tokio::test]
-    async fn test_write_failure_returns_internal_server_error() {
-        let temp_dir = TempDir::new("upload_test").expect("create temp dir failed");
+    async fn test_chunks_before_the_last_must_reach_the_minimum_size() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
         let upload_dir = temp_dir.path().join("uploads");
-        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            ServerConfig {
+                min_chunk_bytes: Some(4),
+                body_limits: BodyLimits {
+                    chunk: Some(8),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+
+        let chunk = |chunk_index: &str, data: &'static str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileMin")
+                .header("X-File-Name", "min.txt")
+                .header("X-Chunk-Index", chunk_index)
+                .header("X-Total-Chunks", "2")
+                .body(Full::new(Bytes::from(data)))
+                .unwrap()
+        };
+        let err = service.call(chunk("0", "Hel")).await.unwrap_err();
+        assert!(matches!(
+            err,
+            SliceBreadServerError::ChunkSizeOutOfRange {
+                chunk_index: 0,
+                size: 3,
+                min: 4,
+                max: Some(8),
+            }
+        ));
+        assert!(!upload_dir.join("fileMin").exists());
+
+        // The last chunk may be as small as it needs to be.
+        let res = service.call(chunk("0", "Hell")).await.unwrap();
+        assert_eq!(res.status(), 200);
+        let res = service.call(chunk("1", "o")).await.unwrap();
+        assert_eq!(res.status(), 201);
+        let content = fs::read_to_string(upload_dir.join("fileMin").join("min.txt"))
+            .await
+            .unwrap();
+        assert_eq!(content, "Hello");
+    }
 
+    #[tokio::test]
+    async fn test_head_file_reports_completed_metadata() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
         let service =
             SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
 
-        let file_id = "test123";
-        let file_name = "hello.txt";
-
-        let conflict_path = upload_dir.join(file_id).join(file_name);
-        tokio::fs::create_dir_all(&conflict_path).await.unwrap();
+        let head = |file_id: &str| {
+            Request::builder()
+                .method("HEAD")
+                .uri(format!("/files/{}", file_id))
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+        let err = service.call(head("fileHead")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::NotFound(_)));
 
-        // Upload chunk 0
-        let req0 = Request::builder()
+        let req = Request::builder()
             .method("POST")
-            .header("X-File-Id", file_id)
-            .header("X-File-Name", file_name)
+            .header("X-File-Id", "fileHead")
+            .header("X-File-Name", "head.txt")
             .header("X-Chunk-Index", "0")
             .header("X-Total-Chunks", "1")
-            .body(Full::new(Bytes::from("Hello, ".to_string())).into())
+            .header("Content-Type", "text/plain")
+            .body(Full::new(Bytes::from("Hello, World!")))
             .unwrap();
-
-        let res = service.call(req0).await;
-        assert!(
-            matches!(res.unwrap_err(), SliceBreadServerError::IoError(ref e) if e.to_string().contains("Is a directory"))
-        );
+        service.call(req).await.unwrap();
+
+        let res = service.call(head("fileHead")).await.unwrap();
+        assert_eq!(res.status(), 200);
+        let sha256 = checksum::to_hex(&checksum::sha256(b"Hello, World!"));
+        let headers = res.headers();
+        assert_eq!(headers["content-length"], "13");
+        assert_eq!(headers["content-type"], "text/plain");
+        assert_eq!(headers["etag"], format!("\"{}\"", sha256));
+        assert_eq!(headers["x-file-sha256"], sha256.as_str());
+        assert!(headers["last-modified"].to_str().unwrap().ends_with(" GMT"));
+        assert!(headers.contains_key("x-upload-started-at"));
+        assert!(headers.contains_key("x-upload-completed-at"));
     }
 
     #[tokio::test]
-    async fn test_output_file_creation_failure() {
-        let temp_dir = TempDir::new("upload_test").expect("create temp dir failed");
+    async fn test_bulk_status_reports_many_uploads_at_once() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
         let upload_dir = temp_dir.path().join("uploads");
-        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
-
         let service =
             SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
 
-        let file_id = "test445562";
-        let file_name = "hello.txt";
-        let file_id_path = upload_dir.join(file_id);
-        tokio::fs::create_dir_all(&file_id_path).await.unwrap();
+        for (file_id, total_chunks) in [("fileDone", "1"), ("fileHalf", "2")] {
+            let req = Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", "bulk.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", total_chunks)
+                .body(Full::new(Bytes::from("Hello")))
+                .unwrap();
+            service.call(req).await.unwrap();
+        }
 
-        let chunk_file = file_id_path.join(file_name);
-        tokio::fs::create_dir_all(&chunk_file).await.unwrap(); // <— key change
+        let query = |body: &'static str| {
+            Request::builder()
+                .method("POST")
+                .uri("/uploads/status")
+                .body(Full::new(Bytes::from(body)))
+                .unwrap()
+        };
+        let res = service
+            .call(query(
+                r#"{"file_ids":["fileHalf","fileMissing","fileDone"]}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["uploads"][0]["file_id"], "fileHalf");
+        assert_eq!(body["uploads"][0]["state"], "uploading");
+        assert_eq!(body["uploads"][0]["chunks_received"], 1);
+        assert_eq!(body["uploads"][1]["file_id"], "fileDone");
+        assert_eq!(body["uploads"][1]["state"], "completed");
+        assert_eq!(body["not_found"], serde_json::json!(["fileMissing"]));
+
+        let err = service.call(query("[1, 2]")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::BadRequest(_)));
+    }
+
+    #[tokio::test]
+    async fn test_renamed_headers_are_accepted_and_returned() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            ServerConfig {
+                header_names:
+                    "X-File-Id=File-Id,X-Chunk-Index=Chunk-Index,X-Chunk-Sha256=Chunk-Sha256"
+                        .parse()
+                        .unwrap(),
+                ..Default::default()
+            },
+        );
 
-        // Upload chunk 0
         let req = Request::builder()
             .method("POST")
-            .header("X-File-Id", file_id)
-            .header("X-File-Name", file_name)
-            .header("X-Chunk-Index", "0")
-            .header("X-Total-Chunks", "1")
-            .body(Full::new(Bytes::from("Hello, ".to_string())).into())
+            .header("File-Id", "fileRenamed")
+            .header("X-File-Name", "renamed.txt")
+            .header("Chunk-Index", "0")
+            .header("X-Total-Chunks", "2")
+            .body(Full::new(Bytes::from("Hello")))
             .unwrap();
+        let res = service.call(req).await.unwrap();
+        assert_eq!(res.status(), 200);
 
-        let res = service.call(req).await;
-
-        assert!(
-            matches!(res.unwrap_err(), SliceBreadServerError::IoError(ref e) if e.to_string().contains("Is a directory"))
+        let probe = Request::builder()
+            .method("HEAD")
+            .uri("/uploads/fileRenamed/chunks/0")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let res = service.call(probe).await.unwrap();
+        assert_eq!(
+            res.headers()["chunk-sha256"],
+            checksum::to_hex(&checksum::sha256(b"Hello")).as_str()
         );
+        assert!(!res.headers().contains_key("x-chunk-sha256"));
     }
 
     #[tokio::test]
-    async fn test_chunks_are_deleted_after_merge() {
+    async fn test_protocol_version_is_negotiated_per_request() {
         let temp_dir = TempDir::new("upload_test").unwrap();
         let upload_dir = temp_dir.path().join("uploads");
-        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
-
         let service =
             SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
 
-        let file_id = "file123";
-        let file_name = "final.txt";
-
-        // Send single chunk (merge will happen)
-        let req = Request::builder()
-            .method("POST")
-            .header("X-File-Id", file_id)
-            .header("X-File-Name", file_name)
-            .header("X-Chunk-Index", "0")
-            .header("X-Total-Chunks", "1")
-            .body(Full::new(Bytes::from("Hello, world!")).into())
-            .unwrap();
+        let req = |version: Option<&str>| {
+            let mut req = Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileVersion")
+                .header("X-File-Name", "version.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "2");
+            if let Some(version) = version {
+                req = req.header("X-SliceBread-Version", version);
+            }
+            req.body(Full::new(Bytes::from("Hello"))).unwrap()
+        };
 
-        let res = service.call(req).await.unwrap();
-        assert_eq!(res.status(), 201);
+        let res = service.call(req(None)).await.unwrap();
+        assert_eq!(res.headers()["x-slicebread-version"], "1");
+        let res = service.call(req(Some("3, 1"))).await.unwrap();
+        assert_eq!(res.headers()["x-slicebread-version"], "1");
 
-        let chunk_path = upload_dir.join(file_id).join("chunk_0.bin");
-        assert!(!chunk_path.exists());
+        let err = service.call(req(Some("3"))).await.unwrap_err();
+        assert!(matches!(
+            err,
+            SliceBreadServerError::UnsupportedVersion { .. }
+        ));
+        let res = err.into_response();
+        assert_eq!(res.status(), 400);
+        assert_eq!(res.headers()["x-slicebread-version"], "1");
     }
 
     #[tokio::test]
-    async fn test_final_file_has_correct_content() {}
-
-    #[tokio::test]
-    async fn test_empty_chunk_upload() {
+    async fn test_deferred_upload_is_assembled_by_complete() {
         let temp_dir = TempDir::new("upload_test").unwrap();
         let upload_dir = temp_dir.path().join("uploads");
-        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
-
         let service =
             SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
 
-        let file_id = "fileEmpty";
-        let file_name = "empty.txt";
+        let chunk = |chunk_index: &str, data: &'static str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileDeferred")
+                .header("X-File-Name", "deferred.txt")
+                .header("X-Chunk-Index", chunk_index)
+                .header("X-Total-Chunks", "2")
+                .header("X-Defer-Assembly", "true")
+                .body(Full::new(Bytes::from(data)))
+                .unwrap()
+        };
+        let complete = |total_chunks: &str| {
+            Request::builder()
+                .method("POST")
+                .uri("/uploads/fileDeferred/complete")
+                .header("X-Total-Chunks", total_chunks)
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+        let output = upload_dir.join("fileDeferred").join("deferred.txt");
+
+        service.call(chunk("1", "World")).await.unwrap();
+        let err = service.call(complete("2")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::MissingChunk(0)));
+
+        service.call(chunk("0", "Hello")).await.unwrap();
+        assert!(!output.exists());
+        let err = service.call(complete("3")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Conflict(_)));
+
+        // Only the request that assembled the file gets 201.
+        for status in [201, 200] {
+            let res = service.call(complete("2")).await.unwrap();
+            assert_eq!(res.status(), status);
+            let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+            assert_eq!(body["state"], "completed");
+        }
+        assert_eq!(fs::read_to_string(&output).await.unwrap(), "HelloWorld");
 
-        let req = Request::builder()
+        let missing = Request::builder()
             .method("POST")
-            .header("X-File-Id", file_id)
-            .header("X-File-Name", file_name)
-            .header("X-Chunk-Index", "0")
-            .header("X-Total-Chunks", "1")
-            .body(Full::new(Bytes::new()).into())
+            .uri("/uploads/fileUnknown/complete")
+            .body(Full::new(Bytes::new()))
             .unwrap();
+        let err = service.call(missing).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::NotFound(_)));
+    }
 
-        let res = service.call(req).await.unwrap();
+    #[tokio::test]
+    async fn test_newer_generation_restarts_the_upload() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let dir = upload_dir.to_str().unwrap().to_string();
+        let service = SliceBreadServer::<Full<Bytes>>::new(dir.clone());
+
+        let chunk =
+            |generation: &str, chunk_index: &str, total_chunks: &str, data: &'static str| {
+                Request::builder()
+                    .method("POST")
+                    .header("X-File-Id", "fileRestarted")
+                    .header("X-File-Name", "restarted.txt")
+                    .header("X-Chunk-Index", chunk_index)
+                    .header("X-Total-Chunks", total_chunks)
+                    .header("X-Upload-Generation", generation)
+                    .body(Full::new(Bytes::from(data)))
+                    .unwrap()
+            };
+        let output = upload_dir.join("fileRestarted").join("restarted.txt");
+
+        service.call(chunk("0", "0", "3", "Stale")).await.unwrap();
+        service.call(chunk("0", "2", "3", "Stale")).await.unwrap();
+        service.call(chunk("1", "0", "2", "Hello")).await.unwrap();
+        let err = service
+            .call(chunk("0", "1", "3", "Stale"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Conflict(_)));
+
+        // The generation outlives a restart, so the stale attempt stays locked out.
+        let service = SliceBreadServer::<Full<Bytes>>::new(dir);
+        let err = service
+            .call(chunk("0", "1", "3", "Stale"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Conflict(_)));
+        let res = service.call(chunk("1", "1", "2", "World")).await.unwrap();
         assert_eq!(res.status(), 201);
-
-        let final_path = upload_dir.join(file_id).join(file_name);
-        let written = tokio::fs::read(final_path).await.unwrap();
-        assert_eq!(written.len(), 0);
+        assert_eq!(fs::read_to_string(&output).await.unwrap(), "HelloWorld");
+        assert!(
+            !upload_dir
+                .join("fileRestarted")
+                .join(crate::constants::GENERATION_FILE)
+                .exists()
+        );
     }
 
     #[tokio::test]
-    async fn test_duplicate_chunk_upload() {
+    async fn test_retransmitted_chunk_reports_progress() {
         let temp_dir = TempDir::new("upload_test").unwrap();
         let upload_dir = temp_dir.path().join("uploads");
-        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
-
         let service =
             SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
 
-        let file_id = "fileDup";
-        let file_name = "dup.txt";
+        let chunk = |chunk_index: &str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileRetransmit")
+                .header("X-File-Name", "retransmit.txt")
+                .header("X-Chunk-Index", chunk_index)
+                .header("X-Total-Chunks", "3")
+                .body(Full::new(Bytes::from("Hello")))
+                .unwrap()
+        };
 
-        let req = |data: &str| {
+        let res = service.call(chunk("0")).await.unwrap();
+        assert_eq!(res.status(), 200);
+        assert!(res.headers().get("X-Chunk-Already-Present").is_none());
+        service.call(chunk("1")).await.unwrap();
+
+        let res = service.call(chunk("0")).await.unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()["X-Chunk-Already-Present"], "true");
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["state"], "uploading");
+        assert_eq!(body["chunks_received"], 2);
+        assert_eq!(body["total_chunks"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_bundle_lands_when_committed() {
+        let temp_dir = TempDir::new("upload_test").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        let service =
+            SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
+
+        let chunk = |file_id: &str,
+                     path: &str,
+                     chunk_index: &str,
+                     total_chunks: &str,
+                     data: &'static str| {
             Request::builder()
                 .method("POST")
                 .header("X-File-Id", file_id)
-                .header("X-File-Name", file_name)
-                .header("X-Chunk-Index", "0")
-                .header("X-Total-Chunks", "1")
-                .body(Full::new(Bytes::from(data.to_string())).into())
+                .header("X-File-Name", "part.txt")
+                .header("X-Chunk-Index", chunk_index)
+                .header("X-Total-Chunks", total_chunks)
+                .header("X-Bundle-Id", "export1")
+                .header("X-Bundle-Path", path)
+                .body(Full::new(Bytes::from(data)))
+                .unwrap()
+        };
+        let commit = |total_files: &str| {
+            Request::builder()
+                .method("POST")
+                .uri("/bundles/export1/commit")
+                .header("X-Total-Files", total_files)
+                .body(Full::new(Bytes::new()))
                 .unwrap()
         };
+        let bundle_dir = upload_dir.join("export1");
 
-        // First upload
-        let res1 = service.call(req("first")).await.unwrap();
-        assert_eq!(res1.status(), 201);
+        service
+            .call(chunk("fileA", "data/a.txt", "0", "2", "Hello"))
+            .await
+            .unwrap();
+        service
+            .call(chunk("fileB", "b.txt", "0", "1", "Bee"))
+            .await
+            .unwrap();
+        let err = service.call(commit("2")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::BadRequest(_)));
 
-        // Second upload with same chunk index
-        let res2 = service.call(req("second")).await.unwrap();
-        assert_eq!(res2.status(), 201);
+        service
+            .call(chunk("fileA", "data/a.txt", "1", "2", "World"))
+            .await
+            .unwrap();
+        assert!(!bundle_dir.exists());
+        let complete = Request::builder()
+            .method("POST")
+            .uri("/uploads/fileA/complete")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let err = service.call(complete).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::BadRequest(_)));
+        let err = service.call(commit("3")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::BadRequest(_)));
 
-        let final_path = upload_dir.join(file_id).join(file_name);
-        let content = tokio::fs::read_to_string(final_path).await.unwrap();
+        let res = service.call(commit("2")).await.unwrap();
+        assert_eq!(res.status(), 201);
+        let manifest: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(manifest["files"].as_array().unwrap().len(), 2);
+        assert!(manifest["committed_at"].is_string());
+        assert_eq!(
+            fs::read_to_string(bundle_dir.join("data/a.txt"))
+                .await
+                .unwrap(),
+            "HelloWorld"
+        );
+        assert_eq!(
+            fs::read_to_string(bundle_dir.join("b.txt")).await.unwrap(),
+            "Bee"
+        );
+        assert!(
+            bundle_dir
+                .join(crate::constants::BUNDLE_MANIFEST_FILE)
+                .exists()
+        );
+        assert!(
+            !upload_dir
+                .join(crate::constants::BUNDLE_STAGING_DIR)
+                .join("export1")
+                .exists()
+        );
 
-        // Depending on implementation: expect "second" or "first"
-        assert!(["first", "second"].contains(&content.as_str()));
+        let status = Request::builder()
+            .uri("/uploads/fileA")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let res = service.call(status).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["state"], "completed");
+
+        let res = service.call(commit("2")).await.unwrap();
+        assert_eq!(res.status(), 200);
     }
 
     #[tokio::test]
-    async fn test_large_chunk_upload() {
+    async fn test_archive_streams_completed_files() {
+        use http_body_util::BodyExt;
+        use std::io::Read;
+
         let temp_dir = TempDir::new("upload_test").unwrap();
         let upload_dir = temp_dir.path().join("uploads");
-        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
-
         let service =
             SliceBreadServer::<Full<Bytes>>::new(upload_dir.to_str().unwrap().to_string());
 
-        let file_id = "fileLarge";
-        let file_name = "large.txt";
+        let chunk = |chunk_index: &str, data: &'static str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", "fileArchived")
+                .header("X-File-Name", "report.txt")
+                .header("X-Chunk-Index", chunk_index)
+                .header("X-Total-Chunks", "2")
+                .body(Full::new(Bytes::from(data)))
+                .unwrap()
+        };
+        let archive = |name: &str| {
+            Request::builder()
+                .uri(format!("/uploads/fileArchived/{}", name))
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+        let entries = |tar: &[u8]| {
+            tar::Archive::new(tar)
+                .entries()
+                .unwrap()
+                .map(|entry| {
+                    let mut entry = entry.unwrap();
+                    let mut contents = String::new();
+                    entry.read_to_string(&mut contents).unwrap();
+                    (entry.path().unwrap().display().to_string(), contents)
+                })
+                .collect::<Vec<_>>()
+        };
 
-        let big_data = "A".repeat(10 * 1024 * 1024); // 10 MB
+        service.call(chunk("0", "Hello")).await.unwrap();
+        let err = service.call(archive("archive.tar")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::Conflict(_)));
+        service.call(chunk("1", "World")).await.unwrap();
+
+        let res = service.call(archive("archive.tar")).await.unwrap();
+        assert_eq!(res.headers()["Content-Type"], "application/x-tar");
+        let tar = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            entries(&tar),
+            vec![("report.txt".to_string(), "HelloWorld".to_string())]
+        );
 
-        let req = Request::builder()
-            .method("POST")
-            .header("X-File-Id", file_id)
-            .header("X-File-Name", file_name)
-            .header("X-Chunk-Index", "0")
-            .header("X-Total-Chunks", "1")
-            .body(Full::new(Bytes::from(big_data.clone())).into())
+        let res = service.call(archive("archive.tar.gz")).await.unwrap();
+        let gz = res.into_body().collect().await.unwrap().to_bytes();
+        let mut tar = Vec::new();
+        flate2::read::GzDecoder::new(&gz[..])
+            .read_to_end(&mut tar)
             .unwrap();
+        assert_eq!(entries(&tar).len(), 1);
 
-        let res = service.call(req).await.unwrap();
-        assert_eq!(res.status(), 201);
+        let err = service.call(archive("archive.zip")).await.unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::NotFound(_)));
+    }
 
-        let final_path = upload_dir.join(file_id).join(file_name);
-        let written = tokio::fs::read_to_string(final_path).await.unwrap();
-        assert_eq!(written.len(), big_data.len());
+    #[tokio::test]
+    async fn test_capabilities_describe_the_configuration() {
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            String::from("uploads"),
+            ServerConfig {
+                max_total_chunks: 500,
+                body_limits: BodyLimits {
+                    chunk: Some(8 * 1024 * 1024),
+                    ..BodyLimits::default()
+                },
+                compress_responses: true,
+                immutable: true,
+                ..ServerConfig::default()
+            },
+        );
+        let res = service
+            .call(
+                Request::builder()
+                    .uri("/capabilities")
+                    .body(Full::new(Bytes::new()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["protocol_versions"], serde_json::json!([1]));
+        assert_eq!(body["checksum_algorithms"][0], "sha-256");
+        assert_eq!(
+            body["response_encodings"],
+            serde_json::json!(["zstd", "gzip"])
+        );
+        assert_eq!(body["features"]["immutable"], true);
+        assert_eq!(body["features"]["upload_policy_required"], false);
+        assert_eq!(body["limits"]["max_total_chunks"], 500);
+        assert_eq!(body["limits"]["max_chunk_bytes"], 8 * 1024 * 1024);
+        assert!(body["limits"]["max_range_bytes"].is_null());
     }
 
     #[tokio::test]
-    async fn test_concurrent_uploads_same_file_id() {
-        use futures_util::future::join_all;
+    async fn test_capabilities_recommend_a_chunk_size() {
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            String::from("uploads"),
+            ServerConfig {
+                max_total_chunks: 10,
+                body_limits: BodyLimits {
+                    chunk: Some(32 * 1024 * 1024),
+                    ..BodyLimits::default()
+                },
+                ..ServerConfig::default()
+            },
+        );
+        let capabilities = |query: &str| {
+            Request::builder()
+                .uri(format!("/capabilities{}", query))
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+
+        let res = service.call(capabilities("")).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(
+            body["recommended_chunk_size"],
+            crate::constants::DEFAULT_RECOMMENDED_CHUNK_SIZE
+        );
+        assert!(body["recommended_total_chunks"].is_null());
+
+        // Small files go in one chunk.
+        let res = service.call(capabilities("?file_size=1000")).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["recommended_chunk_size"], 1000);
+        assert_eq!(body["recommended_total_chunks"], 1);
+
+        // Large ones in chunks big enough to stay within the chunk count.
+        let res = service
+            .call(capabilities("?file_size=209715200"))
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["recommended_chunk_size"], 20 * 1024 * 1024);
+        assert_eq!(body["recommended_total_chunks"], 10);
+
+        let err = service
+            .call(capabilities("?file_size=big"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SliceBreadServerError::BadRequest(_)));
+    }
 
+    #[tokio::test]
+    async fn test_tenant_quota_is_enforced_and_reported() {
         let temp_dir = TempDir::new("upload_test").unwrap();
         let upload_dir = temp_dir.path().join("uploads");
-        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
-
-        let service = Arc::new(SliceBreadServer::<Full<Bytes>>::new(
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
             upload_dir.to_str().unwrap().to_string(),
-        ));
-
-        let file_id = "fileConcurrent";
-        let file_name = "multi.txt";
-
-        let chunks = vec!["One", "Two", "Three", "Four"];
+            ServerConfig {
+                tenant_quota_bytes: Some(8),
+                ..Default::default()
+            },
+        );
 
-        let futures = chunks.iter().enumerate().map(|(i, chunk)| {
-            let service = Arc::clone(&service);
-            let req = Request::builder()
+        let chunk = |chunk_index: &str, data: &'static str| {
+            Request::builder()
                 .method("POST")
-                .header("X-File-Id", file_id)
-                .header("X-File-Name", file_name)
-                .header("X-Chunk-Index", i.to_string())
-                .header("X-Total-Chunks", chunks.len().to_string())
-                .body(Full::new(Bytes::from(chunk.to_string())).into())
-                .unwrap();
-            async move {
-                let res = service.call(req).await;
-                assert!(res.is_ok());
+                .header("X-File-Id", "fileQuota")
+                .header("X-File-Name", "quota.txt")
+                .header("X-Chunk-Index", chunk_index)
+                .header("X-Total-Chunks", "2")
+                .header("X-Tenant-Id", "acme")
+                .body(Full::new(Bytes::from(data)))
+                .unwrap()
+        };
+        service.call(chunk("0", "Hello")).await.unwrap();
+        let err = service.call(chunk("1", "World")).await.unwrap_err();
+        assert!(matches!(
+            err,
+            SliceBreadServerError::InsufficientStorage {
+                limit: 8,
+                used: 5,
+                ..
             }
-        });
-
-        join_all(futures).await;
+        ));
+        assert_eq!(err.into_response().headers()["X-Quota-Remaining"], "3");
 
-        let final_path = upload_dir.join(file_id).join(file_name);
-        let result = tokio::fs::read_to_string(final_path).await.unwrap();
+        let quota = |tenant: &str| {
+            Request::builder()
+                .uri("/quota")
+                .header("X-Tenant-Id", tenant)
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+        let res = service.call(quota("acme")).await.unwrap();
+        assert_eq!(res.headers()["X-Quota-Used"], "5");
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["limit"], 8);
+        assert_eq!(body["remaining"], 3);
+        let res = service.call(quota("other")).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["used"], 0);
+
+        // Completed files count after a restart, and deleting them frees the quota.
+        let file = |file_id: &str, data: &'static str| {
+            Request::builder()
+                .method("POST")
+                .header("X-File-Id", file_id)
+                .header("X-File-Name", "small.txt")
+                .header("X-Chunk-Index", "0")
+                .header("X-Total-Chunks", "1")
+                .header("X-Tenant-Id", "acme")
+                .body(Full::new(Bytes::from(data)))
+                .unwrap()
+        };
+        let res = service.call(file("fileSmall", "abc")).await.unwrap();
+        assert_eq!(res.status(), 201);
+        let service = SliceBreadServer::<Full<Bytes>>::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            ServerConfig {
+                tenant_quota_bytes: Some(8),
+                ..Default::default()
+            },
+        );
+        assert_eq!(service.restore_usage().await.unwrap(), 1);
+        let res = service.call(quota("acme")).await.unwrap();
+        assert_eq!(res.headers()["X-Quota-Used"], "3");
+        let err = service.call(file("fileBig", "123456")).await.unwrap_err();
+        assert!(matches!(
+            err,
+            SliceBreadServerError::InsufficientStorage { used: 3, .. }
+        ));
 
-        for chunk in &chunks {
-            assert!(result.contains(chunk));
-        }
+        let delete = Request::builder()
+            .method("DELETE")
+            .uri("/files/fileSmall")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        assert_eq!(service.call(delete).await.unwrap().status(), 204);
+        let res = service.call(quota("acme")).await.unwrap();
+        assert_eq!(res.headers()["X-Quota-Used"], "0");
+        let res = service.call(file("fileBig", "123456")).await.unwrap();
+        assert_eq!(res.status(), 201);
     }
 }
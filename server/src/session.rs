@@ -0,0 +1,1670 @@
+use std::{
+    collections::{HashMap, VecDeque, hash_map},
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use hyper::{HeaderMap, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bitmap::ChunkBitmap,
+    body::ResponseBody,
+    bundle::BundleMember,
+    checksum::{self, ChunkDigest},
+    constants,
+    digest::ExpectedDigest,
+    error::SliceBreadServerError,
+    histogram::UploadHistograms,
+    journal::{ChunkJournal, JournalRecord},
+    ranges::RangeSet,
+    share::Share,
+    stats::{StatsSnapshot, StorageStats, Usage},
+    tags::Tags,
+    user_metadata::UserMetadata,
+};
+
+/// Metadata a client declares on the first chunk of an upload; every later
+/// chunk for the same file id must agree with it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub tenant: String,
+    pub file_name: String,
+    pub total_chunks: usize,
+    pub content_type: String,
+    pub repr_digests: Vec<ExpectedDigest>,
+    /// Size of the whole file, from `X-File-Size`, if the client knows it.
+    pub file_size: Option<u64>,
+    /// Sent as byte ranges at arbitrary offsets rather than indexed chunks.
+    pub byte_ranges: bool,
+    /// Unpack the assembled archive, from `X-Extract`, which any chunk may set.
+    pub extract: bool,
+    /// Wait for `POST /uploads/{file_id}/complete` rather than assembling as
+    /// soon as the last chunk arrives, from `X-Defer-Assembly`.
+    pub defer_assembly: bool,
+    /// How long the upload may take before it expires, counted from its first
+    /// chunk. Later chunks may shorten it but not extend it.
+    pub max_duration: Option<Duration>,
+    /// Client that started the upload, counted against its session limit.
+    pub client_ip: Option<IpAddr>,
+    /// Attempt at the upload, from `X-Upload-Generation`. A client restarts an
+    /// upload by sending a higher one, which discards what the last attempt stored.
+    pub generation: u64,
+    /// Bundle the file belongs to, which assembles it only when the whole
+    /// bundle is committed.
+    pub bundle: Option<BundleMember>,
+    /// Principal that started the upload, if the client was authenticated.
+    pub owner: Option<String>,
+    /// Access the owner grants others, from `X-Shared-With`.
+    pub shares: Vec<Share>,
+    /// Kind of upload, from `X-Upload-Category`, which retention rules can single out.
+    pub category: Option<String>,
+    /// Labels from `X-Upload-Tags`.
+    pub tags: Tags,
+    /// Client metadata from `X-Meta-*` headers.
+    pub user_metadata: UserMetadata,
+    /// SHA-256 the upload policy requires of the assembled file.
+    pub required_sha256: Option<ChunkDigest>,
+}
+
+/// Caps on uploads in progress at once, so one client can't monopolize the
+/// server; `None` disables the corresponding check.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionLimits {
+    pub max_per_client: Option<usize>,
+    pub max_per_tenant: Option<usize>,
+}
+
+struct SessionEntry {
+    session: Session,
+    started_at: DateTime<Utc>,
+    chunks: HashMap<usize, ChunkRecord>,
+    /// Chunks on disk, including ones stored before a restart that have no record.
+    received: ChunkBitmap,
+    ranges: RangeSet,
+    assembly: Option<Assembly>,
+    /// Chunk requests for an index that was already claimed, or whose write failed.
+    retries: u64,
+}
+
+/// How far the assembly of an upload has got, in chunks, or in bytes for byte ranges.
+struct Assembly {
+    done: u64,
+    total: u64,
+    cancelled: bool,
+}
+
+impl SessionEntry {
+    fn is_complete(&self) -> bool {
+        if self.session.byte_ranges {
+            self.session.file_size == Some(self.ranges.covered())
+        } else {
+            self.received.is_full()
+        }
+    }
+
+    fn expires_at(&self) -> Option<DateTime<Utc>> {
+        let max_duration = chrono::Duration::from_std(self.session.max_duration?).ok()?;
+        self.started_at.checked_add_signed(max_duration)
+    }
+
+    fn progress(&self) -> Progress {
+        let chunks_received = self.received.count();
+        let total_chunks = self.session.total_chunks;
+        let bytes_received: u64 = if self.session.byte_ranges {
+            self.ranges.covered()
+        } else {
+            self.chunks
+                .values()
+                .filter(|chunk| chunk.written)
+                .map(|chunk| chunk.size)
+                .sum()
+        };
+        let (bytes_total, bytes_total_estimated) = match self.session.file_size {
+            Some(size) => (size, false),
+            None if chunks_received == 0 => (0, true),
+            None => (
+                bytes_received * total_chunks as u64 / chunks_received as u64,
+                chunks_received < total_chunks,
+            ),
+        };
+
+        let elapsed = (Utc::now() - self.started_at).as_seconds_f64();
+        let remaining = bytes_total.saturating_sub(bytes_received);
+        let eta_seconds = (bytes_received > 0 && elapsed > 0.0)
+            .then(|| (remaining as f64 / (bytes_received as f64 / elapsed)).ceil() as u64);
+
+        Progress {
+            chunks_received,
+            total_chunks,
+            bytes_received,
+            bytes_total,
+            bytes_total_estimated,
+            eta_seconds,
+            assembly_percent: self.assembly.as_ref().map(|assembly| match assembly.total {
+                0 => 100.0,
+                total => (assembly.done as f64 * 1000.0 / total as f64).round() / 10.0,
+            }),
+            expires_at: self.expires_at(),
+        }
+    }
+}
+
+/// An upload in flight as written out on shutdown, so that a restarted server
+/// resumes it where it left off rather than from chunk 0.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    pub file_id: String,
+    pub session: Session,
+    pub started_at: DateTime<Utc>,
+    /// Chunks written, with the digest and size recorded for each.
+    chunks: Vec<ChunkSnapshot>,
+    /// Chunk indexes on disk, including ones stored before an earlier restart
+    /// that have no record.
+    received: Vec<usize>,
+    /// Byte ranges written, as half-open intervals.
+    ranges: Vec<(u64, u64)>,
+    #[serde(default)]
+    retries: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ChunkSnapshot {
+    index: usize,
+    sha256: String,
+    size: u64,
+}
+
+impl SessionSnapshot {
+    /// An upload with nothing written yet.
+    pub fn new(file_id: String, session: Session, started_at: DateTime<Utc>) -> Self {
+        Self {
+            file_id,
+            session,
+            started_at,
+            chunks: Vec::new(),
+            received: Vec::new(),
+            ranges: Vec::new(),
+            retries: 0,
+        }
+    }
+
+    /// Adds a written chunk. Adding the same index again doesn't count its
+    /// bytes twice on `restore`.
+    pub fn add_chunk(&mut self, index: usize, sha256: String, size: u64) {
+        self.chunks.push(ChunkSnapshot {
+            index,
+            sha256,
+            size,
+        });
+        self.received.push(index);
+    }
+
+    pub fn add_range(&mut self, start: u64, end: u64) {
+        self.ranges.push((start, end));
+    }
+
+    /// The journal records that rebuild this upload, stamped `at`.
+    pub fn journal_records(&self, at: DateTime<Utc>) -> Vec<JournalRecord> {
+        let file_id = || self.file_id.clone();
+        let mut records = vec![JournalRecord::Session {
+            file_id: file_id(),
+            session: Box::new(self.session.clone()),
+            started_at: self.started_at,
+        }];
+        records.extend(self.chunks.iter().map(|chunk| JournalRecord::Chunk {
+            file_id: file_id(),
+            index: chunk.index,
+            size: chunk.size,
+            sha256: chunk.sha256.clone(),
+            at,
+        }));
+        records.extend(
+            self.ranges
+                .iter()
+                .map(|&(start, end)| JournalRecord::Range {
+                    file_id: file_id(),
+                    start,
+                    end,
+                    at,
+                }),
+        );
+        records
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ChunkRecord {
+    digest: ChunkDigest,
+    size: u64,
+    /// False while the request that reserved the index is still writing it.
+    written: bool,
+}
+
+/// Outcome of reserving a chunk index, so that clients uploading the same
+/// file in parallel never overwrite each other's chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkClaim {
+    /// The caller must write the chunk, then commit or release it.
+    Reserved,
+    /// Identical content is already stored.
+    Duplicate,
+    /// Another request is writing identical content right now.
+    InProgress,
+    /// Different content is stored, or being written, under this index.
+    Conflict,
+}
+
+/// Byte-level progress of an upload, as reported by its status endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Progress {
+    pub chunks_received: usize,
+    pub total_chunks: usize,
+    pub bytes_received: u64,
+    pub bytes_total: u64,
+    /// Without `X-File-Size`, the total is extrapolated from the average chunk received so far.
+    pub bytes_total_estimated: bool,
+    /// Seconds left at the average rate since the upload started.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta_seconds: Option<u64>,
+    /// Set while the file is being assembled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assembly_percent: Option<f64>,
+    /// When an upload with a maximum duration will be expired if unfinished.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Response recorded for an Idempotency-Key so a retried request gets the
+/// same answer without being processed again.
+#[derive(Debug, Clone)]
+pub struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: ResponseBody,
+}
+
+impl StoredResponse {
+    pub fn into_response(self) -> Result<Response<ResponseBody>, hyper::http::Error> {
+        let mut builder = Response::builder().status(self.status);
+        if let Some(headers) = builder.headers_mut() {
+            headers.extend(self.headers);
+        }
+        builder
+            .header(constants::HEADER_IDEMPOTENT_REPLAYED, "true")
+            .body(self.body)
+    }
+}
+
+/// Idempotency-Keys are scoped to the tenant that sent them, so one tenant
+/// can't replay another's response by guessing its key.
+type IdempotencyKey = (String, String);
+
+enum IdempotencyEntry {
+    InProgress {
+        fingerprint: ChunkDigest,
+        file_id: String,
+        started: Instant,
+    },
+    Completed {
+        fingerprint: ChunkDigest,
+        file_id: String,
+        started: Instant,
+        completed: Instant,
+        response: StoredResponse,
+    },
+}
+
+impl IdempotencyEntry {
+    fn fingerprint(&self) -> &ChunkDigest {
+        match self {
+            Self::InProgress { fingerprint, .. } | Self::Completed { fingerprint, .. } => {
+                fingerprint
+            }
+        }
+    }
+
+    fn file_id(&self) -> &str {
+        match self {
+            Self::InProgress { file_id, .. } | Self::Completed { file_id, .. } => file_id,
+        }
+    }
+
+    fn started(&self) -> Instant {
+        match self {
+            Self::InProgress { started, .. } | Self::Completed { started, .. } => *started,
+        }
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        let since = match self {
+            Self::InProgress { started, .. } => started,
+            Self::Completed { completed, .. } => completed,
+        };
+        now.duration_since(*since) > constants::IDEMPOTENCY_KEY_TTL
+    }
+}
+
+/// Stored Idempotency-Keys, with the order they were first stored in so that
+/// the oldest can be evicted without a scan once `MAX_IDEMPOTENCY_KEYS` are held.
+#[derive(Default)]
+struct IdempotencyKeys {
+    entries: HashMap<IdempotencyKey, IdempotencyEntry>,
+    /// A key released and stored again is queued again; the older item no
+    /// longer matches the entry's start and is skipped.
+    order: VecDeque<(Instant, IdempotencyKey)>,
+}
+
+impl IdempotencyKeys {
+    fn insert(&mut self, key: IdempotencyKey, entry: IdempotencyEntry) {
+        while self.entries.len() >= constants::MAX_IDEMPOTENCY_KEYS {
+            let Some((started, oldest)) = self.order.pop_front() else {
+                break;
+            };
+            if self
+                .entries
+                .get(&oldest)
+                .is_some_and(|entry| entry.started() == started)
+            {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.order.push_back((entry.started(), key.clone()));
+        self.entries.insert(key, entry);
+        if self.order.len() > 2 * constants::MAX_IDEMPOTENCY_KEYS {
+            self.forget_released();
+        }
+    }
+
+    /// Drops the queue items of keys no longer stored.
+    fn forget_released(&mut self) {
+        let entries = &self.entries;
+        self.order.retain(|(started, key)| {
+            entries
+                .get(key)
+                .is_some_and(|entry| entry.started() == *started)
+        });
+    }
+}
+
+pub enum IdempotencyState<'a> {
+    /// First time this key is seen; the caller must process the request.
+    Started(PendingKey<'a>),
+    Replay(StoredResponse),
+    InProgress,
+    /// The key was used before for a request with a different fingerprint.
+    Mismatch,
+}
+
+/// Claim on an Idempotency-Key while its request runs. Dropping it without
+/// calling `complete` (an error or a cancelled request) releases the key so the
+/// client can retry.
+pub struct PendingKey<'a> {
+    store: &'a SessionStore,
+    key: IdempotencyKey,
+    completed: bool,
+}
+
+impl PendingKey<'_> {
+    pub fn complete(mut self, response: &Response<ResponseBody>) {
+        let mut keys = self.store.lock_idempotency();
+        if let Some(entry) = keys.entries.get_mut(&self.key) {
+            *entry = IdempotencyEntry::Completed {
+                fingerprint: *entry.fingerprint(),
+                file_id: entry.file_id().to_string(),
+                started: entry.started(),
+                completed: Instant::now(),
+                response: StoredResponse {
+                    status: response.status(),
+                    headers: response.headers().clone(),
+                    body: response.body().clone(),
+                },
+            };
+        }
+        self.completed = true;
+    }
+}
+
+impl Drop for PendingKey<'_> {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.store.lock_idempotency().entries.remove(&self.key);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, SessionEntry>>,
+    idempotency_keys: Mutex<IdempotencyKeys>,
+    /// Single-use upload policies seen, by nonce: the file id each is bound
+    /// to and when the policy expires.
+    policy_nonces: Mutex<HashMap<String, (String, DateTime<Utc>)>>,
+    stats: StorageStats,
+    histograms: UploadHistograms,
+    limits: SessionLimits,
+    journal: Mutex<Option<ChunkJournal>>,
+}
+
+impl SessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(limits: SessionLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Appends every later change to uploads in flight to `journal`, in place
+    /// of any journal set before.
+    pub fn set_journal(&self, journal: ChunkJournal) {
+        *self.journal.lock().expect("chunk journal lock poisoned") = Some(journal);
+    }
+
+    /// Appends to the journal, if there is one. Called with the sessions
+    /// locked, so that records go out in the order the changes were made.
+    fn journal(&self, record: impl FnOnce() -> JournalRecord) {
+        if let Some(journal) = &mut *self.journal.lock().expect("chunk journal lock poisoned")
+            && let Err(err) = journal.append(record())
+        {
+            tracing::warn!(%err, "Failed to append to the chunk journal");
+        }
+    }
+
+    /// Waits until everything journaled so far is written, and synced if the
+    /// journal syncs.
+    pub async fn journal_flushed(&self) {
+        let flushed = self
+            .journal
+            .lock()
+            .expect("chunk journal lock poisoned")
+            .as_ref()
+            .map(ChunkJournal::flushed);
+        if let Some(flushed) = flushed {
+            flushed.await;
+        }
+    }
+
+    /// Rewrites the journal to hold just the uploads in flight, if anything
+    /// was appended since it last was, so it doesn't grow with every upload
+    /// the process takes. Run periodically.
+    pub fn compact_journal(&self) {
+        let sessions = self.sessions.lock().expect("session store lock poisoned");
+        if let Some(journal) = &mut *self.journal.lock().expect("chunk journal lock poisoned")
+            && journal.has_grown()
+        {
+            let at = Utc::now();
+            let records = snapshot_of(&sessions)
+                .iter()
+                .flat_map(|upload| upload.journal_records(at))
+                .collect();
+            if let Err(err) = journal.rewrite(records) {
+                tracing::warn!(%err, "Failed to compact the chunk journal");
+            }
+        }
+    }
+
+    /// Records `declared` for `file_id` if this is the first chunk seen, otherwise
+    /// checks it against what was recorded before. Returns the recorded session.
+    pub fn register(
+        &self,
+        file_id: &str,
+        declared: Session,
+    ) -> Result<Session, SliceBreadServerError> {
+        let mut sessions = self.sessions.lock().expect("session store lock poisoned");
+        let Some(SessionEntry {
+            session: existing, ..
+        }) = sessions.get_mut(file_id)
+        else {
+            self.check_limits(&sessions, &declared)?;
+            self.stats.upload_started(&declared.tenant);
+            sessions.insert(
+                file_id.to_string(),
+                SessionEntry {
+                    session: declared.clone(),
+                    started_at: Utc::now(),
+                    chunks: HashMap::new(),
+                    received: ChunkBitmap::new(declared.total_chunks),
+                    ranges: RangeSet::new(),
+                    assembly: None,
+                    retries: 0,
+                },
+            );
+            self.journal(|| JournalRecord::Session {
+                file_id: file_id.to_string(),
+                session: Box::new(declared.clone()),
+                started_at: sessions[file_id].started_at,
+            });
+            return Ok(declared);
+        };
+        let before = existing.clone();
+
+        if existing.tenant != declared.tenant {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "File id {} belongs to a different tenant",
+                file_id
+            )));
+        }
+        if existing.file_name != declared.file_name {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "File name mismatch for {}: expected {}, got {}",
+                file_id, existing.file_name, declared.file_name
+            )));
+        }
+        if existing.byte_ranges != declared.byte_ranges {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "File id {} is already being uploaded {}",
+                file_id,
+                if existing.byte_ranges {
+                    "as byte ranges"
+                } else {
+                    "in chunks"
+                }
+            )));
+        }
+        if existing.total_chunks != declared.total_chunks {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "Total chunks mismatch for {}: expected {}, got {}",
+                file_id, existing.total_chunks, declared.total_chunks
+            )));
+        }
+        if existing.owner.is_some() && declared.owner.is_some() && existing.owner != declared.owner
+        {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "File id {} belongs to a different owner",
+                file_id
+            )));
+        }
+        if existing.bundle != declared.bundle {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "Bundle mismatch for {}",
+                file_id
+            )));
+        }
+        if existing.generation != declared.generation {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "Generation mismatch for {}: expected {}, got {}",
+                file_id, existing.generation, declared.generation
+            )));
+        }
+
+        if let Some(size) = declared.file_size {
+            match existing.file_size {
+                None => existing.file_size = Some(size),
+                Some(existing_size) if existing_size != size => {
+                    return Err(SliceBreadServerError::Conflict(format!(
+                        "File size mismatch for {}: expected {}, got {}",
+                        file_id, existing_size, size
+                    )));
+                }
+                Some(_) => {}
+            }
+        }
+
+        if let Some(category) = declared.category {
+            match &existing.category {
+                None => existing.category = Some(category),
+                Some(existing_category) if *existing_category != category => {
+                    return Err(SliceBreadServerError::Conflict(format!(
+                        "Category mismatch for {}: expected {}, got {}",
+                        file_id, existing_category, category
+                    )));
+                }
+                Some(_) => {}
+            }
+        }
+
+        if !declared.tags.is_empty() {
+            if existing.tags.is_empty() {
+                existing.tags = declared.tags;
+            } else if existing.tags != declared.tags {
+                return Err(SliceBreadServerError::Conflict(format!(
+                    "{} mismatch for {}",
+                    constants::HEADER_UPLOAD_TAGS,
+                    file_id
+                )));
+            }
+        }
+
+        if !declared.user_metadata.is_empty() {
+            if existing.user_metadata.is_empty() {
+                existing.user_metadata = declared.user_metadata;
+            } else if existing.user_metadata != declared.user_metadata {
+                return Err(SliceBreadServerError::Conflict(format!(
+                    "{}* mismatch for {}",
+                    constants::USER_METADATA_PREFIX,
+                    file_id
+                )));
+            }
+        }
+
+        if let Some(sha256) = declared.required_sha256 {
+            match existing.required_sha256 {
+                None => existing.required_sha256 = Some(sha256),
+                Some(existing_sha256) if existing_sha256 != sha256 => {
+                    return Err(SliceBreadServerError::Conflict(format!(
+                        "Upload policy digest mismatch for {}",
+                        file_id
+                    )));
+                }
+                Some(_) => {}
+            }
+        }
+
+        existing.extract |= declared.extract;
+        existing.defer_assembly |= declared.defer_assembly;
+        existing.max_duration = match (existing.max_duration, declared.max_duration) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+
+        // The whole-file digest may only be known once the last chunk is sent.
+        if !declared.repr_digests.is_empty() {
+            if existing.repr_digests.is_empty() {
+                existing.repr_digests = declared.repr_digests;
+            } else if existing.repr_digests != declared.repr_digests {
+                return Err(SliceBreadServerError::Conflict(format!(
+                    "{} mismatch for {}",
+                    constants::HEADER_REPR_DIGEST,
+                    file_id
+                )));
+            }
+        }
+
+        let session = existing.clone();
+        if session != before {
+            let started_at = sessions[file_id].started_at;
+            self.journal(|| JournalRecord::Session {
+                file_id: file_id.to_string(),
+                session: Box::new(session.clone()),
+                started_at,
+            });
+        }
+        Ok(session)
+    }
+
+    /// What was declared for `file_id`, if an upload is in flight.
+    pub fn session(&self, file_id: &str) -> Option<Session> {
+        self.sessions
+            .lock()
+            .expect("session store lock poisoned")
+            .get(file_id)
+            .map(|entry| entry.session.clone())
+    }
+
+    pub fn started_at(&self, file_id: &str) -> Option<DateTime<Utc>> {
+        self.sessions
+            .lock()
+            .expect("session store lock poisoned")
+            .get(file_id)
+            .map(|entry| entry.started_at)
+    }
+
+    pub fn chunk_digest(&self, file_id: &str, chunk_index: usize) -> Option<ChunkDigest> {
+        self.sessions
+            .lock()
+            .expect("session store lock poisoned")
+            .get(file_id)
+            .and_then(|entry| entry.chunks.get(&chunk_index))
+            .filter(|chunk| chunk.written)
+            .map(|chunk| chunk.digest)
+    }
+
+    pub fn progress(&self, file_id: &str) -> Option<Progress> {
+        let sessions = self.sessions.lock().expect("session store lock poisoned");
+        sessions.get(file_id).map(SessionEntry::progress)
+    }
+
+    /// Progress of every upload in flight, by file id.
+    pub fn in_progress(&self) -> Vec<(String, Progress)> {
+        self.sessions
+            .lock()
+            .expect("session store lock poisoned")
+            .iter()
+            .map(|(file_id, entry)| (file_id.clone(), entry.progress()))
+            .collect()
+    }
+
+    /// Uploads in flight that belong to `bundle_id`.
+    pub fn bundle_members(&self, bundle_id: &str) -> Vec<(String, Session)> {
+        self.sessions
+            .lock()
+            .expect("session store lock poisoned")
+            .iter()
+            .filter(|(_, entry)| {
+                entry
+                    .session
+                    .bundle
+                    .as_ref()
+                    .is_some_and(|bundle| bundle.bundle_id == bundle_id)
+            })
+            .map(|(file_id, entry)| (file_id.clone(), entry.session.clone()))
+            .collect()
+    }
+
+    /// Refuses a new upload from a tenant or client that already has its
+    /// maximum number in progress.
+    fn check_limits(
+        &self,
+        sessions: &HashMap<String, SessionEntry>,
+        declared: &Session,
+    ) -> Result<(), SliceBreadServerError> {
+        if let Some(max) = self.limits.max_per_tenant
+            && sessions
+                .values()
+                .filter(|entry| entry.session.tenant == declared.tenant)
+                .count()
+                >= max
+        {
+            return Err(SliceBreadServerError::TooManySessions(format!(
+                "Tenant {} already has {} uploads in progress",
+                declared.tenant, max
+            )));
+        }
+        if let (Some(max), Some(client_ip)) = (self.limits.max_per_client, declared.client_ip)
+            && sessions
+                .values()
+                .filter(|entry| entry.session.client_ip == Some(client_ip))
+                .count()
+                >= max
+        {
+            return Err(SliceBreadServerError::TooManySessions(format!(
+                "Client {} already has {} uploads in progress",
+                client_ip, max
+            )));
+        }
+        Ok(())
+    }
+
+    /// Stores the digest of a chunk that is already on disk, counting its bytes
+    /// towards the tenant's usage the first time the index is seen.
+    pub fn record_chunk(&self, file_id: &str, chunk_index: usize, digest: ChunkDigest, bytes: u64) {
+        if self.reserve_chunk(file_id, chunk_index, digest, bytes) == ChunkClaim::Reserved {
+            self.commit_chunk(file_id, chunk_index);
+        }
+    }
+
+    /// Claims `chunk_index` for a request about to write it.
+    pub fn reserve_chunk(
+        &self,
+        file_id: &str,
+        chunk_index: usize,
+        digest: ChunkDigest,
+        bytes: u64,
+    ) -> ChunkClaim {
+        let mut sessions = self.sessions.lock().expect("session store lock poisoned");
+        let Some(entry) = sessions.get_mut(file_id) else {
+            return ChunkClaim::Reserved;
+        };
+        let claim = match entry.chunks.get(&chunk_index) {
+            Some(chunk) if chunk.digest != digest => ChunkClaim::Conflict,
+            Some(chunk) if chunk.written => ChunkClaim::Duplicate,
+            Some(_) => ChunkClaim::InProgress,
+            None => {
+                entry.chunks.insert(
+                    chunk_index,
+                    ChunkRecord {
+                        digest,
+                        size: bytes,
+                        written: false,
+                    },
+                );
+                return ChunkClaim::Reserved;
+            }
+        };
+        entry.retries += 1;
+        claim
+    }
+
+    /// Marks a reserved chunk as written.
+    pub fn commit_chunk(&self, file_id: &str, chunk_index: usize) {
+        if let Some(entry) = self
+            .sessions
+            .lock()
+            .expect("session store lock poisoned")
+            .get_mut(file_id)
+            && let Some(chunk) = entry.chunks.get_mut(&chunk_index)
+            && !chunk.written
+        {
+            chunk.written = true;
+            entry.received.insert(chunk_index);
+            self.stats.bytes_stored(&entry.session.tenant, chunk.size);
+            self.histograms.chunk_size.observe(chunk.size as f64);
+            self.journal(|| JournalRecord::Chunk {
+                file_id: file_id.to_string(),
+                index: chunk_index,
+                size: chunk.size,
+                sha256: checksum::to_hex(&chunk.digest),
+                at: Utc::now(),
+            });
+        }
+    }
+
+    /// Gives up a reservation whose write failed.
+    pub fn release_chunk(&self, file_id: &str, chunk_index: usize) {
+        if let Some(entry) = self
+            .sessions
+            .lock()
+            .expect("session store lock poisoned")
+            .get_mut(file_id)
+            && entry
+                .chunks
+                .get(&chunk_index)
+                .is_some_and(|chunk| !chunk.written)
+        {
+            entry.chunks.remove(&chunk_index);
+            entry.retries += 1;
+        }
+    }
+
+    /// Adds chunks found on disk, e.g. after a restart, to an upload's received set.
+    pub fn restore_received(&self, file_id: &str, stored: &ChunkBitmap) {
+        if let Some(entry) = self
+            .sessions
+            .lock()
+            .expect("session store lock poisoned")
+            .get_mut(file_id)
+            && !entry.session.byte_ranges
+        {
+            entry.received.union(stored);
+        }
+    }
+
+    /// First chunk index of an upload not received yet.
+    pub fn missing_chunk(&self, file_id: &str) -> Option<usize> {
+        self.sessions
+            .lock()
+            .expect("session store lock poisoned")
+            .get(file_id)
+            .map_or(Some(0), |entry| entry.received.first_missing())
+    }
+
+    /// Records a byte range that was written, counting the bytes it newly
+    /// covers towards the tenant's usage.
+    pub fn record_range(&self, file_id: &str, start: u64, end: u64) {
+        if let Some(entry) = self
+            .sessions
+            .lock()
+            .expect("session store lock poisoned")
+            .get_mut(file_id)
+        {
+            let added = entry.ranges.insert(start, end);
+            self.stats.bytes_stored(&entry.session.tenant, added);
+            if added > 0 {
+                self.journal(|| JournalRecord::Range {
+                    file_id: file_id.to_string(),
+                    start,
+                    end,
+                    at: Utc::now(),
+                });
+            }
+        }
+    }
+
+    /// Returns true for exactly one caller once every chunk (or byte) is written.
+    /// That caller must assemble the file, then `complete` the session or
+    /// `release_assembly` on failure.
+    pub fn claim_assembly(&self, file_id: &str) -> bool {
+        let mut sessions = self.sessions.lock().expect("session store lock poisoned");
+        let Some(entry) = sessions.get_mut(file_id) else {
+            return false;
+        };
+        if entry.assembly.is_some() || !entry.is_complete() {
+            return false;
+        }
+        let total = if entry.session.byte_ranges {
+            entry.session.file_size.unwrap_or(0)
+        } else {
+            entry.session.total_chunks as u64
+        };
+        entry.assembly = Some(Assembly {
+            done: 0,
+            total,
+            cancelled: false,
+        });
+        true
+    }
+
+    /// Whether the upload has outlived its maximum duration.
+    pub fn is_expired(&self, file_id: &str, now: DateTime<Utc>) -> bool {
+        self.sessions
+            .lock()
+            .expect("session store lock poisoned")
+            .get(file_id)
+            .and_then(SessionEntry::expires_at)
+            .is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Uploads past their maximum duration. Ones being assembled are left to finish.
+    pub fn expired(&self, now: DateTime<Utc>) -> Vec<(String, Session)> {
+        self.sessions
+            .lock()
+            .expect("session store lock poisoned")
+            .iter()
+            .filter(|(_, entry)| {
+                entry.assembly.is_none()
+                    && entry
+                        .expires_at()
+                        .is_some_and(|expires_at| expires_at <= now)
+            })
+            .map(|(file_id, entry)| (file_id.clone(), entry.session.clone()))
+            .collect()
+    }
+
+    pub fn release_assembly(&self, file_id: &str) {
+        if let Some(entry) = self
+            .sessions
+            .lock()
+            .expect("session store lock poisoned")
+            .get_mut(file_id)
+        {
+            entry.assembly = None;
+        }
+    }
+
+    /// Records assembly progress. Returns false once the assembly has been
+    /// cancelled, after which the assembler must clean up and give up its claim.
+    pub fn advance_assembly(&self, file_id: &str, done: u64) -> bool {
+        let mut sessions = self.sessions.lock().expect("session store lock poisoned");
+        match sessions
+            .get_mut(file_id)
+            .and_then(|entry| entry.assembly.as_mut())
+        {
+            Some(assembly) => {
+                assembly.done = done;
+                !assembly.cancelled
+            }
+            None => true,
+        }
+    }
+
+    /// Asks a running assembly to stop; false if none is running.
+    pub fn cancel_assembly(&self, file_id: &str) -> bool {
+        let mut sessions = self.sessions.lock().expect("session store lock poisoned");
+        match sessions
+            .get_mut(file_id)
+            .and_then(|entry| entry.assembly.as_mut())
+        {
+            Some(assembly) => {
+                assembly.cancelled = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Makes way for `generation` of an upload, returning the session of an older
+    /// generation it replaces, whose stored data the caller must then discard.
+    /// Only one caller can take a given session, and a stale generation is refused.
+    pub fn replace_generation(
+        &self,
+        file_id: &str,
+        generation: u64,
+    ) -> Result<Option<Session>, SliceBreadServerError> {
+        let mut sessions = self.sessions.lock().expect("session store lock poisoned");
+        let Some(entry) = sessions.get(file_id) else {
+            return Ok(None);
+        };
+        if entry.session.generation > generation {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "Upload {} is at generation {}, got {}",
+                file_id, entry.session.generation, generation
+            )));
+        }
+        if entry.session.generation == generation {
+            return Ok(None);
+        }
+        if entry.assembly.is_some() {
+            return Err(SliceBreadServerError::Conflict(format!(
+                "Upload {} is being assembled",
+                file_id
+            )));
+        }
+        let entry = sessions.remove(file_id).expect("entry checked above");
+        self.journal(|| JournalRecord::End {
+            file_id: file_id.to_string(),
+        });
+        self.stats
+            .upload_abandoned(&entry.session.tenant, entry.progress().bytes_received);
+        Ok(Some(entry.session))
+    }
+
+    /// Forgets an upload that finished assembling.
+    pub fn complete(&self, file_id: &str) {
+        if let Some(entry) = self.take(file_id) {
+            self.stats.upload_completed(&entry.session.tenant);
+            let elapsed = (Utc::now() - entry.started_at).num_milliseconds().max(0);
+            self.histograms
+                .upload_size
+                .observe(entry.progress().bytes_received as f64);
+            self.histograms.duration.observe(elapsed as f64 / 1000.0);
+            self.histograms.retries.observe(entry.retries as f64);
+        }
+    }
+
+    /// Forgets an upload that will not be finished, releasing the bytes its
+    /// chunks were counted for.
+    pub fn remove(&self, file_id: &str) {
+        if let Some(entry) = self.take(file_id) {
+            self.stats
+                .upload_abandoned(&entry.session.tenant, entry.progress().bytes_received);
+        }
+    }
+
+    /// Counts a completed file found on disk, e.g. at startup, towards its
+    /// tenant's usage.
+    pub fn file_stored(&self, tenant: &str, bytes: u64) {
+        self.stats.bytes_stored(tenant, bytes);
+    }
+
+    /// Releases the bytes of a completed file that was deleted.
+    pub fn file_removed(&self, tenant: &str, bytes: u64) {
+        self.stats.file_removed(tenant, bytes);
+    }
+
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    pub fn histograms(&self) -> &UploadHistograms {
+        &self.histograms
+    }
+
+    /// Uploads in flight, to be written out on shutdown. Chunks still being
+    /// written are left out, as is how far an assembly got: the next request
+    /// after the restart claims the assembly again.
+    pub fn snapshot(&self) -> Vec<SessionSnapshot> {
+        snapshot_of(&self.sessions.lock().expect("session store lock poisoned"))
+    }
+
+    /// Puts back uploads written out by `snapshot`, counting them towards usage
+    /// again. Session limits aren't checked, since the uploads were admitted
+    /// before, and uploads already in flight here are left alone. Returns how
+    /// many were restored.
+    pub fn restore(&self, snapshots: Vec<SessionSnapshot>) -> usize {
+        let mut sessions = self.sessions.lock().expect("session store lock poisoned");
+        let mut restored = 0;
+        for snapshot in snapshots {
+            let hash_map::Entry::Vacant(slot) = sessions.entry(snapshot.file_id) else {
+                continue;
+            };
+            let mut entry = SessionEntry {
+                received: ChunkBitmap::new(snapshot.session.total_chunks),
+                session: snapshot.session,
+                started_at: snapshot.started_at,
+                chunks: HashMap::new(),
+                ranges: RangeSet::new(),
+                assembly: None,
+                retries: snapshot.retries,
+            };
+            for index in snapshot.received {
+                entry.received.insert(index);
+            }
+            for chunk in snapshot.chunks {
+                // A chunk without a usable digest is still on disk, and is
+                // checked against its stored copy if it is sent again.
+                if let Some(digest) = checksum::from_hex(&chunk.sha256)
+                    && entry.received.contains(chunk.index)
+                {
+                    entry.chunks.insert(
+                        chunk.index,
+                        ChunkRecord {
+                            digest,
+                            size: chunk.size,
+                            written: true,
+                        },
+                    );
+                }
+            }
+            for (start, end) in snapshot.ranges {
+                entry.ranges.insert(start, end);
+            }
+            self.stats.upload_started(&entry.session.tenant);
+            self.stats
+                .bytes_stored(&entry.session.tenant, entry.progress().bytes_received);
+            slot.insert(entry);
+            restored += 1;
+        }
+        restored
+    }
+
+    /// Uploads whose assembly is under way.
+    pub fn assembling(&self) -> Vec<String> {
+        self.sessions
+            .lock()
+            .expect("session store lock poisoned")
+            .iter()
+            .filter(|(_, entry)| entry.assembly.is_some())
+            .map(|(file_id, _)| file_id.clone())
+            .collect()
+    }
+
+    pub fn usage(&self, tenant: &str) -> Usage {
+        self.stats.usage(tenant)
+    }
+
+    fn take(&self, file_id: &str) -> Option<SessionEntry> {
+        let mut sessions = self.sessions.lock().expect("session store lock poisoned");
+        let entry = sessions.remove(file_id)?;
+        self.journal(|| JournalRecord::End {
+            file_id: file_id.to_string(),
+        });
+        Some(entry)
+    }
+
+    /// Claims `key` for `tenant`'s request to `file_id`, or says why it can't
+    /// be. Expired keys are still answered until `expire_idempotency_keys`
+    /// drops them.
+    pub fn begin_idempotent(
+        &self,
+        tenant: &str,
+        key: &str,
+        file_id: &str,
+        fingerprint: ChunkDigest,
+    ) -> IdempotencyState<'_> {
+        let now = Instant::now();
+        let key = (tenant.to_string(), key.to_string());
+        let mut keys = self.lock_idempotency();
+
+        match keys
+            .entries
+            .get(&key)
+            .filter(|entry| !entry.is_expired(now))
+        {
+            Some(entry) if *entry.fingerprint() != fingerprint => IdempotencyState::Mismatch,
+            Some(IdempotencyEntry::InProgress { .. }) => IdempotencyState::InProgress,
+            Some(IdempotencyEntry::Completed { response, .. }) => {
+                IdempotencyState::Replay(response.clone())
+            }
+            None => {
+                keys.insert(
+                    key.clone(),
+                    IdempotencyEntry::InProgress {
+                        fingerprint,
+                        file_id: file_id.to_string(),
+                        started: now,
+                    },
+                );
+                IdempotencyState::Started(PendingKey {
+                    store: self,
+                    key,
+                    completed: false,
+                })
+            }
+        }
+    }
+
+    /// Forgets Idempotency-Keys past `IDEMPOTENCY_KEY_TTL`; run periodically.
+    pub fn expire_idempotency_keys(&self) {
+        let now = Instant::now();
+        let mut keys = self.lock_idempotency();
+        keys.entries.retain(|_, entry| !entry.is_expired(now));
+        keys.forget_released();
+    }
+
+    /// Forgets every Idempotency-Key used for `file_id`, with the responses
+    /// stored for them, so nothing of a purged file is replayed.
+    pub fn forget_idempotency_keys(&self, file_id: &str) {
+        let mut keys = self.lock_idempotency();
+        keys.entries.retain(|_, entry| entry.file_id() != file_id);
+        keys.forget_released();
+    }
+
+    /// Binds a single-use policy to `file_id`. Returns false if it is already
+    /// bound to another upload. Nonces are forgotten once their policy expires,
+    /// when it could not be used anyway.
+    pub fn claim_policy_nonce(
+        &self,
+        nonce: &str,
+        file_id: &str,
+        expires: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        let mut nonces = self
+            .policy_nonces
+            .lock()
+            .expect("session store lock poisoned");
+        nonces.retain(|_, (_, expires)| *expires > now);
+        let (bound, _) = nonces
+            .entry(nonce.to_string())
+            .or_insert_with(|| (file_id.to_string(), expires));
+        bound == file_id
+    }
+
+    fn lock_idempotency(&self) -> std::sync::MutexGuard<'_, IdempotencyKeys> {
+        self.idempotency_keys
+            .lock()
+            .expect("session store lock poisoned")
+    }
+}
+
+fn snapshot_of(sessions: &HashMap<String, SessionEntry>) -> Vec<SessionSnapshot> {
+    sessions
+        .iter()
+        .map(|(file_id, entry)| SessionSnapshot {
+            file_id: file_id.clone(),
+            session: entry.session.clone(),
+            started_at: entry.started_at,
+            chunks: entry
+                .chunks
+                .iter()
+                .filter(|(_, chunk)| chunk.written)
+                .map(|(&index, chunk)| ChunkSnapshot {
+                    index,
+                    sha256: checksum::to_hex(&chunk.digest),
+                    size: chunk.size,
+                })
+                .collect(),
+            received: entry.received.iter().collect(),
+            ranges: entry.ranges.iter().collect(),
+            retries: entry.retries,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(file_name: &str, total_chunks: usize) -> Session {
+        Session {
+            tenant: constants::DEFAULT_TENANT.to_string(),
+            file_name: file_name.to_string(),
+            total_chunks,
+            content_type: "text/plain".to_string(),
+            repr_digests: Vec::new(),
+            file_size: None,
+            byte_ranges: false,
+            extract: false,
+            defer_assembly: false,
+            max_duration: None,
+            client_ip: None,
+            generation: 0,
+            bundle: None,
+            owner: None,
+            shares: Vec::new(),
+            category: None,
+            tags: Tags::new(),
+            user_metadata: UserMetadata::new(),
+            required_sha256: None,
+        }
+    }
+
+    #[test]
+    fn test_matching_metadata_is_accepted() {
+        let store = SessionStore::new();
+        store.register("id", session("a.txt", 3)).unwrap();
+        store.register("id", session("a.txt", 3)).unwrap();
+    }
+
+    #[test]
+    fn test_mismatching_metadata_is_rejected() {
+        let store = SessionStore::new();
+        store.register("id", session("a.txt", 3)).unwrap();
+
+        assert!(matches!(
+            store.register("id", session("b.txt", 3)),
+            Err(SliceBreadServerError::Conflict(_))
+        ));
+        assert!(matches!(
+            store.register("id", session("a.txt", 4)),
+            Err(SliceBreadServerError::Conflict(_))
+        ));
+    }
+
+    #[test]
+    fn test_session_limits_apply_to_new_uploads_only() {
+        let store = SessionStore::with_limits(SessionLimits {
+            max_per_client: Some(1),
+            max_per_tenant: Some(2),
+        });
+        let from = |ip: &str, tenant: &str| Session {
+            client_ip: Some(ip.parse().unwrap()),
+            tenant: tenant.to_string(),
+            ..session("a.txt", 3)
+        };
+        store.register("a", from("10.0.0.1", "acme")).unwrap();
+        store.register("a", from("10.0.0.1", "acme")).unwrap();
+        assert!(matches!(
+            store.register("b", from("10.0.0.1", "acme")),
+            Err(SliceBreadServerError::TooManySessions(_))
+        ));
+        store.register("b", from("10.0.0.2", "acme")).unwrap();
+        assert!(matches!(
+            store.register("c", from("10.0.0.3", "acme")),
+            Err(SliceBreadServerError::TooManySessions(_))
+        ));
+        store.register("c", from("10.0.0.3", "other")).unwrap();
+
+        store.remove("a");
+        store.register("d", from("10.0.0.1", "acme")).unwrap();
+    }
+
+    #[test]
+    fn test_chunk_digests_are_tracked_per_session() {
+        let store = SessionStore::new();
+        store.register("id", session("a.txt", 3)).unwrap();
+        store.record_chunk("id", 1, [7; 32], 4);
+
+        assert_eq!(store.chunk_digest("id", 1), Some([7; 32]));
+        assert_eq!(store.chunk_digest("id", 0), None);
+
+        store.remove("id");
+        assert_eq!(store.chunk_digest("id", 1), None);
+    }
+
+    #[test]
+    fn test_policy_nonce_binds_to_one_upload_until_expiry() {
+        let store = SessionStore::new();
+        let now = Utc::now();
+        let expires = now + chrono::Duration::minutes(5);
+        assert!(store.claim_policy_nonce("n", "file1", expires, now));
+        assert!(store.claim_policy_nonce("n", "file1", expires, now));
+        assert!(!store.claim_policy_nonce("n", "file2", expires, now));
+        assert!(store.claim_policy_nonce("m", "file2", expires, now));
+        assert!(store.claim_policy_nonce("n", "file2", expires, expires));
+    }
+
+    #[test]
+    fn test_idempotency_key_lifecycle() {
+        let store = SessionStore::new();
+
+        let IdempotencyState::Started(pending) =
+            store.begin_idempotent("acme", "key", "file", [1; 32])
+        else {
+            panic!("expected a fresh key");
+        };
+        assert!(matches!(
+            store.begin_idempotent("acme", "key", "file", [1; 32]),
+            IdempotencyState::InProgress
+        ));
+
+        let response = Response::builder()
+            .status(201)
+            .body("done".to_string().into())
+            .unwrap();
+        pending.complete(&response);
+
+        let IdempotencyState::Replay(stored) =
+            store.begin_idempotent("acme", "key", "file", [1; 32])
+        else {
+            panic!("expected a replay");
+        };
+        let replayed = stored.into_response().unwrap();
+        assert_eq!(replayed.status(), 201);
+        assert_eq!(replayed.body(), "done");
+        assert_eq!(
+            replayed.headers()[constants::HEADER_IDEMPOTENT_REPLAYED],
+            "true"
+        );
+
+        assert!(matches!(
+            store.begin_idempotent("acme", "key", "file", [2; 32]),
+            IdempotencyState::Mismatch
+        ));
+        // Another tenant's key of the same name is its own.
+        assert!(matches!(
+            store.begin_idempotent("globex", "key", "file", [1; 32]),
+            IdempotencyState::Started(_)
+        ));
+    }
+
+    #[test]
+    fn test_oldest_idempotency_key_makes_room() {
+        let store = SessionStore::new();
+        let response = Response::builder()
+            .status(201)
+            .body(ResponseBody::default())
+            .unwrap();
+        for i in 0..=constants::MAX_IDEMPOTENCY_KEYS {
+            let IdempotencyState::Started(pending) =
+                store.begin_idempotent("acme", &i.to_string(), "file", [1; 32])
+            else {
+                panic!("expected a fresh key");
+            };
+            pending.complete(&response);
+        }
+        assert!(matches!(
+            store.begin_idempotent("acme", "1", "file", [1; 32]),
+            IdempotencyState::Replay(_)
+        ));
+        let state = store.begin_idempotent("acme", "0", "file", [1; 32]);
+        assert!(matches!(state, IdempotencyState::Started(_)));
+        assert_eq!(
+            store.lock_idempotency().entries.len(),
+            constants::MAX_IDEMPOTENCY_KEYS
+        );
+        drop(state);
+
+        store.expire_idempotency_keys();
+        assert_eq!(
+            store.lock_idempotency().order.len(),
+            constants::MAX_IDEMPOTENCY_KEYS - 1
+        );
+    }
+
+    #[test]
+    fn test_dropped_pending_key_is_released() {
+        let store = SessionStore::new();
+
+        let state = store.begin_idempotent("acme", "key", "file", [1; 32]);
+        assert!(matches!(state, IdempotencyState::Started(_)));
+        drop(state);
+
+        assert!(matches!(
+            store.begin_idempotent("acme", "key", "file", [2; 32]),
+            IdempotencyState::Started(_)
+        ));
+    }
+
+    #[test]
+    fn test_stats_follow_session_lifecycle() {
+        let store = SessionStore::new();
+        store.register("done", session("a.txt", 1)).unwrap();
+        store.record_chunk("done", 0, [1; 32], 10);
+        store.record_chunk("done", 0, [1; 32], 10);
+        store.complete("done");
+
+        store.register("open", session("b.txt", 2)).unwrap();
+        store.record_chunk("open", 0, [2; 32], 3);
+
+        let stats = store.stats();
+        assert_eq!(stats.total.bytes_stored, 13);
+        assert_eq!(stats.total.uploads_completed, 1);
+        assert_eq!(stats.total.uploads_in_progress, 1);
+        assert_eq!(stats.tenants[constants::DEFAULT_TENANT], stats.total);
+    }
+
+    #[test]
+    fn test_removed_session_can_be_redeclared() {
+        let store = SessionStore::new();
+        store.register("id", session("a.txt", 3)).unwrap();
+        store.remove("id");
+        store.register("id", session("b.txt", 1)).unwrap();
+    }
+
+    #[test]
+    fn test_progress_counts_bytes_not_chunks() {
+        let store = SessionStore::new();
+        store.register("id", session("a.txt", 4)).unwrap();
+        assert_eq!(store.progress("id").unwrap().bytes_total, 0);
+
+        store.record_chunk("id", 0, [1; 32], 100);
+        store.record_chunk("id", 3, [2; 32], 20);
+        let progress = store.progress("id").unwrap();
+        assert_eq!(progress.chunks_received, 2);
+        assert_eq!(progress.bytes_received, 120);
+        assert_eq!(progress.bytes_total, 240);
+        assert!(progress.bytes_total_estimated);
+
+        let declared = Session {
+            file_size: Some(1000),
+            ..session("a.txt", 4)
+        };
+        store.register("id", declared.clone()).unwrap();
+        let progress = store.progress("id").unwrap();
+        assert_eq!(progress.bytes_total, 1000);
+        assert!(!progress.bytes_total_estimated);
+
+        let conflicting = Session {
+            file_size: Some(999),
+            ..declared
+        };
+        assert!(matches!(
+            store.register("id", conflicting),
+            Err(SliceBreadServerError::Conflict(_))
+        ));
+        assert_eq!(store.progress("missing"), None);
+    }
+
+    #[test]
+    fn test_parallel_writers_coordinate_chunks_and_assembly() {
+        let store = SessionStore::new();
+        store.register("id", session("a.txt", 2)).unwrap();
+
+        assert_eq!(
+            store.reserve_chunk("id", 1, [1; 32], 5),
+            ChunkClaim::Reserved
+        );
+        assert_eq!(
+            store.reserve_chunk("id", 1, [1; 32], 5),
+            ChunkClaim::InProgress
+        );
+        assert_eq!(
+            store.reserve_chunk("id", 1, [2; 32], 5),
+            ChunkClaim::Conflict
+        );
+        assert_eq!(store.chunk_digest("id", 1), None);
+
+        store.release_chunk("id", 1);
+        assert_eq!(
+            store.reserve_chunk("id", 1, [2; 32], 5),
+            ChunkClaim::Reserved
+        );
+        store.commit_chunk("id", 1);
+        assert_eq!(
+            store.reserve_chunk("id", 1, [2; 32], 5),
+            ChunkClaim::Duplicate
+        );
+        assert!(!store.claim_assembly("id"));
+
+        store.record_chunk("id", 0, [3; 32], 5);
+        assert!(store.claim_assembly("id"));
+        assert!(!store.claim_assembly("id"));
+
+        store.release_assembly("id");
+        assert!(store.claim_assembly("id"));
+        assert_eq!(store.stats().total.bytes_stored, 10);
+    }
+
+    #[test]
+    fn test_byte_ranges_complete_once_the_whole_file_is_covered() {
+        let store = SessionStore::new();
+        let declared = Session {
+            file_size: Some(10),
+            byte_ranges: true,
+            ..session("a.bin", 1)
+        };
+        store.register("id", declared.clone()).unwrap();
+        assert!(matches!(
+            store.register("id", session("a.bin", 1)),
+            Err(SliceBreadServerError::Conflict(_))
+        ));
+
+        store.record_range("id", 6, 10);
+        store.record_range("id", 0, 4);
+        store.record_range("id", 2, 4);
+        assert!(!store.claim_assembly("id"));
+        assert_eq!(store.progress("id").unwrap().bytes_received, 8);
+
+        store.record_range("id", 3, 7);
+        assert!(store.claim_assembly("id"));
+        assert_eq!(store.stats().total.bytes_stored, 10);
+    }
+
+    #[test]
+    fn test_assembly_reports_progress_and_can_be_cancelled() {
+        let store = SessionStore::new();
+        store.register("id", session("a.bin", 4)).unwrap();
+        assert!(!store.cancel_assembly("id"));
+        for index in 0..4 {
+            store.record_chunk("id", index, [index as u8; 32], 5);
+        }
+        assert_eq!(store.progress("id").unwrap().assembly_percent, None);
+
+        assert!(store.claim_assembly("id"));
+        assert!(store.advance_assembly("id", 1));
+        assert_eq!(store.progress("id").unwrap().assembly_percent, Some(25.0));
+
+        assert!(store.cancel_assembly("id"));
+        assert!(!store.advance_assembly("id", 2));
+        store.release_assembly("id");
+        assert_eq!(store.progress("id").unwrap().assembly_percent, None);
+        assert!(store.claim_assembly("id"));
+        assert!(store.advance_assembly("id", 2));
+    }
+
+    #[test]
+    fn test_newer_generation_replaces_the_session() {
+        let store = SessionStore::new();
+        let generation = |generation| Session {
+            generation,
+            ..session("a.bin", 2)
+        };
+        store.register("id", generation(1)).unwrap();
+        store.record_chunk("id", 0, [0; 32], 5);
+
+        assert!(store.replace_generation("id", 1).unwrap().is_none());
+        assert!(matches!(
+            store.replace_generation("id", 0),
+            Err(SliceBreadServerError::Conflict(_))
+        ));
+        assert!(matches!(
+            store.register("id", generation(2)),
+            Err(SliceBreadServerError::Conflict(_))
+        ));
+
+        let replaced = store.replace_generation("id", 2).unwrap().unwrap();
+        assert_eq!(replaced.generation, 1);
+        assert!(store.replace_generation("id", 3).unwrap().is_none());
+        store.register("id", generation(2)).unwrap();
+        assert_eq!(store.progress("id").unwrap().bytes_received, 0);
+    }
+
+    #[test]
+    fn test_snapshot_restores_chunks_and_counters() {
+        let store = SessionStore::new();
+        store.register("id", session("a.txt", 3)).unwrap();
+        store.record_chunk("id", 0, [1; 32], 10);
+        store.reserve_chunk("id", 2, [2; 32], 7);
+        let mut on_disk = ChunkBitmap::new(3);
+        on_disk.insert(1);
+        store.restore_received("id", &on_disk);
+        let snapshot = store.snapshot();
+
+        let restored = SessionStore::new();
+        let json = serde_json::to_vec(&snapshot).unwrap();
+        assert_eq!(restored.restore(serde_json::from_slice(&json).unwrap()), 1);
+        assert_eq!(restored.session("id"), store.session("id"));
+        assert_eq!(restored.started_at("id"), store.started_at("id"));
+        assert_eq!(restored.chunk_digest("id", 0), Some([1; 32]));
+        // The chunk still being written is uploaded again.
+        assert_eq!(restored.missing_chunk("id"), Some(2));
+        assert_eq!(
+            restored.reserve_chunk("id", 2, [2; 32], 7),
+            ChunkClaim::Reserved
+        );
+        let stats = restored.stats();
+        assert_eq!(stats.total.bytes_stored, 10);
+        assert_eq!(stats.total.uploads_in_progress, 1);
+
+        // Uploads already in flight win over the snapshot.
+        assert_eq!(restored.restore(snapshot), 0);
+        assert_eq!(restored.stats().total.uploads_in_progress, 1);
+    }
+}
@@ -0,0 +1,103 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::hmac;
+
+/// What a share lets its holder do with a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShareAccess {
+    Read,
+    ReadWrite,
+}
+
+/// Access to a file granted by its owner, either to a named principal or to
+/// whoever holds a signed link.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Share {
+    pub id: String,
+    /// `None` for a link share.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub principal: Option<String>,
+    pub access: ShareAccess,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Share {
+    pub fn new(
+        principal: Option<String>,
+        access: ShareAccess,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Self {
+        Self {
+            id: URL_SAFE_NO_PAD.encode(rand::random::<[u8; 16]>()),
+            principal,
+            access,
+            expires_at,
+        }
+    }
+
+    /// Whether the share is live at `now` and covers writes if `write` is set.
+    pub fn allows(&self, write: bool, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+            && (!write || self.access == ShareAccess::ReadWrite)
+    }
+}
+
+/// Token for the link share `share_id` on `file_id`, sent as `?share=`.
+/// Encoded as `share_id "." base64url(HMAC-SHA256(secret, file_id "/" share_id))`,
+/// so it is only good for the file it was made for.
+pub fn sign_link(file_id: &str, share_id: &str, secret: &[u8]) -> String {
+    let tag = hmac::sign(
+        &hmac::Key::new(hmac::HMAC_SHA256, secret),
+        format!("{}/{}", file_id, share_id).as_bytes(),
+    );
+    format!("{}.{}", share_id, URL_SAFE_NO_PAD.encode(tag.as_ref()))
+}
+
+/// The share id in `token`, if it was signed for `file_id`.
+pub fn verify_link<'a>(token: &'a str, file_id: &str, secret: &[u8]) -> Option<&'a str> {
+    let (share_id, tag) = token.split_once('.')?;
+    let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+    hmac::verify(
+        &hmac::Key::new(hmac::HMAC_SHA256, secret),
+        format!("{}/{}", file_id, share_id).as_bytes(),
+        &tag,
+    )
+    .ok()?;
+    Some(share_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_links_are_bound_to_their_file() {
+        let token = sign_link("fileA", "share1", b"secret");
+        assert_eq!(verify_link(&token, "fileA", b"secret"), Some("share1"));
+        assert_eq!(verify_link(&token, "fileB", b"secret"), None);
+        assert_eq!(verify_link(&token, "fileA", b"other"), None);
+        assert_eq!(verify_link("share1", "fileA", b"secret"), None);
+    }
+
+    #[test]
+    fn test_shares_expire_and_limit_writes() {
+        let now = Utc::now();
+        let share = Share::new(
+            Some("carol".to_string()),
+            ShareAccess::Read,
+            Some(now + Duration::minutes(5)),
+        );
+        assert!(share.allows(false, now));
+        assert!(!share.allows(true, now));
+        assert!(!share.allows(false, now + Duration::minutes(5)));
+
+        let share = Share::new(None, ShareAccess::ReadWrite, None);
+        assert!(share.allows(true, now));
+    }
+}
@@ -0,0 +1,71 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{replication::ReplicaStatus, share::Share, tags::Tags, user_metadata::UserMetadata};
+
+/// Contents of `<file_name>.meta.json`, written next to every assembled file so
+/// downstream jobs can pick up uploads without asking the server about them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileMetadata {
+    pub file_id: String,
+    pub file_name: String,
+    pub size: u64,
+    pub sha256: String,
+    pub merkle_root: String,
+    pub content_type: String,
+    pub uploader: String,
+    pub started_at: DateTime<Utc>,
+    pub completed_at: DateTime<Utc>,
+    /// Principal that uploaded the file, when uploads are authenticated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shares: Vec<Share>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(default, skip_serializing_if = "Tags::is_empty")]
+    pub tags: Tags,
+    /// Client metadata from `X-Meta-*` headers, keyed without the prefix.
+    #[serde(default, skip_serializing_if = "UserMetadata::is_empty")]
+    pub user_metadata: UserMetadata,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub replication: Vec<ReplicaStatus>,
+    /// Directory next to the file that `X-Extract` unpacked it into.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extracted: Option<String>,
+}
+
+pub fn path_for(output_path: &Path) -> PathBuf {
+    let mut name = output_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".meta.json");
+    output_path.with_file_name(name)
+}
+
+pub async fn read(output_path: &Path) -> std::io::Result<FileMetadata> {
+    let json = tokio::fs::read(path_for(output_path)).await?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+/// Writes the sidecar through a temporary file so readers never see a partial one.
+pub async fn write(output_path: &Path, metadata: &FileMetadata) -> std::io::Result<()> {
+    let path = path_for(output_path);
+    let tmp = path.with_extension("json.tmp");
+    let json = serde_json::to_vec_pretty(metadata)?;
+    tokio::fs::write(&tmp, json).await?;
+    tokio::fs::rename(&tmp, &path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sidecar_sits_next_to_file() {
+        assert_eq!(
+            path_for(Path::new("uploads/abc/report.pdf")),
+            PathBuf::from("uploads/abc/report.pdf.meta.json")
+        );
+    }
+}
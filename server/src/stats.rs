@@ -0,0 +1,106 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    pub bytes_stored: u64,
+    pub uploads_in_progress: u64,
+    pub uploads_completed: u64,
+}
+
+/// Point-in-time copy of [`StorageStats`], served by `GET /admin/stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct StatsSnapshot {
+    #[serde(flatten)]
+    pub total: Usage,
+    pub tenants: BTreeMap<String, Usage>,
+}
+
+/// Storage counters kept up to date as chunks arrive and uploads finish, so
+/// reading them never has to walk the upload directory.
+#[derive(Default)]
+pub struct StorageStats {
+    inner: Mutex<StatsSnapshot>,
+}
+
+impl StorageStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn upload_started(&self, tenant: &str) {
+        self.update(tenant, |usage| usage.uploads_in_progress += 1);
+    }
+
+    pub fn bytes_stored(&self, tenant: &str, bytes: u64) {
+        self.update(tenant, |usage| usage.bytes_stored += bytes);
+    }
+
+    /// Assembly swaps the chunks for a final file of the same size, so only the
+    /// upload counters move.
+    pub fn upload_completed(&self, tenant: &str) {
+        self.update(tenant, |usage| {
+            usage.uploads_in_progress = usage.uploads_in_progress.saturating_sub(1);
+            usage.uploads_completed += 1;
+        });
+    }
+
+    /// A completed file was deleted, releasing its bytes.
+    pub fn file_removed(&self, tenant: &str, bytes: u64) {
+        self.update(tenant, |usage| {
+            usage.bytes_stored = usage.bytes_stored.saturating_sub(bytes);
+        });
+    }
+
+    pub fn upload_abandoned(&self, tenant: &str, bytes: u64) {
+        self.update(tenant, |usage| {
+            usage.uploads_in_progress = usage.uploads_in_progress.saturating_sub(1);
+            usage.bytes_stored = usage.bytes_stored.saturating_sub(bytes);
+        });
+    }
+
+    pub fn usage(&self, tenant: &str) -> Usage {
+        let inner = self.inner.lock().expect("stats lock poisoned");
+        inner.tenants.get(tenant).cloned().unwrap_or_default()
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        self.inner.lock().expect("stats lock poisoned").clone()
+    }
+
+    fn update(&self, tenant: &str, apply: impl Fn(&mut Usage)) {
+        let mut inner = self.inner.lock().expect("stats lock poisoned");
+        apply(&mut inner.total);
+        apply(inner.tenants.entry(tenant.to_string()).or_default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters_are_tracked_per_tenant_and_in_total() {
+        let stats = StorageStats::new();
+        stats.upload_started("a");
+        stats.upload_started("b");
+        stats.bytes_stored("a", 10);
+        stats.bytes_stored("b", 5);
+        stats.upload_completed("a");
+        stats.upload_abandoned("b", 5);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(
+            snapshot.total,
+            Usage {
+                bytes_stored: 10,
+                uploads_in_progress: 0,
+                uploads_completed: 1,
+            }
+        );
+        assert_eq!(snapshot.tenants["a"].uploads_completed, 1);
+        assert_eq!(snapshot.tenants["b"].bytes_stored, 0);
+        assert_eq!(snapshot.tenants["b"].uploads_completed, 0);
+    }
+}
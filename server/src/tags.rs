@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+
+use crate::constants;
+
+/// Key/value labels on an upload, from `X-Upload-Tags`.
+pub type Tags = BTreeMap<String, String>;
+
+/// Parses `key=value` pairs separated by commas, e.g. `env=prod, team=data`.
+/// Keys are letters, digits, `_`, `-` and `.`; values may be empty but can't
+/// hold commas.
+pub fn parse(value: &str) -> Result<Tags, String> {
+    let mut tags = Tags::new();
+    for pair in value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (key, value) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("Expected key=value, got: {}", pair))?;
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty()
+            || key.len() > constants::MAX_TAG_KEY_LEN
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        {
+            return Err(format!("Invalid tag key: {}", key));
+        }
+        if value.len() > constants::MAX_TAG_VALUE_LEN {
+            return Err(format!(
+                "Value of tag {} is longer than {} bytes",
+                key,
+                constants::MAX_TAG_VALUE_LEN
+            ));
+        }
+        if tags.insert(key.to_string(), value.to_string()).is_some() {
+            return Err(format!("Tag {} is given twice", key));
+        }
+    }
+    if tags.len() > constants::MAX_TAGS {
+        return Err(format!("More than {} tags", constants::MAX_TAGS));
+    }
+    Ok(tags)
+}
+
+/// Whether `tags` satisfy every filter, each `key:value` for an exact match
+/// or a bare `key` for any value.
+pub fn matches(tags: &Tags, filters: &[String]) -> bool {
+    filters.iter().all(|filter| match filter.split_once(':') {
+        Some((key, value)) => tags.get(key).is_some_and(|tag| tag == value),
+        None => tags.contains_key(filter),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tags() {
+        let tags = parse("env=prod, team=data-eng,empty=").unwrap();
+        assert_eq!(tags["env"], "prod");
+        assert_eq!(tags["team"], "data-eng");
+        assert_eq!(tags["empty"], "");
+        assert!(parse("").unwrap().is_empty());
+
+        for invalid in ["env", "=prod", "env=a,env=b", "bad key=x", "a/b=c"] {
+            assert!(parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_filters_must_all_match() {
+        let tags = parse("env=prod,team=data").unwrap();
+        assert!(matches(&tags, &[]));
+        assert!(matches(
+            &tags,
+            &["env:prod".to_string(), "team".to_string()]
+        ));
+        assert!(!matches(&tags, &["env:dev".to_string()]));
+        assert!(!matches(
+            &tags,
+            &["env:prod".to_string(), "owner".to_string()]
+        ));
+    }
+}
@@ -0,0 +1,79 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+/// Ingest bandwidth limits in bytes per second; `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ThrottleConfig {
+    pub global_bytes_per_sec: Option<u64>,
+    pub connection_bytes_per_sec: Option<u64>,
+}
+
+/// Token bucket holding up to one second of traffic. Taking more than is
+/// available puts the bucket into debt and sleeps until it is paid back, so a
+/// single large frame is delayed rather than rejected.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: u64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec.max(1);
+        Self {
+            rate,
+            state: Mutex::new(BucketState {
+                tokens: rate as f64,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    pub async fn acquire(&self, bytes: usize) {
+        let wait = {
+            let mut state = self.state.lock().expect("token bucket lock poisoned");
+            let now = Instant::now();
+            let refill = now.duration_since(state.refilled).as_secs_f64() * self.rate as f64;
+            state.tokens = (state.tokens + refill).min(self.rate as f64) - bytes as f64;
+            state.refilled = now;
+
+            if state.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-state.tokens / self.rate as f64)
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_burst_is_free_and_excess_waits() {
+        let bucket = TokenBucket::new(1000);
+
+        let started = Instant::now();
+        bucket.acquire(1000).await;
+        assert!(started.elapsed() < Duration::from_millis(50));
+
+        let started = Instant::now();
+        bucket.acquire(100).await;
+        assert!(started.elapsed() >= Duration::from_millis(90));
+    }
+}
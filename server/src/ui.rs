@@ -0,0 +1,45 @@
+/// Files of the single-page UI served under `/ui`, compiled into the binary.
+const ASSETS: &[(&str, &str, &[u8])] = &[
+    (
+        "index.html",
+        "text/html; charset=utf-8",
+        include_bytes!("../ui/index.html"),
+    ),
+    (
+        "app.js",
+        "text/javascript; charset=utf-8",
+        include_bytes!("../ui/app.js"),
+    ),
+    (
+        "style.css",
+        "text/css; charset=utf-8",
+        include_bytes!("../ui/style.css"),
+    ),
+];
+
+/// Content type and contents of the asset `name`; an empty name is the page itself.
+pub fn asset(name: &str) -> Option<(&'static str, &'static [u8])> {
+    let name = if name.is_empty() { "index.html" } else { name };
+    ASSETS
+        .iter()
+        .find(|(asset, ..)| *asset == name)
+        .map(|&(_, content_type, contents)| (content_type, contents))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_and_its_assets_are_embedded() {
+        let (content_type, page) = asset("").unwrap();
+        assert!(content_type.starts_with("text/html"));
+        let page = std::str::from_utf8(page).unwrap();
+        for (name, ..) in ASSETS {
+            if *name != "index.html" {
+                assert!(page.contains(&format!("/ui/{}", name)), "{}", name);
+            }
+        }
+        assert!(asset("../Cargo.toml").is_none());
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::constants;
+
+/// Client-defined metadata carried in `X-Meta-*` headers, like S3 user
+/// metadata. Keys are the lowercased header names without the prefix.
+pub type UserMetadata = BTreeMap<String, String>;
+
+/// Collects the `X-Meta-*` headers of a request.
+pub fn from_headers(headers: &HeaderMap) -> Result<UserMetadata, String> {
+    let mut metadata = UserMetadata::new();
+    let mut size = 0;
+    for (name, value) in headers {
+        let Some(key) = name
+            .as_str()
+            .strip_prefix(constants::USER_METADATA_PREFIX)
+            .filter(|key| !key.is_empty())
+        else {
+            continue;
+        };
+        let value = value.to_str().map_err(|_| {
+            format!(
+                "{}{} must be visible ASCII",
+                constants::USER_METADATA_PREFIX,
+                key
+            )
+        })?;
+        size += key.len() + value.len();
+        if metadata
+            .insert(key.to_string(), value.to_string())
+            .is_some()
+        {
+            return Err(format!(
+                "{}{} is given twice",
+                constants::USER_METADATA_PREFIX,
+                key
+            ));
+        }
+    }
+    if size > constants::MAX_USER_METADATA_BYTES {
+        return Err(format!(
+            "{}* headers are larger than {} bytes",
+            constants::USER_METADATA_PREFIX,
+            constants::MAX_USER_METADATA_BYTES
+        ));
+    }
+    Ok(metadata)
+}
+
+/// Adds `metadata` to a response as `X-Meta-*` headers.
+pub fn to_headers(metadata: &UserMetadata, headers: &mut HeaderMap) {
+    for (key, value) in metadata {
+        let name = HeaderName::try_from(format!("{}{}", constants::USER_METADATA_PREFIX, key));
+        if let (Ok(name), Ok(value)) = (name, HeaderValue::from_str(value)) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips_through_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Meta-Project", HeaderValue::from_static("apollo"));
+        headers.insert("x-meta-reviewed-by", HeaderValue::from_static("ops"));
+        headers.insert("X-File-Id", HeaderValue::from_static("abc"));
+
+        let metadata = from_headers(&headers).unwrap();
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata["project"], "apollo");
+        assert_eq!(metadata["reviewed-by"], "ops");
+
+        let mut response = HeaderMap::new();
+        to_headers(&metadata, &mut response);
+        assert_eq!(response["x-meta-project"], "apollo");
+        assert_eq!(response.len(), 2);
+    }
+
+    #[test]
+    fn test_refuses_oversized_or_repeated_metadata() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "X-Meta-Notes",
+            HeaderValue::from_str(&"a".repeat(constants::MAX_USER_METADATA_BYTES)).unwrap(),
+        );
+        assert!(from_headers(&headers).is_err());
+
+        let mut headers = HeaderMap::new();
+        headers.append("X-Meta-Notes", HeaderValue::from_static("one"));
+        headers.append("X-Meta-Notes", HeaderValue::from_static("two"));
+        assert!(from_headers(&headers).is_err());
+    }
+}
@@ -0,0 +1,354 @@
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{Request, Response, StatusCode, header::HeaderName};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use server::{config::ServerConfig, crypto, http::HttpConfig, server::SliceBreadServer, tls};
+use tempdir::TempDir;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{
+    TlsAcceptor, TlsConnector,
+    rustls::{
+        self, RootCertStore,
+        pki_types::{PrivateKeyDer, ServerName},
+    },
+};
+
+#[derive(Clone, Copy)]
+enum Protocol {
+    Http1,
+    Http2,
+}
+
+struct TestServer {
+    addr: SocketAddr,
+    upload_dir: PathBuf,
+    tls_roots: Option<RootCertStore>,
+    _temp_dir: TempDir,
+}
+
+impl TestServer {
+    async fn start() -> Self {
+        Self::start_with_tls(false).await
+    }
+
+    async fn start_with_tls(enable_tls: bool) -> Self {
+        Self::start_with(enable_tls, ServerConfig::default()).await
+    }
+
+    async fn start_with(enable_tls: bool, config: ServerConfig) -> Self {
+        let temp_dir = TempDir::new("integration").unwrap();
+        let upload_dir = temp_dir.path().join("uploads");
+        tokio::fs::create_dir_all(&upload_dir).await.unwrap();
+
+        let (acceptor, tls_roots) = if enable_tls {
+            let (acceptor, roots) = self_signed_tls(&temp_dir);
+            (Some(acceptor), Some(roots))
+        } else {
+            (None, None)
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Arc::new(SliceBreadServer::with_config(
+            upload_dir.to_str().unwrap().to_string(),
+            config,
+        ));
+        tokio::spawn(server::serve(listener, server, acceptor));
+
+        Self {
+            addr,
+            upload_dir,
+            tls_roots,
+            _temp_dir: temp_dir,
+        }
+    }
+
+    fn chunk(&self, file_id: &str, index: usize, total: usize, data: &str) -> Request<Full<Bytes>> {
+        Request::builder()
+            .method("POST")
+            .uri(format!("http://{}/", self.addr))
+            .header("X-File-Id", file_id)
+            .header("X-File-Name", "upload.txt")
+            .header("X-Chunk-Index", index.to_string())
+            .header("X-Total-Chunks", total.to_string())
+            .body(Full::new(Bytes::from(data.to_string())))
+            .unwrap()
+    }
+
+    async fn send(&self, protocol: Protocol, req: Request<Full<Bytes>>) -> Response<Bytes> {
+        let stream = TcpStream::connect(self.addr).await.unwrap();
+        match &self.tls_roots {
+            Some(roots) => {
+                let alpn = match protocol {
+                    Protocol::Http1 => b"http/1.1".to_vec(),
+                    Protocol::Http2 => b"h2".to_vec(),
+                };
+                let mut config =
+                    rustls::ClientConfig::builder_with_provider(crypto::tls_provider())
+                        .with_safe_default_protocol_versions()
+                        .unwrap()
+                        .with_root_certificates(roots.clone())
+                        .with_no_client_auth();
+                config.alpn_protocols = vec![alpn.clone()];
+
+                let name = ServerName::try_from("localhost").unwrap();
+                let stream = TlsConnector::from(Arc::new(config))
+                    .connect(name, stream)
+                    .await
+                    .unwrap();
+                assert_eq!(stream.get_ref().1.alpn_protocol(), Some(alpn.as_slice()));
+                send_over(TokioIo::new(stream), protocol, req).await
+            }
+            None => send_over(TokioIo::new(stream), protocol, req).await,
+        }
+    }
+}
+
+async fn send_over<IO>(io: IO, protocol: Protocol, req: Request<Full<Bytes>>) -> Response<Bytes>
+where
+    IO: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let res = match protocol {
+        Protocol::Http1 => {
+            let (mut sender, conn) = hyper::client::conn::http1::handshake(io).await.unwrap();
+            tokio::spawn(conn);
+            sender.send_request(req).await.unwrap()
+        }
+        Protocol::Http2 => {
+            let (mut sender, conn) =
+                hyper::client::conn::http2::handshake(TokioExecutor::new(), io)
+                    .await
+                    .unwrap();
+            tokio::spawn(conn);
+            sender.send_request(req).await.unwrap()
+        }
+    };
+
+    let (parts, body) = res.into_parts();
+    let body = body.collect().await.unwrap().to_bytes();
+    Response::from_parts(parts, body)
+}
+
+fn self_signed_tls(temp_dir: &TempDir) -> (TlsAcceptor, RootCertStore) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_path = temp_dir.path().join("cert.pem");
+    let key_path = temp_dir.path().join("key.pem");
+    std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+    std::fs::write(&key_path, certified.signing_key.serialize_pem()).unwrap();
+
+    let server_config = tls::load_server_config(&cert_path, &key_path, None).unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots.add(certified.cert.der().clone()).unwrap();
+
+    (TlsAcceptor::from(Arc::new(server_config)), roots)
+}
+
+async fn upload_three_chunks(server: &TestServer, protocol: Protocol, file_id: &str) {
+    for (i, data) in ["alpha-", "beta-", "gamma"].iter().enumerate() {
+        let res = server
+            .send(protocol, server.chunk(file_id, i, 3, data))
+            .await;
+        let expected = if i == 2 {
+            StatusCode::CREATED
+        } else {
+            StatusCode::OK
+        };
+        assert_eq!(res.status(), expected);
+    }
+
+    let content = tokio::fs::read_to_string(server.upload_dir.join(file_id).join("upload.txt"))
+        .await
+        .unwrap();
+    assert_eq!(content, "alpha-beta-gamma");
+}
+
+#[tokio::test]
+async fn test_http1_upload() {
+    let server = TestServer::start().await;
+    upload_three_chunks(&server, Protocol::Http1, "h1file").await;
+}
+
+#[tokio::test]
+async fn test_http2_prior_knowledge_upload() {
+    let server = TestServer::start().await;
+    upload_three_chunks(&server, Protocol::Http2, "h2file").await;
+}
+
+#[tokio::test]
+async fn test_tls_http1_upload() {
+    let server = TestServer::start_with_tls(true).await;
+    upload_three_chunks(&server, Protocol::Http1, "tlsh1file").await;
+}
+
+#[tokio::test]
+async fn test_tls_http2_upload() {
+    let server = TestServer::start_with_tls(true).await;
+    upload_three_chunks(&server, Protocol::Http2, "tlsh2file").await;
+}
+
+#[tokio::test]
+async fn test_missing_header_returns_400() {
+    let server = TestServer::start().await;
+    let req = Request::builder()
+        .method("POST")
+        .uri(format!("http://{}/", server.addr))
+        .header("X-File-Id", "missing")
+        .body(Full::new(Bytes::from("data")))
+        .unwrap();
+
+    let res = server.send(Protocol::Http1, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert!(String::from_utf8_lossy(res.body()).contains("Missing header"));
+    assert!(String::from_utf8_lossy(res.body()).contains(r#""code":"missing_header""#));
+}
+
+#[tokio::test]
+async fn test_chunk_index_out_of_range_returns_400() {
+    let server = TestServer::start().await;
+    let res = server
+        .send(Protocol::Http2, server.chunk("outofrange", 5, 2, "data"))
+        .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_io_failure_returns_500() {
+    let server = TestServer::start().await;
+    tokio::fs::create_dir_all(server.upload_dir.join("conflict").join("upload.txt"))
+        .await
+        .unwrap();
+
+    let res = server
+        .send(Protocol::Http1, server.chunk("conflict", 0, 1, "data"))
+        .await;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+async fn test_http_limits_are_applied() {
+    let config = ServerConfig {
+        http: HttpConfig {
+            http1_max_headers: Some(16),
+            http2_max_concurrent_streams: Some(4),
+            header_read_timeout: Some(Duration::from_secs(5)),
+            ..HttpConfig::default()
+        },
+        ..ServerConfig::default()
+    };
+    let server = TestServer::start_with(false, config).await;
+    upload_three_chunks(&server, Protocol::Http2, "tunedfile").await;
+
+    let mut req = server.chunk("tunedfile", 0, 1, "data");
+    for i in 0..32 {
+        req.headers_mut().insert(
+            format!("x-extra-{}", i).parse::<HeaderName>().unwrap(),
+            "1".parse().unwrap(),
+        );
+    }
+    let res = server.send(Protocol::Http1, req).await;
+    assert_eq!(res.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn test_mtls_client_certificate_maps_to_tenant() {
+    let temp_dir = TempDir::new("integration").unwrap();
+    let upload_dir = temp_dir.path().join("uploads");
+
+    let mut ca_params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    let ca = rcgen::CertifiedIssuer::self_signed(ca_params, rcgen::KeyPair::generate().unwrap())
+        .unwrap();
+    let mut client_params =
+        rcgen::CertificateParams::new(vec!["render-01.internal".to_string()]).unwrap();
+    client_params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ClientAuth];
+    let client_key = rcgen::KeyPair::generate().unwrap();
+    let client_cert = client_params.signed_by(&client_key, &ca).unwrap();
+
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_path = temp_dir.path().join("cert.pem");
+    let key_path = temp_dir.path().join("key.pem");
+    let ca_path = temp_dir.path().join("client-ca.pem");
+    std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+    std::fs::write(&key_path, certified.signing_key.serialize_pem()).unwrap();
+    std::fs::write(&ca_path, ca.pem()).unwrap();
+    let server_config = tls::load_server_config(&cert_path, &key_path, Some(&ca_path)).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        client_identities: vec!["render-01.internal=acme".parse().unwrap()],
+        ..ServerConfig::default()
+    };
+    let server = Arc::new(SliceBreadServer::with_config(
+        upload_dir.to_str().unwrap().to_string(),
+        config,
+    ));
+    tokio::spawn(server::serve(
+        listener,
+        server,
+        Some(TlsAcceptor::from(Arc::new(server_config))),
+    ));
+
+    let mut roots = RootCertStore::empty();
+    roots.add(certified.cert.der().clone()).unwrap();
+    let client_config = |with_cert: bool| {
+        let builder = rustls::ClientConfig::builder_with_provider(crypto::tls_provider())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots.clone());
+        let config = if with_cert {
+            builder
+                .with_client_auth_cert(
+                    vec![client_cert.der().clone()],
+                    PrivateKeyDer::try_from(client_key.serialize_der()).unwrap(),
+                )
+                .unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+        TlsConnector::from(Arc::new(config))
+    };
+    let request = || {
+        Request::builder()
+            .method("POST")
+            .uri(format!("https://{}/", addr))
+            .header("X-File-Id", "mtlsfile")
+            .header("X-File-Name", "upload.txt")
+            .header("X-Chunk-Index", "0")
+            .header("X-Total-Chunks", "1")
+            .body(Full::new(Bytes::from("signed")))
+            .unwrap()
+    };
+    let name = ServerName::try_from("localhost").unwrap();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let anonymous = match client_config(false).connect(name.clone(), stream).await {
+        Ok(stream) => {
+            let io = TokioIo::new(stream);
+            match hyper::client::conn::http1::handshake(io).await {
+                Ok((mut sender, conn)) => {
+                    tokio::spawn(conn);
+                    sender.send_request(request()).await.ok()
+                }
+                Err(_) => None,
+            }
+        }
+        Err(_) => None,
+    };
+    assert!(
+        anonymous.is_none(),
+        "a client without a certificate was served"
+    );
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let stream = client_config(true).connect(name, stream).await.unwrap();
+    let res = send_over(TokioIo::new(stream), Protocol::Http1, request()).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+
+    let sidecar =
+        std::fs::read_to_string(upload_dir.join("mtlsfile").join("upload.txt.meta.json")).unwrap();
+    assert!(sidecar.contains(r#""uploader": "acme""#));
+}
@@ -0,0 +1,91 @@
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{Request, service::Service};
+use proptest::prelude::*;
+use server::server::SliceBreadServer;
+use tempdir::TempDir;
+
+const FILE_ID: &str = "prop";
+const FILE_NAME: &str = "prop.bin";
+
+#[derive(Debug, Clone)]
+struct UploadPlan {
+    data: Vec<u8>,
+    chunks: Vec<Vec<u8>>,
+    arrival: Vec<usize>,
+}
+
+/// Splits random bytes at random cut points, then sends every chunk once in a
+/// shuffled order with some indices retransmitted at random positions.
+fn upload_plan() -> impl Strategy<Value = UploadPlan> {
+    (prop::collection::vec(any::<u8>(), 0..4096), 1usize..16)
+        .prop_flat_map(|(data, total)| {
+            let cuts = prop::collection::vec(0..=data.len(), total - 1);
+            let order = Just((0..total).collect::<Vec<_>>()).prop_shuffle();
+            let duplicates = prop::collection::vec((0..total, any::<prop::sample::Index>()), 0..4);
+            (Just(data), cuts, order, duplicates)
+        })
+        .prop_map(|(data, mut cuts, mut arrival, duplicates)| {
+            cuts.sort_unstable();
+            let mut bounds = vec![0];
+            bounds.extend(cuts);
+            bounds.push(data.len());
+            let chunks = bounds
+                .windows(2)
+                .map(|w| data[w[0]..w[1]].to_vec())
+                .collect();
+
+            for (chunk, position) in duplicates {
+                let at = position.index(arrival.len() + 1);
+                arrival.insert(at, chunk);
+            }
+
+            UploadPlan {
+                data,
+                chunks,
+                arrival,
+            }
+        })
+}
+
+fn chunk_request(index: usize, total: usize, data: &[u8]) -> Request<Full<Bytes>> {
+    Request::builder()
+        .method("POST")
+        .header("X-File-Id", FILE_ID)
+        .header("X-File-Name", FILE_NAME)
+        .header("X-Chunk-Index", index.to_string())
+        .header("X-Total-Chunks", total.to_string())
+        .body(Full::new(Bytes::copy_from_slice(data)))
+        .unwrap()
+}
+
+async fn run_plan(plan: UploadPlan) -> Result<(), TestCaseError> {
+    let temp_dir = TempDir::new("reassembly").unwrap();
+    let service =
+        SliceBreadServer::<Full<Bytes>>::new(temp_dir.path().to_str().unwrap().to_string());
+    let total = plan.chunks.len();
+    let final_path = temp_dir.path().join(FILE_ID).join(FILE_NAME);
+
+    for &index in &plan.arrival {
+        // Whichever chunk completes the set assembles the file, in any arrival order.
+        let res = service
+            .call(chunk_request(index, total, &plan.chunks[index]))
+            .await
+            .map_err(|e| TestCaseError::fail(e.to_string()))?;
+        prop_assert!(res.status().is_success());
+    }
+
+    let assembled = tokio::fs::read(&final_path).await.unwrap();
+    prop_assert_eq!(assembled, plan.data);
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn prop_assembled_bytes_match_input(plan in upload_plan()) {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(run_plan(plan))?;
+    }
+}
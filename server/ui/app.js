@@ -0,0 +1,135 @@
+"use strict";
+
+// Sends files with the same chunked protocol as any other client: one POST /
+// per chunk, in order, with the upload's metadata repeated on every chunk.
+// Chunks are as large as GET /capabilities recommends for the file.
+const DEFAULT_CHUNK_SIZE = 1024 * 1024;
+
+const drop = document.getElementById("drop");
+const picker = document.getElementById("picker");
+const transfers = document.getElementById("transfers");
+const uploads = document.getElementById("uploads");
+
+function newFileId() {
+  if (window.crypto && crypto.randomUUID) {
+    return crypto.randomUUID();
+  }
+  return Date.now().toString(36) + Math.random().toString(36).slice(2);
+}
+
+function formatBytes(bytes) {
+  const units = ["B", "KiB", "MiB", "GiB", "TiB"];
+  let unit = 0;
+  while (bytes >= 1024 && unit < units.length - 1) {
+    bytes /= 1024;
+    unit += 1;
+  }
+  return `${bytes.toFixed(unit === 0 ? 0 : 1)} ${units[unit]}`;
+}
+
+async function errorMessage(response) {
+  try {
+    return (await response.json()).message;
+  } catch {
+    return `${response.status} ${response.statusText}`;
+  }
+}
+
+async function chunkSize(file) {
+  try {
+    const response = await fetch(`/capabilities?file_size=${file.size}`);
+    if (response.ok) {
+      return (await response.json()).recommended_chunk_size || DEFAULT_CHUNK_SIZE;
+    }
+  } catch {
+    // Older servers don't recommend one.
+  }
+  return DEFAULT_CHUNK_SIZE;
+}
+
+async function upload(file) {
+  const item = document.createElement("li");
+  const label = document.createElement("div");
+  const bar = document.createElement("progress");
+  label.textContent = file.name;
+  bar.max = file.size || 1;
+  bar.value = 0;
+  item.append(label, bar);
+  transfers.prepend(item);
+
+  const fileId = newFileId();
+  try {
+    const size = await chunkSize(file);
+    const totalChunks = Math.max(1, Math.ceil(file.size / size));
+    for (let index = 0; index < totalChunks; index += 1) {
+      const chunk = file.slice(index * size, (index + 1) * size);
+      const response = await fetch("/", {
+        method: "POST",
+        headers: {
+          "X-File-Id": fileId,
+          "X-File-Name": encodeURIComponent(file.name),
+          "X-File-Size": String(file.size),
+          "X-Chunk-Index": String(index),
+          "X-Total-Chunks": String(totalChunks),
+        },
+        body: chunk,
+      });
+      if (!response.ok) {
+        throw new Error(await errorMessage(response));
+      }
+      bar.value = Math.min(file.size, (index + 1) * size);
+    }
+    label.textContent = `${file.name}: done`;
+  } catch (err) {
+    label.textContent = `${file.name}: ${err.message}`;
+    label.className = "failed";
+  }
+  refresh();
+}
+
+async function refresh() {
+  const response = await fetch("/uploads");
+  if (!response.ok) {
+    return;
+  }
+  const rows = (await response.json()).map((status) => {
+    const row = document.createElement("tr");
+    const total = status.bytes_total_estimated ? `~${formatBytes(status.bytes_total)}` : formatBytes(status.bytes_total);
+    const eta = status.eta_seconds === undefined ? "" : `${status.eta_seconds}s`;
+    for (const text of [
+      status.file_id,
+      status.state,
+      `${status.chunks_received}/${status.total_chunks}`,
+      `${formatBytes(status.bytes_received)} of ${total}`,
+      eta,
+    ]) {
+      const cell = document.createElement("td");
+      cell.textContent = text;
+      row.append(cell);
+    }
+    return row;
+  });
+  uploads.replaceChildren(...rows);
+}
+
+drop.addEventListener("dragover", (event) => {
+  event.preventDefault();
+  drop.classList.add("over");
+});
+drop.addEventListener("dragleave", () => drop.classList.remove("over"));
+drop.addEventListener("drop", (event) => {
+  event.preventDefault();
+  drop.classList.remove("over");
+  for (const file of event.dataTransfer.files) {
+    upload(file);
+  }
+});
+picker.addEventListener("change", () => {
+  for (const file of picker.files) {
+    upload(file);
+  }
+  picker.value = "";
+});
+
+refresh();
+setInterval(refresh, 2000);